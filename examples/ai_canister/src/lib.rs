@@ -10,18 +10,23 @@ use ic_stable_structures::{
 };
 use rand::{rngs::StdRng, RngCore, SeedableRng};
 use serde::{Deserialize, Serialize};
-use serde_bytes::ByteBuf;
-use std::{borrow::Cow, cell::RefCell, collections::BTreeSet, time::Duration};
+use serde_bytes::{ByteArray, ByteBuf};
+use sha3::{Digest, Sha3_256};
+use std::{borrow::Cow, cell::RefCell, collections::BTreeSet, io::Read, time::Duration};
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 
 const STATE_MEMORY_ID: MemoryId = MemoryId::new(0);
 const FS_DATA_MEMORY_ID: MemoryId = MemoryId::new(1);
+// the instruction limit of a timer execution is 40B
+const LOAD_INSTRUCTIONS_BUDGET: u64 = 10_000_000_000;
+const LOAD_BLOCK_SIZE: usize = 1024 * 1024;
 
 thread_local! {
     static RNG: RefCell<Option<StdRng>> = const { RefCell::new(None) };
     static STATE: RefCell<State> = RefCell::new(State::default());
     static AI_MODEL: RefCell<Option<AIModel>> = const { RefCell::new(None) };
+    static MODEL_LOADER: RefCell<Option<ModelLoader>> = const { RefCell::new(None) };
 
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
         RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
@@ -65,11 +70,24 @@ pub fn init_rand() {
     register_custom_getrandom!(custom_getrandom);
 }
 
-#[derive(Default)]
+// the weights are not materialized in the heap, they are read from the stable memory
+// with `fs::open_file(model)` when they are needed.
 pub struct AIModel {
     pub config: Vec<u8>,
     pub tokenizer: Vec<u8>,
-    pub model: Vec<u8>,
+    pub model: u32,          // the file id of the verified weights
+    pub hash: ByteArray<32>, // the SHA3-256 hash of the weights
+}
+
+// verifies the model across timer rounds, so the loading is not limited by the
+// instruction limit of one message.
+struct ModelLoader {
+    config: Vec<u8>,
+    tokenizer: Vec<u8>,
+    model: u32,
+    expected_hash: Option<ByteArray<32>>, // the hash of the uploaded file, if it is set
+    reader: fs::FileReader,
+    hasher: Sha3_256,
 }
 
 #[derive(CandidType, Clone, Default, Deserialize, Serialize)]
@@ -77,6 +95,8 @@ pub struct State {
     pub ai_config: u32,
     pub ai_tokenizer: u32,
     pub ai_model: u32,
+    #[serde(default)]
+    pub ai_model_loaded: u64, // the loaded bytes of the model
 }
 
 impl Storable for State {
@@ -129,20 +149,74 @@ pub mod state {
         });
     }
 
+    // starts loading the model, the config and the tokenizer are small and read at once,
+    // the weights are streamed and hashed by `load_model_round`.
     pub fn load_model(args: &LoadModelInput) -> Result<(), String> {
-        AI_MODEL.with(|r| {
-            // let start = ic_cdk::api::performance_counter(1);
-            *r.borrow_mut() = Some(AIModel {
-                config: fs::get_full_chunks(args.config_id)?,
-                tokenizer: fs::get_full_chunks(args.tokenizer_id)?,
-                model: fs::get_full_chunks(args.model_id)?,
-            });
-            // ic_cdk::println!(
-            //     "load_model_instructions: {}",
-            //     ic_cdk::api::performance_counter(1) - start
-            // );
-            Ok(())
-        })
+        let loader = ModelLoader {
+            config: fs::get_full_chunks(args.config_id)?,
+            tokenizer: fs::get_full_chunks(args.tokenizer_id)?,
+            model: args.model_id,
+            expected_hash: fs::get_file(args.model_id).and_then(|f| f.hash),
+            reader: fs::open_file(args.model_id)?,
+            hasher: Sha3_256::new(),
+        };
+        AI_MODEL.with(|r| *r.borrow_mut() = None);
+        MODEL_LOADER.with(|r| *r.borrow_mut() = Some(loader));
+        with_mut(|s| s.ai_model_loaded = 0);
+        ic_cdk_timers::set_timer(Duration::from_secs(0), load_model_round);
+        Ok(())
+    }
+
+    // reads the model block by block while the instruction budget allows,
+    // and schedules the next round if the model is not fully loaded. the model is
+    // rejected if its hash does not match the hash of the uploaded file.
+    fn load_model_round() {
+        let res = MODEL_LOADER.with(|r| {
+            let mut r = r.borrow_mut();
+            let loader = match r.as_mut() {
+                None => return Ok(false),
+                Some(loader) => loader,
+            };
+
+            let mut buf = vec![0u8; LOAD_BLOCK_SIZE];
+            while ic_cdk::api::instruction_counter() < LOAD_INSTRUCTIONS_BUDGET {
+                let n = loader
+                    .reader
+                    .read(&mut buf)
+                    .map_err(|err| err.to_string())?;
+                if n == 0 {
+                    let loader = r.take().expect("loader should exist");
+                    let hash: [u8; 32] = loader.hasher.finalize().into();
+                    let hash = ByteArray::from(hash);
+                    if loader.expected_hash.is_some_and(|h| h != hash) {
+                        Err("model hash mismatch".to_string())?;
+                    }
+                    AI_MODEL.with(|m| {
+                        *m.borrow_mut() = Some(AIModel {
+                            config: loader.config,
+                            tokenizer: loader.tokenizer,
+                            model: loader.model,
+                            hash,
+                        })
+                    });
+                    return Ok(false);
+                }
+                loader.hasher.update(&buf[..n]);
+                with_mut(|s| s.ai_model_loaded = loader.reader.position());
+            }
+            Ok::<bool, String>(true)
+        });
+
+        match res {
+            Ok(true) => {
+                ic_cdk_timers::set_timer(Duration::from_secs(0), load_model_round);
+            }
+            Ok(false) => {}
+            Err(err) => {
+                MODEL_LOADER.with(|r| *r.borrow_mut() = None);
+                ic_cdk::println!("failed to load model: {}", err);
+            }
+        }
     }
 
     // pub fn run_ai<W>(
@@ -157,7 +231,8 @@ pub mod state {
     //     AI_MODEL.with(|r| match r.borrow().as_ref() {
     //         None => Err("AI model not loaded".to_string()),
     //         Some(m) => {
-    //             let mut ai = ai::TextGeneration::load(args, &m.config, &m.tokenizer, &m.model)
+    //             let weights = fs::open_file(m.model)?;
+    //             let mut ai = ai::TextGeneration::load(args, &m.config, &m.tokenizer, weights)
     //                 .map_err(|err| format!("{:?}", err))?;
     //             ai.run(prompt, sample_len, w)
     //                 .map_err(|err| format!("{:?}", err))
@@ -189,6 +264,7 @@ fn set_max_file_size(size: u64) -> Result<(), String> {
     Ok(())
 }
 
+// starts loading the model in the background, the progress is reported by `state`.
 #[ic_cdk::update(guard = "is_controller_or_manager")]
 fn admin_load_model(args: LoadModelInput) -> Result<u64, String> {
     state::load_model(&args)?;
@@ -217,16 +293,21 @@ fn post_upgrade() {
     init_rand();
     state::load();
     fs::load();
-    state::with(|s| {
+    let args = state::with(|s| {
         if s.ai_model > 0 {
-            let _ = state::load_model(&LoadModelInput {
+            Some(LoadModelInput {
                 config_id: s.ai_config,
                 tokenizer_id: s.ai_tokenizer,
                 model_id: s.ai_model,
             })
-            .map_err(|err| ic_cdk::trap(&format!("failed to load model: {:?}", err)));
+        } else {
+            None
         }
     });
+    if let Some(args) = args {
+        let _ = state::load_model(&args)
+            .map_err(|err| ic_cdk::trap(&format!("failed to load model: {:?}", err)));
+    }
 }
 
 fn is_controller() -> Result<(), String> {
@@ -5,6 +5,8 @@ use candid::{
 use ic_agent::{Agent, Identity};
 use ic_oss_types::format_error;
 
+use crate::error::{Error, ErrorKind};
+
 pub async fn build_agent(host: &str, identity: Box<dyn Identity>) -> Result<Agent, String> {
     let agent = Agent::builder()
         .with_url(host)
@@ -24,39 +26,77 @@ pub async fn update_call<In, Out>(
     canister_id: &Principal,
     method_name: &str,
     args: In,
-) -> Result<Out, String>
+) -> Result<Out, Error>
 where
     In: ArgumentEncoder + Send,
     Out: CandidType + for<'a> candid::Deserialize<'a>,
 {
-    let input = encode_args(args).map_err(format_error)?;
+    let input = encode_args(args).map_err(|err| codec_error(err, method_name, canister_id))?;
     let res = agent
         .update(canister_id, method_name)
         .with_arg(input)
         .call_and_wait()
         .await
-        .map_err(format_error)?;
-    let output = Decode!(res.as_slice(), Out).map_err(format_error)?;
+        .map_err(|err| Error::new(format_error(err)).with_context(method_name, canister_id))?;
+    let output =
+        Decode!(res.as_slice(), Out).map_err(|err| codec_error(err, method_name, canister_id))?;
     Ok(output)
 }
 
+/// Calls the update method that returns `Result<Out, String>`, the error message of the
+/// canister is classified into the returned error.
+pub async fn update_call_result<In, Out>(
+    agent: &Agent,
+    canister_id: &Principal,
+    method_name: &str,
+    args: In,
+) -> Result<Out, Error>
+where
+    In: ArgumentEncoder + Send,
+    Out: CandidType + for<'a> candid::Deserialize<'a>,
+{
+    let res: Result<Out, String> = update_call(agent, canister_id, method_name, args).await?;
+    res.map_err(|err| Error::new(err).with_context(method_name, canister_id))
+}
+
 pub async fn query_call<In, Out>(
     agent: &Agent,
     canister_id: &Principal,
     method_name: &str,
     args: In,
-) -> Result<Out, String>
+) -> Result<Out, Error>
 where
     In: ArgumentEncoder + Send,
     Out: CandidType + for<'a> candid::Deserialize<'a>,
 {
-    let input = encode_args(args).map_err(format_error)?;
+    let input = encode_args(args).map_err(|err| codec_error(err, method_name, canister_id))?;
     let res = agent
         .query(canister_id, method_name)
         .with_arg(input)
         .call()
         .await
-        .map_err(format_error)?;
-    let output = Decode!(res.as_slice(), Out).map_err(format_error)?;
+        .map_err(|err| Error::new(format_error(err)).with_context(method_name, canister_id))?;
+    let output =
+        Decode!(res.as_slice(), Out).map_err(|err| codec_error(err, method_name, canister_id))?;
     Ok(output)
 }
+
+/// Calls the query method that returns `Result<Out, String>`, the error message of the
+/// canister is classified into the returned error.
+pub async fn query_call_result<In, Out>(
+    agent: &Agent,
+    canister_id: &Principal,
+    method_name: &str,
+    args: In,
+) -> Result<Out, Error>
+where
+    In: ArgumentEncoder + Send,
+    Out: CandidType + for<'a> candid::Deserialize<'a>,
+{
+    let res: Result<Out, String> = query_call(agent, canister_id, method_name, args).await?;
+    res.map_err(|err| Error::new(err).with_context(method_name, canister_id))
+}
+
+fn codec_error(err: impl std::fmt::Debug, method_name: &str, canister_id: &Principal) -> Error {
+    Error::with_kind(ErrorKind::Codec, format_error(err)).with_context(method_name, canister_id)
+}
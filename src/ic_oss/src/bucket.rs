@@ -1,25 +1,49 @@
 use bytes::{Bytes, BytesMut};
 use candid::{CandidType, Principal};
-use ic_agent::Agent;
-use ic_oss_types::{bucket::*, file::*, folder::*, format_error};
+use ciborium::from_reader;
+use ic_agent::{
+    hash_tree::{HashTree, LookupResult},
+    Agent, Certificate,
+};
+use ic_oss_types::{
+    bucket::*,
+    check_api_version,
+    cose::TokenInfo,
+    file::*,
+    folder::*,
+    format_error,
+    nft::{Account, NftCollection},
+    stream::{StreamChunk, StreamHandle},
+    ApiCompatibility, BUCKET_API_VERSION,
+};
 use serde::{Deserialize, Serialize};
 use serde_bytes::{ByteArray, ByteBuf};
 use sha3::{Digest, Sha3_256};
 use std::{collections::BTreeSet, sync::Arc};
-use tokio::io::AsyncRead;
-use tokio::sync::{mpsc, RwLock, Semaphore};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, OnceCell, RwLock};
 use tokio_stream::StreamExt;
 use tokio_util::codec::{Decoder, FramedRead};
 
-use crate::agent::{query_call, update_call};
+use crate::{
+    agent::{query_call, query_call_result, update_call_result},
+    crypto::{ChunkCipher, KeySource, CHUNK_OVERHEAD},
+    error::{Error, ErrorKind},
+    throttle::{with_backoff, Throttle},
+    token::{StaticToken, TokenProvider},
+};
 
 #[derive(Clone)]
 pub struct Client {
     concurrency: u8,
+    chunk_size: Arc<OnceCell<u32>>,
     agent: Arc<Agent>,
     bucket: Principal,
     set_readonly: bool,
-    access_token: Option<ByteBuf>,
+    dedup: bool,
+    token_provider: Arc<dyn TokenProvider>,
+    throttle: Arc<Throttle>,
+    key_source: Option<KeySource>,
 }
 
 #[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
@@ -42,10 +66,14 @@ impl Client {
     pub fn new(agent: Arc<Agent>, bucket: Principal) -> Client {
         Client {
             concurrency: 16,
+            chunk_size: Arc::new(OnceCell::new()),
             agent,
             bucket,
             set_readonly: false,
-            access_token: None,
+            dedup: false,
+            token_provider: Arc::new(StaticToken(None)),
+            throttle: Arc::new(Throttle::default()),
+            key_source: None,
         }
     }
 
@@ -55,244 +83,962 @@ impl Client {
         }
     }
 
+    /// Sets the chunk size of the uploads and downloads instead of loading it from
+    /// the bucket, it should be the chunk size of the bucket.
+    pub fn set_chunk_size(&mut self, chunk_size: u32) {
+        if (MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk_size) {
+            self.chunk_size = Arc::new(OnceCell::from(chunk_size));
+        }
+    }
+
+    /// Returns the chunk size of the bucket, or CHUNK_SIZE if it is not loaded yet.
+    pub fn chunk_size(&self) -> u32 {
+        self.chunk_size.get().copied().unwrap_or(CHUNK_SIZE)
+    }
+
+    /// Reads the chunk size that the bucket was installed with, it is loaded once and
+    /// the uploads and downloads call it, so it does not need to be called before them.
+    pub async fn load_chunk_size(&self) -> Result<u32, Error> {
+        self.chunk_size
+            .get_or_try_init(|| async {
+                // the buckets before 1.2 have the default chunk size
+                let version = self.api_semver().await?;
+                if check_api_version(CHUNK_SIZE_API_VERSION, &version)? == ApiCompatibility::Older {
+                    return Ok(CHUNK_SIZE);
+                }
+                let info = self.get_bucket_info().await?;
+                if !(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&info.chunk_size) {
+                    Err(format!("invalid chunk size {}", info.chunk_size))?;
+                }
+                Ok::<u32, Error>(info.chunk_size)
+            })
+            .await
+            .copied()
+    }
+
     pub fn set_readonly(&mut self, readonly: bool) {
         self.set_readonly = readonly;
     }
 
+    /// Skips uploading the content of a file if a file with the same hash exists in the
+    /// bucket, the existing file is copied to the target on the bucket side instead, or
+    /// returned if it is the target. It requires the hash index of the bucket.
+    pub fn set_dedup(&mut self, dedup: bool) {
+        self.dedup = dedup;
+    }
+
+    /// Caps the throughput of the file uploads and downloads in bytes per second,
+    /// None for unlimited. The rate limited requests are retried after a backoff anyway.
+    pub fn set_limit_rate(&mut self, bytes_per_sec: Option<u64>) {
+        self.throttle = Arc::new(Throttle::new(bytes_per_sec));
+    }
+
+    /// Sets a fixed access token for the requests.
+    pub fn set_access_token(&mut self, access_token: Option<ByteBuf>) {
+        self.token_provider = Arc::new(StaticToken(access_token));
+    }
+
+    /// Sets the provider that is consulted for the access token of each request.
+    pub fn set_token_provider(&mut self, provider: Arc<dyn TokenProvider>) {
+        self.token_provider = provider;
+    }
+
+    /// Sets the key source of the client side encryption, the content of the files with
+    /// a DEK is encrypted on upload and decrypted on download, see `crate::crypto`.
+    /// Without a key source the content is transferred as it is.
+    pub fn set_key_source(&mut self, key_source: Option<KeySource>) {
+        self.key_source = key_source;
+    }
+
+    /// the caller of agent should be canister controller
+    pub async fn admin_set_managers(&self, args: BTreeSet<Principal>) -> Result<(), Error> {
+        update_call_result(&self.agent, &self.bucket, "admin_set_managers", (args,)).await
+    }
+
+    /// the caller of agent should be canister controller
+    pub async fn admin_set_auditors(&self, args: BTreeSet<Principal>) -> Result<(), Error> {
+        update_call_result(&self.agent, &self.bucket, "admin_set_auditors", (args,)).await
+    }
+
+    /// the caller of agent should be canister controller
+    pub async fn admin_update_bucket(&self, args: UpdateBucketInput) -> Result<(), Error> {
+        update_call_result(&self.agent, &self.bucket, "admin_update_bucket", (args,)).await
+    }
+
+    /// the caller of agent should be canister controller
+    pub async fn admin_set_backup_target(
+        &self,
+        args: Option<BackupTargetInput>,
+    ) -> Result<(), Error> {
+        update_call_result(
+            &self.agent,
+            &self.bucket,
+            "admin_set_backup_target",
+            (args,),
+        )
+        .await
+    }
+
+    /// the caller of agent should be canister controller
+    pub async fn admin_set_folder_security_headers(
+        &self,
+        folder: u32,
+        headers: Option<SecurityHeaders>,
+    ) -> Result<(), Error> {
+        update_call_result(
+            &self.agent,
+            &self.bucket,
+            "admin_set_folder_security_headers",
+            (folder, headers),
+        )
+        .await
+    }
+
+    /// the caller of agent should be canister controller
+    pub async fn admin_set_well_known(&self, args: WellKnown) -> Result<(), Error> {
+        update_call_result(&self.agent, &self.bucket, "admin_set_well_known", (args,)).await
+    }
+
+    /// the caller of agent should be canister controller
+    pub async fn admin_set_folder_inbox(&self, folder: u32, inbox: bool) -> Result<(), Error> {
+        update_call_result(
+            &self.agent,
+            &self.bucket,
+            "admin_set_folder_inbox",
+            (folder, inbox),
+        )
+        .await
+    }
+
+    /// the caller of agent should be canister controller
+    /// the caller of agent should be canister controller
+    pub async fn admin_set_folder_limits(
+        &self,
+        folder: u32,
+        limits: Option<FolderLimits>,
+    ) -> Result<(), Error> {
+        update_call_result(
+            &self.agent,
+            &self.bucket,
+            "admin_set_folder_limits",
+            (folder, limits),
+        )
+        .await
+    }
+
+    pub async fn admin_set_nft_collection(
+        &self,
+        collection: Option<NftCollection>,
+    ) -> Result<(), Error> {
+        update_call_result(
+            &self.agent,
+            &self.bucket,
+            "admin_set_nft_collection",
+            (collection,),
+        )
+        .await
+    }
+
     /// the caller of agent should be canister controller
-    pub async fn admin_set_managers(&self, args: BTreeSet<Principal>) -> Result<(), String> {
-        update_call(&self.agent, &self.bucket, "admin_set_managers", (args,)).await?
+    pub async fn admin_mint_nft(&self, file: u32, owner: Account) -> Result<(), Error> {
+        update_call_result(&self.agent, &self.bucket, "admin_mint_nft", (file, owner)).await
+    }
+
+    /// the caller of agent should be canister controller
+    pub async fn admin_set_folder_unlisted(
+        &self,
+        folder: u32,
+        unlisted: bool,
+    ) -> Result<(), Error> {
+        update_call_result(
+            &self.agent,
+            &self.bucket,
+            "admin_set_folder_unlisted",
+            (folder, unlisted),
+        )
+        .await
+    }
+
+    /// the caller of agent should be canister controller
+    pub async fn admin_run_gc(
+        &self,
+        start: Option<GcCursor>,
+        max_scan: Option<u32>,
+    ) -> Result<GcReport, Error> {
+        update_call_result(&self.agent, &self.bucket, "admin_run_gc", (start, max_scan)).await
+    }
+
+    /// the caller of agent should be canister controller
+    pub async fn admin_prepare_upgrade(&self) -> Result<UpgradeReport, Error> {
+        update_call_result(&self.agent, &self.bucket, "admin_prepare_upgrade", ()).await
+    }
+
+    /// the caller of agent should be canister controller
+    pub async fn admin_create_role(&self, name: String, policies: String) -> Result<(), Error> {
+        update_call_result(
+            &self.agent,
+            &self.bucket,
+            "admin_create_role",
+            (name, policies),
+        )
+        .await
+    }
+
+    /// the caller of agent should be canister controller
+    pub async fn admin_delete_role(&self, name: String) -> Result<bool, Error> {
+        update_call_result(&self.agent, &self.bucket, "admin_delete_role", (name,)).await
+    }
+
+    /// the caller of agent should be canister controller
+    pub async fn admin_assign_role(&self, principal: Principal, role: String) -> Result<(), Error> {
+        update_call_result(
+            &self.agent,
+            &self.bucket,
+            "admin_assign_role",
+            (principal, role),
+        )
+        .await
     }
 
     /// the caller of agent should be canister controller
-    pub async fn admin_set_auditors(&self, args: BTreeSet<Principal>) -> Result<(), String> {
-        update_call(&self.agent, &self.bucket, "admin_set_auditors", (args,)).await?
+    pub async fn admin_revoke_role(
+        &self,
+        principal: Principal,
+        role: String,
+    ) -> Result<bool, Error> {
+        update_call_result(
+            &self.agent,
+            &self.bucket,
+            "admin_revoke_role",
+            (principal, role),
+        )
+        .await
     }
 
     /// the caller of agent should be canister controller
-    pub async fn admin_update_bucket(&self, args: UpdateBucketInput) -> Result<(), String> {
-        update_call(&self.agent, &self.bucket, "admin_update_bucket", (args,)).await?
+    pub async fn admin_list_roles(&self) -> Result<Vec<RoleInfo>, Error> {
+        query_call_result(&self.agent, &self.bucket, "admin_list_roles", ()).await
+    }
+
+    /// Returns the semantic version of the bucket's interface, buckets without `api_semver`
+    /// are reported by the major version of `api_version`.
+    pub async fn api_semver(&self) -> Result<String, Error> {
+        match query_call(&self.agent, &self.bucket, "api_semver", ()).await {
+            Ok(version) => Ok(version),
+            Err(_) => {
+                let major: u16 = query_call(&self.agent, &self.bucket, "api_version", ()).await?;
+                Ok(format!("{}.0.0", major))
+            }
+        }
+    }
+
+    /// Checks the interface version of the bucket against the SDK, it returns an error
+    /// if the major versions are different, so the responses would not be decoded.
+    pub async fn check_api_version(&self) -> Result<ApiCompatibility, Error> {
+        let version = self.api_semver().await?;
+        Ok(check_api_version(BUCKET_API_VERSION, &version)?)
+    }
+
+    /// Returns the features supported by the bucket, see `ic_oss_types::bucket::FEATURE_*`.
+    pub async fn get_features(&self) -> Result<BTreeSet<String>, Error> {
+        query_call(&self.agent, &self.bucket, "get_features", ()).await
     }
 
-    pub async fn get_bucket_info(&self) -> Result<BucketInfo, String> {
-        query_call(
+    /// Returns the effective role and policies of the principal, or of the agent's identity
+    /// with the access token if None, to debug permission errors.
+    pub async fn get_access_info(&self, principal: Option<Principal>) -> Result<AccessInfo, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(
             &self.agent,
             &self.bucket,
-            "get_bucket_info",
-            (&self.access_token,),
+            "get_access_info",
+            (principal, &token),
         )
-        .await?
+        .await
     }
 
-    pub async fn get_file_info(&self, id: u32) -> Result<FileInfo, String> {
-        query_call(
+    /// Decodes and verifies the access token without side effects, the matching policies
+    /// are the token's own policies, the roles of the subject do not apply to a token.
+    pub async fn introspect_token(&self, sign1_token: ByteBuf) -> Result<TokenInfo, Error> {
+        query_call_result(
             &self.agent,
             &self.bucket,
-            "get_file_info",
-            (id, &self.access_token),
+            "introspect_token",
+            (sign1_token,),
         )
-        .await?
+        .await
     }
 
-    pub async fn get_file_info_by_hash(&self, hash: ByteArray<32>) -> Result<FileInfo, String> {
-        query_call(
+    pub async fn get_bucket_info(&self) -> Result<BucketInfo, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(&self.agent, &self.bucket, "get_bucket_info", (&token,)).await
+    }
+
+    pub async fn get_backup_status(&self) -> Result<Option<BackupStatus>, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(&self.agent, &self.bucket, "get_backup_status", (&token,)).await
+    }
+
+    /// Returns the COSE_Sign1 receipt of the file signed by the bucket for the caller,
+    /// it can be verified with `Receipt::from_sign1`. The bucket returns the cached receipt
+    /// if the file content is unchanged, and rate limits the new receipts of a reader.
+    pub async fn sign_receipt(&self, id: u32) -> Result<ByteBuf, Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(&self.agent, &self.bucket, "sign_receipt", (id, &token)).await
+    }
+
+    pub async fn get_access_logs(
+        &self,
+        prev: Option<u64>,
+        take: Option<u32>,
+    ) -> Result<Vec<AccessLog>, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(
+            &self.agent,
+            &self.bucket,
+            "get_access_logs",
+            (prev, take, &token),
+        )
+        .await
+    }
+
+    pub async fn get_metrics_history(&self, hours: u32) -> Result<Vec<MetricsSample>, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(
+            &self.agent,
+            &self.bucket,
+            "get_metrics_history",
+            (hours, &token),
+        )
+        .await
+    }
+
+    /// the caller of agent should be canister controller
+    pub async fn get_usage(&self, since: u64) -> Result<BucketUsage, Error> {
+        query_call_result(&self.agent, &self.bucket, "get_usage", (since,)).await
+    }
+
+    pub async fn get_scrub_report(&self) -> Result<ScrubReport, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(&self.agent, &self.bucket, "get_scrub_report", (&token,)).await
+    }
+
+    pub async fn get_state_digest(&self) -> Result<StateDigest, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(&self.agent, &self.bucket, "get_state_digest", (&token,)).await
+    }
+
+    pub async fn get_file_info(&self, id: u32) -> Result<FileInfo, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(&self.agent, &self.bucket, "get_file_info", (id, &token)).await
+    }
+
+    /// returns the status of all the chunks, the pages of the bucket are merged.
+    pub async fn get_file_fill_status(&self, id: u32) -> Result<FileFillStatus, Error> {
+        let token = self.token_provider.access_token().await?;
+        let mut status: FileFillStatus = query_call_result(
+            &self.agent,
+            &self.bucket,
+            "get_file_fill_status",
+            (id, None::<u32>, &token),
+        )
+        .await?;
+        while let Some(next) = status.next {
+            let page: FileFillStatus = query_call_result(
+                &self.agent,
+                &self.bucket,
+                "get_file_fill_status",
+                (id, Some(next), &token),
+            )
+            .await?;
+            status.extend(&page);
+        }
+        Ok(status)
+    }
+
+    pub async fn get_file_info_by_hash(&self, hash: ByteArray<32>) -> Result<FileInfo, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(
             &self.agent,
             &self.bucket,
             "get_file_info_by_hash",
-            (hash, &self.access_token),
+            (hash, &token),
         )
-        .await?
+        .await
+    }
+
+    pub async fn get_file_proof(&self, id: u32) -> Result<FileProof, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(&self.agent, &self.bucket, "get_file_proof", (id, &token)).await
     }
 
-    pub async fn get_file_ancestors(&self, id: u32) -> Result<Vec<FolderName>, String> {
-        query_call(
+    /// Verifies that the state digest is certified by the bucket canister:
+    /// the certificate is signed by the IC, the witness matches the certified data
+    /// of the canister and contains the root of the digest.
+    pub fn verify_state_digest(&self, digest: &StateDigest) -> Result<(), Error> {
+        let certificate = digest
+            .certificate
+            .as_ref()
+            .ok_or("the state digest is not certified")?;
+        let certificate: Certificate = from_reader(certificate.as_slice()).map_err(format_error)?;
+        self.agent
+            .verify(&certificate, self.bucket)
+            .map_err(format_error)?;
+        let certified_data = match certificate.tree.lookup_path([
+            b"canister".as_slice(),
+            self.bucket.as_slice(),
+            b"certified_data".as_slice(),
+        ]) {
+            LookupResult::Found(data) => data,
+            _ => Err("certified data not found in the certificate".to_string())?,
+        };
+
+        let witness: HashTree = from_reader(digest.witness.as_slice()).map_err(format_error)?;
+        if witness.digest().as_slice() != certified_data {
+            Err("the witness does not match the certified data".to_string())?;
+        }
+        match witness.lookup_path([b"state_digest".as_slice()]) {
+            LookupResult::Found(root) if root == digest.root.as_slice() => Ok(()),
+            _ => Err("the state digest does not match the witness".into()),
+        }
+    }
+
+    /// Verifies a downloaded file offline against the certified state digest of the bucket,
+    /// the hash is the SHA3-256 hash of the file content. Returns the verified proof.
+    pub async fn verify_file(&self, id: u32, hash: &[u8; 32]) -> Result<FileProof, Error> {
+        let proof = self.get_file_proof(id).await?;
+        proof.verify(hash)?;
+        self.verify_state_digest(&proof.digest)?;
+        Ok(proof)
+    }
+
+    /// Reports which of the file hashes already exist in the bucket, at most 100 hashes.
+    pub async fn has_hashes(&self, hashes: Vec<ByteArray<32>>) -> Result<Vec<bool>, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(&self.agent, &self.bucket, "has_hashes", (hashes, &token)).await
+    }
+
+    pub async fn get_file_ancestors(&self, id: u32) -> Result<Vec<FolderName>, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(
             &self.agent,
             &self.bucket,
             "get_file_ancestors",
-            (id, &self.access_token),
+            (id, &token),
         )
-        .await?
+        .await
     }
 
+    /// returns the stored chunks, the chunks of an encrypted file are not decrypted
     pub async fn get_file_chunks(
         &self,
         id: u32,
         index: u32,
         take: Option<u32>,
-    ) -> Result<Vec<FileChunk>, String> {
-        query_call(
-            &self.agent,
-            &self.bucket,
-            "get_file_chunks",
-            (id, index, take, &self.access_token),
-        )
-        .await?
+    ) -> Result<Vec<FileChunk>, Error> {
+        let token = self.token_provider.access_token().await?;
+        let chunks: Vec<FileChunk> = with_backoff(&self.throttle, || {
+            query_call_result(
+                &self.agent,
+                &self.bucket,
+                "get_file_chunks",
+                (id, index, take, &token),
+            )
+        })
+        .await?;
+        self.throttle
+            .acquire(chunks.iter().map(|c| c.1.len() as u64).sum())
+            .await;
+        Ok(chunks)
+    }
+
+    pub async fn get_file_chunks_by_index(
+        &self,
+        id: u32,
+        indexes: Vec<u32>,
+    ) -> Result<Vec<FileChunk>, Error> {
+        let token = self.token_provider.access_token().await?;
+        let chunks: Vec<FileChunk> = with_backoff(&self.throttle, || {
+            query_call_result(
+                &self.agent,
+                &self.bucket,
+                "get_file_chunks_by_index",
+                (id, indexes.clone(), &token),
+            )
+        })
+        .await?;
+        self.throttle
+            .acquire(chunks.iter().map(|c| c.1.len() as u64).sum())
+            .await;
+        Ok(chunks)
+    }
+
+    /// opens a stream to read the file chunk by chunk with `read_next`. The stream returns
+    /// the stored content, an encrypted file is rejected if the client has a key source,
+    /// it should be read with `download`.
+    pub async fn open_read(&self, id: u32) -> Result<StreamHandle, Error> {
+        self.reject_encrypted(id).await?;
+        let token = self.token_provider.access_token().await?;
+        query_call_result(&self.agent, &self.bucket, "open_read", (id, &token)).await
     }
 
+    pub async fn read_next(&self, handle: StreamHandle) -> Result<StreamChunk, Error> {
+        let token = self.token_provider.access_token().await?;
+        let chunk: StreamChunk = with_backoff(&self.throttle, || {
+            query_call_result(&self.agent, &self.bucket, "read_next", (&handle, &token))
+        })
+        .await?;
+        self.throttle.acquire(chunk.data.len() as u64).await;
+        Ok(chunk)
+    }
+
+    /// reads exactly the requested bytes of a file, the length will be truncated to MAX_FILE_SIZE_PER_CALL.
+    /// An encrypted file is rejected if the client has a key source, see `open_read`.
+    pub async fn read_range(&self, id: u32, offset: u64, len: u64) -> Result<ByteBuf, Error> {
+        self.reject_encrypted(id).await?;
+        let token = self.token_provider.access_token().await?;
+        let data: ByteBuf = with_backoff(&self.throttle, || {
+            query_call_result(
+                &self.agent,
+                &self.bucket,
+                "read_range",
+                (id, offset, len, &token),
+            )
+        })
+        .await?;
+        self.throttle.acquire(data.len() as u64).await;
+        Ok(data)
+    }
+
+    #[deprecated(note = "use list_files_with_options instead")]
     pub async fn list_files(
         &self,
         parent: u32,
         prev: Option<u32>,
         take: Option<u32>,
-    ) -> Result<Vec<FileInfo>, String> {
-        query_call(
+    ) -> Result<Vec<FileInfo>, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(
             &self.agent,
             &self.bucket,
             "list_files",
-            (parent, prev, take, &self.access_token),
+            (parent, prev, take, &token),
         )
-        .await?
+        .await
     }
 
-    pub async fn get_folder_info(&self, id: u32) -> Result<FolderInfo, String> {
-        query_call(
+    /// resolves a subfolder or file in the parent by its name
+    pub async fn get_child_by_name(&self, parent: u32, name: String) -> Result<FolderChild, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(
             &self.agent,
             &self.bucket,
-            "get_folder_info",
-            (id, &self.access_token),
+            "get_child_by_name",
+            (parent, name, &token),
         )
-        .await?
+        .await
+    }
+
+    /// resolves a file or folder by its path from the root folder, e.g. "assets/logo.png",
+    /// an empty path is the root folder. It is the path addressing of storage adapters.
+    pub async fn get_child_by_path(&self, path: &str) -> Result<FolderChild, Error> {
+        let names = split_path(path)?;
+        let (name, folders) = match names.split_last() {
+            Some(v) => v,
+            None => return Ok(FolderChild::Folder(self.get_folder_info(0).await?)),
+        };
+        let mut parent = 0;
+        for folder in folders {
+            parent = match self.get_child_by_name(parent, folder.to_string()).await? {
+                FolderChild::Folder(info) => info.id,
+                FolderChild::File(_) => Err(Error::with_kind(
+                    ErrorKind::NotFound,
+                    format!("folder not found: {}", folder),
+                ))?,
+            };
+        }
+        self.get_child_by_name(parent, name.to_string()).await
+    }
+
+    /// creates the missing folders on the path from the root folder, returns the id of
+    /// the last folder.
+    pub async fn create_folders(&self, path: &str) -> Result<u32, Error> {
+        let mut parent = 0;
+        for name in split_path(path)? {
+            parent = match self.get_child_by_name(parent, name.to_string()).await {
+                Ok(FolderChild::Folder(info)) => info.id,
+                Ok(FolderChild::File(_)) => Err(format!("{} is a file", name))?,
+                Err(err) if err.kind == ErrorKind::NotFound => {
+                    self.create_folder(CreateFolderInput {
+                        parent,
+                        name: name.to_string(),
+                    })
+                    .await?
+                    .id
+                }
+                Err(err) => Err(err)?,
+            };
+        }
+        Ok(parent)
     }
 
-    pub async fn get_folder_ancestors(&self, id: u32) -> Result<Vec<FolderName>, String> {
-        query_call(
+    pub async fn get_folder_info(&self, id: u32) -> Result<FolderInfo, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(&self.agent, &self.bucket, "get_folder_info", (id, &token)).await
+    }
+
+    pub async fn get_folder_ancestors(&self, id: u32) -> Result<Vec<FolderName>, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(
             &self.agent,
             &self.bucket,
             "get_folder_ancestors",
-            (id, &self.access_token),
+            (id, &token),
         )
-        .await?
+        .await
     }
 
+    #[deprecated(note = "use list_folders_with_options instead")]
     pub async fn list_folders(
         &self,
         parent: u32,
         prev: Option<u32>,
         take: Option<u32>,
-    ) -> Result<Vec<FolderInfo>, String> {
-        query_call(
+    ) -> Result<Vec<FolderInfo>, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(
             &self.agent,
             &self.bucket,
             "list_folders",
-            (parent, prev, take, &self.access_token),
+            (parent, prev, take, &token),
         )
-        .await?
+        .await
     }
 
-    pub async fn create_file(&self, file: CreateFileInput) -> Result<CreateFileOutput, String> {
-        update_call(
+    pub async fn list_files_with_options(
+        &self,
+        parent: u32,
+        options: ListOptions,
+    ) -> Result<ListFilesOutput, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(
             &self.agent,
             &self.bucket,
-            "create_file",
-            (file, &self.access_token),
+            "list_files_with_options",
+            (parent, options, &token),
         )
-        .await?
+        .await
+    }
+
+    pub async fn list_folders_with_options(
+        &self,
+        parent: u32,
+        options: ListOptions,
+    ) -> Result<ListFoldersOutput, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(
+            &self.agent,
+            &self.bucket,
+            "list_folders_with_options",
+            (parent, options, &token),
+        )
+        .await
+    }
+
+    pub async fn create_file(&self, file: CreateFileInput) -> Result<CreateFileOutput, Error> {
+        file.validate()?;
+        let token = self.token_provider.access_token().await?;
+        update_call_result(&self.agent, &self.bucket, "create_file", (file, &token)).await
+    }
+
+    /// fetches the content of the https url into a new file by the bucket's HTTPS outcalls
+    pub async fn import_from_url(
+        &self,
+        url: String,
+        parent: u32,
+        name: String,
+    ) -> Result<FileInfo, Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(
+            &self.agent,
+            &self.bucket,
+            "import_from_url",
+            (url, parent, name, &token),
+        )
+        .await
+    }
+
+    /// copies a file from a source canister that implements the bucket's file read APIs
+    pub async fn pull_file_from(
+        &self,
+        canister: Principal,
+        remote_id: u32,
+        parent: u32,
+    ) -> Result<FileInfo, Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(
+            &self.agent,
+            &self.bucket,
+            "pull_file_from",
+            (canister, remote_id, parent, &token),
+        )
+        .await
+    }
+
+    /// creates a small file in the anonymous upload folder with an upload challenge
+    pub async fn create_file_with_challenge(
+        &self,
+        file: CreateFileInput,
+        challenge: UploadChallenge,
+    ) -> Result<CreateFileOutput, Error> {
+        file.validate()?;
+        update_call_result(
+            &self.agent,
+            &self.bucket,
+            "create_file_with_challenge",
+            (file, challenge),
+        )
+        .await
+    }
+
+    /// sets the hash of the fully uploaded file and makes it readonly in one call
+    pub async fn finalize_file(
+        &self,
+        input: FinalizeFileInput,
+    ) -> Result<FinalizeFileOutput, Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(&self.agent, &self.bucket, "finalize_file", (input, &token)).await
+    }
+
+    pub async fn request_hash_verification(&self, id: u32) -> Result<(), Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(
+            &self.agent,
+            &self.bucket,
+            "request_hash_verification",
+            (id, &token),
+        )
+        .await
+    }
+
+    /// password_hash is an argon2 PHC string, None removes the password
+    pub async fn set_file_password(
+        &self,
+        id: u32,
+        password_hash: Option<String>,
+    ) -> Result<(), Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(
+            &self.agent,
+            &self.bucket,
+            "set_file_password",
+            (id, password_hash, &token),
+        )
+        .await
     }
 
     pub async fn update_file_chunk(
         &self,
         input: UpdateFileChunkInput,
-    ) -> Result<UpdateFileChunkOutput, String> {
-        update_call(
+    ) -> Result<UpdateFileChunkOutput, Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(
             &self.agent,
             &self.bucket,
             "update_file_chunk",
-            (input, &self.access_token),
+            (input, &token),
         )
-        .await?
+        .await
+    }
+
+    pub async fn acquire_chunk_lease(
+        &self,
+        input: ChunkLeaseInput,
+    ) -> Result<ChunkLeaseInfo, Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(
+            &self.agent,
+            &self.bucket,
+            "acquire_chunk_lease",
+            (input, &token),
+        )
+        .await
+    }
+
+    pub async fn release_chunk_lease(&self, lease: u64) -> Result<bool, Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(
+            &self.agent,
+            &self.bucket,
+            "release_chunk_lease",
+            (lease, &token),
+        )
+        .await
     }
 
     pub async fn update_file_info(
         &self,
         input: UpdateFileInput,
-    ) -> Result<UpdateFileOutput, String> {
-        update_call(
+    ) -> Result<UpdateFileOutput, Error> {
+        input.validate()?;
+        let token = self.token_provider.access_token().await?;
+        update_call_result(
             &self.agent,
             &self.bucket,
             "update_file_info",
-            (input, &self.access_token),
+            (input, &token),
+        )
+        .await
+    }
+
+    pub async fn move_file(&self, input: MoveInput) -> Result<UpdateFileOutput, Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(&self.agent, &self.bucket, "move_file", (input, &token)).await
+    }
+
+    /// points the tag to a fully uploaded file, it is served at `/t/<name>`
+    pub async fn set_tag(&self, name: String, id: u32) -> Result<(), Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(&self.agent, &self.bucket, "set_tag", (name, id, &token)).await
+    }
+
+    pub async fn delete_tag(&self, name: String) -> Result<bool, Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(&self.agent, &self.bucket, "delete_tag", (name, &token)).await
+    }
+
+    pub async fn get_tag(&self, name: String) -> Result<u32, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(&self.agent, &self.bucket, "get_tag", (name, &token)).await
+    }
+
+    /// creates a share link "/s/<code>" to download the file without an access token
+    pub async fn create_share(&self, input: CreateShareInput) -> Result<ShareInfo, Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(&self.agent, &self.bucket, "create_share", (input, &token)).await
+    }
+
+    pub async fn revoke_share(&self, code: String) -> Result<bool, Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(&self.agent, &self.bucket, "revoke_share", (code, &token)).await
+    }
+
+    pub async fn list_shares(&self, file: u32) -> Result<Vec<ShareInfo>, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(&self.agent, &self.bucket, "list_shares", (file, &token)).await
+    }
+
+    pub async fn add_annotation(&self, file: u32, text: String) -> Result<u32, Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(
+            &self.agent,
+            &self.bucket,
+            "add_annotation",
+            (file, text, &token),
         )
-        .await?
+        .await
     }
 
-    pub async fn move_file(&self, input: MoveInput) -> Result<UpdateFileOutput, String> {
-        update_call(
+    pub async fn list_annotations(
+        &self,
+        file: u32,
+        prev: Option<u32>,
+        take: Option<u32>,
+    ) -> Result<Vec<Annotation>, Error> {
+        let token = self.token_provider.access_token().await?;
+        query_call_result(
             &self.agent,
             &self.bucket,
-            "move_file",
-            (input, &self.access_token),
+            "list_annotations",
+            (file, prev, take, &token),
         )
-        .await?
+        .await
     }
 
-    pub async fn delete_file(&self, id: u32) -> Result<bool, String> {
-        update_call(
+    /// copies a fully uploaded file into the folder on the bucket side
+    pub async fn copy_file(
+        &self,
+        id: u32,
+        to: u32,
+        name: Option<String>,
+    ) -> Result<CreateFileOutput, Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(
             &self.agent,
             &self.bucket,
-            "delete_file",
-            (id, &self.access_token),
+            "copy_file",
+            (id, to, name, &token),
         )
-        .await?
+        .await
+    }
+
+    /// moves the files from one folder to another in one update call
+    pub async fn move_files(
+        &self,
+        ids: BTreeSet<u32>,
+        from: u32,
+        to: u32,
+    ) -> Result<UpdateFileOutput, Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(
+            &self.agent,
+            &self.bucket,
+            "move_files",
+            (ids, from, to, &token),
+        )
+        .await
+    }
+
+    pub async fn delete_file(&self, id: u32) -> Result<bool, Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(&self.agent, &self.bucket, "delete_file", (id, &token)).await
     }
 
     pub async fn batch_delete_subfiles(
         &self,
         parent: u32,
         ids: BTreeSet<u32>,
-    ) -> Result<Vec<u32>, String> {
-        update_call(
+    ) -> Result<Vec<u32>, Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(
             &self.agent,
             &self.bucket,
             "batch_delete_subfiles",
-            (parent, ids, &self.access_token),
+            (parent, ids, &token),
         )
-        .await?
+        .await
     }
 
     pub async fn create_folder(
         &self,
         input: CreateFolderInput,
-    ) -> Result<CreateFolderOutput, String> {
-        update_call(
-            &self.agent,
-            &self.bucket,
-            "create_folder",
-            (input, &self.access_token),
-        )
-        .await?
+    ) -> Result<CreateFolderOutput, Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(&self.agent, &self.bucket, "create_folder", (input, &token)).await
     }
 
     pub async fn update_folder_info(
         &self,
         input: UpdateFolderInput,
-    ) -> Result<UpdateFolderOutput, String> {
-        update_call(
+    ) -> Result<UpdateFolderOutput, Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(
             &self.agent,
             &self.bucket,
             "update_folder_info",
-            (input, &self.access_token),
+            (input, &token),
         )
-        .await?
+        .await
     }
 
-    pub async fn move_folder(&self, input: MoveInput) -> Result<UpdateFolderOutput, String> {
-        update_call(
-            &self.agent,
-            &self.bucket,
-            "move_folder",
-            (input, &self.access_token),
-        )
-        .await?
+    pub async fn move_folder(&self, input: MoveInput) -> Result<UpdateFolderOutput, Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(&self.agent, &self.bucket, "move_folder", (input, &token)).await
     }
 
-    pub async fn delete_folder(&self, id: u32) -> Result<bool, String> {
-        update_call(
-            &self.agent,
-            &self.bucket,
-            "delete_folder",
-            (id, &self.access_token),
-        )
-        .await?
+    pub async fn delete_folder(&self, id: u32) -> Result<bool, Error> {
+        let token = self.token_provider.access_token().await?;
+        update_call_result(&self.agent, &self.bucket, "delete_folder", (id, &token)).await
     }
 
     pub async fn upload<T, F>(
@@ -300,24 +1046,46 @@ impl Client {
         stream: T,
         mut file: CreateFileInput,
         on_progress: F,
-    ) -> Result<UploadFileChunksResult, String>
+    ) -> Result<UploadFileChunksResult, Error>
     where
         T: AsyncRead,
         F: Fn(Progress),
     {
-        if let Some(size) = file.size {
+        file.validate()?;
+        let chunk_size = self.load_chunk_size().await?;
+        let cipher = self.file_cipher(file.dek.as_ref(), chunk_size)?;
+        // the size of the content to read, the stored size of an encrypted file is larger
+        let content_size = file.size;
+        if let Some(cipher) = &cipher {
+            // the last chunk is flagged in the encryption, so the size should be known
+            if content_size.is_none() {
+                Err("the size of an encrypted file is required")?;
+            }
+            // the hash of an encrypted file is computed over the ciphertext
+            file.hash = None;
+            file.size = content_size.map(|size| cipher.encrypted_size(size));
+        }
+        if let (Some(size), Some(content_size)) = (file.size, content_size) {
             if size <= MAX_FILE_SIZE_PER_CALL {
                 // upload a small file in one request
-                let content = try_read_all(stream, size as u32).await?;
+                let content = try_read_all(stream, content_size as u32).await?;
+                let content = match &cipher {
+                    Some(cipher) => Bytes::from(cipher.encrypt_content(&content)?),
+                    None => content,
+                };
                 if file.hash.is_none() {
                     let mut hasher = Sha3_256::new();
                     hasher.update(&content);
                     let hash: [u8; 32] = hasher.finalize().into();
                     file.hash = Some(hash.into());
                 }
+                if let Some(res) = self.existing_file(&file, &on_progress).await? {
+                    return Ok(res);
+                }
                 file.content = Some(ByteBuf::from(content.to_vec()));
                 file.status = if self.set_readonly { Some(1) } else { None };
-                let res = self.create_file(file).await?;
+                self.throttle.acquire(size).await;
+                let res = with_backoff(&self.throttle, || self.create_file(file.clone())).await?;
 
                 on_progress(Progress {
                     filled: size,
@@ -334,16 +1102,117 @@ impl Client {
             }
         }
 
+        if let Some(res) = self.existing_file(&file, &on_progress).await? {
+            return Ok(res);
+        }
+
         // create file
         let hash = file.hash;
         let size = file.size;
         let res = self.create_file(file).await?;
         let res = self
-            .upload_chunks(stream, res.id, size, hash, &BTreeSet::new(), on_progress)
+            .upload_file_chunks(
+                stream,
+                res.id,
+                size,
+                hash,
+                &BTreeSet::new(),
+                cipher,
+                on_progress,
+            )
             .await;
         Ok(res)
     }
 
+    // returns the cipher of the file content if the file has a DEK and the client has
+    // a key source
+    fn file_cipher(
+        &self,
+        dek: Option<&ByteBuf>,
+        chunk_size: u32,
+    ) -> Result<Option<ChunkCipher>, String> {
+        match (dek, &self.key_source) {
+            (Some(dek), Some(key_source)) => {
+                let dek = key_source.unwrap_dek(dek)?;
+                Ok(Some(ChunkCipher::new(&dek, chunk_size)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // the ranges of an encrypted file are ciphertext, they can not be decrypted without
+    // the chunk boundaries
+    async fn reject_encrypted(&self, id: u32) -> Result<(), Error> {
+        if self.key_source.is_some() && self.get_file_info(id).await?.dek.is_some() {
+            Err(format!(
+                "file {} is encrypted, it should be read with download",
+                id
+            ))?;
+        }
+        Ok(())
+    }
+
+    // creates the file from an existing file with the same hash if dedup is enabled, the
+    // existing file is returned if it is already the target.
+    async fn existing_file<F>(
+        &self,
+        file: &CreateFileInput,
+        on_progress: &F,
+    ) -> Result<Option<UploadFileChunksResult>, Error>
+    where
+        F: Fn(Progress),
+    {
+        let hash = match file.hash {
+            Some(hash) if self.dedup => hash,
+            _ => return Ok(None),
+        };
+        if !self
+            .has_hashes(vec![hash])
+            .await?
+            .first()
+            .copied()
+            .unwrap_or(false)
+        {
+            return Ok(None);
+        }
+
+        let info = self.get_file_info_by_hash(hash).await?;
+        let id = if info.parent == file.parent && info.name == file.name {
+            info.id
+        } else {
+            let id = self
+                .copy_file(info.id, file.parent, Some(file.name.clone()))
+                .await?
+                .id;
+            let mut input = UpdateFileInput::new(id);
+            if !file.content_type.is_empty() && file.content_type != info.content_type {
+                input = input.with_content_type(file.content_type.clone());
+            }
+            if self.set_readonly {
+                input.status = Some(1);
+            }
+            if input.content_type.is_some() || input.status.is_some() {
+                self.update_file_info(input).await?;
+            }
+            id
+        };
+        on_progress(Progress {
+            filled: info.filled,
+            size: Some(info.size),
+            chunk_index: 0,
+            concurrency: 1,
+        });
+        Ok(Some(UploadFileChunksResult {
+            id,
+            filled: info.filled,
+            uploaded_chunks: BTreeSet::new(),
+            error: None,
+        }))
+    }
+
+    /// Uploads the chunks of the created file, e.g. to resume an upload. The `size` is the
+    /// size of the content, and the chunks are encrypted if the file has a DEK and the
+    /// client has a key source.
     pub async fn upload_chunks<T, F>(
         &self,
         stream: T,
@@ -353,6 +1222,59 @@ impl Client {
         exclude_chunks: &BTreeSet<u32>,
         on_progress: F,
     ) -> UploadFileChunksResult
+    where
+        T: AsyncRead,
+        F: Fn(Progress),
+    {
+        let cipher = match self.load_chunk_size().await {
+            Err(err) => Err(err.to_string()),
+            Ok(chunk_size) => match self.key_source {
+                Some(_) => self
+                    .get_file_info(id)
+                    .await
+                    .map_err(|err| err.to_string())
+                    .and_then(|info| self.file_cipher(info.dek.as_ref(), chunk_size)),
+                None => Ok(None),
+            },
+        };
+        match cipher {
+            Ok(cipher) => {
+                let (size, hash) = match &cipher {
+                    Some(_) if size.is_none() => {
+                        return UploadFileChunksResult {
+                            id,
+                            filled: 0,
+                            uploaded_chunks: exclude_chunks.clone(),
+                            error: Some("the size of an encrypted file is required".to_string()),
+                        };
+                    }
+                    Some(cipher) => (size.map(|size| cipher.encrypted_size(size)), None),
+                    None => (size, hash),
+                };
+                self.upload_file_chunks(stream, id, size, hash, exclude_chunks, cipher, on_progress)
+                    .await
+            }
+            Err(err) => UploadFileChunksResult {
+                id,
+                filled: 0,
+                uploaded_chunks: exclude_chunks.clone(),
+                error: Some(err),
+            },
+        }
+    }
+
+    // the size and the hash are of the stored content
+    #[allow(clippy::too_many_arguments)]
+    async fn upload_file_chunks<T, F>(
+        &self,
+        stream: T,
+        id: u32,
+        size: Option<u64>,
+        hash: Option<ByteArray<32>>,
+        exclude_chunks: &BTreeSet<u32>,
+        cipher: Option<ChunkCipher>,
+        on_progress: F,
+    ) -> UploadFileChunksResult
     where
         T: AsyncRead,
         F: Fn(Progress),
@@ -360,7 +1282,15 @@ impl Client {
         // upload chunks
         let bucket = self.bucket;
         let has_hash = hash.is_some();
-        let mut frames = Box::pin(FramedRead::new(stream, ChunksCodec::new(CHUNK_SIZE)));
+        // the ciphertext of the excluded chunks is unknown, the hash can not be computed
+        let skip_hash = cipher.is_some() && !exclude_chunks.is_empty();
+        let last_index = size
+            .map(|size| file_chunks(size, self.chunk_size()).saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        let frame_size = cipher
+            .as_ref()
+            .map_or(self.chunk_size(), |cipher| cipher.plain_chunk_size());
+        let mut frames = Box::pin(FramedRead::new(stream, ChunksCodec::new(frame_size)));
         let (tx, mut rx) = mpsc::channel::<Result<Progress, String>>(self.concurrency as usize);
         let output = Arc::new(RwLock::new(UploadFileChunksResult {
             id,
@@ -372,40 +1302,37 @@ impl Client {
         let uploading_loop = async {
             let mut index = 0;
             let mut hasher = Sha3_256::new();
-            let semaphore = Arc::new(Semaphore::new(self.concurrency as usize));
 
             loop {
-                let access_token = self.access_token.clone();
+                let access_token = self.token_provider.access_token().await?;
                 let tx1 = tx.clone();
                 let output = output.clone();
-                let permit = semaphore
-                    .clone()
-                    .acquire_owned()
-                    .await
-                    .map_err(format_error)?;
-                let concurrency = (self.concurrency as usize - semaphore.available_permits()) as u8;
+                // the concurrency window shrinks when the requests are rate limited
+                let permit = self.throttle.acquire_slot(self.concurrency as usize).await;
+                let concurrency = self.throttle.window().1 as u8;
 
                 match frames.next().await {
                     None => {
                         drop(tx);
-                        semaphore.close();
-                        return Ok(Into::<[u8; 32]>::into(hasher.finalize()));
+                        if skip_hash {
+                            return Ok(None);
+                        }
+                        return Ok(Some(Into::<[u8; 32]>::into(hasher.finalize())));
                     }
                     Some(Err(err)) => {
                         drop(tx);
-                        semaphore.close();
                         return Err(err.to_string());
                     }
                     Some(Ok(chunk)) => {
                         let chunk_index = index;
                         index += 1;
-                        let chunk_len = chunk.len() as u32;
-
-                        if !has_hash {
-                            hasher.update(&chunk);
-                        }
-
                         if exclude_chunks.contains(&chunk_index) {
+                            // the excluded chunk is already stored and not encrypted again
+                            let chunk_len =
+                                chunk.len() as u32 + cipher.as_ref().map_or(0, |_| CHUNK_OVERHEAD);
+                            if !has_hash && !skip_hash {
+                                hasher.update(&chunk);
+                            }
                             let mut r = output.write().await;
                             r.filled += chunk_len as u64;
                             on_progress(Progress {
@@ -418,24 +1345,41 @@ impl Client {
                             continue;
                         }
 
+                        let chunk = match &cipher {
+                            Some(cipher) => Bytes::from(cipher.encrypt_chunk(
+                                chunk_index,
+                                chunk_index == last_index,
+                                &chunk,
+                            )?),
+                            None => chunk,
+                        };
+                        let chunk_len = chunk.len() as u32;
+                        if !has_hash && !skip_hash {
+                            hasher.update(&chunk);
+                        }
+
                         let agent = self.agent.clone();
+                        let throttle = self.throttle.clone();
                         tokio::spawn(async move {
                             let res = async {
-                                let out: Result<UpdateFileChunkOutput, String> = update_call(
-                                    &agent,
-                                    &bucket,
-                                    "update_file_chunk",
-                                    (
-                                        UpdateFileChunkInput {
-                                            id,
-                                            chunk_index,
-                                            content: ByteBuf::from(chunk.to_vec()),
-                                        },
-                                        &access_token,
-                                    ),
-                                )
+                                throttle.acquire(chunk_len as u64).await;
+                                let out: UpdateFileChunkOutput = with_backoff(&throttle, || {
+                                    update_call_result(
+                                        &agent,
+                                        &bucket,
+                                        "update_file_chunk",
+                                        (
+                                            UpdateFileChunkInput {
+                                                id,
+                                                chunk_index,
+                                                content: ByteBuf::from(chunk.to_vec()),
+                                                lease: None,
+                                            },
+                                            &access_token,
+                                        ),
+                                    )
+                                })
                                 .await?;
-                                let out = out?;
                                 Ok(Progress {
                                     filled: out.filled,
                                     size,
@@ -478,7 +1422,7 @@ impl Client {
             let _ = self
                 .update_file_info(UpdateFileInput {
                     id,
-                    hash: Some(hash.unwrap_or(hash_new.into())),
+                    hash: hash.or(hash_new.map(ByteArray::from)),
                     status: if self.set_readonly { Some(1) } else { None },
                     size,
                     ..Default::default()
@@ -495,6 +1439,111 @@ impl Client {
 
         output
     }
+
+    /// downloads the file into the writer and returns the SHA3-256 hash of the content,
+    /// the chunks are requested concurrently within the concurrency window and written in order.
+    /// The content of a file with a DEK is decrypted if the client has a key source, the hash
+    /// is of the stored ciphertext then and the progress is in the bytes of the content.
+    pub async fn download<W, F>(
+        &self,
+        id: u32,
+        w: &mut W,
+        on_progress: F,
+    ) -> Result<[u8; 32], Error>
+    where
+        W: AsyncWrite + Unpin,
+        F: Fn(Progress),
+    {
+        let info = self.get_file_info(id).await?;
+        if info.size != info.filled {
+            Err("file not fully uploaded")?;
+        }
+        let chunk_size = self.load_chunk_size().await?;
+        let cipher = self.file_cipher(info.dek.as_ref(), chunk_size)?;
+        // the progress is reported in the bytes of the content
+        let size = cipher
+            .as_ref()
+            .map_or(info.size, |cipher| cipher.plain_size(info.size));
+
+        let take = chunks_per_download(chunk_size);
+        let batches = futures::stream::iter((0..info.chunks).step_by(take as usize)).map(
+            |index| async move {
+                let _slot = self.throttle.acquire_slot(self.concurrency as usize).await;
+                let concurrency = self.throttle.window().1 as u8;
+                let chunks = self.get_file_chunks(id, index, Some(take)).await?;
+                Ok::<_, Error>((chunks, concurrency))
+            },
+        );
+        let mut batches = Box::pin(futures::StreamExt::buffered(
+            batches,
+            self.concurrency as usize,
+        ));
+
+        let mut hasher = Sha3_256::new();
+        let mut filled = 0u64;
+        let mut stored = 0u64;
+        let mut next = 0u32;
+        while let Some(res) = batches.next().await {
+            let (chunks, concurrency) = res?;
+            for chunk in chunks {
+                if chunk.0 != next {
+                    Err(format!("chunk {} of file {} is missing", next, id))?;
+                }
+                next += 1;
+                hasher.update(&chunk.1);
+                stored += chunk.1.len() as u64;
+                match &cipher {
+                    Some(cipher) => {
+                        let last = chunk.0 + 1 == info.chunks;
+                        let content = cipher.decrypt_chunk(chunk.0, last, &chunk.1)?;
+                        w.write_all(&content).await.map_err(format_error)?;
+                        filled += content.len() as u64;
+                    }
+                    None => {
+                        w.write_all(&chunk.1).await.map_err(format_error)?;
+                        filled += chunk.1.len() as u64;
+                    }
+                }
+                on_progress(Progress {
+                    filled,
+                    size: Some(size),
+                    chunk_index: chunk.0,
+                    concurrency,
+                });
+            }
+        }
+        w.flush().await.map_err(format_error)?;
+        if next != info.chunks || stored != info.size {
+            Err(format!(
+                "incomplete download of file {}, {} of {} bytes",
+                id, stored, info.size
+            ))?;
+        }
+
+        let hash: [u8; 32] = hasher.finalize().into();
+        if let Some(h) = info.hash {
+            if *h != hash {
+                Err(format!(
+                    "file hash mismatch, expected {}, got {}",
+                    hex::encode(*h),
+                    hex::encode(hash)
+                ))?;
+            }
+        }
+        Ok(hash)
+    }
+}
+
+// the chunks of a download request of the default CHUNK_SIZE, it should be less than
+// MAX_FILE_SIZE_PER_CALL
+const CHUNKS_PER_DOWNLOAD: u32 = 6;
+
+// the bucket API version that reports the chunk size in BucketInfo
+const CHUNK_SIZE_API_VERSION: &str = "1.2.0";
+
+// the chunks of a download request of the chunk size
+pub fn chunks_per_download(chunk_size: u32) -> u32 {
+    (CHUNKS_PER_DOWNLOAD * CHUNK_SIZE / chunk_size.max(1)).max(1)
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -541,3 +1590,32 @@ async fn try_read_all<T: AsyncRead>(stream: T, size: u32) -> Result<Bytes, Strin
     }
     Ok(res)
 }
+
+// splits the path into the names of the folders and the file, the leading and
+// trailing slashes are ignored.
+fn split_path(path: &str) -> Result<Vec<&str>, Error> {
+    let path = path.trim_matches('/');
+    if path.is_empty() {
+        return Ok(Vec::new());
+    }
+    let names: Vec<&str> = path.split('/').collect();
+    if !names.iter().all(|name| valid_file_name(name)) {
+        Err(format!("invalid path: {:?}", path))?;
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_path_works() {
+        assert!(split_path("").unwrap().is_empty());
+        assert!(split_path("/").unwrap().is_empty());
+        assert_eq!(split_path("a").unwrap(), vec!["a"]);
+        assert_eq!(split_path("/a/b.txt/").unwrap(), vec!["a", "b.txt"]);
+        assert!(split_path("a//b").is_err());
+        assert!(split_path("a/../b").is_err());
+    }
+}
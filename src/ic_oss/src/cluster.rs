@@ -1,13 +1,21 @@
 use candid::{Nat, Principal};
 use ic_agent::Agent;
-use ic_oss_types::{cluster::*, cose::Token};
+use ic_oss_types::{
+    check_api_version,
+    cluster::*,
+    cose::{Token, TokenInfo},
+    ApiCompatibility, CLUSTER_API_VERSION,
+};
 use serde_bytes::{ByteArray, ByteBuf};
 use std::{
     collections::{BTreeMap, BTreeSet},
     sync::Arc,
 };
 
-use crate::agent::{query_call, update_call};
+use crate::{
+    agent::{query_call, query_call_result, update_call_result},
+    error::Error,
+};
 
 #[derive(Clone)]
 pub struct Client {
@@ -21,29 +29,29 @@ impl Client {
     }
 
     /// the caller of agent should be canister controller
-    pub async fn admin_set_managers(&self, args: BTreeSet<Principal>) -> Result<(), String> {
-        update_call(&self.agent, &self.cluster, "admin_set_managers", (args,)).await?
+    pub async fn admin_set_managers(&self, args: BTreeSet<Principal>) -> Result<(), Error> {
+        update_call_result(&self.agent, &self.cluster, "admin_set_managers", (args,)).await
     }
 
     /// the caller of agent should be canister manager
-    pub async fn admin_sign_access_token(&self, args: Token) -> Result<ByteBuf, String> {
-        update_call(
+    pub async fn admin_sign_access_token(&self, args: Token) -> Result<ByteBuf, Error> {
+        update_call_result(
             &self.agent,
             &self.cluster,
             "admin_sign_access_token",
             (args,),
         )
-        .await?
+        .await
     }
 
-    pub async fn admin_ed25519_access_token(&self, args: Token) -> Result<ByteBuf, String> {
-        update_call(
+    pub async fn admin_ed25519_access_token(&self, args: Token) -> Result<ByteBuf, Error> {
+        update_call_result(
             &self.agent,
             &self.cluster,
             "admin_ed25519_access_token",
             (args,),
         )
-        .await?
+        .await
     }
 
     pub async fn admin_weak_access_token(
@@ -51,133 +59,229 @@ impl Client {
         args: Token,
         now_sec: u64,
         expiration_sec: u64,
-    ) -> Result<ByteBuf, String> {
-        query_call(
+    ) -> Result<ByteBuf, Error> {
+        query_call_result(
             &self.agent,
             &self.cluster,
             "admin_weak_access_token",
             (args, now_sec, expiration_sec),
         )
-        .await?
+        .await
     }
 
     /// the caller of agent should be canister manager
-    pub async fn admin_attach_policies(&self, args: Token) -> Result<(), String> {
-        update_call(&self.agent, &self.cluster, "admin_attach_policies", (args,)).await?
+    pub async fn admin_attach_policies(&self, args: Token) -> Result<(), Error> {
+        update_call_result(&self.agent, &self.cluster, "admin_attach_policies", (args,)).await
     }
 
     /// the caller of agent should be canister manager
-    pub async fn admin_detach_policies(&self, args: Token) -> Result<(), String> {
-        update_call(&self.agent, &self.cluster, "admin_detach_policies", (args,)).await?
+    pub async fn admin_detach_policies(&self, args: Token) -> Result<(), Error> {
+        update_call_result(&self.agent, &self.cluster, "admin_detach_policies", (args,)).await
     }
 
-    pub async fn access_token(&self, audience: Principal) -> Result<ByteBuf, String> {
-        update_call(&self.agent, &self.cluster, "access_token", (audience,)).await?
+    pub async fn access_token(&self, audience: Principal) -> Result<ByteBuf, Error> {
+        update_call_result(&self.agent, &self.cluster, "access_token", (audience,)).await
     }
 
-    pub async fn ed25519_access_token(&self, audience: Principal) -> Result<ByteBuf, String> {
-        update_call(
+    pub async fn ed25519_access_token(&self, audience: Principal) -> Result<ByteBuf, Error> {
+        update_call_result(
             &self.agent,
             &self.cluster,
             "ed25519_access_token",
             (audience,),
         )
-        .await?
+        .await
+    }
+
+    /// Decodes and verifies the access token without side effects.
+    pub async fn introspect_token(&self, sign1_token: ByteBuf) -> Result<TokenInfo, Error> {
+        query_call_result(
+            &self.agent,
+            &self.cluster,
+            "introspect_token",
+            (sign1_token,),
+        )
+        .await
+    }
+
+    pub async fn api_semver(&self) -> Result<String, Error> {
+        query_call(&self.agent, &self.cluster, "api_semver", ()).await
+    }
+
+    /// Checks the interface version of the cluster against the SDK, it returns an error
+    /// if the major versions are different, so the responses would not be decoded.
+    pub async fn check_api_version(&self) -> Result<ApiCompatibility, Error> {
+        let version = self.api_semver().await?;
+        Ok(check_api_version(CLUSTER_API_VERSION, &version)?)
+    }
+
+    pub async fn get_cluster_info(&self) -> Result<ClusterInfo, Error> {
+        query_call_result(&self.agent, &self.cluster, "get_cluster_info", ()).await
+    }
+
+    pub async fn get_bucket_wasm(&self, hash: ByteArray<32>) -> Result<WasmInfo, Error> {
+        query_call_result(&self.agent, &self.cluster, "get_bucket_wasm", (hash,)).await
+    }
+
+    pub async fn get_buckets(&self) -> Result<Vec<Principal>, Error> {
+        query_call_result(&self.agent, &self.cluster, "get_buckets", ()).await
+    }
+
+    pub async fn get_tenants(&self) -> Result<Vec<Tenant>, Error> {
+        query_call_result(&self.agent, &self.cluster, "get_tenants", ()).await
+    }
+
+    pub async fn get_tenant_usage(&self, tenant: String) -> Result<TenantUsage, Error> {
+        query_call_result(&self.agent, &self.cluster, "get_tenant_usage", (tenant,)).await
+    }
+
+    /// the caller of agent should be canister controller
+    pub async fn admin_set_tenant(&self, args: Tenant) -> Result<(), Error> {
+        update_call_result(&self.agent, &self.cluster, "admin_set_tenant", (args,)).await
+    }
+
+    /// the caller of agent should be canister controller
+    pub async fn admin_remove_tenant(&self, id: String) -> Result<(), Error> {
+        update_call_result(&self.agent, &self.cluster, "admin_remove_tenant", (id,)).await
+    }
+
+    /// the caller of agent should be canister controller or manager
+    pub async fn find_file_by_hash(
+        &self,
+        hash: ByteArray<32>,
+        prev: Option<Principal>,
+        take: Option<u32>,
+    ) -> Result<FindFileByHashOutput, Error> {
+        update_call_result(
+            &self.agent,
+            &self.cluster,
+            "find_file_by_hash",
+            (hash, prev, take),
+        )
+        .await
     }
 
-    pub async fn get_cluster_info(&self) -> Result<ClusterInfo, String> {
-        query_call(&self.agent, &self.cluster, "get_cluster_info", ()).await?
+    /// the caller of agent should be canister controller or manager
+    pub async fn admin_set_placement(
+        &self,
+        shard_key: String,
+        bucket: Option<Principal>,
+    ) -> Result<(), Error> {
+        update_call_result(
+            &self.agent,
+            &self.cluster,
+            "admin_set_placement",
+            (shard_key, bucket),
+        )
+        .await
     }
 
-    pub async fn get_bucket_wasm(&self, hash: ByteArray<32>) -> Result<WasmInfo, String> {
-        query_call(&self.agent, &self.cluster, "get_bucket_wasm", (hash,)).await?
+    pub async fn get_placements(&self) -> Result<BTreeMap<String, Principal>, Error> {
+        query_call_result(&self.agent, &self.cluster, "get_placements", ()).await
     }
 
-    pub async fn get_buckets(&self) -> Result<Vec<Principal>, String> {
-        query_call(&self.agent, &self.cluster, "get_buckets", ()).await?
+    /// resolves the bucket that serves the path, the result should be verified by
+    /// `BucketLookup::verify` with the cluster's weak_ed25519_token_public_key
+    pub async fn lookup(&self, path: String) -> Result<BucketLookup, Error> {
+        query_call_result(&self.agent, &self.cluster, "lookup", (path,)).await
     }
 
-    pub async fn get_deployed_buckets(&self) -> Result<Vec<BucketDeploymentInfo>, String> {
-        query_call(&self.agent, &self.cluster, "get_deployed_buckets", ()).await?
+    pub async fn get_deployed_buckets(&self) -> Result<Vec<BucketDeploymentInfo>, Error> {
+        query_call_result(&self.agent, &self.cluster, "get_deployed_buckets", ()).await
     }
 
     pub async fn bucket_deployment_logs(
         &self,
         prev: Option<Nat>,
         take: Option<Nat>,
-    ) -> Result<Vec<BucketDeploymentInfo>, String> {
-        query_call(
+    ) -> Result<Vec<BucketDeploymentInfo>, Error> {
+        query_call_result(
             &self.agent,
             &self.cluster,
             "bucket_deployment_logs",
             (prev, take),
         )
-        .await?
+        .await
     }
 
     pub async fn get_subject_policies(
         &self,
         subject: Principal,
-    ) -> Result<BTreeMap<Principal, String>, String> {
-        query_call(
+    ) -> Result<BTreeMap<Principal, String>, Error> {
+        query_call_result(
             &self.agent,
             &self.cluster,
             "get_subject_policies",
             (subject,),
         )
-        .await?
+        .await
     }
 
     pub async fn get_subject_policies_for(
         &self,
         subject: Principal,
         audience: Principal,
-    ) -> Result<String, String> {
-        query_call(
+    ) -> Result<String, Error> {
+        query_call_result(
             &self.agent,
             &self.cluster,
             "get_subject_policies_for",
             (subject, audience),
         )
-        .await?
+        .await
     }
 
     pub async fn admin_add_wasm(
         &self,
         args: AddWasmInput,
         force_prev_hash: Option<ByteArray<32>>,
-    ) -> Result<(), String> {
-        update_call(
+    ) -> Result<(), Error> {
+        update_call_result(
             &self.agent,
             &self.cluster,
             "admin_add_wasm",
             (args, force_prev_hash),
         )
-        .await?
+        .await
     }
 
     pub async fn admin_deploy_bucket(
         &self,
         args: DeployWasmInput,
         ignore_prev_hash: Option<ByteArray<32>>,
-    ) -> Result<(), String> {
-        update_call(
+    ) -> Result<(), Error> {
+        update_call_result(
             &self.agent,
             &self.cluster,
             "admin_deploy_bucket",
             (args, ignore_prev_hash),
         )
-        .await?
+        .await
+    }
+
+    /// the caller of agent should be canister controller
+    pub async fn admin_apply_bucket_template(
+        &self,
+        canister: Principal,
+        template: BucketTemplate,
+    ) -> Result<(), Error> {
+        update_call_result(
+            &self.agent,
+            &self.cluster,
+            "admin_apply_bucket_template",
+            (canister, template),
+        )
+        .await
     }
 
-    pub async fn admin_upgrade_all_buckets(&self, args: Option<ByteBuf>) -> Result<(), String> {
-        update_call(
+    pub async fn admin_upgrade_all_buckets(&self, args: Option<ByteBuf>) -> Result<(), Error> {
+        update_call_result(
             &self.agent,
             &self.cluster,
             "admin_upgrade_all_buckets",
             (args,),
         )
-        .await?
+        .await
     }
 
     pub async fn admin_batch_call_buckets(
@@ -185,17 +289,36 @@ impl Client {
         buckets: BTreeSet<Principal>,
         method: String,
         args: Option<ByteBuf>,
-    ) -> Result<Vec<ByteBuf>, String> {
-        update_call(
+    ) -> Result<Vec<ByteBuf>, Error> {
+        update_call_result(
             &self.agent,
             &self.cluster,
             "admin_batch_call_buckets",
             (buckets, method, args),
         )
-        .await?
+        .await
+    }
+
+    pub async fn admin_topup_all_buckets(&self) -> Result<u128, Error> {
+        update_call_result(&self.agent, &self.cluster, "admin_topup_all_buckets", ()).await
+    }
+
+    pub async fn admin_decommission_bucket(
+        &self,
+        canister: Principal,
+        target: Principal,
+        delete_canister: bool,
+    ) -> Result<(), Error> {
+        update_call_result(
+            &self.agent,
+            &self.cluster,
+            "admin_decommission_bucket",
+            (canister, target, delete_canister),
+        )
+        .await
     }
 
-    pub async fn admin_topup_all_buckets(&self) -> Result<u128, String> {
-        update_call(&self.agent, &self.cluster, "admin_topup_all_buckets", ()).await?
+    pub async fn get_bucket_decommission(&self) -> Result<Option<BucketDecommissionStatus>, Error> {
+        query_call_result(&self.agent, &self.cluster, "get_bucket_decommission", ()).await
     }
 }
@@ -0,0 +1,220 @@
+use aes_gcm::{
+    aead::{Aead, Payload},
+    Aes256Gcm, Key, KeyInit, Nonce,
+};
+use ic_oss_types::{
+    cose::{cose_decrypt0, cose_encrypt0, encrypt0_key_id, hkdf256, DEK_AAD},
+    file::file_chunks,
+};
+use serde_bytes::ByteBuf;
+
+// The client side encryption of the file content with a data encryption key (DEK):
+//
+// 1. the key-encryption key (KEK) is derived from a BYOK secret or a vetKey, see `KeySource`;
+// 2. a random DEK is generated for a new file and wrapped with the KEK into a COSE_Encrypt0
+//    by `KeySource::wrap_dek`, it is stored in the bucket as `CreateFileInput.dek`;
+// 3. the bucket `Client` with the key source (see `Client::set_key_source`) encrypts the
+//    chunks of the files that have a DEK when uploading, and decrypts them when downloading.
+//
+// The key id in the COSE_Encrypt0 header is the derivation info of the KEK, so a file can
+// be decrypted by any holder of the key source. The bucket only stores the ciphertext.
+//
+// Each chunk is sealed with a random nonce that is stored in front of it, so a chunk can be
+// written again, e.g. by a resumed upload, without reusing a nonce. The chunk index and
+// whether it is the last chunk are bound as the associated data like the STREAM construction,
+// so the chunks can not be reordered and a truncated file fails to decrypt.
+
+pub const NONCE_SIZE: u32 = 12;
+
+// the AES-256-GCM tag appended to each encrypted chunk
+pub const TAG_SIZE: u32 = 16;
+
+// the bytes added to each chunk by the encryption
+pub const CHUNK_OVERHEAD: u32 = NONCE_SIZE + TAG_SIZE;
+
+static BYOK_SALT: &[u8] = b"ic_oss_byok";
+static VETKD_SALT: &[u8] = b"ic_oss_vetkd";
+
+/// The key material that the key-encryption keys (KEK) are derived from.
+#[derive(Clone)]
+pub enum KeySource {
+    /// A secret key brought by the user, it should have at least 32 random bytes.
+    Byok(Vec<u8>),
+    /// The key material decrypted from a vetKey, e.g. by `TransportSecretKey::decrypt_and_hash`
+    /// of the ic-vetkd-utils.
+    VetKey(Vec<u8>),
+}
+
+impl KeySource {
+    /// Derives the KEK for the key id with HKDF-SHA256.
+    pub fn kek(&self, key_id: &[u8]) -> [u8; 32] {
+        match self {
+            KeySource::Byok(secret) => hkdf256(secret, BYOK_SALT, key_id),
+            KeySource::VetKey(key) => hkdf256(key, VETKD_SALT, key_id),
+        }
+    }
+
+    /// Wraps the DEK with the KEK of the key id, the result is set to `FileMetadata.dek`.
+    pub fn wrap_dek(&self, dek: &[u8; 32], key_id: &[u8]) -> Result<ByteBuf, String> {
+        cose_encrypt0(
+            dek,
+            &self.kek(key_id),
+            random_nonce()?,
+            DEK_AAD,
+            Some(key_id.to_vec()),
+        )
+    }
+
+    /// Unwraps the DEK from `FileMetadata.dek`.
+    pub fn unwrap_dek(&self, wrapped: &[u8]) -> Result<[u8; 32], String> {
+        let key_id = encrypt0_key_id(wrapped)?;
+        let dek = cose_decrypt0(wrapped, &self.kek(&key_id), DEK_AAD)?;
+        dek.try_into().map_err(|_| "invalid DEK".to_string())
+    }
+
+    /// Generates a random DEK for a new file and returns it with the wrapped DEK.
+    pub fn new_dek(&self, key_id: &[u8]) -> Result<([u8; 32], ByteBuf), String> {
+        let mut dek = [0u8; 32];
+        getrandom::getrandom(&mut dek).map_err(|err| err.to_string())?;
+        let wrapped = self.wrap_dek(&dek, key_id)?;
+        Ok((dek, wrapped))
+    }
+}
+
+/// Encrypts and decrypts the chunks of a file with its DEK.
+#[derive(Clone)]
+pub struct ChunkCipher {
+    cipher: Aes256Gcm,
+    chunk_size: u32, // the chunk size of the bucket
+}
+
+impl ChunkCipher {
+    pub fn new(dek: &[u8; 32], chunk_size: u32) -> Self {
+        ChunkCipher {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(dek)),
+            chunk_size,
+        }
+    }
+
+    /// The size of the content in a chunk, the rest is the nonce and the tag.
+    pub fn plain_chunk_size(&self) -> u32 {
+        self.chunk_size - CHUNK_OVERHEAD
+    }
+
+    /// Returns the size of the encrypted file with the content size.
+    pub fn encrypted_size(&self, size: u64) -> u64 {
+        size + file_chunks(size, self.plain_chunk_size()) as u64 * CHUNK_OVERHEAD as u64
+    }
+
+    /// Returns the size of the content of the encrypted file.
+    pub fn plain_size(&self, encrypted_size: u64) -> u64 {
+        let chunks = file_chunks(encrypted_size, self.chunk_size) as u64;
+        encrypted_size.saturating_sub(chunks * CHUNK_OVERHEAD as u64)
+    }
+
+    /// Seals the chunk with a random nonce, `last` tells whether it is the last chunk
+    /// of the file.
+    pub fn encrypt_chunk(
+        &self,
+        chunk_index: u32,
+        last: bool,
+        chunk: &[u8],
+    ) -> Result<Vec<u8>, String> {
+        let nonce = random_nonce()?;
+        let data = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: chunk,
+                    aad: &chunk_aad(chunk_index, last),
+                },
+            )
+            .map_err(|_| format!("failed to encrypt chunk {}", chunk_index))?;
+        Ok([nonce.as_slice(), &data].concat())
+    }
+
+    pub fn decrypt_chunk(
+        &self,
+        chunk_index: u32,
+        last: bool,
+        chunk: &[u8],
+    ) -> Result<Vec<u8>, String> {
+        if chunk.len() < CHUNK_OVERHEAD as usize {
+            return Err(format!("invalid encrypted chunk {}", chunk_index));
+        }
+        let (nonce, data) = chunk.split_at(NONCE_SIZE as usize);
+        self.cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: data,
+                    aad: &chunk_aad(chunk_index, last),
+                },
+            )
+            .map_err(|_| format!("failed to decrypt chunk {}", chunk_index))
+    }
+
+    /// Encrypts the whole content of a small file that is uploaded in one request.
+    pub fn encrypt_content(&self, content: &[u8]) -> Result<Vec<u8>, String> {
+        let mut buf = Vec::with_capacity(self.encrypted_size(content.len() as u64) as usize);
+        let chunks = file_chunks(content.len() as u64, self.plain_chunk_size());
+        for (i, chunk) in content.chunks(self.plain_chunk_size() as usize).enumerate() {
+            let i = i as u32;
+            buf.extend(self.encrypt_chunk(i, i + 1 == chunks, chunk)?);
+        }
+        Ok(buf)
+    }
+}
+
+fn random_nonce() -> Result<[u8; 12], String> {
+    let mut nonce = [0u8; 12];
+    getrandom::getrandom(&mut nonce).map_err(|err| err.to_string())?;
+    Ok(nonce)
+}
+
+fn chunk_aad(chunk_index: u32, last: bool) -> Vec<u8> {
+    [DEK_AAD, &chunk_index.to_be_bytes(), &[last as u8]].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dek_works() {
+        let byok = KeySource::Byok(vec![7u8; 32]);
+        let (dek, wrapped) = byok.new_dek(b"bucket/f1").unwrap();
+        assert_eq!(byok.unwrap_dek(&wrapped).unwrap(), dek);
+        assert!(KeySource::Byok(vec![8u8; 32]).unwrap_dek(&wrapped).is_err());
+        assert!(KeySource::VetKey(vec![7u8; 32])
+            .unwrap_dek(&wrapped)
+            .is_err());
+
+        let cipher = ChunkCipher::new(&dek, 1024);
+        let content: Vec<u8> = (0..2500).map(|i| (i % 251) as u8).collect();
+        let data = cipher.encrypt_content(&content).unwrap();
+        assert_eq!(data.len() as u64, cipher.encrypted_size(2500));
+        assert_eq!(data.len(), 2500 + 3 * 28);
+        assert_eq!(cipher.plain_size(data.len() as u64), 2500);
+
+        let chunks: Vec<&[u8]> = data.chunks(1024).collect();
+        let mut plain = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            plain.extend(cipher.decrypt_chunk(i as u32, i == 2, chunk).unwrap());
+        }
+        assert_eq!(plain, content);
+        // the chunks can not be reordered and the file can not be truncated
+        assert!(cipher.decrypt_chunk(1, false, chunks[0]).is_err());
+        assert!(cipher.decrypt_chunk(1, true, chunks[1]).is_err());
+
+        // a chunk written again gets another nonce
+        let c1 = cipher.encrypt_chunk(0, false, &content[..100]).unwrap();
+        let c2 = cipher.encrypt_chunk(0, false, &content[..100]).unwrap();
+        assert_ne!(c1[..12], c2[..12]);
+        assert_eq!(
+            cipher.decrypt_chunk(0, false, &c2).unwrap(),
+            &content[..100]
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use candid::Principal;
+use std::fmt;
+
+/// The classification of an error returned by the SDK, derived from the agent error
+/// or the error message of the canister.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The request did not reach the canister or timed out, it can be retried.
+    Transport,
+    /// The canister is temporarily unable to serve the request, e.g. a rate limit.
+    RateLimited,
+    /// A limit of the bucket or the cluster is exceeded, e.g. the max file size.
+    Quota,
+    /// The caller is not authenticated, e.g. the access token is missing or expired.
+    Unauthorized,
+    /// The caller is authenticated but not allowed to perform the operation.
+    PermissionDenied,
+    NotFound,
+    /// The request arguments or the response could not be encoded or decoded.
+    Codec,
+    Other,
+}
+
+/// An error of a call to the bucket or the cluster, with the method and the canister
+/// of the request if it was sent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub method: Option<String>,
+    pub canister: Option<Principal>,
+}
+
+impl Error {
+    /// Creates an error from the message, the kind is classified from the message.
+    pub fn new(message: String) -> Self {
+        Self {
+            kind: classify(&message),
+            message,
+            method: None,
+            canister: None,
+        }
+    }
+
+    pub fn with_kind(kind: ErrorKind, message: String) -> Self {
+        Self {
+            kind,
+            message,
+            method: None,
+            canister: None,
+        }
+    }
+
+    /// Attaches the request context to the error.
+    pub fn with_context(mut self, method: &str, canister: &Principal) -> Self {
+        self.method = Some(method.to_string());
+        self.canister = Some(*canister);
+        self
+    }
+
+    /// Returns true if the same request may succeed later without any change.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self.kind, ErrorKind::Transport | ErrorKind::RateLimited)
+    }
+
+    /// Returns true if the request was rejected for the identity or the access token.
+    pub fn is_auth(&self) -> bool {
+        matches!(
+            self.kind,
+            ErrorKind::Unauthorized | ErrorKind::PermissionDenied
+        )
+    }
+
+    /// Returns true if the request was rejected for a limit or a quota.
+    pub fn is_quota(&self) -> bool {
+        matches!(self.kind, ErrorKind::Quota | ErrorKind::RateLimited)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.method, &self.canister) {
+            (Some(method), Some(canister)) => {
+                write!(f, "{} on {}: {}", method, canister, self.message)
+            }
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Self::new(message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Self::new(message.to_string())
+    }
+}
+
+impl From<Error> for String {
+    fn from(err: Error) -> Self {
+        err.to_string()
+    }
+}
+
+// classifies the error messages of the agent and the canisters, the agent errors are
+// formatted with Debug, e.g. "TransportError(...)" or "CertifiedReject(...)".
+fn classify(message: &str) -> ErrorKind {
+    let msg = message.to_lowercase();
+    let has = |patterns: &[&str]| patterns.iter().any(|p| msg.contains(p));
+    if has(&[
+        "transporterror",
+        "timeoutwaitingforresponse",
+        "systransient",
+        "timed out",
+        "connection",
+    ]) {
+        ErrorKind::Transport
+    } else if has(&[
+        "try again later",
+        "rate limit",
+        "too many requests",
+        "status: 429",
+        "status: 503",
+    ]) {
+        ErrorKind::RateLimited
+    } else if has(&[
+        "unauthorized",
+        "token expired",
+        "not yet valid",
+        "signature verification failed",
+        "invalid cose sign1 token",
+        "is not a controller",
+    ]) {
+        ErrorKind::Unauthorized
+    } else if has(&[
+        "permission denied",
+        "not allowed",
+        "not writable",
+        "is archived",
+    ]) {
+        ErrorKind::PermissionDenied
+    } else if has(&[
+        "exceed",
+        "out of cycles",
+        "insufficient cycles",
+        "too large",
+    ]) {
+        ErrorKind::Quota
+    } else if has(&["not found"]) {
+        ErrorKind::NotFound
+    } else {
+        ErrorKind::Other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_works() {
+        for (msg, kind) in [
+            (
+                "TransportError(reqwest::Error { .. })",
+                ErrorKind::Transport,
+            ),
+            (
+                "CertifiedReject(RejectResponse { reject_code: SysTransient, .. })",
+                ErrorKind::Transport,
+            ),
+            (
+                "anonymous upload quota exceeded, try again later",
+                ErrorKind::RateLimited,
+            ),
+            (
+                "HttpError(HttpErrorPayload { status: 429, content_type: None, .. })",
+                ErrorKind::RateLimited,
+            ),
+            ("token expired", ErrorKind::Unauthorized),
+            ("Unauthorized", ErrorKind::Unauthorized),
+            ("permission denied", ErrorKind::PermissionDenied),
+            ("bucket is not writable", ErrorKind::PermissionDenied),
+            ("file size exceeds the limit 1024", ErrorKind::Quota),
+            ("file not found: 1", ErrorKind::NotFound),
+            ("invalid tag name", ErrorKind::Other),
+        ] {
+            assert_eq!(classify(msg), kind, "{}", msg);
+        }
+
+        let err =
+            Error::from("permission denied").with_context("get_file_info", &Principal::anonymous());
+        assert!(err.is_auth());
+        assert!(!err.is_retryable());
+        assert!(!err.is_quota());
+        assert_eq!(
+            err.to_string(),
+            "get_file_info on 2vxsx-fae: permission denied"
+        );
+
+        let err = Error::from("anonymous upload quota exceeded, try again later");
+        assert!(err.is_retryable());
+        assert!(err.is_quota());
+        assert_eq!(
+            String::from(err),
+            "anonymous upload quota exceeded, try again later"
+        );
+    }
+}
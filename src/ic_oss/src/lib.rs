@@ -1,6 +1,12 @@
 pub mod agent;
 pub mod bucket;
 pub mod cluster;
+pub mod crypto;
+pub mod error;
+#[cfg(feature = "opendal")]
+pub mod opendal;
+pub mod throttle;
+pub mod token;
 
 #[cfg(test)]
 mod tests {
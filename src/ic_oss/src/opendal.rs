@@ -0,0 +1,422 @@
+use ::opendal::{
+    raw::{
+        oio, Access, AccessorInfo, OpCreateDir, OpDelete, OpList, OpRead, OpStat, OpWrite,
+        RpCreateDir, RpDelete, RpList, RpRead, RpStat, RpWrite,
+    },
+    Buffer, Capability, EntryMode, Error, ErrorKind, Metadata, Operator, OperatorBuilder, Result,
+    Scheme,
+};
+use bytes::BytesMut;
+use ic_oss_types::{
+    file::{CreateFileInput, FileInfo, ListOptions},
+    folder::FolderChild,
+};
+use serde_bytes::ByteBuf;
+use std::{fmt, sync::Arc};
+
+use crate::{bucket::Client, error};
+
+static OCTET_STREAM: &str = "application/octet-stream";
+const LIST_PAGE_SIZE: u32 = 100;
+
+/// An Apache OpenDAL service backed by a bucket, the paths are resolved from the root
+/// folder of the bucket, e.g. "assets/logo.png" and "assets/" for a folder.
+///
+/// ```rust,ignore
+/// let op = ic_oss::opendal::IcOss::operator(ic_oss::bucket::Client::new(agent, bucket));
+/// op.write("assets/logo.png", content).await?;
+/// let content = op.read("assets/logo.png").await?;
+/// ```
+#[derive(Clone)]
+pub struct IcOss {
+    client: Arc<Client>,
+}
+
+impl IcOss {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client: Arc::new(client),
+        }
+    }
+
+    /// Creates an OpenDAL operator with the bucket client.
+    pub fn operator(client: Client) -> Operator {
+        OperatorBuilder::new(Self::new(client)).finish()
+    }
+
+    async fn file(&self, path: &str) -> Result<FileInfo> {
+        match self.client.get_child_by_path(path).await {
+            Ok(FolderChild::File(info)) => Ok(info),
+            Ok(FolderChild::Folder(_)) => Err(Error::new(
+                ErrorKind::IsADirectory,
+                format!("{} is a folder", path),
+            )),
+            Err(err) => Err(parse_error(err)),
+        }
+    }
+}
+
+impl fmt::Debug for IcOss {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IcOss").finish_non_exhaustive()
+    }
+}
+
+impl Access for IcOss {
+    type Reader = Buffer;
+    type Writer = IcOssWriter;
+    type Lister = oio::PageLister<IcOssLister>;
+    type BlockingReader = ();
+    type BlockingWriter = ();
+    type BlockingLister = ();
+
+    fn info(&self) -> Arc<AccessorInfo> {
+        let mut info = AccessorInfo::default();
+        info.set_scheme(Scheme::Custom("ic_oss"))
+            .set_root("/")
+            .set_native_capability(Capability {
+                stat: true,
+                read: true,
+                write: true,
+                create_dir: true,
+                delete: true,
+                list: true,
+                ..Default::default()
+            });
+        info.into()
+    }
+
+    async fn create_dir(&self, path: &str, _: OpCreateDir) -> Result<RpCreateDir> {
+        self.client
+            .create_folders(path)
+            .await
+            .map_err(parse_error)?;
+        Ok(RpCreateDir::default())
+    }
+
+    async fn stat(&self, path: &str, _: OpStat) -> Result<RpStat> {
+        let meta = match self.client.get_child_by_path(path).await {
+            Ok(FolderChild::Folder(_)) => Metadata::new(EntryMode::DIR),
+            // a path with a trailing slash addresses a folder
+            Ok(FolderChild::File(_)) if path.ends_with('/') => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("folder not found: {}", path),
+            ))?,
+            Ok(FolderChild::File(info)) => file_metadata(&info),
+            Err(err) => Err(parse_error(err))?,
+        };
+        Ok(RpStat::new(meta))
+    }
+
+    // reads the range by read_range calls, each one is truncated to MAX_FILE_SIZE_PER_CALL
+    async fn read(&self, path: &str, args: OpRead) -> Result<(RpRead, Self::Reader)> {
+        let info = self.file(path).await?;
+        let range = args.range();
+        let end = match range.size() {
+            Some(size) => range.offset().saturating_add(size).min(info.size),
+            None => info.size,
+        };
+        let mut pos = range.offset();
+        let mut buf = Vec::with_capacity(end.saturating_sub(pos) as usize);
+        while pos < end {
+            let data = self
+                .client
+                .read_range(info.id, pos, end - pos)
+                .await
+                .map_err(parse_error)?;
+            if data.is_empty() {
+                break;
+            }
+            pos += data.len() as u64;
+            buf.extend_from_slice(&data);
+        }
+        Ok((RpRead::new(), Buffer::from(buf)))
+    }
+
+    async fn write(&self, path: &str, args: OpWrite) -> Result<(RpWrite, Self::Writer)> {
+        let (parent, name) = match path.trim_end_matches('/').rsplit_once('/') {
+            Some((parent, name)) => (parent.to_string(), name.to_string()),
+            None => (String::new(), path.to_string()),
+        };
+        let writer = IcOssWriter {
+            client: self.client.clone(),
+            parent,
+            name,
+            content_type: args.content_type().unwrap_or(OCTET_STREAM).to_string(),
+            buf: BytesMut::new(),
+        };
+        Ok((RpWrite::default(), writer))
+    }
+
+    // deletes the file, or the folder if the path has a trailing slash, a missing path
+    // is not an error
+    async fn delete(&self, path: &str, _: OpDelete) -> Result<RpDelete> {
+        let res = match self.client.get_child_by_path(path).await {
+            Ok(FolderChild::Folder(info)) if path.ends_with('/') => {
+                self.client.delete_folder(info.id).await
+            }
+            Ok(FolderChild::File(info)) if !path.ends_with('/') => {
+                self.client.delete_file(info.id).await
+            }
+            Ok(_) => Ok(false),
+            Err(err) if err.kind == error::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        };
+        res.map_err(parse_error)?;
+        Ok(RpDelete::default())
+    }
+
+    async fn list(&self, path: &str, _: OpList) -> Result<(RpList, Self::Lister)> {
+        // listing a missing folder yields no entries
+        let parent = match self.client.get_child_by_path(path).await {
+            Ok(FolderChild::Folder(info)) => Some(info.id),
+            Ok(FolderChild::File(_)) => None,
+            Err(err) if err.kind == error::ErrorKind::NotFound => None,
+            Err(err) => Err(parse_error(err))?,
+        };
+        let mut prefix = path.trim_start_matches('/').to_string();
+        if !prefix.is_empty() && !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+        let lister = IcOssLister {
+            client: self.client.clone(),
+            parent,
+            prefix,
+        };
+        Ok((RpList::default(), oio::PageLister::new(lister)))
+    }
+}
+
+/// Buffers the written content and uploads it as a file on close, the written file
+/// replaces an existing file of the path.
+pub struct IcOssWriter {
+    client: Arc<Client>,
+    parent: String,
+    name: String,
+    content_type: String,
+    buf: BytesMut,
+}
+
+impl oio::Write for IcOssWriter {
+    async fn write(&mut self, bs: Buffer) -> Result<()> {
+        self.buf.extend_from_slice(&bs.to_bytes());
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        let parent = self
+            .client
+            .create_folders(&self.parent)
+            .await
+            .map_err(parse_error)?;
+        match self
+            .client
+            .get_child_by_name(parent, self.name.clone())
+            .await
+        {
+            Ok(FolderChild::File(info)) => {
+                self.client
+                    .delete_file(info.id)
+                    .await
+                    .map_err(parse_error)?;
+            }
+            Ok(FolderChild::Folder(_)) => Err(Error::new(
+                ErrorKind::IsADirectory,
+                format!("{} is a folder", self.name),
+            ))?,
+            Err(err) if err.kind == error::ErrorKind::NotFound => {}
+            Err(err) => Err(parse_error(err))?,
+        }
+
+        let content = std::mem::take(&mut self.buf).freeze();
+        let input = CreateFileInput::new(parent, self.name.clone(), self.content_type.clone())
+            .with_size(content.len() as u64);
+        let res = self
+            .client
+            .upload(content.as_ref(), input, |_| {})
+            .await
+            .map_err(parse_error)?;
+        if let Some(err) = res.error {
+            Err(Error::new(
+                ErrorKind::Unexpected,
+                format!("failed to upload {}: {}", self.name, err),
+            ))?;
+        }
+        Ok(())
+    }
+
+    async fn abort(&mut self) -> Result<()> {
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+/// Lists the subfolders and then the files of a folder, the page token is the phase
+/// and the cursor of the bucket's list APIs.
+pub struct IcOssLister {
+    client: Arc<Client>,
+    parent: Option<u32>,
+    prefix: String,
+}
+
+impl oio::PageList for IcOssLister {
+    async fn next_page(&self, ctx: &mut oio::PageContext) -> Result<()> {
+        let parent = match self.parent {
+            Some(parent) => parent,
+            None => {
+                ctx.done = true;
+                return Ok(());
+            }
+        };
+        let token = ListToken::decode(&ctx.token)?;
+        let options = ListOptions {
+            cursor: token.cursor.map(ByteBuf::from),
+            take: Some(LIST_PAGE_SIZE),
+            ..Default::default()
+        };
+        let next_cursor = if token.files {
+            let output = self
+                .client
+                .list_files_with_options(parent, options)
+                .await
+                .map_err(parse_error)?;
+            for info in output.files {
+                let path = format!("{}{}", self.prefix, info.name);
+                ctx.entries
+                    .push_back(oio::Entry::new(&path, file_metadata(&info)));
+            }
+            output.next_cursor
+        } else {
+            let output = self
+                .client
+                .list_folders_with_options(parent, options)
+                .await
+                .map_err(parse_error)?;
+            for info in output.folders {
+                let path = format!("{}{}/", self.prefix, info.name);
+                ctx.entries
+                    .push_back(oio::Entry::new(&path, Metadata::new(EntryMode::DIR)));
+            }
+            output.next_cursor
+        };
+
+        match (next_cursor, token.files) {
+            (None, true) => ctx.done = true,
+            (cursor, files) => {
+                // the files are listed after the last page of the subfolders
+                ctx.token = ListToken {
+                    files: files || cursor.is_none(),
+                    cursor: cursor.map(ByteBuf::into_vec),
+                }
+                .encode();
+            }
+        }
+        Ok(())
+    }
+}
+
+// the page token of the lister: "d:<hex cursor>" for the subfolders and "f:<hex cursor>"
+// for the files, the empty token is the first page of the subfolders.
+#[derive(Debug, PartialEq, Eq)]
+struct ListToken {
+    files: bool,
+    cursor: Option<Vec<u8>>,
+}
+
+impl ListToken {
+    fn decode(token: &str) -> Result<Self> {
+        let (files, cursor) = match token.split_once(':') {
+            None if token.is_empty() => (false, ""),
+            Some(("d", cursor)) => (false, cursor),
+            Some(("f", cursor)) => (true, cursor),
+            _ => Err(Error::new(
+                ErrorKind::Unexpected,
+                format!("invalid list token: {}", token),
+            ))?,
+        };
+        let cursor = if cursor.is_empty() {
+            None
+        } else {
+            Some(hex::decode(cursor).map_err(|err| {
+                Error::new(
+                    ErrorKind::Unexpected,
+                    format!("invalid list token: {}", err),
+                )
+            })?)
+        };
+        Ok(Self { files, cursor })
+    }
+
+    fn encode(&self) -> String {
+        format!(
+            "{}:{}",
+            if self.files { "f" } else { "d" },
+            self.cursor.as_ref().map(hex::encode).unwrap_or_default()
+        )
+    }
+}
+
+fn file_metadata(info: &FileInfo) -> Metadata {
+    let mut meta = Metadata::new(EntryMode::FILE)
+        .with_content_length(info.size)
+        .with_content_type(info.content_type.clone());
+    if let Some(hash) = &info.hash {
+        meta = meta.with_etag(format!("\"{}\"", hex::encode(hash.as_ref())));
+    }
+    if let Some(updated_at) = chrono::DateTime::from_timestamp_millis(info.updated_at as i64) {
+        meta = meta.with_last_modified(updated_at);
+    }
+    meta
+}
+
+fn parse_error(err: error::Error) -> Error {
+    let kind = match err.kind {
+        error::ErrorKind::NotFound => ErrorKind::NotFound,
+        error::ErrorKind::Unauthorized | error::ErrorKind::PermissionDenied => {
+            ErrorKind::PermissionDenied
+        }
+        error::ErrorKind::RateLimited => ErrorKind::RateLimited,
+        _ => ErrorKind::Unexpected,
+    };
+    let res = Error::new(kind, err.message);
+    if err.is_retryable() {
+        res.set_temporary()
+    } else {
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_token_works() {
+        let token = ListToken::decode("").unwrap();
+        assert_eq!(
+            token,
+            ListToken {
+                files: false,
+                cursor: None
+            }
+        );
+        for token in [
+            ListToken {
+                files: false,
+                cursor: Some(vec![1, 2]),
+            },
+            ListToken {
+                files: true,
+                cursor: None,
+            },
+            ListToken {
+                files: true,
+                cursor: Some(vec![255]),
+            },
+        ] {
+            assert_eq!(ListToken::decode(&token.encode()).unwrap(), token);
+        }
+        assert!(ListToken::decode("x:").is_err());
+        assert!(ListToken::decode("f:zz").is_err());
+    }
+}
@@ -0,0 +1,260 @@
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    sync::Notify,
+    time::{sleep, Duration, Instant},
+};
+
+use crate::error::{Error, ErrorKind};
+
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(32);
+pub const MAX_BACKOFF_RETRIES: u32 = 6;
+pub const MAX_WINDOW: usize = 64;
+
+/// A token bucket on bytes that is shared by the requests of a client, it caps the
+/// throughput of the uploads and downloads. When the boundary node or the canister
+/// rejects a request for the rate limit, all the requests pause for a backoff.
+///
+/// It also holds the concurrency window of the chunk transfers, the window is
+/// adapted with AIMD: it grows by one after a window of successful requests and
+/// halves when a request is rate limited.
+#[derive(Debug)]
+pub struct Throttle {
+    rate: Option<u64>, // bytes per second, None: unlimited
+    state: Mutex<State>,
+    slot_released: Notify,
+}
+
+/// A slot of the concurrency window, it is released when dropped.
+#[derive(Debug)]
+pub struct Slot(Arc<Throttle>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().in_flight -= 1;
+        self.0.slot_released.notify_waiters();
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    tokens: f64, // it can be negative when a large request is taken on credit
+    updated_at: Instant,
+    paused_until: Option<Instant>,
+    backoff: Duration,
+    window: f64,
+    in_flight: usize,
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl Throttle {
+    pub fn new(rate: Option<u64>) -> Self {
+        let rate = rate.filter(|r| *r > 0);
+        Self {
+            rate,
+            state: Mutex::new(State {
+                tokens: rate.unwrap_or_default() as f64,
+                updated_at: Instant::now(),
+                paused_until: None,
+                backoff: Duration::ZERO,
+                window: MAX_WINDOW as f64,
+                in_flight: 0,
+            }),
+            slot_released: Notify::new(),
+        }
+    }
+
+    pub fn rate(&self) -> Option<u64> {
+        self.rate
+    }
+
+    /// Waits until the bytes can be transferred without exceeding the rate, the burst
+    /// is up to one second of the rate.
+    pub async fn acquire(&self, bytes: u64) {
+        let wait = self.reserve(bytes, Instant::now());
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+
+    /// Waits for a slot of the concurrency window, the window is capped at `max`.
+    pub async fn acquire_slot(self: &Arc<Self>, max: usize) -> Slot {
+        loop {
+            let released = self.slot_released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+            {
+                let mut s = self.state.lock().unwrap();
+                s.window = s.window.min(max.clamp(1, MAX_WINDOW) as f64);
+                if s.in_flight < s.window as usize {
+                    s.in_flight += 1;
+                    return Slot(self.clone());
+                }
+            }
+            released.await;
+        }
+    }
+
+    /// Returns the current concurrency window and the number of requests in flight.
+    pub fn window(&self) -> (usize, usize) {
+        let s = self.state.lock().unwrap();
+        (s.window as usize, s.in_flight)
+    }
+
+    /// Pauses the requests for an exponential backoff and returns it, the
+    /// concurrency window is halved.
+    pub fn backoff(&self) -> Duration {
+        let mut s = self.state.lock().unwrap();
+        s.window = (s.window / 2.0).max(1.0);
+        s.backoff = (s.backoff * 2).clamp(MIN_BACKOFF, MAX_BACKOFF);
+        let until = Instant::now() + s.backoff;
+        s.paused_until = Some(s.paused_until.map_or(until, |p| p.max(until)));
+        s.backoff
+    }
+
+    /// Resets the backoff after a successful request, the concurrency window grows
+    /// by one after a window of successful requests.
+    pub fn reset_backoff(&self) {
+        let mut s = self.state.lock().unwrap();
+        s.backoff = Duration::ZERO;
+        let window = s.window;
+        s.window = (window + 1.0 / window).min(MAX_WINDOW as f64);
+        let grown = s.window as usize > window as usize;
+        drop(s);
+        if grown {
+            self.slot_released.notify_waiters();
+        }
+    }
+
+    // takes the bytes from the bucket and returns how long the caller should wait.
+    fn reserve(&self, bytes: u64, now: Instant) -> Duration {
+        let mut s = self.state.lock().unwrap();
+        let paused = match s.paused_until {
+            Some(until) if until > now => until - now,
+            _ => {
+                s.paused_until = None;
+                Duration::ZERO
+            }
+        };
+
+        let rate = match self.rate {
+            Some(rate) => rate as f64,
+            None => return paused,
+        };
+        let elapsed = now.saturating_duration_since(s.updated_at).as_secs_f64();
+        s.tokens = (s.tokens + elapsed * rate).min(rate) - bytes as f64;
+        s.updated_at = now;
+        let wait = if s.tokens < 0.0 {
+            Duration::from_secs_f64(-s.tokens / rate)
+        } else {
+            Duration::ZERO
+        };
+        wait.max(paused)
+    }
+}
+
+/// Calls the request until it is not rate limited, it backs off on the throttle
+/// between the attempts.
+pub async fn with_backoff<T, F, Fut>(throttle: &Throttle, call: F) -> Result<T, Error>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let mut attempts = 0;
+    loop {
+        throttle.acquire(0).await;
+        match call().await {
+            Err(err) if err.kind == ErrorKind::RateLimited && attempts < MAX_BACKOFF_RETRIES => {
+                attempts += 1;
+                throttle.backoff();
+            }
+            res => {
+                if res.is_ok() {
+                    throttle.reset_backoff();
+                }
+                return res;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserve_works() {
+        let now = Instant::now();
+        let throttle = Throttle::new(None);
+        assert_eq!(throttle.reserve(u64::MAX, now), Duration::ZERO);
+
+        let throttle = Throttle::new(Some(1000));
+        assert_eq!(throttle.reserve(1000, now), Duration::ZERO);
+        assert_eq!(throttle.reserve(500, now), Duration::from_millis(500));
+        // the debt is paid in 0.5s, then 0.5s of tokens are refilled
+        assert_eq!(
+            throttle.reserve(500, now + Duration::from_secs(1)),
+            Duration::ZERO
+        );
+        // the burst is capped at one second of the rate
+        assert_eq!(
+            throttle.reserve(2000, now + Duration::from_secs(10)),
+            Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn backoff_works() {
+        let throttle = Throttle::new(None);
+        assert_eq!(throttle.backoff(), MIN_BACKOFF);
+        assert_eq!(throttle.backoff(), MIN_BACKOFF * 2);
+        assert!(throttle.reserve(0, Instant::now()) > MIN_BACKOFF);
+        for _ in 0..10 {
+            throttle.backoff();
+        }
+        assert_eq!(throttle.backoff(), MAX_BACKOFF);
+
+        throttle.reset_backoff();
+        assert_eq!(throttle.backoff(), MIN_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn window_works() {
+        let throttle = Arc::new(Throttle::new(None));
+        let s1 = throttle.acquire_slot(4).await;
+        let _s2 = throttle.acquire_slot(4).await;
+        assert_eq!(throttle.window(), (4, 2));
+
+        // multiplicative decrease
+        throttle.backoff();
+        throttle.backoff();
+        assert_eq!(throttle.window(), (1, 2));
+        let waiting = tokio::spawn({
+            let throttle = throttle.clone();
+            async move { throttle.acquire_slot(4).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        // additive increase, it takes a window of successes to grow by one
+        throttle.reset_backoff();
+        assert_eq!(throttle.window(), (2, 2));
+        for _ in 0..2 {
+            throttle.reset_backoff();
+        }
+        assert_eq!(throttle.window(), (2, 2));
+        drop(s1);
+        let _s3 = waiting.await.unwrap();
+        assert_eq!(throttle.window(), (2, 2));
+        throttle.reset_backoff();
+        assert_eq!(throttle.window(), (3, 2));
+    }
+}
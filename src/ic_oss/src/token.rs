@@ -0,0 +1,89 @@
+use async_trait::async_trait;
+use candid::Principal;
+use ic_oss_types::cose::sign1_expiration;
+use serde_bytes::ByteBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+
+use crate::cluster;
+
+/// Provides the access token for the requests of the bucket Client.
+#[async_trait]
+pub trait TokenProvider: Send + Sync {
+    /// Returns the access token to use, None means the request is sent without token.
+    async fn access_token(&self) -> Result<Option<ByteBuf>, String>;
+}
+
+/// A fixed access token, it will not be renewed.
+pub struct StaticToken(pub Option<ByteBuf>);
+
+#[async_trait]
+impl TokenProvider for StaticToken {
+    async fn access_token(&self) -> Result<Option<ByteBuf>, String> {
+        Ok(self.0.clone())
+    }
+}
+
+/// Acquires access tokens from the cluster for the agent's identity,
+/// and renews the token before it expires.
+pub struct ClusterTokenProvider {
+    cluster: cluster::Client,
+    audience: Principal, // the bucket or the audience group
+    ed25519: bool,
+    renew_before: i64, // in seconds
+    cached: RwLock<Option<(ByteBuf, i64)>>,
+}
+
+impl ClusterTokenProvider {
+    /// Creates a provider that requests ed25519 signed tokens if `ed25519` is true,
+    /// otherwise secp256k1 signed tokens.
+    pub fn new(cluster: cluster::Client, audience: Principal, ed25519: bool) -> Self {
+        ClusterTokenProvider {
+            cluster,
+            audience,
+            ed25519,
+            renew_before: 60,
+            cached: RwLock::new(None),
+        }
+    }
+
+    /// Sets how many seconds before the expiration the token should be renewed, default is 60.
+    pub fn set_renew_before(&mut self, seconds: u64) {
+        self.renew_before = seconds as i64;
+    }
+
+    fn valid_token(&self, cached: &Option<(ByteBuf, i64)>, now_sec: i64) -> Option<ByteBuf> {
+        match cached {
+            Some((token, exp)) if now_sec + self.renew_before < *exp => Some(token.clone()),
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+impl TokenProvider for ClusterTokenProvider {
+    async fn access_token(&self) -> Result<Option<ByteBuf>, String> {
+        let now_sec = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|err| err.to_string())?
+            .as_secs() as i64;
+        if let Some(token) = self.valid_token(&self.cached.read().await, now_sec) {
+            return Ok(Some(token));
+        }
+
+        let mut cached = self.cached.write().await;
+        // the token may have been renewed by another request
+        if let Some(token) = self.valid_token(&cached, now_sec) {
+            return Ok(Some(token));
+        }
+
+        let token = if self.ed25519 {
+            self.cluster.ed25519_access_token(self.audience).await?
+        } else {
+            self.cluster.access_token(self.audience).await?
+        };
+        let exp = sign1_expiration(&token).ok_or("invalid access token")?;
+        *cached = Some((token.clone(), exp));
+        Ok(Some(token))
+    }
+}
@@ -1,8 +1,16 @@
 use candid::Principal;
-use ic_oss_types::bucket::UpdateBucketInput;
+use ic_oss_types::{
+    bucket::{
+        validate_role, BackupTargetInput, FolderLimits, GcCursor, GcReport, RoleInfo,
+        SecurityHeaders, TaskInfo, UpdateBucketInput, UpgradeReport, WellKnown,
+    },
+    nft::{Account, NftCollection},
+};
 use std::collections::BTreeSet;
 
-use crate::{is_controller, store, validate_principals};
+use crate::{
+    is_any_controller, is_controller, job, store, validate_principals, MILLISECONDS, SECONDS,
+};
 
 #[ic_cdk::update(guard = "is_controller")]
 fn admin_set_managers(args: BTreeSet<Principal>) -> Result<(), String> {
@@ -58,9 +66,27 @@ fn admin_set_auditors(args: BTreeSet<Principal>) -> Result<(), String> {
     Ok(())
 }
 
+// called by the cluster to sync the audience groups that the bucket belongs to,
+// it is allowed in the governance managed mode too.
+#[ic_cdk::update(guard = "is_any_controller")]
+fn admin_set_audience_groups(args: BTreeSet<Principal>) -> Result<(), String> {
+    if args.contains(&Principal::anonymous()) {
+        Err("anonymous user is not allowed".to_string())?;
+    }
+    store::state::with_mut(|r| {
+        r.audience_groups = args;
+    });
+    Ok(())
+}
+
 #[ic_cdk::update(guard = "is_controller")]
 fn admin_update_bucket(args: UpdateBucketInput) -> Result<(), String> {
     args.validate()?;
+    if let Some(ref anonymous_upload) = args.anonymous_upload {
+        if store::fs::get_folder(anonymous_upload.folder).is_none() {
+            Err(format!("folder {} not found", anonymous_upload.folder))?;
+        }
+    }
     store::state::with_mut(|s| {
         if let Some(name) = args.name {
             s.name = name;
@@ -92,10 +118,210 @@ fn admin_update_bucket(args: UpdateBucketInput) -> Result<(), String> {
         if let Some(trusted_eddsa_pub_keys) = args.trusted_eddsa_pub_keys {
             s.trusted_eddsa_pub_keys = trusted_eddsa_pub_keys;
         }
+        if let Some(allowed_origins) = args.allowed_origins {
+            s.allowed_origins = allowed_origins;
+        }
+        if let Some(require_signed_url) = args.require_signed_url {
+            s.require_signed_url = require_signed_url;
+        }
+        if let Some(anonymous_upload) = args.anonymous_upload {
+            s.anonymous_upload = anonymous_upload;
+        }
+        if let Some(enable_compression) = args.enable_compression {
+            s.enable_compression = enable_compression;
+        }
+        if let Some(name_policy) = args.name_policy {
+            s.name_policy = name_policy;
+        }
+        if let Some(security_headers) = args.security_headers {
+            s.security_headers = security_headers;
+        }
+        if let Some(access_log_sample_rate) = args.access_log_sample_rate {
+            s.access_log_sample_rate = access_log_sample_rate;
+        }
+        if let Some(receipt_key_name) = args.receipt_key_name {
+            if s.receipt_key_name != receipt_key_name {
+                s.receipt_key_name = receipt_key_name;
+                s.receipt_public_key = None;
+                s.signed_receipts = store::SignedReceipts::default();
+            }
+        }
+        if let Some(max_annotations) = args.max_annotations {
+            s.max_annotations = max_annotations;
+        }
+        if let Some(hide_forbidden) = args.hide_forbidden {
+            s.hide_forbidden = hide_forbidden;
+        }
+        if let Some(site_tag) = args.site_tag {
+            s.site_tag = site_tag;
+        }
     });
     Ok(())
 }
 
+// sets the S3-compatible bucket that the files are exported to periodically,
+// None removes the backup target.
+#[ic_cdk::update(guard = "is_controller")]
+fn admin_set_backup_target(args: Option<BackupTargetInput>) -> Result<(), String> {
+    let args = match args {
+        Some(args) => args,
+        None => {
+            store::state::with_mut(|s| s.backup_target = None);
+            return Ok(());
+        }
+    };
+
+    args.validate()?;
+    if args.policy.expires_at <= ic_cdk::api::time() / SECONDS {
+        Err("the signed policy expired".to_string())?;
+    }
+    store::state::with_mut(|s| match s.backup_target {
+        Some(ref mut t) => t.replace(args),
+        None => s.backup_target = Some(store::BackupTarget::new(args)),
+    });
+    Ok(())
+}
+
+// overrides the bucket's security headers for the files in the folder and its
+// subfolders, None removes the override.
+#[ic_cdk::update(guard = "is_controller")]
+fn admin_set_folder_security_headers(
+    folder: u32,
+    headers: Option<SecurityHeaders>,
+) -> Result<(), String> {
+    if let Some(ref headers) = headers {
+        headers.validate()?;
+    }
+    store::fs::set_folder_security_headers(folder, headers)
+}
+
+// marks the folder as unlisted, its children and the children of its subfolders
+// can not be listed without a token, even if the bucket is public.
+#[ic_cdk::update(guard = "is_controller")]
+fn admin_set_folder_unlisted(folder: u32, unlisted: bool) -> Result<(), String> {
+    store::fs::set_folder_unlisted(folder, unlisted)
+}
+
+// overrides the bucket's max_children and max_folder_depth for the folder, e.g. for a
+// dataset folder that holds many files. None removes the override.
+#[ic_cdk::update(guard = "is_controller")]
+fn admin_set_folder_limits(folder: u32, limits: Option<FolderLimits>) -> Result<(), String> {
+    store::fs::set_folder_limits(folder, limits)
+}
+
+// marks the folder as an upload-only inbox, the token holders can create files in it
+// and its subfolders, but only the managers and auditors can list or read them.
+#[ic_cdk::update(guard = "is_controller")]
+fn admin_set_folder_inbox(folder: u32, inbox: bool) -> Result<(), String> {
+    store::fs::set_folder_inbox(folder, inbox)
+}
+
+// enables the ICRC-7 facade with the collection metadata, None disables it.
+// the minted tokens are kept and show up again when the facade is enabled.
+#[ic_cdk::update(guard = "is_controller")]
+fn admin_set_nft_collection(collection: Option<NftCollection>) -> Result<(), String> {
+    if let Some(collection) = &collection {
+        collection.validate()?;
+    }
+    store::state::with_mut(|s| s.nft_collection = collection);
+    Ok(())
+}
+
+// sets the custom domains and the Internet Identity alternative origins, they are
+// served at /.well-known/ic-domains and /.well-known/ii-alternative-origins.
+#[ic_cdk::update(guard = "is_controller")]
+fn admin_set_well_known(args: WellKnown) -> Result<(), String> {
+    args.validate()?;
+    store::state::with_mut(|s| s.well_known = args);
+    Ok(())
+}
+
+// mints the file as an ICRC-7 token owned by the account, the token id is the file id.
+#[ic_cdk::update(guard = "is_controller")]
+fn admin_mint_nft(file: u32, owner: Account) -> Result<(), String> {
+    store::nft::mint(file, &owner, ic_cdk::api::time() / MILLISECONDS)
+}
+
+#[ic_cdk::update(guard = "is_controller")]
+fn admin_burn_nft(file: u32) -> Result<bool, String> {
+    Ok(store::nft::burn(file))
+}
+
+// removes the orphaned chunks, dangling hash index entries and folder references
+// that past bugs may have left in the stable memory. A call scans a batch of the entries,
+// the next call continues from the returned cursor.
+#[ic_cdk::update(guard = "is_controller")]
+fn admin_run_gc(start: Option<GcCursor>, max_scan: Option<u32>) -> Result<GcReport, String> {
+    let max_scan = max_scan.map_or(store::GC_MAX_SCAN, |n| n as usize);
+    Ok(store::fs::run_gc(start, max_scan))
+}
+
+// saves the heap state to stable memory as pre_upgrade does and decodes it as
+// post_upgrade does, so the cost of the next upgrade can be checked before the upgrade
+// proposal. The migrations of post_upgrade are bounded by MIGRATION_MAX_SCAN, the rest
+// runs on timers.
+#[ic_cdk::update(guard = "is_controller")]
+fn admin_prepare_upgrade() -> Result<UpgradeReport, String> {
+    let start = ic_cdk::api::instruction_counter();
+    let state = store::state::flush();
+    let save_instructions = ic_cdk::api::instruction_counter().saturating_sub(start);
+    let start = ic_cdk::api::instruction_counter();
+    store::state::decode(&state);
+    let load_instructions = ic_cdk::api::instruction_counter().saturating_sub(start);
+    Ok(UpgradeReport::new(
+        store::state::with(|s| s.schema_version),
+        state.len() as u64,
+        save_instructions,
+        load_instructions,
+    ))
+}
+
+// lists the background tasks with their schedules
+#[ic_cdk::query(guard = "is_controller")]
+fn admin_list_tasks() -> Result<Vec<TaskInfo>, String> {
+    Ok(job::list_tasks())
+}
+
+// overrides the interval of a background task in seconds, 0 disables it
+#[ic_cdk::update(guard = "is_controller")]
+fn admin_set_task_interval(name: String, interval_secs: u64) -> Result<(), String> {
+    job::set_task_interval(&name, interval_secs)
+}
+
+// runs a background task now without changing its schedule
+#[ic_cdk::update(guard = "is_controller")]
+fn admin_trigger_task(name: String) -> Result<(), String> {
+    job::trigger_task(&name)
+}
+
+// creates or replaces a named bundle of policies, the principals that are assigned
+// the role are granted the policies without an access token.
+#[ic_cdk::update(guard = "is_controller")]
+fn admin_create_role(name: String, policies: String) -> Result<(), String> {
+    store::role::create(name, policies)
+}
+
+// deletes the role and revokes it from its members.
+#[ic_cdk::update(guard = "is_controller")]
+fn admin_delete_role(name: String) -> Result<bool, String> {
+    Ok(store::role::delete(&name))
+}
+
+#[ic_cdk::update(guard = "is_controller")]
+fn admin_assign_role(principal: Principal, role: String) -> Result<(), String> {
+    store::role::assign(principal, role)
+}
+
+#[ic_cdk::update(guard = "is_controller")]
+fn admin_revoke_role(principal: Principal, role: String) -> Result<bool, String> {
+    Ok(store::role::revoke(&principal, &role))
+}
+
+#[ic_cdk::query(guard = "is_controller")]
+fn admin_list_roles() -> Result<Vec<RoleInfo>, String> {
+    Ok(store::role::list())
+}
+
 // ----- Use validate2_xxxxxx instead of validate_xxxxxx -----
 
 #[ic_cdk::update]
@@ -107,7 +333,7 @@ fn validate_admin_set_managers(args: BTreeSet<Principal>) -> Result<(), String>
 #[ic_cdk::update]
 fn validate2_admin_set_managers(args: BTreeSet<Principal>) -> Result<String, String> {
     validate_principals(&args)?;
-    Ok("ok".to_string())
+    Ok(render_proposal("admin_set_managers", &args))
 }
 
 #[ic_cdk::update]
@@ -119,7 +345,7 @@ fn validate_admin_set_auditors(args: BTreeSet<Principal>) -> Result<(), String>
 #[ic_cdk::update]
 fn validate2_admin_set_auditors(args: BTreeSet<Principal>) -> Result<String, String> {
     validate_principals(&args)?;
-    Ok("ok".to_string())
+    Ok(render_proposal("admin_set_auditors", &args))
 }
 
 #[ic_cdk::update]
@@ -130,29 +356,164 @@ fn validate_admin_update_bucket(args: UpdateBucketInput) -> Result<(), String> {
 #[ic_cdk::update]
 fn validate2_admin_update_bucket(args: UpdateBucketInput) -> Result<String, String> {
     args.validate()?;
-    Ok("ok".to_string())
+    Ok(render_proposal("admin_update_bucket", &args))
 }
 
 #[ic_cdk::update]
 fn validate_admin_add_managers(args: BTreeSet<Principal>) -> Result<String, String> {
     validate_principals(&args)?;
-    Ok("ok".to_string())
+    Ok(render_proposal("admin_add_managers", &args))
 }
 
 #[ic_cdk::update]
 fn validate_admin_remove_managers(args: BTreeSet<Principal>) -> Result<String, String> {
     validate_principals(&args)?;
-    Ok("ok".to_string())
+    Ok(render_proposal("admin_remove_managers", &args))
 }
 
 #[ic_cdk::update]
 fn validate_admin_add_auditors(args: BTreeSet<Principal>) -> Result<String, String> {
     validate_principals(&args)?;
-    Ok("ok".to_string())
+    Ok(render_proposal("admin_add_auditors", &args))
 }
 
 #[ic_cdk::update]
 fn validate_admin_remove_auditors(args: BTreeSet<Principal>) -> Result<String, String> {
     validate_principals(&args)?;
-    Ok("ok".to_string())
+    Ok(render_proposal("admin_remove_auditors", &args))
+}
+
+#[ic_cdk::update]
+fn validate2_admin_set_folder_security_headers(
+    folder: u32,
+    headers: Option<SecurityHeaders>,
+) -> Result<String, String> {
+    if let Some(ref headers) = headers {
+        headers.validate()?;
+    }
+    Ok(render_proposal(
+        "admin_set_folder_security_headers",
+        &(folder, headers),
+    ))
+}
+
+#[ic_cdk::update]
+fn validate2_admin_set_folder_unlisted(folder: u32, unlisted: bool) -> Result<String, String> {
+    Ok(render_proposal(
+        "admin_set_folder_unlisted",
+        &(folder, unlisted),
+    ))
+}
+
+#[ic_cdk::update]
+fn validate2_admin_set_folder_limits(
+    folder: u32,
+    limits: Option<FolderLimits>,
+) -> Result<String, String> {
+    if let Some(limits) = &limits {
+        limits.validate()?;
+    }
+    Ok(render_proposal(
+        "admin_set_folder_limits",
+        &(folder, limits),
+    ))
+}
+
+#[ic_cdk::update]
+fn validate2_admin_set_folder_inbox(folder: u32, inbox: bool) -> Result<String, String> {
+    Ok(render_proposal("admin_set_folder_inbox", &(folder, inbox)))
+}
+
+#[ic_cdk::update]
+fn validate2_admin_run_gc(
+    start: Option<GcCursor>,
+    max_scan: Option<u32>,
+) -> Result<String, String> {
+    Ok(render_proposal("admin_run_gc", &(start, max_scan)))
+}
+
+#[ic_cdk::update]
+fn validate2_admin_prepare_upgrade() -> Result<String, String> {
+    Ok(render_proposal("admin_prepare_upgrade", &()))
+}
+
+#[ic_cdk::update]
+fn validate2_admin_set_nft_collection(collection: Option<NftCollection>) -> Result<String, String> {
+    if let Some(collection) = &collection {
+        collection.validate()?;
+    }
+    Ok(render_proposal("admin_set_nft_collection", &collection))
+}
+
+#[ic_cdk::update]
+fn validate2_admin_set_well_known(args: WellKnown) -> Result<String, String> {
+    args.validate()?;
+    Ok(render_proposal("admin_set_well_known", &args))
+}
+
+#[ic_cdk::update]
+fn validate2_admin_mint_nft(file: u32, owner: Account) -> Result<String, String> {
+    if owner.owner == Principal::anonymous() {
+        Err("anonymous owner is not allowed".to_string())?;
+    }
+    Ok(render_proposal("admin_mint_nft", &(file, owner)))
+}
+
+#[ic_cdk::update]
+fn validate2_admin_burn_nft(file: u32) -> Result<String, String> {
+    Ok(render_proposal("admin_burn_nft", &file))
+}
+
+#[ic_cdk::update]
+fn validate2_admin_set_task_interval(name: String, interval_secs: u64) -> Result<String, String> {
+    job::validate_task_interval(&name, interval_secs)?;
+    Ok(render_proposal(
+        "admin_set_task_interval",
+        &(name, interval_secs),
+    ))
+}
+
+#[ic_cdk::update]
+fn validate2_admin_trigger_task(name: String) -> Result<String, String> {
+    job::validate_task_interval(&name, 0)?;
+    Ok(render_proposal("admin_trigger_task", &name))
+}
+
+#[ic_cdk::update]
+fn validate2_admin_create_role(name: String, policies: String) -> Result<String, String> {
+    validate_role(&name, &policies)?;
+    Ok(render_proposal("admin_create_role", &(name, policies)))
+}
+
+#[ic_cdk::update]
+fn validate2_admin_delete_role(name: String) -> Result<String, String> {
+    Ok(render_proposal("admin_delete_role", &name))
+}
+
+#[ic_cdk::update]
+fn validate2_admin_assign_role(principal: Principal, role: String) -> Result<String, String> {
+    if !store::state::with(|s| s.roles.contains_key(&role)) {
+        Err(format!("role not found: {}", role))?;
+    }
+    Ok(render_proposal("admin_assign_role", &(principal, role)))
+}
+
+#[ic_cdk::update]
+fn validate2_admin_revoke_role(principal: Principal, role: String) -> Result<String, String> {
+    Ok(render_proposal("admin_revoke_role", &(principal, role)))
+}
+
+#[ic_cdk::update]
+fn validate2_admin_set_backup_target(args: Option<BackupTargetInput>) -> Result<String, String> {
+    // the policy is signed off the canister, it can be published in a proposal
+    if let Some(args) = &args {
+        args.validate()?;
+    }
+    Ok(render_proposal("admin_set_backup_target", &args))
+}
+
+// renders the args of a proposal, SNS shows the result of the validator as the
+// payload of the proposal.
+fn render_proposal(method: &str, args: &impl std::fmt::Debug) -> String {
+    format!("{}: {:#?}", method, args)
 }
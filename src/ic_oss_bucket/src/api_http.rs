@@ -1,20 +1,24 @@
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
-use candid::{define_function, CandidType};
+use candid::{define_function, CandidType, Principal};
 use hyperx::header::{Charset, ContentDisposition, DispositionParam, DispositionType};
 use hyperx::header::{ContentRangeSpec, Header, IfRange, Range, Raw};
+use hyperx::header::{HttpDate, IfModifiedSince, IfNoneMatch};
 use ic_http_certification::{HeaderField, HttpRequest};
 use ic_oss_types::{
-    file::{UrlFileParam, CHUNK_SIZE, MAX_FILE_SIZE_PER_CALL},
+    bucket::{url_origin, SecurityHeaders, FILE_PROOF_HEADER},
+    cose::sha256,
+    file::{custom_cache_control, SiteManifest, UrlFileParam, MAX_FILE_SIZE_PER_CALL},
     to_cbor_bytes,
 };
 use ic_stable_structures::Storable;
 use once_cell::sync::Lazy;
 use serde::Deserialize;
-use serde_bytes::ByteBuf;
+use serde_bytes::{ByteArray, ByteBuf};
 use std::path::Path;
 use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::{permission, store, SECONDS};
+use crate::{permission, store, MILLISECONDS, SECONDS};
 
 #[derive(CandidType, Deserialize, Clone, Default)]
 pub struct HttpStreamingResponse {
@@ -31,6 +35,11 @@ pub struct StreamingCallbackToken {
     pub chunk_index: u32,
     pub chunks: u32,
     pub token: Option<ByteBuf>,
+    pub password: Option<String>,
+    pub share: Option<String>,
+    // the stream ticket of a counted share link download and its expiration
+    pub ticket: Option<ByteBuf>,
+    pub expires_at: Option<u64>,
 }
 
 impl StreamingCallbackToken {
@@ -43,6 +52,10 @@ impl StreamingCallbackToken {
                 chunk_index: self.chunk_index + 1,
                 chunks: self.chunks,
                 token: self.token,
+                password: self.password,
+                share: self.share,
+                ticket: self.ticket,
+                expires_at: self.expires_at,
             })
         }
     }
@@ -82,21 +95,102 @@ static IC_CERTIFICATE_EXPRESSION_HEADER: &str = "ic-certificateexpression";
 // https://mmrxu-fqaaa-aaaap-ahhna-cai.icp0.io/f/1
 // http://mmrxu-fqaaa-aaaap-ahhna-cai.localhost:4943/f/1 // download file by id 1
 // http://mmrxu-fqaaa-aaaap-ahhna-cai.localhost:4943/h/8546ffa4296a6960e9e64e95de178d40c231a0cd358a65477bc56a105dda1c1d //download file by hash 854...
+// http://mmrxu-fqaaa-aaaap-ahhna-cai.localhost:4943/t/latest // download the file that the tag "latest" points to
+// http://mmrxu-fqaaa-aaaap-ahhna-cai.localhost:4943/f/1?pwd=xxx // download the password protected file by id 1
+// http://mmrxu-fqaaa-aaaap-ahhna-cai.localhost:4943/s/J7sKbXc3Yf0nWz2q8Hd1vA // download the file by a share link
 #[ic_cdk::query(hidden = true)]
 fn http_request(request: HttpRequest) -> HttpStreamingResponse {
-    let witness = store::state::http_tree_with(|t| {
-        t.witness(&store::state::DEFAULT_CERT_ENTRY, request.url())
-            .expect("get witness failed")
+    // the downloads of share links are counted by http_request_update
+    if url_path(request.url()).starts_with("/s/") {
+        return HttpStreamingResponse {
+            status_code: 200,
+            upgrade: Some(true),
+            ..Default::default()
+        };
+    }
+
+    let sample_rate = store::state::with(|s| s.access_log_sample_rate);
+    if sampled(request.url(), ic_cdk::api::time(), sample_rate) {
+        // the sampled request is served by http_request_update, so it can be logged
+        return HttpStreamingResponse {
+            status_code: 200,
+            upgrade: Some(true),
+            ..Default::default()
+        };
+    }
+    http_response(&request)
+}
+
+#[ic_cdk::update(hidden = true)]
+fn http_request_update(request: HttpRequest) -> HttpStreamingResponse {
+    let mut res = http_response(&request);
+    let now_ms = ic_cdk::api::time() / MILLISECONDS;
+    // the limit was checked in http_response, the update call makes it atomic
+    if let Some(code) = UrlFileParam::from_url(request.url())
+        .ok()
+        .and_then(|param| param.share)
+    {
+        if request.method() != "HEAD" && counted_download(&res) {
+            if store::share::record_download(&code, now_ms) {
+                // the rest of the counted download is streamed with the ticket
+                if let Some(StreamingStrategy::Callback { ref mut token, .. }) =
+                    res.streaming_strategy
+                {
+                    let ticket = store::share::issue_stream_ticket(&code, now_ms);
+                    token.expires_at = ticket.map(|(expires_at, _)| expires_at);
+                    token.ticket = ticket.map(|(_, ticket)| ByteBuf::from(ticket.to_vec()));
+                }
+            } else {
+                res = HttpStreamingResponse {
+                    status_code: 410,
+                    headers: vec![("content-type".to_string(), "text/plain".to_string())],
+                    body: ByteBuf::from("share download limit reached".as_bytes()),
+                    ..Default::default()
+                };
+            }
+        }
+    }
+
+    let bytes = if request.method() == "HEAD" {
+        0
+    } else {
+        response_bytes(&res)
+    };
+    let sample_rate = store::state::with(|s| s.access_log_sample_rate);
+    if sample_rate > 0 {
+        store::access_log::append(store::AccessLogEntry {
+            path: url_path(request.url()).to_string(),
+            status: res.status_code,
+            bytes,
+            subject: token_subject(request.url(), now_ms / 1000),
+            timestamp: now_ms,
+        });
+    }
+    // exact counters of the requests served by update calls, the queries can not be recorded
+    store::metrics::record(now_ms, |m| {
+        m.requests += 1;
+        m.bytes_out += bytes;
     });
-    let certified_data = ic_cdk::api::data_certificate().expect("no data certificate available");
+    res
+}
+
+fn http_response(request: &HttpRequest) -> HttpStreamingResponse {
     let mut headers = vec![
         ("content-type".to_string(), "text/plain".to_string()),
         ("x-content-type-options".to_string(), "nosniff".to_string()),
-        (
+    ];
+    // the responses of update calls go through consensus and are not certified
+    if let Some(certified_data) = ic_cdk::api::data_certificate() {
+        let witness = store::state::http_tree_with(|t| {
+            t.witness(&store::state::DEFAULT_CERT_ENTRY, request.url())
+                .expect("get witness failed")
+        });
+        let witness = store::state::http_witness(witness);
+        headers.push((
             IC_CERTIFICATE_EXPRESSION_HEADER.to_string(),
             store::state::DEFAULT_CEL_EXPR.clone(),
-        ),
-        (
+        ));
+        headers.push((
             IC_CERTIFICATE_HEADER.to_string(),
             format!(
                 "certificate=:{}:, tree=:{}:, expr_path=:{}:, version=2",
@@ -106,195 +200,331 @@ fn http_request(request: HttpRequest) -> HttpStreamingResponse {
                     &store::state::DEFAULT_EXPR_PATH.to_expr_path()
                 ))
             ),
-        ),
-    ];
+        ));
+    }
+    set_security_headers(
+        &mut headers,
+        &store::state::with(|s| s.security_headers.clone()),
+    );
 
-    match UrlFileParam::from_url(request.url()) {
-        Err(err) => HttpStreamingResponse {
-            status_code: 400,
+    if let Some(name) = url_path(request.url()).strip_prefix("/.well-known/") {
+        return well_known_response(
             headers,
-            body: ByteBuf::from(err.as_bytes()),
-            ..Default::default()
+            store::state::with(|s| s.well_known.file(name)),
+            request.method() == "HEAD",
+        );
+    }
+
+    match UrlFileParam::from_url(request.url()) {
+        Err(err) => match site_asset(request.url()) {
+            // the paths that are not reserved by the file urls are served by the site
+            Some((id, cache_control, status_code)) => {
+                let param = UrlFileParam {
+                    file: id,
+                    hash: None,
+                    tag: None,
+                    share: None,
+                    token: None,
+                    password: None,
+                    name: None,
+                    inline: true,
+                };
+                file_response(request, headers, id, param, &cache_control, status_code)
+            }
+            None => HttpStreamingResponse {
+                status_code: 400,
+                headers,
+                body: ByteBuf::from(err.as_bytes()),
+                ..Default::default()
+            },
         },
         Ok(param) => {
             let id = if let Some(hash) = param.hash {
                 store::fs::get_file_id(&hash).unwrap_or_default()
+            } else if let Some(ref tag) = param.tag {
+                store::fs::get_tag(tag).unwrap_or_default()
+            } else if let Some(ref code) = param.share {
+                store::share::get(code)
+                    .map(|share| share.file)
+                    .unwrap_or_default()
             } else {
                 param.file
             };
+            // a tag can be moved to another file, so the response should be revalidated
+            let cache_control = if param.tag.is_some() {
+                "no-cache"
+            } else if param.share.is_some() {
+                // the downloads of share links should reach the canister to be counted
+                "private, no-store"
+            } else {
+                "max-age=2592000, public"
+            };
+            file_response(request, headers, id, param, cache_control, 200)
+        }
+    }
+}
+
+// resolves the url to a file of the static site that the site tag points to, the
+// assets without a cache control are revalidated because a release can replace them.
+fn site_asset(url: &str) -> Option<(u32, String, u16)> {
+    let tag = store::state::with(|s| s.site_tag.clone());
+    if tag.is_empty() {
+        return None;
+    }
+    let manifest = store::fs::get_tag(&tag)?;
+    let manifest = store::fs::get_full_chunks(manifest).ok()?;
+    let manifest = SiteManifest::from_cbor(&manifest).ok()?;
+    let (asset, status_code) = manifest.resolve(url)?;
+    let cache_control = if asset.cache_control.is_empty() {
+        "no-cache".to_string()
+    } else {
+        asset.cache_control.clone()
+    };
+    Some((asset.id, cache_control, status_code))
+}
+
+fn file_response(
+    request: &HttpRequest,
+    mut headers: Vec<HeaderField>,
+    id: u32,
+    param: UrlFileParam,
+    cache_control: &str,
+    status_code: u16,
+) -> HttpStreamingResponse {
+    match store::fs::get_file(id) {
+        None => HttpStreamingResponse {
+            status_code: 404,
+            headers,
+            body: ByteBuf::from("file not found".as_bytes()),
+            ..Default::default()
+        },
+        Some(file) => {
+            set_security_headers(&mut headers, &store::fs::get_security_headers(file.parent));
+            // the responses of password protected files should not be shared by caches
+            let cache_control = if file.password_hash.is_some() {
+                "private, no-store".to_string()
+            } else if param.tag.is_none() && param.share.is_none() {
+                // the file can override the default caching of the id and hash urls
+                custom_cache_control(file.custom.as_ref())
+                    .unwrap_or(cache_control)
+                    .to_string()
+            } else {
+                cache_control.to_string()
+            };
+            let password = param
+                .password
+                .clone()
+                .or_else(|| basic_auth_password(request.headers()));
+            let checked = match param.share {
+                Some(ref code) => check_share_read(
+                    code,
+                    id,
+                    &file,
+                    password.as_deref(),
+                    true,
+                    ic_cdk::api::time() / MILLISECONDS,
+                ),
+                None => check_http_read(
+                    ic_cdk::caller(),
+                    &ic_cdk::id(),
+                    id,
+                    &file,
+                    param.token.clone(),
+                    password.as_deref(),
+                    request_origin(request.headers()),
+                    ic_cdk::api::time() / SECONDS,
+                ),
+            };
+            if let Err((status_code, err)) = checked {
+                if status_code == 401 && (file.password_hash.is_some() || param.share.is_some()) {
+                    headers.push((
+                        "www-authenticate".to_string(),
+                        "Basic realm=\"ic-oss\", charset=\"UTF-8\"".to_string(),
+                    ));
+                }
+                return HttpStreamingResponse {
+                    status_code,
+                    headers,
+                    body: ByteBuf::from(err.as_bytes()),
+                    ..Default::default()
+                };
+            }
 
-            match store::fs::get_file(id) {
-                None => HttpStreamingResponse {
-                    status_code: 404,
+            if file.size != file.filled {
+                return HttpStreamingResponse {
+                    status_code: 422,
                     headers,
-                    body: ByteBuf::from("file not found".as_bytes()),
+                    body: ByteBuf::from("file not fully uploaded".as_bytes()),
                     ..Default::default()
-                },
-                Some(file) => {
-                    if !file.read_by_hash(&param.token) {
-                        let canister = ic_cdk::id();
-                        let ctx = match store::state::with(|s| {
-                            s.read_permission(
-                                ic_cdk::caller(),
-                                &canister,
-                                param.token,
-                                ic_cdk::api::time() / SECONDS,
-                            )
-                        }) {
-                            Ok(ctx) => ctx,
-                            Err((status_code, err)) => {
-                                return HttpStreamingResponse {
-                                    status_code,
-                                    headers,
-                                    body: ByteBuf::from(err.as_bytes()),
-                                    ..Default::default()
-                                };
-                            }
-                        };
+                };
+            }
 
-                        if file.status < 0 && ctx.role < store::Role::Auditor {
-                            return HttpStreamingResponse {
-                                status_code: 403,
-                                headers,
-                                body: ByteBuf::from("file archived".as_bytes()),
-                                ..Default::default()
-                            };
-                        }
-
-                        if !permission::check_file_read(&ctx.ps, &canister, id, file.parent) {
-                            return HttpStreamingResponse {
-                                status_code: 403,
-                                headers,
-                                body: ByteBuf::from("permission denied".as_bytes()),
-                                ..Default::default()
-                            };
-                        }
-                    }
+            let etag = file
+                .hash
+                .as_ref()
+                .map(|hash| BASE64.encode(hash.as_ref()))
+                .unwrap_or_default();
 
-                    if file.size != file.filled {
-                        return HttpStreamingResponse {
-                            status_code: 422,
-                            headers,
-                            body: ByteBuf::from("file not fully uploaded".as_bytes()),
-                            ..Default::default()
-                        };
-                    }
+            headers.push(("accept-ranges".to_string(), "bytes".to_string()));
+            if !etag.is_empty() {
+                headers.push(("etag".to_string(), format!("\"{}\"", etag)));
+            }
+            // marketplaces can verify the previews of the files exposed as NFTs,
+            // the proof is certified only in query calls
+            if ic_cdk::api::data_certificate().is_some() && store::nft::is_exposed(id) {
+                headers.push((
+                    FILE_PROOF_HEADER.to_string(),
+                    store::state::certified_file_proof(id, &file).to_header_value(),
+                ));
+            }
+            headers.push((
+                "last-modified".to_string(),
+                HttpDate::from(modified_time(file.updated_at)).to_string(),
+            ));
+            headers[0].1 = if file.content_type.is_empty() {
+                OCTET_STREAM.to_string()
+            } else {
+                file.content_type.clone()
+            };
 
-                    let etag = file
-                        .hash
-                        .as_ref()
-                        .map(|hash| BASE64.encode(hash.as_ref()))
-                        .unwrap_or_default();
+            if not_modified(request.headers(), &etag, file.updated_at) {
+                headers.push(("cache-control".to_string(), cache_control.to_string()));
+                return HttpStreamingResponse {
+                    status_code: 304,
+                    headers,
+                    body: ByteBuf::new(),
+                    ..Default::default()
+                };
+            }
 
-                    headers.push(("accept-ranges".to_string(), "bytes".to_string()));
-                    if !etag.is_empty() {
-                        headers.push(("etag".to_string(), format!("\"{}\"", etag)));
-                    }
-                    headers[0].1 = if file.content_type.is_empty() {
-                        OCTET_STREAM.to_string()
-                    } else {
-                        file.content_type.clone()
-                    };
-
-                    if request.method() == "HEAD" {
-                        headers.push(("content-length".to_string(), file.size.to_string()));
-                        headers.push((
-                            "cache-control".to_string(),
-                            "max-age=2592000, public".to_string(),
-                        ));
-
-                        let filename = if param.inline {
-                            ""
-                        } else if let Some(ref name) = param.name {
-                            name
-                        } else {
-                            &file.name
-                        };
+            if request.method() == "HEAD" {
+                headers.push(("content-length".to_string(), file.size.to_string()));
+                headers.push(("cache-control".to_string(), cache_control.to_string()));
 
-                        headers.push((
-                            "content-disposition".to_string(),
-                            content_disposition(filename),
-                        ));
+                let filename = if param.inline {
+                    ""
+                } else if let Some(ref name) = param.name {
+                    name
+                } else {
+                    &file.name
+                };
 
+                headers.push((
+                    "content-disposition".to_string(),
+                    content_disposition(filename),
+                ));
+
+                return HttpStreamingResponse {
+                    status_code,
+                    headers,
+                    body: ByteBuf::new(),
+                    ..Default::default()
+                };
+            }
+
+            if let Some(range_req) = detect_range(request.headers(), file.size, &etag) {
+                match range_req {
+                    Err(err) => {
                         return HttpStreamingResponse {
-                            status_code: 200,
+                            status_code: 416,
                             headers,
-                            body: ByteBuf::new(),
+                            body: ByteBuf::from(err.to_bytes()),
                             ..Default::default()
                         };
                     }
-
-                    if let Some(range_req) = detect_range(request.headers(), file.size, &etag) {
-                        match range_req {
-                            Err(err) => {
-                                return HttpStreamingResponse {
-                                    status_code: 416,
-                                    headers,
-                                    body: ByteBuf::from(err.to_bytes()),
-                                    ..Default::default()
-                                };
-                            }
-                            Ok(range) => {
-                                return range_response(headers, id, file, range);
-                            }
-                        }
+                    Ok(range) => {
+                        return range_response(headers, id, file, range);
                     }
+                }
+            }
 
-                    let filename = if param.inline {
-                        ""
-                    } else if let Some(ref name) = param.name {
-                        name
-                    } else {
-                        &file.name
-                    };
+            let filename = if param.inline {
+                ""
+            } else if let Some(ref name) = param.name {
+                name
+            } else {
+                &file.name
+            };
 
-                    headers.push((
-                        "content-disposition".to_string(),
-                        content_disposition(filename),
-                    ));
+            headers.push((
+                "content-disposition".to_string(),
+                content_disposition(filename),
+            ));
 
-                    // return all chunks for small file
-                    let (chunk_index, body) = if file.size <= MAX_FILE_SIZE_PER_CALL {
-                        (
-                            file.chunks.saturating_sub(1),
-                            store::fs::get_full_chunks(id)
-                                .map(ByteBuf::from)
-                                .unwrap_or_default(),
-                        )
-                    } else {
-                        // return first chunk for large file
-                        (
-                            0,
-                            store::fs::get_chunk(id, 0)
-                                .map(|chunk| chunk.1)
-                                .unwrap_or_default(),
-                        )
-                    };
-
-                    let streaming_strategy = create_strategy(StreamingCallbackToken {
-                        id,
-                        chunk_index,
-                        chunks: file.chunks,
-                        token: None, // TODO: access token for callback
-                    });
-
-                    // small file
-                    if streaming_strategy.is_none() {
-                        headers.push(("content-length".to_string(), body.len().to_string()));
-                        headers.push((
-                            "cache-control".to_string(),
-                            "max-age=2592000, public".to_string(),
-                        ));
-                    }
+            // return all chunks for small file
+            let (chunk_index, body) = if file.size <= MAX_FILE_SIZE_PER_CALL {
+                (
+                    file.chunks.saturating_sub(1),
+                    store::fs::get_full_chunks(id)
+                        .map(ByteBuf::from)
+                        .unwrap_or_default(),
+                )
+            } else {
+                // return first chunk for large file
+                (
+                    0,
+                    store::fs::get_chunk(id, 0)
+                        .map(|chunk| chunk.1)
+                        .unwrap_or_default(),
+                )
+            };
 
-                    HttpStreamingResponse {
-                        status_code: 200,
-                        headers,
-                        body,
-                        streaming_strategy,
-                        upgrade: None,
-                    }
-                }
+            let streaming_strategy = create_strategy(StreamingCallbackToken {
+                id,
+                chunk_index,
+                chunks: file.chunks,
+                token: param.token,
+                password,
+                share: param.share,
+                ticket: None,
+                expires_at: None,
+            });
+
+            // small file
+            if streaming_strategy.is_none() {
+                headers.push(("content-length".to_string(), body.len().to_string()));
+                headers.push(("cache-control".to_string(), cache_control.to_string()));
+            }
+
+            HttpStreamingResponse {
+                status_code,
+                headers,
+                body,
+                streaming_strategy,
+                upgrade: None,
+            }
+        }
+    }
+}
+
+// serves the well-known file with the headers that the boundary nodes and Internet
+// Identity expect, Internet Identity fetches the alternative origins with CORS.
+fn well_known_response(
+    mut headers: Vec<HeaderField>,
+    file: Option<(&str, String)>,
+    head: bool,
+) -> HttpStreamingResponse {
+    match file {
+        None => HttpStreamingResponse {
+            status_code: 404,
+            headers,
+            body: ByteBuf::from("file not found".as_bytes()),
+            ..Default::default()
+        },
+        Some((content_type, content)) => {
+            headers[0].1 = content_type.to_string();
+            headers.push(("access-control-allow-origin".to_string(), "*".to_string()));
+            headers.push(("cache-control".to_string(), "max-age=300".to_string()));
+            headers.push(("content-length".to_string(), content.len().to_string()));
+            HttpStreamingResponse {
+                status_code: 200,
+                headers,
+                body: if head {
+                    ByteBuf::new()
+                } else {
+                    ByteBuf::from(content.into_bytes())
+                },
+                ..Default::default()
             }
         }
     }
@@ -302,6 +532,36 @@ fn http_request(request: HttpRequest) -> HttpStreamingResponse {
 
 #[ic_cdk::query(hidden = true)]
 fn http_request_streaming_callback(token: StreamingCallbackToken) -> StreamingCallbackHttpResponse {
+    // the callback can be called directly, so the access should be checked again
+    let file = store::fs::get_file(token.id).unwrap_or_else(|| ic_cdk::trap("file not found"));
+    let now_ms = ic_cdk::api::time() / MILLISECONDS;
+    let checked = match token.share {
+        // the download was counted by the first response that issued the ticket
+        Some(ref code) => check_stream_ticket(code, &token, now_ms).and_then(|_| {
+            check_share_read(
+                code,
+                token.id,
+                &file,
+                token.password.as_deref(),
+                false,
+                now_ms,
+            )
+        }),
+        None => check_http_read(
+            ic_cdk::caller(),
+            &ic_cdk::id(),
+            token.id,
+            &file,
+            token.token.clone(),
+            token.password.as_deref(),
+            None,
+            ic_cdk::api::time() / SECONDS,
+        ),
+    };
+    if let Err((_, err)) = checked {
+        ic_cdk::trap(&err);
+    }
+
     match store::fs::get_chunk(token.id, token.chunk_index) {
         None => ic_cdk::trap("chunk not found"),
         Some(chunk) => StreamingCallbackHttpResponse {
@@ -311,6 +571,232 @@ fn http_request_streaming_callback(token: StreamingCallbackToken) -> StreamingCa
     }
 }
 
+// checks the read access of a file over HTTP. the access token is verified
+// against the folder scoped policies by resolving the file's ancestor folders.
+// a password protected file can be read with its password instead of a token.
+#[allow(clippy::too_many_arguments)]
+fn check_http_read(
+    caller: Principal,
+    canister: &Principal,
+    id: u32,
+    file: &store::FileMetadata,
+    token: Option<ByteBuf>,
+    password: Option<&str>,
+    origin: Option<String>,
+    now_sec: u64,
+) -> Result<(), (u16, String)> {
+    if file.read_by_hash(&token) {
+        return Ok(());
+    }
+
+    if file.password_hash.is_some() && token.is_none() {
+        if store::state::with(|s| s.status) < 0 {
+            Err((403, "bucket is archived".to_string()))?;
+        }
+        if file.status < 0 {
+            Err((403, "file archived".to_string()))?;
+        }
+        return match password {
+            Some(password) if file.verify_password(password) => Ok(()),
+            Some(_) => Err((401, "invalid password".to_string())),
+            None => Err((401, "password required".to_string())),
+        };
+    }
+
+    store::state::with(|s| {
+        let ctx = s
+            .http_gateway_check(&caller, origin, canister, &token, now_sec)
+            .and_then(|_| s.file_read_permission(caller, canister, token, now_sec, file));
+        let ctx = ctx.map_err(|err| s.file_read_error(err))?;
+
+        if file.status < 0 && ctx.role < store::Role::Auditor {
+            Err(s.file_read_error((403, "file archived".to_string())))?;
+        }
+
+        if !permission::check_file_read(&ctx.ps, canister, id, file.parent) {
+            Err(s.file_read_error((403, "permission denied".to_string())))?;
+        }
+        Ok(())
+    })
+}
+
+// checks the read access of a file by a share link, the share link is the credential
+// so the access token and the visibility of the bucket don't apply.
+fn check_share_read(
+    code: &str,
+    id: u32,
+    file: &store::FileMetadata,
+    password: Option<&str>,
+    check_limit: bool,
+    now_ms: u64,
+) -> Result<(), (u16, String)> {
+    let share = match store::share::get(code) {
+        Some(share) if share.file == id => share,
+        _ => Err((404, "share not found".to_string()))?,
+    };
+    if store::state::with(|s| s.status) < 0 {
+        Err((403, "bucket is archived".to_string()))?;
+    }
+    if file.status < 0 {
+        Err((403, "file archived".to_string()))?;
+    }
+    if share.is_expired(now_ms) {
+        Err((410, "share expired".to_string()))?;
+    }
+    if check_limit && share.is_exhausted() {
+        Err((410, "share download limit reached".to_string()))?;
+    }
+    if share.password_hash.is_some() {
+        match password {
+            Some(password) if share.verify_password(password) => {}
+            Some(_) => Err((401, "invalid password".to_string()))?,
+            None => Err((401, "password required".to_string()))?,
+        }
+    }
+    Ok(())
+}
+
+// the streaming callbacks of a share link serve only the downloads that were counted,
+// the links created before the tickets can not stream large files.
+fn check_stream_ticket(
+    code: &str,
+    token: &StreamingCallbackToken,
+    now_ms: u64,
+) -> Result<(), (u16, String)> {
+    match (token.expires_at, token.ticket.as_ref()) {
+        (Some(expires_at), Some(ticket))
+            if store::share::verify_stream_ticket(code, expires_at, ticket, now_ms) =>
+        {
+            Ok(())
+        }
+        _ => Err((403, "invalid stream ticket".to_string())),
+    }
+}
+
+// a download is counted when the full content or the first range is served
+fn counted_download(res: &HttpStreamingResponse) -> bool {
+    match res.status_code {
+        200 => true,
+        206 => res
+            .headers
+            .iter()
+            .any(|(name, value)| name == "content-range" && value.starts_with("bytes 0-")),
+        _ => false,
+    }
+}
+
+// returns the password of the `Authorization: Basic` header, the user name is ignored
+fn basic_auth_password(headers: &[(String, String)]) -> Option<String> {
+    headers.iter().find_map(|(name, value)| {
+        if name.to_lowercase() != "authorization" {
+            return None;
+        }
+        let (scheme, credentials) = value.trim().split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return None;
+        }
+        let data = BASE64.decode(credentials.trim()).ok()?;
+        let credentials = String::from_utf8(data).ok()?;
+        let (_, password) = credentials.split_once(':')?;
+        Some(password.to_string())
+    })
+}
+
+// the `Origin` header takes precedence over the `Referer` header
+fn request_origin(headers: &[(String, String)]) -> Option<String> {
+    ["origin", "referer"].iter().find_map(|key| {
+        headers.iter().find_map(|(name, value)| {
+            if name.to_lowercase() == *key {
+                url_origin(value)
+            } else {
+                None
+            }
+        })
+    })
+}
+
+// samples the request deterministically by its url and the time, the rate is a percentage.
+fn sampled(url: &str, now_ns: u64, rate: u8) -> bool {
+    if rate == 0 {
+        return false;
+    }
+    if rate >= 100 {
+        return true;
+    }
+    let hash = sha256(&[url.as_bytes(), &now_ns.to_be_bytes()].concat());
+    u16::from_be_bytes([hash[0], hash[1]]) % 100 < rate as u16
+}
+
+// the query string is dropped, it may contain the access token
+fn url_path(url: &str) -> &str {
+    url.split(['?', '#']).next().unwrap_or_default()
+}
+
+// the size of the response body, including the chunks returned by the streaming callbacks
+fn response_bytes(res: &HttpStreamingResponse) -> u64 {
+    match res.streaming_strategy {
+        Some(StreamingStrategy::Callback { ref token, .. }) => store::fs::get_file(token.id)
+            .map(|file| file.size)
+            .unwrap_or_default(),
+        None => res.body.len() as u64,
+    }
+}
+
+// returns the hash of the access token's subject, the token is not logged
+fn token_subject(url: &str, now_sec: u64) -> Option<ByteArray<32>> {
+    let token = UrlFileParam::from_url(url).ok()?.token?;
+    let subject = store::state::with(|s| s.token_subject(&token, &ic_cdk::id(), now_sec))?;
+    Some(sha256(subject.as_slice()).into())
+}
+
+// sets the configured security headers, an empty value removes the header.
+fn set_security_headers(headers: &mut Vec<HeaderField>, security_headers: &SecurityHeaders) {
+    for (name, value) in security_headers.iter() {
+        if let Some(value) = value {
+            headers.retain(|(n, _)| n != name);
+            if !value.is_empty() {
+                headers.push((name.to_string(), value.clone()));
+            }
+        }
+    }
+}
+
+// the HTTP dates have a resolution of seconds
+fn modified_time(updated_at_ms: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(updated_at_ms / 1000)
+}
+
+// returns true if the client's cached copy is still valid, If-Modified-Since is
+// ignored when If-None-Match is present (RFC 9110 section 13.2.2).
+fn not_modified(headers: &[(String, String)], etag: &str, updated_at_ms: u64) -> bool {
+    let mut if_none_match = None;
+    let mut if_modified_since = None;
+    for (name, value) in headers {
+        match name.to_lowercase().as_str() {
+            "if-none-match" => {
+                if_none_match = Some(IfNoneMatch::parse_header(&Raw::from(value.as_str())))
+            }
+            "if-modified-since" => {
+                if_modified_since = IfModifiedSince::parse_header(&Raw::from(value.as_str())).ok()
+            }
+            _ => {}
+        }
+    }
+
+    match if_none_match {
+        Some(Ok(IfNoneMatch::Any)) => true,
+        // the weak comparison is used for If-None-Match
+        Some(Ok(IfNoneMatch::Items(tags))) => {
+            !etag.is_empty() && tags.iter().any(|tag| tag.tag() == etag)
+        }
+        Some(Err(_)) => false,
+        None => match if_modified_since {
+            Some(IfModifiedSince(since)) => modified_time(updated_at_ms) <= SystemTime::from(since),
+            None => false,
+        },
+    }
+}
+
 fn detect_range(
     headers: &[(String, String)],
     full_length: u64,
@@ -378,34 +864,17 @@ fn range_response(
     metadata: store::FileMetadata,
     (start, end): (u64, u64),
 ) -> HttpStreamingResponse {
-    let chunk_index = start / CHUNK_SIZE as u64;
-    let chunk_offset = (start % CHUNK_SIZE as u64) as usize;
-    let chunk_end = end / CHUNK_SIZE as u64;
-    let end_offset = (end % CHUNK_SIZE as u64) as usize;
-
-    let mut body = ByteBuf::with_capacity((end + 1 - start) as usize);
-    for i in chunk_index..=chunk_end {
-        let chunk = store::fs::get_chunk(id, i as u32)
-            .map(|chunk| chunk.1)
-            .unwrap_or_default();
-        let start = if i == chunk_index { chunk_offset } else { 0 };
-        let end = if i == chunk_end {
-            end_offset
-        } else {
-            CHUNK_SIZE as usize - 1
-        };
-
-        if end >= chunk.len() {
+    let body = match store::fs::read_range(id, start, end + 1 - start) {
+        Ok(body) => ByteBuf::from(body),
+        Err(err) => {
             return HttpStreamingResponse {
                 status_code: 416,
                 headers,
-                body: ByteBuf::from(format!("invalid range at chunk {i}").to_bytes()),
+                body: ByteBuf::from(err.to_bytes()),
                 ..Default::default()
             };
         }
-
-        body.extend_from_slice(&chunk[start..=end]);
-    }
+    };
 
     headers[0].1 = if metadata.content_type.is_empty() {
         OCTET_STREAM.to_string()
@@ -463,6 +932,7 @@ fn content_disposition(filename: &str) -> String {
 #[cfg(test)]
 mod test {
     use super::*;
+    use ic_oss_types::cose::Token;
 
     #[test]
     fn test_content_disposition() {
@@ -480,4 +950,518 @@ mod test {
             "attachment; filename=\"test.txt\"",
         );
     }
+
+    #[test]
+    fn test_sampled() {
+        assert!(!sampled("/f/1", 0, 0));
+        assert!(sampled("/f/1", 0, 100));
+        let n = (0..1000u64)
+            .filter(|i| sampled("/f/1", i * 1_000_000_000, 30))
+            .count();
+        assert!(n > 200 && n < 400, "sampled {} of 1000", n);
+    }
+
+    #[test]
+    fn test_url_path() {
+        assert_eq!(url_path("/f/1"), "/f/1");
+        assert_eq!(url_path("/f/1?token=abc&inline"), "/f/1");
+        assert_eq!(url_path("/t/latest#top"), "/t/latest");
+        assert_eq!(url_path(""), "");
+    }
+
+    #[test]
+    fn test_well_known_response() {
+        let headers = vec![("content-type".to_string(), "text/plain".to_string())];
+        let res = well_known_response(headers.clone(), None, false);
+        assert_eq!(res.status_code, 404);
+
+        let file = Some(("application/json", "{}".to_string()));
+        let res = well_known_response(headers.clone(), file.clone(), false);
+        assert_eq!(res.status_code, 200);
+        assert_eq!(res.body.as_slice(), b"{}");
+        assert!(res
+            .headers
+            .contains(&("content-type".to_string(), "application/json".to_string())));
+        assert!(res
+            .headers
+            .contains(&("access-control-allow-origin".to_string(), "*".to_string())));
+        assert!(res
+            .headers
+            .contains(&("content-length".to_string(), "2".to_string())));
+
+        let res = well_known_response(headers, file, true);
+        assert_eq!(res.status_code, 200);
+        assert!(res.body.is_empty());
+    }
+
+    #[test]
+    fn test_set_security_headers() {
+        let mut headers = vec![
+            ("content-type".to_string(), "text/plain".to_string()),
+            ("x-content-type-options".to_string(), "nosniff".to_string()),
+        ];
+        set_security_headers(&mut headers, &SecurityHeaders::default());
+        assert_eq!(headers.len(), 2);
+
+        set_security_headers(
+            &mut headers,
+            &SecurityHeaders {
+                content_security_policy: Some("default-src 'self'".to_string()),
+                content_type_options: Some("".to_string()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            headers,
+            vec![
+                ("content-type".to_string(), "text/plain".to_string()),
+                (
+                    "content-security-policy".to_string(),
+                    "default-src 'self'".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_not_modified() {
+        let etag = "AQID";
+        let updated_at = 1_700_000_000_500;
+        let date = HttpDate::from(modified_time(updated_at)).to_string();
+        let header = |name: &str, value: &str| vec![(name.to_string(), value.to_string())];
+
+        assert!(!not_modified(&[], etag, updated_at));
+        assert!(not_modified(
+            &header("If-None-Match", "\"AQID\""),
+            etag,
+            updated_at
+        ));
+        assert!(not_modified(
+            &header("if-none-match", "W/\"AQID\""),
+            etag,
+            updated_at
+        ));
+        assert!(not_modified(
+            &header("if-none-match", "\"abc\", \"AQID\""),
+            etag,
+            updated_at
+        ));
+        assert!(not_modified(&header("if-none-match", "*"), "", updated_at));
+        assert!(!not_modified(
+            &header("if-none-match", "\"abc\""),
+            etag,
+            updated_at
+        ));
+        assert!(!not_modified(
+            &header("if-none-match", "\"\""),
+            "",
+            updated_at
+        ));
+
+        assert!(not_modified(
+            &header("if-modified-since", &date),
+            etag,
+            updated_at
+        ));
+        assert!(!not_modified(
+            &header("if-modified-since", &date),
+            etag,
+            updated_at + 1000
+        ));
+        assert!(!not_modified(
+            &header("if-modified-since", "invalid"),
+            etag,
+            updated_at
+        ));
+        // If-Modified-Since is ignored when If-None-Match is present
+        assert!(!not_modified(
+            &[
+                ("if-none-match".to_string(), "\"abc\"".to_string()),
+                ("if-modified-since".to_string(), date),
+            ],
+            etag,
+            updated_at
+        ));
+    }
+
+    #[test]
+    fn test_request_origin() {
+        assert_eq!(request_origin(&[]), None);
+        assert_eq!(
+            request_origin(&[(
+                "Referer".to_string(),
+                "https://example.com/page.html".to_string()
+            )]),
+            Some("https://example.com".to_string())
+        );
+        assert_eq!(
+            request_origin(&[
+                (
+                    "referer".to_string(),
+                    "https://example.com/page.html".to_string()
+                ),
+                ("origin".to_string(), "https://a.example.com".to_string()),
+            ]),
+            Some("https://a.example.com".to_string())
+        );
+    }
+
+    #[test]
+    fn test_basic_auth_password() {
+        assert_eq!(basic_auth_password(&[]), None);
+        assert_eq!(
+            basic_auth_password(&[(
+                "Authorization".to_string(),
+                format!("Basic {}", BASE64.encode("user:p@ss:word"))
+            )]),
+            Some("p@ss:word".to_string())
+        );
+        assert_eq!(
+            basic_auth_password(&[(
+                "authorization".to_string(),
+                format!("basic {}", BASE64.encode(":secret"))
+            )]),
+            Some("secret".to_string())
+        );
+        assert_eq!(
+            basic_auth_password(&[("authorization".to_string(), "Bearer abc".to_string())]),
+            None
+        );
+        assert_eq!(
+            basic_auth_password(&[(
+                "authorization".to_string(),
+                format!("Basic {}", BASE64.encode("secret"))
+            )]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_check_http_read_with_password() {
+        let canister = Principal::from_text("mmrxu-fqaaa-aaaap-ahhna-cai").unwrap();
+        let caller = Principal::anonymous();
+        let now_sec = 1720676064;
+        store::state::with_mut(|s| s.visibility = 1);
+
+        let id = store::fs::add_file(store::FileMetadata {
+            name: "f1.txt".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        store::fs::set_file_password(
+            id,
+            Some(
+                "$argon2id$v=19$m=8,t=1,p=1$c29tZXNhbHQ$QQCd8VPOryZiCF3MtwQnPTK7odKCaPcXJGplgNEuj1A"
+                    .to_string(),
+            ),
+            |_| Ok(()),
+        )
+        .unwrap();
+        let file = store::fs::get_file(id).unwrap();
+        let check = |token: Option<ByteBuf>, password: Option<&str>| {
+            check_http_read(caller, &canister, id, &file, token, password, None, now_sec)
+        };
+
+        // the public visibility doesn't apply to the password protected file
+        assert_eq!(check(None, None).unwrap_err().0, 401);
+        assert_eq!(check(None, Some("wrong")).unwrap_err().0, 401);
+        assert!(check(None, Some("secret")).is_ok());
+        // the token is verified as if the bucket is private
+        assert_eq!(
+            check(Some(ByteBuf::from(vec![1, 2, 3])), Some("secret"))
+                .unwrap_err()
+                .0,
+            401
+        );
+    }
+
+    #[test]
+    fn test_check_share_read() {
+        let id = store::fs::add_file(store::FileMetadata {
+            name: "f1.txt".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let file = store::fs::get_file(id).unwrap();
+        let share = store::Share {
+            file: id,
+            created_by: Principal::anonymous(),
+            created_at: 1000,
+            expires_at: Some(5000),
+            max_downloads: Some(1),
+            downloads: 0,
+            password_hash: None,
+            stream_key: Some([7u8; 32].into()),
+        };
+        store::share::create("s1".to_string(), share.clone(), 1000).unwrap();
+        store::share::create(
+            "s2".to_string(),
+            store::Share {
+                password_hash: Some(
+                    "$argon2id$v=19$m=8,t=1,p=1$c29tZXNhbHQ$QQCd8VPOryZiCF3MtwQnPTK7odKCaPcXJGplgNEuj1A"
+                        .to_string(),
+                ),
+                ..share
+            },
+            1000,
+        )
+        .unwrap();
+
+        assert!(check_share_read("s1", id, &file, None, true, 1000).is_ok());
+        assert_eq!(
+            check_share_read("s1", id + 1, &file, None, true, 1000)
+                .unwrap_err()
+                .0,
+            404
+        );
+        assert_eq!(
+            check_share_read("s3", id, &file, None, true, 1000)
+                .unwrap_err()
+                .0,
+            404
+        );
+        assert_eq!(
+            check_share_read("s1", id, &file, None, true, 5000)
+                .unwrap_err()
+                .0,
+            410
+        );
+
+        // the limit is checked before the download, the streaming callbacks check the ticket
+        assert!(store::share::record_download("s1", 1000));
+        assert_eq!(
+            check_share_read("s1", id, &file, None, true, 1000)
+                .unwrap_err()
+                .0,
+            410
+        );
+        assert!(check_share_read("s1", id, &file, None, false, 1000).is_ok());
+
+        assert_eq!(
+            check_share_read("s2", id, &file, None, true, 1000)
+                .unwrap_err()
+                .0,
+            401
+        );
+        assert_eq!(
+            check_share_read("s2", id, &file, Some("wrong"), true, 1000)
+                .unwrap_err()
+                .0,
+            401
+        );
+        assert!(check_share_read("s2", id, &file, Some("secret"), true, 1000).is_ok());
+
+        // the streaming callbacks require the ticket of a counted download
+        let mut token = StreamingCallbackToken {
+            id,
+            chunk_index: 1,
+            chunks: 3,
+            token: None,
+            password: None,
+            share: Some("s1".to_string()),
+            ticket: None,
+            expires_at: None,
+        };
+        assert_eq!(check_stream_ticket("s1", &token, 1000).unwrap_err().0, 403);
+        let (expires_at, ticket) = store::share::issue_stream_ticket("s1", 1000).unwrap();
+        token.expires_at = Some(expires_at);
+        token.ticket = Some(ByteBuf::from(ticket.to_vec()));
+        assert!(check_stream_ticket("s1", &token, 1000).is_ok());
+        assert!(check_stream_ticket("s2", &token, 1000).is_err());
+        assert!(check_stream_ticket("s1", &token, expires_at).is_err());
+    }
+
+    #[test]
+    fn test_site_asset() {
+        use ic_oss_types::file::SiteAsset;
+
+        let asset = |id: u32, cache_control: &str| SiteAsset {
+            id,
+            cache_control: cache_control.to_string(),
+            ..Default::default()
+        };
+        let manifest = SiteManifest {
+            index: "index.html".to_string(),
+            not_found: Some("404.html".to_string()),
+            files: [
+                ("index.html".to_string(), asset(2, "")),
+                ("404.html".to_string(), asset(3, "")),
+                (
+                    "app.js".to_string(),
+                    asset(4, "max-age=31536000, immutable"),
+                ),
+            ]
+            .into(),
+            ..Default::default()
+        };
+        let data = manifest.to_cbor();
+        let id = store::fs::add_file(store::FileMetadata {
+            name: "site-manifest.cbor".to_string(),
+            size: data.len() as u64,
+            ..Default::default()
+        })
+        .unwrap();
+        store::fs::update_chunk(id, 0, 999, data, |_| Ok(())).unwrap();
+        store::fs::set_tag("current".to_string(), id).unwrap();
+
+        // the site is disabled until the site tag is set
+        assert_eq!(site_asset("/"), None);
+        store::state::with_mut(|s| s.site_tag = "current".to_string());
+        assert_eq!(site_asset("/"), Some((2, "no-cache".to_string(), 200)));
+        assert_eq!(
+            site_asset("/app.js?v=2"),
+            Some((4, "max-age=31536000, immutable".to_string(), 200))
+        );
+        assert_eq!(
+            site_asset("/missing"),
+            Some((3, "no-cache".to_string(), 404))
+        );
+
+        store::state::with_mut(|s| s.site_tag = "next".to_string());
+        assert_eq!(site_asset("/"), None);
+    }
+
+    #[test]
+    fn test_counted_download() {
+        let res = |status_code: u16, headers: Vec<HeaderField>| HttpStreamingResponse {
+            status_code,
+            headers,
+            ..Default::default()
+        };
+        assert!(counted_download(&res(200, vec![])));
+        assert!(!counted_download(&res(304, vec![])));
+        assert!(!counted_download(&res(401, vec![])));
+        assert!(counted_download(&res(
+            206,
+            vec![("content-range".to_string(), "bytes 0-99/1000".to_string())]
+        )));
+        assert!(!counted_download(&res(
+            206,
+            vec![(
+                "content-range".to_string(),
+                "bytes 100-199/1000".to_string()
+            )]
+        )));
+    }
+
+    #[test]
+    fn test_check_http_read_with_scoped_token() {
+        let signing_key = ed25519_dalek::SigningKey::from_bytes(&[8u8; 32]);
+        let canister = Principal::from_text("mmrxu-fqaaa-aaaap-ahhna-cai").unwrap();
+        let caller = Principal::anonymous();
+        let now_sec = 1720676064;
+        store::state::with_mut(|s| {
+            s.trusted_eddsa_pub_keys = vec![signing_key.verifying_key().to_bytes().into()];
+        });
+
+        let sign_bound_token = |audience: Principal, policies: &str, bound: Option<Principal>| {
+            let token = Token {
+                subject: caller,
+                audience,
+                policies: policies.to_string(),
+                caller: bound,
+                jti: None,
+            };
+            Some(
+                token
+                    .sign_ed25519(&signing_key, None, now_sec as i64, 3600)
+                    .unwrap(),
+            )
+        };
+        let sign_token =
+            |audience: Principal, policies: &str| sign_bound_token(audience, policies, None);
+
+        let fd1 = store::fs::add_folder(store::FolderMetadata {
+            parent: 0,
+            name: "fd1".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let fd2 = store::fs::add_folder(store::FolderMetadata {
+            parent: 0,
+            name: "fd2".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let fd3 = store::fs::add_folder(store::FolderMetadata {
+            parent: fd1,
+            name: "fd3".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut files = Vec::new();
+        for (parent, name) in [(fd1, "f1.txt"), (fd2, "f2.txt"), (fd3, "f3.txt")] {
+            let file = store::FileMetadata {
+                parent,
+                name: name.to_string(),
+                ..Default::default()
+            };
+            let id = store::fs::add_file(file.clone()).unwrap();
+            files.push((id, file));
+        }
+        let check = |(id, file): &(u32, store::FileMetadata), token: Option<ByteBuf>| {
+            check_http_read(caller, &canister, *id, file, token, None, None, now_sec)
+        };
+
+        // private bucket without token
+        assert_eq!(check(&files[0], None).unwrap_err().0, 401);
+
+        // token scoped to folder fd1 and its subfolders
+        let token = sign_token(canister, &format!("Folder.Read.File:{}", fd1));
+        assert!(check(&files[0], token.clone()).is_ok());
+        assert_eq!(check(&files[1], token.clone()).unwrap_err().0, 403);
+        assert!(check(&files[2], token.clone()).is_ok());
+
+        // token scoped to a single file
+        let token = sign_token(canister, &format!("File.Read:{}", files[1].0));
+        assert_eq!(check(&files[0], token.clone()).unwrap_err().0, 403);
+        assert!(check(&files[1], token.clone()).is_ok());
+
+        // token with bucket-wide file read permission
+        let token = sign_token(canister, "Bucket.Read.File");
+        for file in &files {
+            assert!(check(file, token.clone()).is_ok());
+        }
+
+        // token for another audience
+        let group = Principal::self_authenticating(b"my-app");
+        let token = sign_token(group, "Bucket.Read.File");
+        assert_eq!(check(&files[0], token.clone()).unwrap_err().0, 401);
+
+        // token for an audience group that the bucket belongs to
+        store::state::with_mut(|s| {
+            s.audience_groups.insert(group);
+        });
+        assert!(check(&files[0], token).is_ok());
+
+        // token bound to the caller can not be replayed by others
+        let token = sign_bound_token(canister, "Bucket.Read.File", Some(caller));
+        assert!(check(&files[0], token.clone()).is_ok());
+        assert_eq!(
+            check_http_read(
+                Principal::management_canister(),
+                &canister,
+                files[0].0,
+                &files[0].1,
+                token,
+                None,
+                None,
+                now_sec
+            )
+            .unwrap_err()
+            .0,
+            401
+        );
+
+        // the denied reads are indistinguishable from the missing files
+        store::state::with_mut(|s| s.hide_forbidden = true);
+        assert_eq!(
+            check(&files[0], None).unwrap_err(),
+            (404, "file not found".to_string())
+        );
+        let token = sign_token(canister, &format!("File.Read:{}", files[1].0));
+        assert_eq!(check(&files[0], token.clone()).unwrap_err().0, 404);
+        assert!(check(&files[1], token).is_ok());
+    }
 }
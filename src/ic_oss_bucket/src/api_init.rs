@@ -1,75 +1,15 @@
-use candid::{CandidType, Principal};
-use ic_oss_types::file::MAX_FILE_SIZE;
-use serde::Deserialize;
+use ic_oss_types::bucket::CanisterArgs;
 
-use crate::store;
-
-#[derive(Clone, Debug, CandidType, Deserialize)]
-pub enum CanisterArgs {
-    Init(InitArgs),
-    Upgrade(UpgradeArgs),
-}
-
-#[derive(Clone, Debug, CandidType, Deserialize)]
-pub struct InitArgs {
-    name: String,              // bucket name
-    file_id: u32,              // the first file id, default is 0
-    max_file_size: u64,        // in bytes, default is 384GB
-    max_folder_depth: u8,      // default is 10
-    max_children: u16, //  maximum number of subfolders and subfiles in a folder., default is 1000
-    max_custom_data_size: u16, // in bytes, default is 4KB
-    enable_hash_index: bool, // if enabled, indexing will be built using file hash, allowing files to be read by their hash and preventing duplicate hash for files. default is false
-    visibility: u8,          // 0: private; 1: public, can be accessed by anyone, default is 0
-    governance_canister: Option<Principal>,
-}
-
-#[derive(Clone, Debug, CandidType, Deserialize)]
-pub struct UpgradeArgs {
-    max_file_size: Option<u64>,
-    max_folder_depth: Option<u8>,
-    max_children: Option<u16>,
-    max_custom_data_size: Option<u16>,
-    enable_hash_index: Option<bool>,
-    governance_canister: Option<Principal>,
-}
-
-impl UpgradeArgs {
-    fn validate(&self) -> Result<(), String> {
-        if let Some(max_file_size) = self.max_file_size {
-            if max_file_size == 0 {
-                return Err("max_file_size should be greater than 0".to_string());
-            }
-            if max_file_size >= MAX_FILE_SIZE {
-                return Err(format!(
-                    "max_file_size should be smaller than or equal to {}",
-                    MAX_FILE_SIZE
-                ));
-            }
-        }
-        if let Some(max_folder_depth) = self.max_folder_depth {
-            if max_folder_depth == 0 {
-                return Err("max_folder_depth should be greater than 0".to_string());
-            }
-        }
-        if let Some(max_children) = self.max_children {
-            if max_children == 0 {
-                return Err("max_children should be greater than 0".to_string());
-            }
-        }
-
-        if let Some(max_custom_data_size) = self.max_custom_data_size {
-            if max_custom_data_size == 0 {
-                return Err("max_custom_data_size should be greater than 0".to_string());
-            }
-        }
-        Ok(())
-    }
-}
+use crate::{job, store};
 
 #[ic_cdk::init]
 fn init(args: Option<CanisterArgs>) {
     match args {
         Some(CanisterArgs::Init(args)) => {
+            if let Err(err) = args.validate() {
+                ic_cdk::trap(&err);
+            }
+
             store::state::with_mut(|b| {
                 if !args.name.is_empty() {
                     b.name = args.name
@@ -78,6 +18,7 @@ fn init(args: Option<CanisterArgs>) {
                 if args.max_file_size > 0 {
                     b.max_file_size = args.max_file_size
                 };
+                b.chunk_size = args.chunk_size.unwrap_or_default();
                 if args.max_folder_depth > 0 {
                     b.max_folder_depth = args.max_folder_depth
                 };
@@ -85,13 +26,17 @@ fn init(args: Option<CanisterArgs>) {
                     b.max_children = args.max_children
                 };
                 if args.visibility > 0 {
-                    b.visibility = 1
+                    b.visibility = args.visibility.min(2)
                 };
                 if args.max_custom_data_size > 0 {
                     b.max_custom_data_size = args.max_custom_data_size
                 };
                 b.enable_hash_index = args.enable_hash_index;
                 b.governance_canister = args.governance_canister;
+                b.governance_managed = args.governance_managed.unwrap_or_default();
+                if b.governance_managed && b.governance_canister.is_none() {
+                    ic_cdk::trap("governance_canister is required in the governance managed mode");
+                }
             });
         }
         Some(CanisterArgs::Upgrade(_)) => {
@@ -102,7 +47,9 @@ fn init(args: Option<CanisterArgs>) {
         None => {}
     }
 
+    store::state::with_mut(|s| s.schema_version = store::state::SCHEMA_VERSION);
     store::state::init_http_certified_data();
+    job::start_tasks();
 }
 
 #[ic_cdk::pre_upgrade]
@@ -139,6 +86,12 @@ fn post_upgrade(args: Option<CanisterArgs>) {
                 if let Some(governance_canister) = args.governance_canister {
                     s.governance_canister = Some(governance_canister);
                 }
+                if let Some(governance_managed) = args.governance_managed {
+                    s.governance_managed = governance_managed;
+                }
+                if s.governance_managed && s.governance_canister.is_none() {
+                    ic_cdk::trap("governance_canister is required in the governance managed mode");
+                }
             });
         }
         Some(CanisterArgs::Init(_)) => {
@@ -150,4 +103,5 @@ fn post_upgrade(args: Option<CanisterArgs>) {
     }
 
     store::state::init_http_certified_data();
+    job::start_tasks();
 }
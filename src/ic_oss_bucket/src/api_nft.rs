@@ -0,0 +1,357 @@
+use candid::{Nat, Principal};
+use ic_oss_types::{nat_to_u64, nft::*};
+
+use crate::{store, SECONDS};
+
+// an opt-in ICRC-7 facade over the files, the token id is the file id and the content
+// is served via HTTP, so files can be owned and traded as NFTs without copying them.
+// see https://github.com/dfinity/ICRC/blob/main/ICRCs/ICRC-7/ICRC-7.md
+
+#[ic_cdk::query]
+fn icrc10_supported_standards() -> Vec<SupportedStandard> {
+    vec![
+        SupportedStandard {
+            name: "ICRC-7".to_string(),
+            url: "https://github.com/dfinity/ICRC/ICRCs/ICRC-7".to_string(),
+        },
+        SupportedStandard {
+            name: "ICRC-10".to_string(),
+            url: "https://github.com/dfinity/ICRC/ICRCs/ICRC-10".to_string(),
+        },
+    ]
+}
+
+#[ic_cdk::query]
+fn icrc7_collection_metadata() -> Vec<(String, MetadataValue)> {
+    let collection = collection();
+    let mut res = vec![
+        (
+            "icrc7:name".to_string(),
+            MetadataValue::Text(collection.name),
+        ),
+        (
+            "icrc7:symbol".to_string(),
+            MetadataValue::Text(collection.symbol),
+        ),
+        (
+            "icrc7:total_supply".to_string(),
+            MetadataValue::Nat(icrc7_total_supply()),
+        ),
+        (
+            "icrc7:max_query_batch_size".to_string(),
+            MetadataValue::Nat(MAX_QUERY_BATCH_SIZE.into()),
+        ),
+        (
+            "icrc7:max_update_batch_size".to_string(),
+            MetadataValue::Nat(MAX_UPDATE_BATCH_SIZE.into()),
+        ),
+        (
+            "icrc7:default_take_value".to_string(),
+            MetadataValue::Nat(DEFAULT_TAKE_VALUE.into()),
+        ),
+        (
+            "icrc7:max_take_value".to_string(),
+            MetadataValue::Nat(MAX_TAKE_VALUE.into()),
+        ),
+        (
+            "icrc7:max_memo_size".to_string(),
+            MetadataValue::Nat(MAX_MEMO_SIZE.into()),
+        ),
+        (
+            "icrc7:permitted_drift".to_string(),
+            MetadataValue::Nat(PERMITTED_DRIFT.into()),
+        ),
+    ];
+    if let Some(description) = collection.description {
+        res.push((
+            "icrc7:description".to_string(),
+            MetadataValue::Text(description),
+        ));
+    }
+    if let Some(logo) = collection.logo {
+        res.push(("icrc7:logo".to_string(), MetadataValue::Text(logo)));
+    }
+    if let Some(supply_cap) = collection.supply_cap {
+        res.push((
+            "icrc7:supply_cap".to_string(),
+            MetadataValue::Nat(supply_cap.into()),
+        ));
+    }
+    res
+}
+
+#[ic_cdk::query]
+fn icrc7_name() -> String {
+    collection().name
+}
+
+#[ic_cdk::query]
+fn icrc7_symbol() -> String {
+    collection().symbol
+}
+
+#[ic_cdk::query]
+fn icrc7_description() -> Option<String> {
+    collection().description
+}
+
+#[ic_cdk::query]
+fn icrc7_logo() -> Option<String> {
+    collection().logo
+}
+
+#[ic_cdk::query]
+fn icrc7_total_supply() -> Nat {
+    if enabled() {
+        store::nft::total().into()
+    } else {
+        Nat::from(0u64)
+    }
+}
+
+#[ic_cdk::query]
+fn icrc7_supply_cap() -> Option<Nat> {
+    collection().supply_cap.map(Nat::from)
+}
+
+#[ic_cdk::query]
+fn icrc7_max_query_batch_size() -> Option<Nat> {
+    Some(MAX_QUERY_BATCH_SIZE.into())
+}
+
+#[ic_cdk::query]
+fn icrc7_max_update_batch_size() -> Option<Nat> {
+    Some(MAX_UPDATE_BATCH_SIZE.into())
+}
+
+#[ic_cdk::query]
+fn icrc7_default_take_value() -> Option<Nat> {
+    Some(DEFAULT_TAKE_VALUE.into())
+}
+
+#[ic_cdk::query]
+fn icrc7_max_take_value() -> Option<Nat> {
+    Some(MAX_TAKE_VALUE.into())
+}
+
+#[ic_cdk::query]
+fn icrc7_max_memo_size() -> Option<Nat> {
+    Some(MAX_MEMO_SIZE.into())
+}
+
+#[ic_cdk::query]
+fn icrc7_atomic_batch_transfers() -> Option<bool> {
+    Some(false)
+}
+
+// the transfers are not deduplicated
+#[ic_cdk::query]
+fn icrc7_tx_window() -> Option<Nat> {
+    None
+}
+
+#[ic_cdk::query]
+fn icrc7_permitted_drift() -> Option<Nat> {
+    Some(PERMITTED_DRIFT.into())
+}
+
+// the metadata of a token is derived from the file, the content is served at "/f/<id>".
+#[ic_cdk::query]
+fn icrc7_token_metadata(token_ids: Vec<Nat>) -> Vec<Option<Vec<(String, MetadataValue)>>> {
+    check_query_batch_size(token_ids.len());
+    let enabled = enabled();
+    let canister = ic_cdk::id();
+    token_ids
+        .iter()
+        .map(|token_id| {
+            let id = token_to_file(token_id).filter(|_| enabled)?;
+            store::nft::get(id)?;
+            let file = store::fs::get_file(id)?;
+            let mut res = vec![
+                ("ic_oss:name".to_string(), MetadataValue::Text(file.name)),
+                (
+                    "ic_oss:content_type".to_string(),
+                    MetadataValue::Text(file.content_type),
+                ),
+                (
+                    "ic_oss:size".to_string(),
+                    MetadataValue::Nat(file.size.into()),
+                ),
+                (
+                    "ic_oss:created_at".to_string(),
+                    MetadataValue::Nat(file.created_at.into()),
+                ),
+                (
+                    "ic_oss:url".to_string(),
+                    MetadataValue::Text(nft_url(&canister, id)),
+                ),
+            ];
+            if let Some(hash) = file.hash {
+                res.push((
+                    "ic_oss:hash".to_string(),
+                    MetadataValue::Blob(hash.to_vec().into()),
+                ));
+            }
+            if let Some(custom) = file.custom {
+                res.extend(custom);
+            }
+            Some(res)
+        })
+        .collect()
+}
+
+#[ic_cdk::query]
+fn icrc7_owner_of(token_ids: Vec<Nat>) -> Vec<Option<Account>> {
+    check_query_batch_size(token_ids.len());
+    let enabled = enabled();
+    token_ids
+        .iter()
+        .map(|token_id| {
+            token_to_file(token_id)
+                .filter(|_| enabled)
+                .and_then(store::nft::get)
+                .map(|token| token.account())
+        })
+        .collect()
+}
+
+#[ic_cdk::query]
+fn icrc7_balance_of(accounts: Vec<Account>) -> Vec<Nat> {
+    check_query_batch_size(accounts.len());
+    let enabled = enabled();
+    accounts
+        .iter()
+        .map(|account| {
+            if enabled {
+                store::nft::balance_of(account).into()
+            } else {
+                Nat::from(0u64)
+            }
+        })
+        .collect()
+}
+
+#[ic_cdk::query]
+fn icrc7_tokens(prev: Option<Nat>, take: Option<Nat>) -> Vec<Nat> {
+    if !enabled() {
+        return Vec::new();
+    }
+    let prev = match prev {
+        Some(prev) => match token_to_file(&prev) {
+            Some(prev) => Some(prev),
+            None => return Vec::new(),
+        },
+        None => None,
+    };
+    store::nft::tokens(prev, take_value(take))
+        .into_iter()
+        .map(Nat::from)
+        .collect()
+}
+
+#[ic_cdk::query]
+fn icrc7_tokens_of(account: Account, prev: Option<Nat>, take: Option<Nat>) -> Vec<Nat> {
+    if !enabled() {
+        return Vec::new();
+    }
+    let prev = match prev {
+        Some(prev) => match token_to_file(&prev) {
+            Some(prev) => Some(prev),
+            None => return Vec::new(),
+        },
+        None => None,
+    };
+    store::nft::tokens_of(&account, prev, take_value(take))
+        .into_iter()
+        .map(Nat::from)
+        .collect()
+}
+
+// returns the certified asset of the token, the proof can be verified with
+// ic_oss_verify::Verifier::verify_file_hash. the certified HTTP responses of
+// the token's file include the proof in the "ic-oss-file-proof" header too.
+#[ic_cdk::query]
+fn get_nft_asset(token_id: Nat) -> Result<NftAsset, String> {
+    let id = token_to_file(&token_id)
+        .filter(|_| enabled())
+        .and_then(|id| store::nft::get(id).map(|_| id))
+        .ok_or("token not found")?;
+    let file = store::fs::get_file(id).ok_or("file not found")?;
+    Ok(NftAsset {
+        token_id,
+        url: nft_url(&ic_cdk::id(), id),
+        content_type: file.content_type.clone(),
+        proof: store::state::certified_file_proof(id, &file),
+    })
+}
+
+#[ic_cdk::update]
+fn icrc7_transfer(args: Vec<TransferArg>) -> Vec<Option<TransferResult>> {
+    if !enabled() {
+        return vec![Some(Err(TransferError::GenericBatchError {
+            error_code: Nat::from(0u64),
+            message: "NFT collection is not enabled".to_string(),
+        }))];
+    }
+    if args.len() > MAX_UPDATE_BATCH_SIZE {
+        return vec![Some(Err(TransferError::GenericBatchError {
+            error_code: Nat::from(1u64),
+            message: format!("batch size exceeds the limit {}", MAX_UPDATE_BATCH_SIZE),
+        }))];
+    }
+
+    let caller = ic_cdk::caller();
+    let now = ic_cdk::api::time();
+    args.into_iter()
+        .map(|arg| {
+            if let Some(memo) = &arg.memo {
+                if memo.len() > MAX_MEMO_SIZE {
+                    return Some(Err(TransferError::GenericError {
+                        error_code: Nat::from(2u64),
+                        message: format!("memo size exceeds the limit {}", MAX_MEMO_SIZE),
+                    }));
+                }
+            }
+            if let Some(created_at_time) = arg.created_at_time {
+                if created_at_time > now + PERMITTED_DRIFT * SECONDS {
+                    return Some(Err(TransferError::CreatedInFuture { ledger_time: now }));
+                }
+            }
+            let id = match token_to_file(&arg.token_id) {
+                Some(id) => id,
+                None => return Some(Err(TransferError::NonExistingTokenId)),
+            };
+            let from = Account {
+                owner: caller,
+                subaccount: arg.from_subaccount,
+            };
+            Some(store::nft::transfer(&from, id, &arg.to).map(Nat::from))
+        })
+        .collect()
+}
+
+fn nft_url(canister: &Principal, id: u32) -> String {
+    format!("https://{}.icp0.io/f/{}", canister, id)
+}
+
+fn collection() -> NftCollection {
+    store::state::with(|s| s.nft_collection.clone().unwrap_or_default())
+}
+
+fn enabled() -> bool {
+    store::state::with(|s| s.nft_collection.is_some())
+}
+
+fn take_value(take: Option<Nat>) -> u32 {
+    take.map(|take| nat_to_u64(&take).min(MAX_TAKE_VALUE as u64) as u32)
+        .unwrap_or(DEFAULT_TAKE_VALUE)
+}
+
+fn check_query_batch_size(size: usize) {
+    if size > MAX_QUERY_BATCH_SIZE {
+        ic_cdk::trap(&format!(
+            "batch size exceeds the limit {}",
+            MAX_QUERY_BATCH_SIZE
+        ));
+    }
+}
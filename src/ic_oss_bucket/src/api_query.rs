@@ -1,19 +1,34 @@
+use candid::Principal;
 use ic_cdk::api::management_canister::main::{
     canister_status, CanisterIdRecord, CanisterStatusResponse,
 };
 use ic_oss_types::{
-    bucket::BucketInfo,
-    file::{FileChunk, FileInfo},
-    folder::{FolderInfo, FolderName},
+    api_major_version,
+    bucket::*,
+    cose::TokenInfo,
+    file::{
+        Annotation, FileChunk, FileFillStatus, FileInfo, ListFilesOutput, ListOptions, ShareInfo,
+        SortBy, MAX_FILE_SIZE_PER_CALL, MAX_FILL_STATUS_CHUNKS,
+    },
+    folder::{FolderChild, FolderInfo, FolderName, ListFoldersOutput},
     format_error,
+    stream::{StreamChunk, StreamHandle},
+    BUCKET_API_VERSION,
 };
 use serde_bytes::{ByteArray, ByteBuf};
+use std::collections::BTreeSet;
 
-use crate::{permission, store, SECONDS};
+use crate::{permission, store, MILLISECONDS, SECONDS};
 
 #[ic_cdk::query]
 fn api_version() -> u16 {
-    1
+    api_major_version(BUCKET_API_VERSION)
+}
+
+// the semantic version of the interface, see ic_oss_types::check_api_version
+#[ic_cdk::query]
+fn api_semver() -> String {
+    BUCKET_API_VERSION.to_string()
 }
 
 #[ic_cdk::query]
@@ -42,6 +57,7 @@ fn get_bucket_info(_access_token: Option<ByteBuf>) -> Result<BucketInfo, String>
         file_id: r.file_id,
         folder_id: r.folder_id,
         max_file_size: r.max_file_size,
+        chunk_size: r.chunk_size(),
         max_folder_depth: r.max_folder_depth,
         max_children: r.max_children,
         max_custom_data_size: r.max_custom_data_size,
@@ -56,9 +72,74 @@ fn get_bucket_info(_access_token: Option<ByteBuf>) -> Result<BucketInfo, String>
         trusted_ecdsa_pub_keys: r.trusted_ecdsa_pub_keys.clone(),
         trusted_eddsa_pub_keys: r.trusted_eddsa_pub_keys.clone(),
         governance_canister: r.governance_canister,
+        allowed_origins: r.allowed_origins.clone(),
+        require_signed_url: r.require_signed_url,
+        anonymous_upload: r.anonymous_upload.clone(),
+        audience_groups: r.audience_groups.clone(),
+        enable_compression: r.enable_compression,
+        compressed_original_bytes: r.compressed_original_bytes,
+        compressed_stored_bytes: r.compressed_stored_bytes,
+        name_policy: r.name_policy.clone(),
+        security_headers: r.security_headers.clone(),
+        folder_security_headers: r.folder_security_headers.clone(),
+        access_log_sample_rate: r.access_log_sample_rate,
+        receipt_key_name: r.receipt_key_name.clone(),
+        receipt_public_key: r.receipt_public_key.clone(),
+        unlisted_folders: r.unlisted_folders.clone(),
+        inbox_folders: r.inbox_folders.clone(),
+        max_annotations: r.max_annotations,
+        annotations: store::annotation::total(),
+        governance_managed: r.governance_managed,
+        well_known: r.well_known.clone(),
+        hide_forbidden: r.hide_forbidden,
+        site_tag: r.site_tag.clone(),
     }))
 }
 
+// returns the features supported by the bucket, the optional features are listed only
+// when they are enabled.
+#[ic_cdk::query]
+fn get_features() -> BTreeSet<String> {
+    let mut features: BTreeSet<String> = [
+        FEATURE_CERTIFIED_STATE,
+        FEATURE_HASH_VERIFICATION,
+        FEATURE_IMPORT_FROM_URL,
+        FEATURE_METRICS,
+        FEATURE_PULL_FILE,
+        FEATURE_RANGE_READ,
+        FEATURE_ROLES,
+        FEATURE_SCRUB,
+        FEATURE_SHARES,
+        FEATURE_TAGS,
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect();
+    store::state::with(|s| {
+        for (enabled, feature) in [
+            (s.access_log_sample_rate > 0, FEATURE_ACCESS_LOGS),
+            (s.max_annotations > 0, FEATURE_ANNOTATIONS),
+            (
+                s.anonymous_upload.max_files_per_hour > 0,
+                FEATURE_ANONYMOUS_UPLOAD,
+            ),
+            (s.backup_target.is_some(), FEATURE_BACKUP),
+            (s.enable_compression, FEATURE_COMPRESSION),
+            (s.governance_managed, FEATURE_GOVERNANCE_MANAGED),
+            (s.enable_hash_index, FEATURE_HASH_INDEX),
+            (s.nft_collection.is_some(), FEATURE_NFT),
+            (!s.receipt_key_name.is_empty(), FEATURE_RECEIPTS),
+            (s.require_signed_url, FEATURE_SIGNED_URL),
+            (!s.well_known.is_empty(), FEATURE_WELL_KNOWN),
+        ] {
+            if enabled {
+                features.insert(feature.to_string());
+            }
+        }
+    });
+    features
+}
+
 #[ic_cdk::update]
 async fn get_canister_status() -> Result<CanisterStatusResponse, String> {
     let canister = ic_cdk::id();
@@ -88,6 +169,224 @@ async fn get_canister_status() -> Result<CanisterStatusResponse, String> {
     Ok(res)
 }
 
+#[ic_cdk::query]
+fn get_backup_status(access_token: Option<ByteBuf>) -> Result<Option<BackupStatus>, String> {
+    let canister = ic_cdk::id();
+    let ctx = match store::state::with(|s| {
+        s.read_permission(
+            ic_cdk::caller(),
+            &canister,
+            access_token,
+            ic_cdk::api::time() / SECONDS,
+        )
+    }) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    if !permission::check_bucket_read(&ctx.ps, &canister) {
+        return Err("permission denied".to_string());
+    }
+
+    Ok(store::state::with(|s| {
+        s.backup_target.as_ref().map(|t| t.status())
+    }))
+}
+
+#[ic_cdk::query]
+fn get_scrub_report(access_token: Option<ByteBuf>) -> Result<ScrubReport, String> {
+    let canister = ic_cdk::id();
+    let ctx = match store::state::with(|s| {
+        s.read_permission(
+            ic_cdk::caller(),
+            &canister,
+            access_token,
+            ic_cdk::api::time() / SECONDS,
+        )
+    }) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    if !permission::check_bucket_read(&ctx.ps, &canister) {
+        return Err("permission denied".to_string());
+    }
+
+    Ok(store::state::with(|s| s.scrub_report.clone()))
+}
+
+// returns the effective role and policies of the principal or the token subject, to
+// debug the permission errors. the caller can introspect itself, only the managers,
+// auditors and controllers can introspect other principals.
+#[ic_cdk::query]
+fn get_access_info(
+    principal: Option<Principal>,
+    access_token: Option<ByteBuf>,
+) -> Result<AccessInfo, String> {
+    let caller = ic_cdk::caller();
+    let principal = principal.unwrap_or(caller);
+    if principal != caller
+        && crate::is_controller().is_err()
+        && !store::state::with(|s| s.managers.contains(&caller) || s.auditors.contains(&caller))
+    {
+        Err("permission denied".to_string())?;
+    }
+
+    let canister = ic_cdk::id();
+    Ok(store::state::with(|s| {
+        s.access_info(
+            principal,
+            &canister,
+            access_token,
+            ic_cdk::api::time() / SECONDS,
+        )
+    }))
+}
+
+// decodes and verifies the access token without side effects, for debugging and for
+// resource servers that validate the tokens on the server side.
+#[ic_cdk::query]
+fn introspect_token(sign1_token: ByteBuf) -> Result<TokenInfo, String> {
+    let canister = ic_cdk::id();
+    store::state::with(|s| {
+        s.introspect_token(&sign1_token, &canister, ic_cdk::api::time() / SECONDS)
+    })
+}
+
+// lists the sampled HTTP requests before the prev id, the newest first.
+// only the managers and auditors can read the access logs.
+#[ic_cdk::query]
+fn get_access_logs(
+    prev: Option<u64>,
+    take: Option<u32>,
+    access_token: Option<ByteBuf>,
+) -> Result<Vec<AccessLog>, String> {
+    let prev = prev.unwrap_or(u64::MAX);
+    let take = take.unwrap_or(100).min(1000);
+    let canister = ic_cdk::id();
+    let ctx = match store::state::with(|s| {
+        s.read_permission(
+            ic_cdk::caller(),
+            &canister,
+            access_token,
+            ic_cdk::api::time() / SECONDS,
+        )
+    }) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    if ctx.role < store::Role::Auditor {
+        return Err("permission denied".to_string());
+    }
+
+    Ok(store::access_log::list(prev, take))
+}
+
+// reports the usage of the bucket to its controllers, the cluster polls it to
+// aggregate the usage of the tenants.
+#[ic_cdk::query]
+fn get_usage(since: u64) -> Result<BucketUsage, String> {
+    let caller = ic_cdk::caller();
+    if !ic_cdk::api::is_controller(&caller) && !store::state::is_controller(&caller) {
+        Err("user is not a controller".to_string())?;
+    }
+
+    let (egress_bytes, until) =
+        store::metrics::bytes_out(ic_cdk::api::time() / MILLISECONDS, since);
+    Ok(BucketUsage {
+        storage_bytes: ic_cdk::api::stable::stable_size() * 65536,
+        total_files: store::fs::total_files(),
+        egress_bytes,
+        until,
+    })
+}
+
+// lists the hourly metrics of the last hours, the oldest first, at most 30 days.
+// only the managers and auditors can read the metrics.
+#[ic_cdk::query]
+fn get_metrics_history(
+    hours: u32,
+    access_token: Option<ByteBuf>,
+) -> Result<Vec<MetricsSample>, String> {
+    let now_ns = ic_cdk::api::time();
+    let canister = ic_cdk::id();
+    let ctx = match store::state::with(|s| {
+        s.read_permission(ic_cdk::caller(), &canister, access_token, now_ns / SECONDS)
+    }) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    if ctx.role < store::Role::Auditor {
+        return Err("permission denied".to_string());
+    }
+
+    Ok(store::metrics::history(now_ns / MILLISECONDS, hours))
+}
+
+#[ic_cdk::query]
+fn get_state_digest(access_token: Option<ByteBuf>) -> Result<StateDigest, String> {
+    let canister = ic_cdk::id();
+    let ctx = match store::state::with(|s| {
+        s.read_permission(
+            ic_cdk::caller(),
+            &canister,
+            access_token,
+            ic_cdk::api::time() / SECONDS,
+        )
+    }) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    if !permission::check_bucket_read(&ctx.ps, &canister) {
+        return Err("permission denied".to_string());
+    }
+
+    Ok(store::state::certified_state_digest())
+}
+
+#[ic_cdk::query]
+fn get_file_proof(id: u32, access_token: Option<ByteBuf>) -> Result<FileProof, String> {
+    let file = store::fs::get_file(id).ok_or("file not found")?;
+    let canister = ic_cdk::id();
+    let ctx = match store::state::with(|s| {
+        s.read_permission(
+            ic_cdk::caller(),
+            &canister,
+            access_token,
+            ic_cdk::api::time() / SECONDS,
+        )
+    }) {
+        Ok(ctx) => ctx,
+        Err(err) => {
+            return Err(store::state::file_read_error(err));
+        }
+    };
+
+    store::state::with(|s| s.inbox_check(&ic_cdk::caller(), file.parent))
+        .map_err(store::state::file_read_error)?;
+    if !permission::check_file_read(&ctx.ps, &canister, id, file.parent) {
+        Err(store::state::file_read_error((
+            403,
+            "permission denied".to_string(),
+        )))?;
+    }
+
+    Ok(store::state::certified_file_proof(id, &file))
+}
+
 #[ic_cdk::query]
 fn get_file_info(id: u32, access_token: Option<ByteBuf>) -> Result<FileInfo, String> {
     match store::fs::get_file(id) {
@@ -104,13 +403,18 @@ fn get_file_info(id: u32, access_token: Option<ByteBuf>) -> Result<FileInfo, Str
                     )
                 }) {
                     Ok(ctx) => ctx,
-                    Err((_, err)) => {
-                        return Err(err);
+                    Err(err) => {
+                        return Err(store::state::file_read_error(err));
                     }
                 };
 
+                store::state::with(|s| s.inbox_check(&ic_cdk::caller(), file.parent))
+                    .map_err(store::state::file_read_error)?;
                 if !permission::check_file_read(&ctx.ps, &canister, id, file.parent) {
-                    Err("permission denied".to_string())?;
+                    Err(store::state::file_read_error((
+                        403,
+                        "permission denied".to_string(),
+                    )))?;
                 }
             }
 
@@ -119,6 +423,20 @@ fn get_file_info(id: u32, access_token: Option<ByteBuf>) -> Result<FileInfo, Str
     }
 }
 
+// reports the chunks present in a file from the start chunk, so that resumable and
+// parallel uploaders can fill exactly the missing ones. A call reports at most
+// MAX_FILL_STATUS_CHUNKS chunks, the next page starts from `next`.
+#[ic_cdk::query]
+fn get_file_fill_status(
+    id: u32,
+    start: Option<u32>,
+    access_token: Option<ByteBuf>,
+) -> Result<FileFillStatus, String> {
+    let file = get_file_info(id, access_token)?;
+    store::fs::get_fill_status(file.id, start.unwrap_or(0), MAX_FILL_STATUS_CHUNKS)
+        .ok_or_else(|| "file not found".to_string())
+}
+
 #[ic_cdk::query]
 fn get_file_info_by_hash(
     hash: ByteArray<32>,
@@ -129,27 +447,69 @@ fn get_file_info_by_hash(
     get_file_info(id, access_token)
 }
 
+// reports which of the file hashes already exist in the bucket, so that clients can skip
+// uploading duplicates. only the files that the caller can read are reported.
+#[ic_cdk::query]
+fn has_hashes(
+    hashes: Vec<ByteArray<32>>,
+    access_token: Option<ByteBuf>,
+) -> Result<Vec<bool>, String> {
+    if hashes.len() > 100 {
+        Err("too many hashes, the limit is 100".to_string())?;
+    }
+    let canister = ic_cdk::id();
+    let caller = ic_cdk::caller();
+    let ctx = match store::state::with(|s| {
+        s.read_permission(
+            caller,
+            &canister,
+            access_token,
+            ic_cdk::api::time() / SECONDS,
+        )
+    }) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    Ok(hashes
+        .iter()
+        .map(|hash| {
+            store::fs::get_file_id(hash)
+                .and_then(|id| store::fs::get_file(id).map(|file| (id, file.parent)))
+                .is_some_and(|(id, parent)| {
+                    store::state::with(|s| s.inbox_check(&caller, parent)).is_ok()
+                        && permission::check_file_read(&ctx.ps, &canister, id, parent)
+                })
+        })
+        .collect())
+}
+
 #[ic_cdk::query]
 fn get_file_ancestors(id: u32, access_token: Option<ByteBuf>) -> Result<Vec<FolderName>, String> {
     let ancestors = store::fs::get_file_ancestors(id);
     if let Some(parent) = ancestors.first() {
         let canister = ic_cdk::id();
-        let ctx = match store::state::with(|s| {
-            s.read_permission(
+        let checked = store::state::with(|s| {
+            let ctx = s.read_permission(
                 ic_cdk::caller(),
                 &canister,
                 access_token,
                 ic_cdk::api::time() / SECONDS,
-            )
-        }) {
-            Ok(ctx) => ctx,
-            Err((_, err)) => {
-                return Err(err);
+            )?;
+            s.inbox_check(&ic_cdk::caller(), parent.id)?;
+            if !permission::check_file_read(&ctx.ps, &canister, id, parent.id) {
+                Err((403, "permission denied".to_string()))?;
             }
-        };
-
-        if !permission::check_file_read(&ctx.ps, &canister, id, parent.id) {
-            Err("permission denied".to_string())?;
+            Ok(())
+        });
+        if let Err((_, err)) = checked {
+            // a missing file has no ancestors
+            if store::state::with(|s| s.hide_forbidden) {
+                return Ok(Vec::new());
+            }
+            Err(err)?;
         }
     }
     Ok(ancestors)
@@ -168,25 +528,32 @@ fn get_file_chunks(
             if !file.read_by_hash(&access_token) {
                 let canister = ic_cdk::id();
                 let ctx = match store::state::with(|s| {
-                    s.read_permission(
+                    s.file_read_permission(
                         ic_cdk::caller(),
                         &canister,
                         access_token,
                         ic_cdk::api::time() / SECONDS,
+                        &file,
                     )
                 }) {
                     Ok(ctx) => ctx,
-                    Err((_, err)) => {
-                        return Err(err);
+                    Err(err) => {
+                        return Err(store::state::file_read_error(err));
                     }
                 };
 
                 if file.status < 0 && ctx.role < store::Role::Auditor {
-                    Err("file archived".to_string())?;
+                    Err(store::state::file_read_error((
+                        403,
+                        "file archived".to_string(),
+                    )))?;
                 }
 
                 if !permission::check_file_read(&ctx.ps, &canister, id, file.parent) {
-                    Err("permission denied".to_string())?;
+                    Err(store::state::file_read_error((
+                        403,
+                        "permission denied".to_string(),
+                    )))?;
                 }
             }
 
@@ -195,6 +562,133 @@ fn get_file_chunks(
     }
 }
 
+// fetches an arbitrary set of chunks, at most 8 chunks per call.
+#[ic_cdk::query]
+fn get_file_chunks_by_index(
+    id: u32,
+    indexes: Vec<u32>,
+    access_token: Option<ByteBuf>,
+) -> Result<Vec<FileChunk>, String> {
+    match store::fs::get_file(id) {
+        None => Err("file not found".to_string()),
+        Some(file) => {
+            if !file.read_by_hash(&access_token) {
+                let canister = ic_cdk::id();
+                let ctx = match store::state::with(|s| {
+                    s.file_read_permission(
+                        ic_cdk::caller(),
+                        &canister,
+                        access_token,
+                        ic_cdk::api::time() / SECONDS,
+                        &file,
+                    )
+                }) {
+                    Ok(ctx) => ctx,
+                    Err(err) => {
+                        return Err(store::state::file_read_error(err));
+                    }
+                };
+
+                if file.status < 0 && ctx.role < store::Role::Auditor {
+                    Err(store::state::file_read_error((
+                        403,
+                        "file archived".to_string(),
+                    )))?;
+                }
+
+                if !permission::check_file_read(&ctx.ps, &canister, id, file.parent) {
+                    Err(store::state::file_read_error((
+                        403,
+                        "permission denied".to_string(),
+                    )))?;
+                }
+            }
+
+            let indexes = &indexes[..indexes.len().min(8)];
+            Ok(store::fs::get_chunks_by_index(id, indexes))
+        }
+    }
+}
+
+// reads exactly the requested bytes of a file, at most MAX_FILE_SIZE_PER_CALL bytes per call.
+#[ic_cdk::query]
+fn read_range(
+    id: u32,
+    offset: u64,
+    len: u64,
+    access_token: Option<ByteBuf>,
+) -> Result<ByteBuf, String> {
+    let file = readable_file(id, access_token)?;
+    if offset >= file.filled {
+        Err(format!("offset out of range, file filled {}", file.filled))?;
+    }
+
+    let len = len.min(file.filled - offset).min(MAX_FILE_SIZE_PER_CALL);
+    store::fs::read_range(id, offset, len).map(ByteBuf::from)
+}
+
+// opens a stream to read a fully uploaded file chunk by chunk, see ic_oss_types::stream.
+#[ic_cdk::query]
+fn open_read(id: u32, access_token: Option<ByteBuf>) -> Result<StreamHandle, String> {
+    let file = readable_file(id, access_token)?;
+    if file.size != file.filled {
+        Err("file not fully uploaded".to_string())?;
+    }
+    Ok(StreamHandle {
+        id,
+        offset: 0,
+        size: file.size,
+        content_type: file.content_type,
+        hash: file.hash,
+        updated_at: file.updated_at,
+    })
+}
+
+#[ic_cdk::query]
+fn read_next(handle: StreamHandle, access_token: Option<ByteBuf>) -> Result<StreamChunk, String> {
+    let file = readable_file(handle.id, access_token)?;
+    let (offset, len) = handle.next_range(file.size, file.updated_at)?;
+    let data = store::fs::read_range(handle.id, offset, len)?;
+    Ok(handle.chunk(data))
+}
+
+// gets the file that the caller can read with the access token.
+fn readable_file(id: u32, access_token: Option<ByteBuf>) -> Result<store::FileMetadata, String> {
+    let file = store::fs::get_file(id).ok_or_else(|| "file not found".to_string())?;
+    if file.read_by_hash(&access_token) {
+        return Ok(file);
+    }
+
+    let canister = ic_cdk::id();
+    let ctx = store::state::with(|s| {
+        s.file_read_permission(
+            ic_cdk::caller(),
+            &canister,
+            access_token,
+            ic_cdk::api::time() / SECONDS,
+            &file,
+        )
+    })
+    .map_err(store::state::file_read_error)?;
+
+    if file.status < 0 && ctx.role < store::Role::Auditor {
+        Err(store::state::file_read_error((
+            403,
+            "file archived".to_string(),
+        )))?;
+    }
+
+    if !permission::check_file_read(&ctx.ps, &canister, id, file.parent) {
+        Err(store::state::file_read_error((
+            403,
+            "permission denied".to_string(),
+        )))?;
+    }
+    Ok(file)
+}
+
+// deprecated: the `prev` id only works for id ordering, use list_files_with_options
+// with its opaque cursor tokens instead. It is marked deprecated in the .did and SDKs.
 #[ic_cdk::query]
 fn list_files(
     parent: u32,
@@ -206,11 +700,12 @@ fn list_files(
     let take = take.unwrap_or(10).min(100);
     let canister = ic_cdk::id();
     let ctx = match store::state::with(|s| {
-        s.read_permission(
+        s.list_permission(
             ic_cdk::caller(),
             &canister,
             access_token,
             ic_cdk::api::time() / SECONDS,
+            parent,
         )
     }) {
         Ok(ctx) => ctx,
@@ -225,6 +720,157 @@ fn list_files(
     Ok(store::fs::list_files(&ctx, parent, prev, take))
 }
 
+// lists files with sorting and filtering options, evaluated server-side.
+// the next_cursor is an opaque token bound to the parent and the options, pass it back
+// unchanged.
+#[ic_cdk::query]
+fn list_files_with_options(
+    parent: u32,
+    options: ListOptions,
+    access_token: Option<ByteBuf>,
+) -> Result<ListFilesOutput, String> {
+    options.validate(parent)?;
+    let canister = ic_cdk::id();
+    let ctx = match store::state::with(|s| {
+        s.list_permission(
+            ic_cdk::caller(),
+            &canister,
+            access_token,
+            ic_cdk::api::time() / SECONDS,
+            parent,
+        )
+    }) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    if !permission::check_file_list(&ctx.ps, &canister, parent) {
+        Err("permission denied".to_string())?;
+    }
+    Ok(store::fs::list_files_with_options(&ctx, parent, &options))
+}
+
+// returns the id of the file that the tag points to.
+#[ic_cdk::query]
+fn get_tag(name: String, access_token: Option<ByteBuf>) -> Result<u32, String> {
+    let id = store::fs::get_tag(&name).ok_or("tag not found")?;
+    let file = store::fs::get_file(id).ok_or_else(|| format!("file not found: {}", id))?;
+    let canister = ic_cdk::id();
+    let ctx = match store::state::with(|s| {
+        s.read_permission(
+            ic_cdk::caller(),
+            &canister,
+            access_token,
+            ic_cdk::api::time() / SECONDS,
+        )
+    }) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    if !permission::check_file_read(&ctx.ps, &canister, id, file.parent) {
+        Err("permission denied".to_string())?;
+    }
+    Ok(id)
+}
+
+// lists the share links of the file, the codes are secrets so the update permission
+// of the file is required.
+#[ic_cdk::query]
+fn list_shares(file: u32, access_token: Option<ByteBuf>) -> Result<Vec<ShareInfo>, String> {
+    let canister = ic_cdk::id();
+    let ctx = match store::state::with(|s| {
+        s.write_permission(
+            ic_cdk::caller(),
+            &canister,
+            access_token,
+            ic_cdk::api::time() / SECONDS,
+        )
+    }) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    let parent = store::fs::get_file(file).ok_or("file not found")?.parent;
+    if !permission::check_file_update(&ctx.ps, &canister, file, parent) {
+        Err("permission denied".to_string())?;
+    }
+    Ok(store::share::list(file))
+}
+
+// lists the annotations of the file after the prev id, the oldest first.
+#[ic_cdk::query]
+fn list_annotations(
+    file: u32,
+    prev: Option<u32>,
+    take: Option<u32>,
+    access_token: Option<ByteBuf>,
+) -> Result<Vec<Annotation>, String> {
+    let metadata = store::fs::get_file(file).ok_or("file not found")?;
+    let canister = ic_cdk::id();
+    let ctx = match store::state::with(|s| {
+        s.file_read_permission(
+            ic_cdk::caller(),
+            &canister,
+            access_token,
+            ic_cdk::api::time() / SECONDS,
+            &metadata,
+        )
+    }) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    if metadata.status < 0 && ctx.role < store::Role::Auditor {
+        Err("file archived".to_string())?;
+    }
+    if !permission::check_file_read(&ctx.ps, &canister, file, metadata.parent) {
+        Err("permission denied".to_string())?;
+    }
+    Ok(store::annotation::list(
+        file,
+        prev.unwrap_or(0),
+        take.unwrap_or(100).min(1000),
+    ))
+}
+
+// resolves a subfolder or file in the parent by its name, for path based addressing.
+#[ic_cdk::query]
+fn get_child_by_name(
+    parent: u32,
+    name: String,
+    access_token: Option<ByteBuf>,
+) -> Result<FolderChild, String> {
+    let canister = ic_cdk::id();
+    let ctx = match store::state::with(|s| {
+        s.list_permission(
+            ic_cdk::caller(),
+            &canister,
+            access_token,
+            ic_cdk::api::time() / SECONDS,
+            parent,
+        )
+    }) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    if !permission::check_file_list(&ctx.ps, &canister, parent) {
+        Err("permission denied".to_string())?;
+    }
+    store::fs::get_child_by_name(parent, &name).ok_or_else(|| "child not found".to_string())
+}
+
 #[ic_cdk::query]
 fn get_folder_info(id: u32, access_token: Option<ByteBuf>) -> Result<FolderInfo, String> {
     match store::fs::get_folder(id) {
@@ -232,11 +878,12 @@ fn get_folder_info(id: u32, access_token: Option<ByteBuf>) -> Result<FolderInfo,
         Some(meta) => {
             let canister = ic_cdk::id();
             let ctx = match store::state::with(|s| {
-                s.read_permission(
+                s.list_permission(
                     ic_cdk::caller(),
                     &canister,
                     access_token,
                     ic_cdk::api::time() / SECONDS,
+                    id,
                 )
             }) {
                 Ok(ctx) => ctx,
@@ -280,6 +927,8 @@ fn get_folder_ancestors(id: u32, access_token: Option<ByteBuf>) -> Result<Vec<Fo
     Ok(ancestors)
 }
 
+// deprecated: the `prev` id only works for id ordering, use list_folders_with_options
+// with its opaque cursor tokens instead. It is marked deprecated in the .did and SDKs.
 #[ic_cdk::query]
 fn list_folders(
     parent: u32,
@@ -292,11 +941,12 @@ fn list_folders(
 
     let canister = ic_cdk::id();
     let ctx = match store::state::with(|s| {
-        s.read_permission(
+        s.list_permission(
             ic_cdk::caller(),
             &canister,
             access_token,
             ic_cdk::api::time() / SECONDS,
+            parent,
         )
     }) {
         Ok(ctx) => ctx,
@@ -310,3 +960,39 @@ fn list_folders(
     }
     Ok(store::fs::list_folders(&ctx, parent, prev, take))
 }
+
+// lists folders with sorting and filtering options, evaluated server-side.
+// the next_cursor is an opaque token bound to the parent and the options, pass it back
+// unchanged.
+#[ic_cdk::query]
+fn list_folders_with_options(
+    parent: u32,
+    options: ListOptions,
+    access_token: Option<ByteBuf>,
+) -> Result<ListFoldersOutput, String> {
+    options.validate(parent)?;
+    if options.sort_by == Some(SortBy::Size) || options.content_type_prefix.is_some() {
+        Err("folders can not be sorted by size or filtered by content type".to_string())?;
+    }
+
+    let canister = ic_cdk::id();
+    let ctx = match store::state::with(|s| {
+        s.list_permission(
+            ic_cdk::caller(),
+            &canister,
+            access_token,
+            ic_cdk::api::time() / SECONDS,
+            parent,
+        )
+    }) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    if !permission::check_folder_list(&ctx.ps, &canister, parent) {
+        Err("permission denied".to_string())?;
+    }
+    Ok(store::fs::list_folders_with_options(&ctx, parent, &options))
+}
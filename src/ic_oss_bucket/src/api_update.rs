@@ -1,8 +1,19 @@
-use ic_oss_types::{file::*, folder::*, to_cbor_bytes};
-use serde_bytes::ByteBuf;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use candid::Principal;
+use ic_cdk::api::management_canister::http_request::{HttpHeader, HttpMethod};
+use ic_oss_types::{
+    bucket::{leading_zero_bits, upload_pow_hash, UploadChallenge},
+    cose::{coset::CborSerializable, sha256, Receipt, RECEIPT_AAD},
+    file::*,
+    folder::*,
+    hash::IncrementalHasher,
+};
+use serde_bytes::{ByteArray, ByteBuf};
 use std::collections::BTreeSet;
 
-use crate::{permission, store, MILLISECONDS, SECONDS};
+use crate::{ecdsa, job, outcall, permission, store, ANONYMOUS, MILLISECONDS, SECONDS};
+
+static RECEIPT_DERIVATION_PATH: &[u8] = b"ic_oss_receipt";
 
 #[ic_cdk::update]
 fn create_file(
@@ -16,23 +27,17 @@ fn create_file(
         if size > s.max_file_size {
             return Err(format!("file size exceeds the limit {}", s.max_file_size));
         }
-        if let Some(ref custom) = input.custom {
-            let len = to_cbor_bytes(custom).len();
-            if len > s.max_custom_data_size as usize {
-                return Err(format!(
-                    "custom data size exceeds the limit {}",
-                    s.max_custom_data_size
-                ));
-            }
-        }
-        Ok(())
+        check_custom_data_size(input.custom.as_ref(), s.max_custom_data_size)
     })?;
 
     let now_ms = ic_cdk::api::time() / MILLISECONDS;
     let canister = ic_cdk::id();
-    let ctx = match store::state::with(|s| {
-        s.write_permission(ic_cdk::caller(), &canister, access_token, now_ms / 1000)
-    }) {
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &canister,
+        access_token,
+        now_ms / 1000,
+    ) {
         Ok(ctx) => ctx,
         Err((_, err)) => {
             return Err(err);
@@ -43,49 +48,103 @@ fn create_file(
         Err("permission denied".to_string())?;
     }
 
-    let res: Result<CreateFileOutput, String> = {
-        let id = store::fs::add_file(store::FileMetadata {
-            parent: input.parent,
-            name: input.name,
-            content_type: input.content_type,
-            size,
-            hash: input.hash,
-            dek: input.dek,
-            custom: input.custom,
-            created_at: now_ms,
-            updated_at: now_ms,
-            ..Default::default()
-        })?;
+    let idempotency = input
+        .idempotency_key
+        .as_ref()
+        .map(|key| store::IdempotencyKeys::digest(&ctx.caller, key));
+    if let Some(digest) = &idempotency {
+        // a retried call returns the file created by the first call if it still exists
+        if let Some((id, created_at)) = store::state::idempotent_file(digest, now_ms) {
+            return Ok(CreateFileOutput { id, created_at });
+        }
+    }
 
-        if let Some(content) = input.content {
-            if size > 0 && content.len() != size as usize {
-                Err("content size mismatch".to_string())?;
+    match add_file(input, size, now_ms) {
+        Ok(output) => {
+            if let Some(digest) = idempotency {
+                store::state::with_mut(|s| s.idempotency_keys.insert(digest, output.id, now_ms));
             }
+            Ok(output)
+        }
+        Err(err) => {
+            // trap and rollback state
+            ic_cdk::trap(&format!("create file failed: {}", err));
+        }
+    }
+}
 
-            for (i, chunk) in content.chunks(CHUNK_SIZE as usize).enumerate() {
-                store::fs::update_chunk(id, i as u32, now_ms, chunk.to_vec(), |_| Ok(()))?;
-            }
+// anonymous callers can create a small file in the drop-box folder
+// with a one-time upload token or a proof of work challenge.
+#[ic_cdk::update]
+fn create_file_with_challenge(
+    input: CreateFileInput,
+    challenge: UploadChallenge,
+) -> Result<CreateFileOutput, String> {
+    input.validate()?;
 
-            if input.status.is_some() {
-                store::fs::update_file(
-                    UpdateFileInput {
-                        id,
-                        status: input.status,
-                        ..Default::default()
-                    },
-                    now_ms,
-                    |_| Ok(()),
-                )?;
-            }
+    let content = input.content.as_ref().ok_or("file content is required")?;
+    let size = content.len() as u64;
+    let now_ms = ic_cdk::api::time() / MILLISECONDS;
+    let now_sec = now_ms / 1000;
+    let canister = ic_cdk::id();
+    let cfg = store::state::with(|s| {
+        if s.status != 0 {
+            Err("bucket is not writable".to_string())?;
+        }
+        if s.anonymous_upload.max_files_per_hour == 0 {
+            Err("anonymous upload is disabled".to_string())?;
+        }
+        if input.parent != s.anonymous_upload.folder {
+            Err(format!(
+                "anonymous upload is only allowed in folder {}",
+                s.anonymous_upload.folder
+            ))?;
         }
+        if size > s.anonymous_upload.max_file_size {
+            Err(format!(
+                "file size exceeds the limit {}",
+                s.anonymous_upload.max_file_size
+            ))?;
+        }
+        check_custom_data_size(input.custom.as_ref(), s.max_custom_data_size)?;
+        Ok::<_, String>(s.anonymous_upload.clone())
+    })?;
 
-        Ok(CreateFileOutput {
-            id,
-            created_at: now_ms,
-        })
+    let (digest, expire_at) = match challenge {
+        UploadChallenge::Token(token) => {
+            let ctx = store::state::write_permission(
+                ic_cdk::caller(),
+                &canister,
+                Some(token.clone()),
+                now_sec,
+            )
+            .map_err(|(_, err)| err)?;
+            if !permission::check_file_create(&ctx.ps, &canister, input.parent) {
+                Err("permission denied".to_string())?;
+            }
+            (sha256(&token), now_sec + UPLOAD_TOKEN_RETENTION)
+        }
+        UploadChallenge::Pow { timestamp, nonce } => {
+            if cfg.pow_difficulty == 0 {
+                Err("proof of work challenge is disabled".to_string())?;
+            }
+            if timestamp.abs_diff(now_sec) > UPLOAD_POW_TTL {
+                Err("proof of work challenge is expired".to_string())?;
+            }
+            let hash = upload_pow_hash(&canister, &sha256(content), timestamp, nonce);
+            if leading_zero_bits(&hash) < cfg.pow_difficulty as u32 {
+                Err("invalid proof of work challenge".to_string())?;
+            }
+            (hash, timestamp + UPLOAD_POW_TTL)
+        }
     };
 
-    match res {
+    store::state::with_mut(|s| {
+        s.upload_challenges
+            .consume(digest, expire_at, now_sec, cfg.max_files_per_hour)
+    })?;
+
+    match add_file(input, size, now_ms) {
         Ok(output) => Ok(output),
         Err(err) => {
             // trap and rollback state
@@ -94,6 +153,387 @@ fn create_file(
     }
 }
 
+// one-time upload tokens are remembered for 24 hours,
+// so they should be issued with a shorter expiration.
+const UPLOAD_TOKEN_RETENTION: u64 = 3600 * 24;
+const UPLOAD_POW_TTL: u64 = 60 * 5;
+
+fn add_file(input: CreateFileInput, size: u64, now_ms: u64) -> Result<CreateFileOutput, String> {
+    let id = store::fs::add_file(store::FileMetadata {
+        parent: input.parent,
+        name: input.name,
+        content_type: input.content_type,
+        size,
+        hash: input.hash,
+        dek: input.dek,
+        custom: input.custom,
+        created_at: now_ms,
+        updated_at: now_ms,
+        ..Default::default()
+    })?;
+
+    if let Some(content) = input.content {
+        if size > 0 && content.len() as u64 != size {
+            Err("content size mismatch".to_string())?;
+        }
+
+        let chunk_size = store::state::with(|s| s.chunk_size());
+        for (i, chunk) in content.chunks(chunk_size as usize).enumerate() {
+            store::fs::update_chunk(id, i as u32, now_ms, chunk.to_vec(), |_| Ok(()))?;
+        }
+
+        if input.status.is_some() {
+            store::fs::update_file(
+                UpdateFileInput {
+                    id,
+                    status: input.status,
+                    ..Default::default()
+                },
+                now_ms,
+                |_| Ok(()),
+            )?;
+        }
+    }
+
+    Ok(CreateFileOutput {
+        id,
+        created_at: now_ms,
+    })
+}
+
+// the outcalls of an import are paid by the bucket, so the imported file is limited
+const MAX_IMPORT_SIZE: u64 = 256 * 1024 * 1024;
+
+// each outcall fetches a range of whole chunks, the response should be less than 2MB,
+// e.g. 7 chunks of the default CHUNK_SIZE
+fn import_range_size(chunk_size: u32) -> u64 {
+    let chunk_size = chunk_size as u64;
+    let max_body = outcall::MAX_RESPONSE_BYTES - outcall::MAX_HEADERS_BYTES;
+    chunk_size * (max_body / chunk_size).max(1)
+}
+
+// fetches the content of the url with HTTPS outcalls into a new file,
+// the content type is captured from the response. The file is limited to MAX_IMPORT_SIZE,
+// and the cycles of an outcall are paid for the requested range only.
+#[ic_cdk::update]
+async fn import_from_url(
+    url: String,
+    parent: u32,
+    name: String,
+    access_token: Option<ByteBuf>,
+) -> Result<FileInfo, String> {
+    if !url.starts_with("https://") {
+        Err("only https url is supported".to_string())?;
+    }
+    if !valid_file_name(&name) {
+        Err("invalid file name".to_string())?;
+    }
+
+    let now_ms = ic_cdk::api::time() / MILLISECONDS;
+    let canister = ic_cdk::id();
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &canister,
+        access_token,
+        now_ms / 1000,
+    ) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    if !permission::check_file_create(&ctx.ps, &canister, parent) {
+        Err("permission denied".to_string())?;
+    }
+
+    let max_size = store::state::with(|s| s.max_file_size).min(MAX_IMPORT_SIZE);
+    let mut id: Option<u32> = None;
+    let res = import_chunks(&url, parent, name, max_size, &mut id).await;
+    match (res, id) {
+        (Ok(()), Some(id)) => store::fs::get_file(id)
+            .map(|file| file.into_info(id))
+            .ok_or_else(|| format!("file not found: {}", id)),
+        (Ok(()), None) => Err("no content imported".to_string()),
+        (Err(err), id) => {
+            // the state is committed across the outcalls, so the partial file should be removed
+            if let Some(id) = id {
+                let now_ms = ic_cdk::api::time() / MILLISECONDS;
+                let _ = store::fs::delete_file(id, now_ms, |_| Ok(()));
+            }
+            Err(format!("import from {} failed: {}", url, err))
+        }
+    }
+}
+
+async fn import_chunks(
+    url: &str,
+    parent: u32,
+    name: String,
+    max_size: u64,
+    id: &mut Option<u32>,
+) -> Result<(), String> {
+    let chunk_size = store::state::with(|s| s.chunk_size());
+    let range_size = import_range_size(chunk_size);
+    let mut hasher = IncrementalHasher::new();
+    let mut offset = 0u64;
+    loop {
+        let (status, res) = outcall::http_call(
+            HttpMethod::GET,
+            url.to_string(),
+            vec![HttpHeader {
+                name: "range".to_string(),
+                value: format!("bytes={}-{}", offset, offset + range_size - 1),
+            }],
+            None,
+            range_size + outcall::MAX_HEADERS_BYTES,
+        )
+        .await?;
+
+        let (total, is_last) = match status {
+            206 => {
+                let (start, end, total) = outcall::header(&res, "content-range")
+                    .and_then(outcall::parse_content_range)
+                    .ok_or("invalid content-range header")?;
+                if start != offset || end - start + 1 != res.body.len() as u64 {
+                    Err("unexpected content range".to_string())?;
+                }
+                let next = end + 1;
+                let is_last = match total {
+                    Some(total) => next >= total,
+                    None => (res.body.len() as u64) < range_size,
+                };
+                if !is_last && (res.body.len() as u64) < range_size {
+                    Err("unexpected partial content".to_string())?;
+                }
+                (total, is_last)
+            }
+            // the server does not support range requests, the response is the full content
+            200 if offset == 0 => (Some(res.body.len() as u64), true),
+            _ => Err(format!("unexpected response status {}", status))?,
+        };
+
+        let size = offset + res.body.len() as u64;
+        if total.unwrap_or(size).max(size) > max_size {
+            Err(format!("file size exceeds the limit {}", max_size))?;
+        }
+
+        let now_ms = ic_cdk::api::time() / MILLISECONDS;
+        let file_id = match *id {
+            Some(file_id) => file_id,
+            None => {
+                let content_type = outcall::header(&res, "content-type")
+                    .filter(|v| !v.is_empty())
+                    .unwrap_or("application/octet-stream");
+                let file_id = store::fs::add_file(store::FileMetadata {
+                    parent,
+                    name: name.clone(),
+                    content_type: content_type.to_string(),
+                    size: total.unwrap_or(0),
+                    // the hash is set when the content is fully imported
+                    created_at: now_ms,
+                    updated_at: now_ms,
+                    ..Default::default()
+                })?;
+                *id = Some(file_id);
+                file_id
+            }
+        };
+
+        hasher.update(&res.body);
+        let start_index = (offset / chunk_size as u64) as u32;
+        for (i, chunk) in res.body.chunks(chunk_size as usize).enumerate() {
+            store::fs::update_chunk(
+                file_id,
+                start_index + i as u32,
+                now_ms,
+                chunk.to_vec(),
+                |_| Ok(()),
+            )?;
+        }
+
+        offset = size;
+        if is_last {
+            if offset == 0 {
+                Err("empty content".to_string())?;
+            }
+            return store::fs::update_file(
+                UpdateFileInput {
+                    id: file_id,
+                    size: Some(offset),
+                    hash: Some(hasher.finalize().into()),
+                    ..Default::default()
+                },
+                now_ms,
+                |_| Ok(()),
+            );
+        }
+    }
+}
+
+// copies a file from a source canister that implements the `get_file_info` and
+// `get_file_chunks` queries of the bucket, such as a canister with ic_oss_can fs.
+// the bucket calls the source with its own identity.
+#[ic_cdk::update]
+async fn pull_file_from(
+    canister: Principal,
+    remote_id: u32,
+    parent: u32,
+    access_token: Option<ByteBuf>,
+) -> Result<FileInfo, String> {
+    let now_ms = ic_cdk::api::time() / MILLISECONDS;
+    let this = ic_cdk::id();
+    if canister == this {
+        Err("cannot pull from the bucket itself".to_string())?;
+    }
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &this,
+        access_token,
+        now_ms / 1000,
+    ) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    if !permission::check_file_create(&ctx.ps, &this, parent) {
+        Err("permission denied".to_string())?;
+    }
+
+    let mut id: Option<u32> = None;
+    let res = pull_chunks(canister, remote_id, parent, &mut id).await;
+    match (res, id) {
+        (Ok(()), Some(id)) => store::fs::get_file(id)
+            .map(|file| file.into_info(id))
+            .ok_or_else(|| format!("file not found: {}", id)),
+        (Ok(()), None) => Err("no content pulled".to_string()),
+        (Err(err), id) => {
+            // the state is committed across the calls, so the partial file should be removed
+            if let Some(id) = id {
+                let now_ms = ic_cdk::api::time() / MILLISECONDS;
+                let _ = store::fs::delete_file(id, now_ms, |_| Ok(()));
+            }
+            Err(format!(
+                "pull file {} from {} failed: {}",
+                remote_id,
+                canister.to_text(),
+                err
+            ))
+        }
+    }
+}
+
+async fn pull_chunks(
+    canister: Principal,
+    remote_id: u32,
+    parent: u32,
+    id: &mut Option<u32>,
+) -> Result<(), String> {
+    let (res,): (Result<FileInfo, String>,) =
+        ic_cdk::call(canister, "get_file_info", (remote_id, None::<ByteBuf>))
+            .await
+            .map_err(|(code, msg)| format!("call get_file_info failed: {:?}, {}", code, msg))?;
+    let info = res?;
+    if info.size == 0 || info.filled != info.size {
+        Err("file not fully uploaded".to_string())?;
+    }
+    store::state::with(|s| {
+        if info.size > s.max_file_size {
+            Err(format!("file size exceeds the limit {}", s.max_file_size))?;
+        }
+        check_custom_data_size(info.custom.as_ref(), s.max_custom_data_size)
+    })?;
+
+    let now_ms = ic_cdk::api::time() / MILLISECONDS;
+    let file_id = store::fs::add_file_without_hash(store::FileMetadata {
+        parent,
+        name: info.name,
+        content_type: info.content_type,
+        size: info.size,
+        dek: info.dek,
+        custom: info.custom,
+        created_at: now_ms,
+        updated_at: now_ms,
+        ..Default::default()
+    })?;
+    *id = Some(file_id);
+
+    let chunk_size = store::state::with(|s| s.chunk_size()) as usize;
+    let encrypted = info.dek.is_some();
+    let mut hasher = IncrementalHasher::new();
+    let mut index = 0u32;
+    // the remote chunks are written again by the chunk size of the bucket
+    let mut buf: Vec<u8> = Vec::with_capacity(chunk_size);
+    let mut next_chunk = 0u32;
+    while index < info.chunks {
+        let (res,): (Result<Vec<FileChunk>, String>,) = ic_cdk::call(
+            canister,
+            "get_file_chunks",
+            (remote_id, index, Some(8u32), None::<ByteBuf>),
+        )
+        .await
+        .map_err(|(code, msg)| format!("call get_file_chunks failed: {:?}, {}", code, msg))?;
+        let chunks = res?;
+        if chunks.is_empty() {
+            Err(format!("file chunk not found: {}", index))?;
+        }
+
+        let now_ms = ic_cdk::api::time() / MILLISECONDS;
+        for FileChunk(i, chunk) in chunks {
+            // the chunks should be continuous to be hashed in order
+            if i != index {
+                Err(format!("file chunk not found: {}", index))?;
+            }
+            // an encrypted chunk is sealed as a whole, it can not be split
+            if encrypted && i + 1 < info.chunks && chunk.len() != chunk_size {
+                Err(format!(
+                    "chunk size mismatch of the encrypted file, expected {} bytes, got {}",
+                    chunk_size,
+                    chunk.len()
+                ))?;
+            }
+            hasher.update(&chunk);
+            buf.extend_from_slice(&chunk);
+            index += 1;
+        }
+
+        while buf.len() >= chunk_size {
+            let rest = buf.split_off(chunk_size);
+            let chunk = std::mem::replace(&mut buf, rest);
+            store::fs::update_chunk(file_id, next_chunk, now_ms, chunk, |_| Ok(()))?;
+            next_chunk += 1;
+        }
+    }
+    if !buf.is_empty() {
+        let now_ms = ic_cdk::api::time() / MILLISECONDS;
+        store::fs::update_chunk(file_id, next_chunk, now_ms, buf, |_| Ok(()))?;
+    }
+
+    let filled = hasher.len();
+    if filled != info.size {
+        Err(format!(
+            "file size mismatch, expected {}, got {}",
+            info.size, filled
+        ))?;
+    }
+    // the remote hash is not trusted, it should match the hash of the received chunks
+    let hash: ByteArray<32> = hasher.finalize().into();
+    if info.hash.is_some_and(|h| h != hash) {
+        Err("file hash mismatch".to_string())?;
+    }
+    store::fs::update_file(
+        UpdateFileInput {
+            id: file_id,
+            hash: Some(hash),
+            ..Default::default()
+        },
+        ic_cdk::api::time() / MILLISECONDS,
+        |_| Ok(()),
+    )
+}
+
 #[ic_cdk::update]
 fn update_file_info(
     input: UpdateFileInput,
@@ -106,23 +546,17 @@ fn update_file_info(
             return Err(format!("file size exceeds the limit {}", s.max_file_size));
         }
 
-        if let Some(ref custom) = input.custom {
-            let len = to_cbor_bytes(custom).len();
-            if len > s.max_custom_data_size as usize {
-                return Err(format!(
-                    "custom data size exceeds the limit {}",
-                    s.max_custom_data_size
-                ));
-            }
-        }
-        Ok(())
+        check_custom_data_size(input.custom.as_ref(), s.max_custom_data_size)
     })?;
 
     let now_ms = ic_cdk::api::time() / MILLISECONDS;
     let canister = ic_cdk::id();
-    let ctx = match store::state::with(|s| {
-        s.write_permission(ic_cdk::caller(), &canister, access_token, now_ms / 1000)
-    }) {
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &canister,
+        access_token,
+        now_ms / 1000,
+    ) {
         Ok(ctx) => ctx,
         Err((_, err)) => {
             return Err(err);
@@ -146,6 +580,193 @@ fn update_file_info(
     }
 }
 
+// sets or removes (None) the password to read the file over HTTP, the password_hash
+// is an argon2 PHC string, e.g. "$argon2id$v=19$m=19456,t=2,p=1$...".
+#[ic_cdk::update]
+fn set_file_password(
+    id: u32,
+    password_hash: Option<String>,
+    access_token: Option<ByteBuf>,
+) -> Result<(), String> {
+    let canister = ic_cdk::id();
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &canister,
+        access_token,
+        ic_cdk::api::time() / SECONDS,
+    ) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    store::fs::set_file_password(
+        id,
+        password_hash,
+        |file| match permission::check_file_update(&ctx.ps, &canister, id, file.parent) {
+            true => Ok(()),
+            false => Err("permission denied".to_string()),
+        },
+    )
+}
+
+// schedules a background job to hash the stored chunks of the file,
+// the result is written into the file's `hash_verified` field.
+#[ic_cdk::update]
+fn request_hash_verification(id: u32, access_token: Option<ByteBuf>) -> Result<(), String> {
+    let canister = ic_cdk::id();
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &canister,
+        access_token,
+        ic_cdk::api::time() / SECONDS,
+    ) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    let file = store::fs::get_file(id).ok_or("file not found")?;
+    if !permission::check_file_update(&ctx.ps, &canister, id, file.parent) {
+        Err("permission denied".to_string())?;
+    }
+    if file.hash.is_none() {
+        Err("file hash is required".to_string())?;
+    }
+    if file.size != file.filled {
+        Err("file not fully uploaded".to_string())?;
+    }
+
+    if store::fs::add_hash_verification(id, file.updated_at) {
+        job::start_hash_verification();
+    }
+    Ok(())
+}
+
+// finalizes a fully uploaded file: sets its hash and makes it readonly in one call, so
+// no chunk write can slip in between, see FinalizeFileInput.
+#[ic_cdk::update]
+fn finalize_file(
+    input: FinalizeFileInput,
+    access_token: Option<ByteBuf>,
+) -> Result<FinalizeFileOutput, String> {
+    let now_ms = ic_cdk::api::time() / MILLISECONDS;
+    let canister = ic_cdk::id();
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &canister,
+        access_token,
+        now_ms / 1000,
+    ) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    let id = input.id;
+    let verified = store::fs::finalize_file(id, input.hash, input.verify, now_ms, |file| {
+        match permission::check_file_update(&ctx.ps, &canister, id, file.parent) {
+            true => Ok(()),
+            false => Err("permission denied".to_string()),
+        }
+    })?;
+    if input.verify && !verified && store::fs::add_hash_verification(id, now_ms) {
+        job::start_hash_verification();
+    }
+    Ok(FinalizeFileOutput {
+        updated_at: now_ms,
+        hash_verified: verified.then_some(true),
+    })
+}
+
+// signs a receipt of the file for the caller with the bucket's threshold ECDSA key,
+// external systems can verify it with the receipt_public_key from get_bucket_info.
+// the receipt of the same file content is returned from the cache, and a caller without
+// the manager role gets one new receipt per RECEIPT_INTERVAL.
+#[ic_cdk::update]
+async fn sign_receipt(id: u32, access_token: Option<ByteBuf>) -> Result<ByteBuf, String> {
+    let canister = ic_cdk::id();
+    let now_ms = ic_cdk::api::time() / MILLISECONDS;
+    let ctx = match store::state::with(|s| {
+        s.read_permission(ic_cdk::caller(), &canister, access_token, now_ms / 1000)
+    }) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+    if ctx.caller == ANONYMOUS {
+        Err("anonymous recipient is not allowed".to_string())?;
+    }
+
+    let file = store::fs::get_file(id).ok_or("file not found")?;
+    if file.status < 0 && ctx.role < store::Role::Auditor {
+        Err("file archived".to_string())?;
+    }
+    store::state::with(|s| s.inbox_check(&ic_cdk::caller(), file.parent))
+        .map_err(|(_, err)| err)?;
+    if !permission::check_file_read(&ctx.ps, &canister, id, file.parent) {
+        Err("permission denied".to_string())?;
+    }
+    if file.size != file.filled {
+        Err("file not fully uploaded".to_string())?;
+    }
+    let hash = file.hash.ok_or("file hash is required")?;
+
+    let (key_name, public_key) =
+        store::state::with(|s| (s.receipt_key_name.clone(), s.receipt_public_key.clone()));
+    if key_name.is_empty() {
+        Err("receipts are disabled".to_string())?;
+    }
+
+    // the receipt of the same content is signed once, and the readers are rate limited
+    let digest = store::SignedReceipts::digest(&ctx.caller, id, &hash);
+    if let Some(receipt) = store::state::with(|s| s.signed_receipts.get(&digest)) {
+        return Ok(receipt);
+    }
+    if ctx.role < store::Role::Manager {
+        store::state::with_mut(|s| s.signed_receipts.reserve(&ctx.caller, now_ms))?;
+    }
+
+    let receipt = Receipt {
+        bucket: canister,
+        file: id,
+        hash,
+        size: file.size,
+        recipient: ctx.caller,
+        timestamp: now_ms,
+    };
+    let mut sign1 = receipt.to_sign1()?;
+    let message_hash = sha256(&sign1.tbs_data(RECEIPT_AAD));
+    sign1.signature = ecdsa::sign_with(
+        &key_name,
+        vec![RECEIPT_DERIVATION_PATH.to_vec()],
+        message_hash,
+    )
+    .await?;
+
+    // publishes the public key for the verifiers
+    if public_key.is_none() {
+        let pk = ecdsa::public_key_with(&key_name, vec![RECEIPT_DERIVATION_PATH.to_vec()]).await?;
+        store::state::with_mut(|s| {
+            if s.receipt_key_name == key_name {
+                s.receipt_public_key = Some(ByteBuf::from(pk.public_key));
+            }
+        });
+    }
+
+    let data = ByteBuf::from(sign1.to_vec().map_err(|err| err.to_string())?);
+    store::state::with_mut(|s| {
+        if s.receipt_key_name == key_name {
+            s.signed_receipts.insert(digest, data.clone(), now_ms);
+        }
+    });
+    Ok(data)
+}
+
 #[ic_cdk::update]
 fn update_file_chunk(
     input: UpdateFileChunkInput,
@@ -153,14 +774,12 @@ fn update_file_chunk(
 ) -> Result<UpdateFileChunkOutput, String> {
     let now_ms = ic_cdk::api::time() / MILLISECONDS;
     let canister = ic_cdk::id();
-    let ctx = match store::state::with(|s| {
-        s.write_permission(
-            ic_cdk::caller(),
-            &canister,
-            access_token,
-            ic_cdk::api::time() / SECONDS,
-        )
-    }) {
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &canister,
+        access_token,
+        ic_cdk::api::time() / SECONDS,
+    ) {
         Ok(ctx) => ctx,
         Err((_, err)) => {
             return Err(err);
@@ -168,6 +787,10 @@ fn update_file_chunk(
     };
 
     let id = input.id;
+    store::state::with(|s| {
+        s.chunk_leases
+            .check(&ctx.caller, id, input.chunk_index, input.lease, now_ms)
+    })?;
     let res = store::fs::update_chunk(
         input.id,
         input.chunk_index,
@@ -191,13 +814,76 @@ fn update_file_chunk(
     }
 }
 
+// leases the chunks of a file to the caller, so the uploaders can fill different
+// parts of the file in parallel, see store::ChunkLeases.
+#[ic_cdk::update]
+fn acquire_chunk_lease(
+    input: ChunkLeaseInput,
+    access_token: Option<ByteBuf>,
+) -> Result<ChunkLeaseInfo, String> {
+    input.validate()?;
+    let now_ms = ic_cdk::api::time() / MILLISECONDS;
+    let canister = ic_cdk::id();
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &canister,
+        access_token,
+        now_ms / 1000,
+    ) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    let file = store::fs::get_file(input.id).ok_or_else(|| "file not found".to_string())?;
+    if file.status != 0 {
+        Err(format!("file {} is not writable", input.id))?;
+    }
+    if !permission::check_file_update(&ctx.ps, &canister, input.id, file.parent) {
+        Err("permission denied".to_string())?;
+    }
+    let chunk_size = store::state::with(|s| s.chunk_size());
+    if file.size > 0 && input.end > file_chunks(file.size, chunk_size) {
+        Err(format!(
+            "chunk range [{}, {}) exceeds the file size {}",
+            input.start, input.end, file.size
+        ))?;
+    }
+
+    store::state::with_mut(|s| s.chunk_leases.acquire(ctx.caller, &input, now_ms))
+}
+
+#[ic_cdk::update]
+fn release_chunk_lease(lease: u64, access_token: Option<ByteBuf>) -> Result<bool, String> {
+    let canister = ic_cdk::id();
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &canister,
+        access_token,
+        ic_cdk::api::time() / SECONDS,
+    ) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    Ok(store::state::with_mut(|s| {
+        s.chunk_leases.release(&ctx.caller, lease)
+    }))
+}
+
 #[ic_cdk::update]
 fn move_file(input: MoveInput, access_token: Option<ByteBuf>) -> Result<UpdateFileOutput, String> {
     let now_ms = ic_cdk::api::time() / MILLISECONDS;
     let canister = ic_cdk::id();
-    let ctx = match store::state::with(|s| {
-        s.write_permission(ic_cdk::caller(), &canister, access_token, now_ms / 1000)
-    }) {
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &canister,
+        access_token,
+        now_ms / 1000,
+    ) {
         Ok(ctx) => ctx,
         Err((_, err)) => {
             return Err(err);
@@ -216,18 +902,291 @@ fn move_file(input: MoveInput, access_token: Option<ByteBuf>) -> Result<UpdateFi
     Ok(UpdateFileOutput { updated_at: now_ms })
 }
 
+// copies a fully uploaded file into the folder without downloading and uploading it,
+// the copy keeps the name of the source file if no name is given.
 #[ic_cdk::update]
-fn delete_file(id: u32, access_token: Option<ByteBuf>) -> Result<bool, String> {
+fn copy_file(
+    id: u32,
+    to: u32,
+    name: Option<String>,
+    access_token: Option<ByteBuf>,
+) -> Result<CreateFileOutput, String> {
+    if let Some(ref name) = name {
+        if !valid_file_name(name) {
+            Err("invalid file name".to_string())?;
+        }
+    }
+
+    let now_ms = ic_cdk::api::time() / MILLISECONDS;
+    let canister = ic_cdk::id();
+    let caller = ic_cdk::caller();
+    let ctx = match store::state::write_permission(caller, &canister, access_token, now_ms / 1000) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    if !permission::check_file_create(&ctx.ps, &canister, to) {
+        Err("permission denied".to_string())?;
+    }
+
+    let id = store::fs::copy_file(id, to, name, now_ms, |file| {
+        store::state::with(|s| s.inbox_check(&caller, file.parent)).map_err(|(_, err)| err)?;
+        match permission::check_file_read(&ctx.ps, &canister, id, file.parent) {
+            true => Ok(()),
+            false => Err("permission denied".to_string()),
+        }
+    })?;
+    Ok(CreateFileOutput {
+        id,
+        created_at: now_ms,
+    })
+}
+
+// moves the files from one folder to another in one call, no file is moved if any check fails.
+#[ic_cdk::update]
+fn move_files(
+    ids: BTreeSet<u32>,
+    from: u32,
+    to: u32,
+    access_token: Option<ByteBuf>,
+) -> Result<UpdateFileOutput, String> {
+    let now_ms = ic_cdk::api::time() / MILLISECONDS;
+    let canister = ic_cdk::id();
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &canister,
+        access_token,
+        now_ms / 1000,
+    ) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    if !permission::check_file_delete(&ctx.ps, &canister, from) {
+        Err("permission denied".to_string())?;
+    }
+
+    if !permission::check_file_create(&ctx.ps, &canister, to) {
+        Err("permission denied".to_string())?;
+    }
+
+    store::fs::move_files(&ids, from, to, now_ms)?;
+    Ok(UpdateFileOutput { updated_at: now_ms })
+}
+
+// points the tag to a fully uploaded file, e.g. "latest" for release workflows.
+// the caller should be able to update both the current and the new target file.
+#[ic_cdk::update]
+fn set_tag(name: String, id: u32, access_token: Option<ByteBuf>) -> Result<(), String> {
+    if !valid_tag_name(&name) {
+        Err("invalid tag name".to_string())?;
+    }
+
+    let now_ms = ic_cdk::api::time() / MILLISECONDS;
+    let canister = ic_cdk::id();
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &canister,
+        access_token,
+        now_ms / 1000,
+    ) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    let file = store::fs::get_file(id).ok_or_else(|| format!("file not found: {}", id))?;
+    if !permission::check_file_update(&ctx.ps, &canister, id, file.parent) {
+        Err("permission denied".to_string())?;
+    }
+    if file.size != file.filled {
+        Err("file not fully uploaded".to_string())?;
+    }
+    check_tag_update(&ctx, &canister, &name)?;
+    store::fs::set_tag(name, id)?;
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn delete_tag(name: String, access_token: Option<ByteBuf>) -> Result<bool, String> {
+    let now_ms = ic_cdk::api::time() / MILLISECONDS;
+    let canister = ic_cdk::id();
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &canister,
+        access_token,
+        now_ms / 1000,
+    ) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    check_tag_update(&ctx, &canister, &name)?;
+    Ok(store::fs::delete_tag(&name).is_some())
+}
+
+// a tag that points to a deleted file can be updated by any writer.
+fn check_tag_update(ctx: &store::Context, canister: &Principal, name: &str) -> Result<(), String> {
+    if let Some(prev) = store::fs::get_tag(name) {
+        if let Some(file) = store::fs::get_file(prev) {
+            if !permission::check_file_update(&ctx.ps, canister, prev, file.parent) {
+                Err("permission denied".to_string())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// creates a share link "/s/<code>" to download the file without an access token,
+// the link can expire, be limited to a number of downloads and protected by a password.
+#[ic_cdk::update]
+async fn create_share(
+    input: CreateShareInput,
+    access_token: Option<ByteBuf>,
+) -> Result<ShareInfo, String> {
     let now_ms = ic_cdk::api::time() / MILLISECONDS;
+    input.validate(now_ms)?;
+
     let canister = ic_cdk::id();
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &canister,
+        access_token,
+        now_ms / 1000,
+    ) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    let file = store::fs::get_file(input.file).ok_or("file not found")?;
+    if !permission::check_file_update(&ctx.ps, &canister, input.file, file.parent) {
+        Err("permission denied".to_string())?;
+    }
+
+    let (rand,) = ic_cdk::api::management_canister::main::raw_rand()
+        .await
+        .map_err(|(_, err)| format!("failed to generate random bytes: {}", err))?;
+    let code = URL_SAFE_NO_PAD.encode(&rand[..16]);
+    store::share::create(
+        code,
+        store::Share {
+            file: input.file,
+            created_by: ctx.caller,
+            created_at: now_ms,
+            expires_at: input.expires_at,
+            max_downloads: input.max_downloads,
+            downloads: 0,
+            password_hash: input.password_hash,
+            stream_key: Some(sha256(&rand).into()),
+        },
+        now_ms,
+    )
+}
+
+#[ic_cdk::update]
+fn revoke_share(code: String, access_token: Option<ByteBuf>) -> Result<bool, String> {
+    let canister = ic_cdk::id();
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &canister,
+        access_token,
+        ic_cdk::api::time() / SECONDS,
+    ) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
+
+    match store::share::get(&code) {
+        None => Ok(false),
+        Some(share) => {
+            // the share of a deleted file can be revoked with the bucket-wide permission
+            let parent = store::fs::get_file(share.file)
+                .map(|file| file.parent)
+                .unwrap_or_default();
+            if !permission::check_file_update(&ctx.ps, &canister, share.file, parent) {
+                Err("permission denied".to_string())?;
+            }
+            Ok(store::share::revoke(&code))
+        }
+    }
+}
+
+// adds an annotation to the file, the readers of the file can comment on it.
+#[ic_cdk::update]
+fn add_annotation(file: u32, text: String, access_token: Option<ByteBuf>) -> Result<u32, String> {
+    if text.trim().is_empty() {
+        Err("annotation text is empty".to_string())?;
+    }
+    if text.len() > MAX_ANNOTATION_SIZE {
+        Err(format!(
+            "annotation text size exceeds the limit {}",
+            MAX_ANNOTATION_SIZE
+        ))?;
+    }
+
+    let canister = ic_cdk::id();
+    let now_ms = ic_cdk::api::time() / MILLISECONDS;
+    let metadata = store::fs::get_file(file).ok_or("file not found")?;
     let ctx = match store::state::with(|s| {
-        s.write_permission(ic_cdk::caller(), &canister, access_token, now_ms / 1000)
+        s.file_read_permission(
+            ic_cdk::caller(),
+            &canister,
+            access_token,
+            now_ms / 1000,
+            &metadata,
+        )
     }) {
         Ok(ctx) => ctx,
         Err((_, err)) => {
             return Err(err);
         }
     };
+    if ctx.caller == ANONYMOUS {
+        Err("anonymous author is not allowed".to_string())?;
+    }
+    if metadata.status < 0 && ctx.role < store::Role::Auditor {
+        Err("file archived".to_string())?;
+    }
+    if !permission::check_file_read(&ctx.ps, &canister, file, metadata.parent) {
+        Err("permission denied".to_string())?;
+    }
+
+    store::annotation::add(
+        file,
+        store::AnnotationEntry {
+            author: ctx.caller,
+            text,
+            created_at: now_ms,
+        },
+    )
+}
+
+#[ic_cdk::update]
+fn delete_file(id: u32, access_token: Option<ByteBuf>) -> Result<bool, String> {
+    let now_ms = ic_cdk::api::time() / MILLISECONDS;
+    let canister = ic_cdk::id();
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &canister,
+        access_token,
+        now_ms / 1000,
+    ) {
+        Ok(ctx) => ctx,
+        Err((_, err)) => {
+            return Err(err);
+        }
+    };
 
     store::fs::delete_file(id, now_ms, |file| {
         match permission::check_file_delete(&ctx.ps, &canister, file.parent) {
@@ -245,9 +1204,12 @@ fn batch_delete_subfiles(
 ) -> Result<Vec<u32>, String> {
     let now_ms = ic_cdk::api::time() / MILLISECONDS;
     let canister = ic_cdk::id();
-    let ctx = match store::state::with(|s| {
-        s.write_permission(ic_cdk::caller(), &canister, access_token, now_ms / 1000)
-    }) {
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &canister,
+        access_token,
+        now_ms / 1000,
+    ) {
         Ok(ctx) => ctx,
         Err((_, err)) => {
             return Err(err);
@@ -269,9 +1231,12 @@ fn create_folder(
     input.validate()?;
     let now_ms = ic_cdk::api::time() / MILLISECONDS;
     let canister = ic_cdk::id();
-    let ctx = match store::state::with(|s| {
-        s.write_permission(ic_cdk::caller(), &canister, access_token, now_ms / 1000)
-    }) {
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &canister,
+        access_token,
+        now_ms / 1000,
+    ) {
         Ok(ctx) => ctx,
         Err((_, err)) => {
             return Err(err);
@@ -315,9 +1280,12 @@ fn update_folder_info(
 
     let now_ms = ic_cdk::api::time() / MILLISECONDS;
     let canister = ic_cdk::id();
-    let ctx = match store::state::with(|s| {
-        s.write_permission(ic_cdk::caller(), &canister, access_token, now_ms / 1000)
-    }) {
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &canister,
+        access_token,
+        now_ms / 1000,
+    ) {
         Ok(ctx) => ctx,
         Err((_, err)) => {
             return Err(err);
@@ -344,9 +1312,12 @@ fn move_folder(
 ) -> Result<UpdateFolderOutput, String> {
     let now_ms = ic_cdk::api::time() / MILLISECONDS;
     let canister = ic_cdk::id();
-    let ctx = match store::state::with(|s| {
-        s.write_permission(ic_cdk::caller(), &canister, access_token, now_ms / 1000)
-    }) {
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &canister,
+        access_token,
+        now_ms / 1000,
+    ) {
         Ok(ctx) => ctx,
         Err((_, err)) => {
             return Err(err);
@@ -369,9 +1340,12 @@ fn move_folder(
 fn delete_folder(id: u32, access_token: Option<ByteBuf>) -> Result<bool, String> {
     let now_ms = ic_cdk::api::time() / MILLISECONDS;
     let canister = ic_cdk::id();
-    let ctx = match store::state::with(|s| {
-        s.write_permission(ic_cdk::caller(), &canister, access_token, now_ms / 1000)
-    }) {
+    let ctx = match store::state::write_permission(
+        ic_cdk::caller(),
+        &canister,
+        access_token,
+        now_ms / 1000,
+    ) {
         Ok(ctx) => ctx,
         Err((_, err)) => {
             return Err(err);
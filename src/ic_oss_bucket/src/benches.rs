@@ -0,0 +1,116 @@
+use canbench_rs::{bench, bench_fn, BenchResult};
+use candid::Principal;
+use ic_oss_types::{file::CHUNK_SIZE, folder::UpdateFolderInput, permission::Policies};
+
+use crate::store::{self, Context, FileMetadata, FolderMetadata, Role};
+
+fn add_file(parent: u32, name: String) -> u32 {
+    store::fs::add_file(FileMetadata {
+        parent,
+        name,
+        content_type: "application/octet-stream".to_string(),
+        ..Default::default()
+    })
+    .unwrap()
+}
+
+// adds a file with the chunks of CHUNK_SIZE bytes.
+fn add_file_with_chunks(chunks: u32) -> u32 {
+    let id = add_file(0, "data.bin".to_string());
+    for i in 0..chunks {
+        store::fs::update_chunk(id, i, 0, vec![i as u8; CHUNK_SIZE as usize], |_| Ok(())).unwrap();
+    }
+    id
+}
+
+fn manager_ctx() -> Context {
+    Context {
+        caller: Principal::anonymous(),
+        ps: Policies::all(),
+        role: Role::Manager,
+    }
+}
+
+#[bench(raw)]
+fn update_chunk() -> BenchResult {
+    let id = add_file(0, "data.bin".to_string());
+    bench_fn(|| {
+        for i in 0..16 {
+            store::fs::update_chunk(id, i, 0, vec![i as u8; CHUNK_SIZE as usize], |_| Ok(()))
+                .unwrap();
+        }
+    })
+}
+
+#[bench(raw)]
+fn get_chunks() -> BenchResult {
+    let id = add_file_with_chunks(16);
+    bench_fn(|| {
+        let chunks = store::fs::get_chunks(id, 0, 16);
+        assert_eq!(chunks.len(), 16);
+    })
+}
+
+#[bench(raw)]
+fn read_range() -> BenchResult {
+    let id = add_file_with_chunks(16);
+    let chunk = CHUNK_SIZE as u64;
+    bench_fn(|| {
+        // the ranges that cross the chunk boundaries
+        for i in 0..15 {
+            let data = store::fs::read_range(id, i * chunk + chunk / 2, chunk).unwrap();
+            assert_eq!(data.len() as u64, chunk);
+        }
+    })
+}
+
+#[bench(raw)]
+fn list_files() -> BenchResult {
+    for i in 0..1000 {
+        add_file(0, format!("file-{}.bin", i));
+    }
+    let ctx = manager_ctx();
+    bench_fn(|| {
+        let mut prev = u32::MAX;
+        loop {
+            let files = store::fs::list_files(&ctx, 0, prev, 100);
+            match files.last() {
+                Some(file) => prev = file.id,
+                None => break,
+            }
+        }
+    })
+}
+
+#[bench(raw)]
+fn folder_tree_ops() -> BenchResult {
+    bench_fn(|| {
+        let mut ids = Vec::with_capacity(100);
+        for i in 0..100 {
+            let id = store::fs::add_folder(FolderMetadata {
+                parent: 0,
+                name: format!("folder-{}", i),
+                ..Default::default()
+            })
+            .unwrap();
+            ids.push(id);
+        }
+        for pair in ids.chunks(2) {
+            store::fs::move_folder(pair[1], 0, pair[0], 0).unwrap();
+            store::fs::update_folder(
+                UpdateFolderInput {
+                    id: pair[1],
+                    name: Some(format!("moved-{}", pair[1])),
+                    status: None,
+                },
+                0,
+                |_| Ok(()),
+            )
+            .unwrap();
+        }
+        for pair in ids.chunks(2) {
+            store::fs::delete_folder(pair[1], 0, |_| Ok(())).unwrap();
+            store::fs::delete_folder(pair[0], 0, |_| Ok(())).unwrap();
+        }
+    })
+}
@@ -0,0 +1,406 @@
+use ic_cdk::api::management_canister::http_request::{HttpHeader, HttpMethod};
+use ic_cdk_timers::TimerId;
+use ic_oss_types::bucket::TaskInfo;
+use std::{cell::RefCell, collections::BTreeMap, time::Duration};
+
+use crate::{outcall, s3, store, MILLISECONDS, SECONDS};
+
+// the instruction limit of a timer execution is 40B
+const INSTRUCTIONS_BUDGET: u64 = 10_000_000_000;
+const MIN_TASK_INTERVAL: u64 = 60; // in seconds
+const BACKUP_FILES_PER_ROUND: usize = 10;
+const BACKUP_MAX_SCAN: usize = 10_000;
+// the request of an HTTPS outcall is limited to 2MB, including the form fields,
+// larger files are exported in parts with a manifest object.
+const BACKUP_PART_SIZE: u64 = 1_900_000;
+
+// a periodic background task, the interval can be overridden by the controllers.
+struct Task {
+    name: &'static str,
+    default_interval_secs: u64, // 0: disabled by default
+    run: fn(),
+    running: fn() -> bool,
+}
+
+static TASKS: &[Task] = &[
+    // checks the backup target, and starts a new export round if it is due
+    Task {
+        name: "backup",
+        default_interval_secs: 600,
+        run: || {
+            if store::state::start_backup_round(ic_cdk::api::time() / MILLISECONDS) {
+                ic_cdk::spawn(backup_round());
+            }
+        },
+        running: || {
+            store::state::with(|s| {
+                s.backup_target
+                    .as_ref()
+                    .is_some_and(|t| t.round_started_at > 0)
+            })
+        },
+    },
+    // removes the orphaned data in batches, see admin_run_gc
+    Task {
+        name: "gc",
+        default_interval_secs: 0,
+        run: || {
+            if store::fs::start_gc() {
+                gc_round();
+            }
+        },
+        running: || store::state::with(|s| s.gc_cursor.is_some()),
+    },
+    // samples the cycles balance into the hourly metrics
+    Task {
+        name: "metrics",
+        default_interval_secs: 600,
+        run: || {
+            store::metrics::record(ic_cdk::api::time() / MILLISECONDS, |m| {
+                m.cycles = ic_cdk::api::canister_balance128()
+            });
+        },
+        running: || false,
+    },
+    // verifies the stored chunks against the file hashes
+    Task {
+        name: "scrub",
+        default_interval_secs: 3600 * 24,
+        run: || {
+            if store::fs::start_scrub(ic_cdk::api::time() / MILLISECONDS) {
+                scrub_round();
+            }
+        },
+        running: || store::state::with(|s| s.scrub_cursor.is_some()),
+    },
+];
+
+#[derive(Default)]
+struct TaskState {
+    timer: Option<TimerId>,
+    last_run_at: u64,
+    runs: u64,
+}
+
+thread_local! {
+    static TASK_STATES: RefCell<BTreeMap<&'static str, TaskState>> = RefCell::new(BTreeMap::new());
+}
+
+// schedules the background tasks, and resumes the unfinished rounds if any.
+// it should be called in init and post_upgrade.
+pub fn start_tasks() {
+    for task in TASKS {
+        schedule(task);
+    }
+
+    if store::state::migrating() {
+        ic_cdk_timers::set_timer(Duration::from_secs(0), migrate_round);
+    }
+    if store::state::with(|s| s.scrub_cursor.is_some()) {
+        ic_cdk_timers::set_timer(Duration::from_secs(0), scrub_round);
+    }
+    if store::state::with(|s| s.gc_cursor.is_some()) {
+        ic_cdk_timers::set_timer(Duration::from_secs(0), gc_round);
+    }
+    if store::state::with(|s| {
+        s.backup_target
+            .as_ref()
+            .is_some_and(|t| t.round_started_at > 0)
+    }) {
+        ic_cdk_timers::set_timer(Duration::from_secs(0), || ic_cdk::spawn(backup_round()));
+    }
+    if store::state::with(|s| !s.hash_verification_jobs.is_empty()) {
+        start_hash_verification();
+    }
+}
+
+pub fn list_tasks() -> Vec<TaskInfo> {
+    TASK_STATES.with(|r| {
+        let states = r.borrow();
+        TASKS
+            .iter()
+            .map(|task| {
+                let state = states.get(task.name);
+                TaskInfo {
+                    name: task.name.to_string(),
+                    interval_secs: interval_secs(task),
+                    default_interval_secs: task.default_interval_secs,
+                    last_run_at: state.map(|s| s.last_run_at).unwrap_or_default(),
+                    runs: state.map(|s| s.runs).unwrap_or_default(),
+                    running: (task.running)(),
+                }
+            })
+            .collect()
+    })
+}
+
+pub fn validate_task_interval(name: &str, interval_secs: u64) -> Result<(), String> {
+    get_task(name)?;
+    if interval_secs > 0 && interval_secs < MIN_TASK_INTERVAL {
+        Err(format!(
+            "interval should be 0 or at least {} seconds",
+            MIN_TASK_INTERVAL
+        ))?;
+    }
+    Ok(())
+}
+
+// overrides the interval of the task and reschedules it, 0 disables the task.
+pub fn set_task_interval(name: &str, interval_secs: u64) -> Result<(), String> {
+    validate_task_interval(name, interval_secs)?;
+    let task = get_task(name)?;
+    store::state::with_mut(|s| {
+        if interval_secs == task.default_interval_secs {
+            s.task_intervals.remove(task.name);
+        } else {
+            s.task_intervals
+                .insert(task.name.to_string(), interval_secs);
+        }
+    });
+    schedule(task);
+    Ok(())
+}
+
+// runs the task in a new timer as soon as possible, the schedule is not changed.
+pub fn trigger_task(name: &str) -> Result<(), String> {
+    let task = get_task(name)?;
+    ic_cdk_timers::set_timer(Duration::from_secs(0), move || execute(task));
+    Ok(())
+}
+
+fn get_task(name: &str) -> Result<&'static Task, String> {
+    TASKS
+        .iter()
+        .find(|t| t.name == name)
+        .ok_or_else(|| format!("task {} not found", name))
+}
+
+fn interval_secs(task: &Task) -> u64 {
+    store::state::with(|s| {
+        s.task_intervals
+            .get(task.name)
+            .copied()
+            .unwrap_or(task.default_interval_secs)
+    })
+}
+
+fn schedule(task: &'static Task) {
+    let interval = interval_secs(task);
+    TASK_STATES.with(|r| {
+        let mut states = r.borrow_mut();
+        let state = states.entry(task.name).or_default();
+        if let Some(timer) = state.timer.take() {
+            ic_cdk_timers::clear_timer(timer);
+        }
+        if interval > 0 {
+            state.timer = Some(ic_cdk_timers::set_timer_interval(
+                Duration::from_secs(interval),
+                move || execute(task),
+            ));
+        }
+    });
+}
+
+fn execute(task: &'static Task) {
+    TASK_STATES.with(|r| {
+        let mut states = r.borrow_mut();
+        let state = states.entry(task.name).or_default();
+        state.last_run_at = ic_cdk::api::time() / MILLISECONDS;
+        state.runs += 1;
+    });
+    (task.run)();
+}
+
+pub fn start_hash_verification() {
+    ic_cdk_timers::set_timer(Duration::from_secs(0), hash_verification_round);
+}
+
+fn hash_verification_round() {
+    if store::fs::hash_verification_round(|| {
+        ic_cdk::api::instruction_counter() < INSTRUCTIONS_BUDGET
+    }) {
+        start_hash_verification();
+    }
+}
+
+fn scrub_round() {
+    if store::fs::scrub_round(ic_cdk::api::time() / MILLISECONDS, || {
+        ic_cdk::api::instruction_counter() < INSTRUCTIONS_BUDGET
+    }) {
+        ic_cdk_timers::set_timer(Duration::from_secs(0), scrub_round);
+    }
+}
+
+// continues the migration steps that are not finished in post_upgrade
+fn migrate_round() {
+    if store::state::migrate(store::state::MIGRATION_MAX_SCAN) {
+        ic_cdk_timers::set_timer(Duration::from_secs(0), migrate_round);
+    }
+}
+
+fn gc_round() {
+    if store::fs::gc_round(store::GC_MAX_SCAN) {
+        ic_cdk_timers::set_timer(Duration::from_secs(0), gc_round);
+    }
+}
+
+// exports a batch of the files updated since the last round to the backup target,
+// the next batch is scheduled by a new timer.
+async fn backup_round() {
+    let Some(target) = store::state::with(|s| s.backup_target.clone()) else {
+        return;
+    };
+    if target.round_started_at == 0 {
+        return;
+    }
+    let round_started_at = target.round_started_at;
+    // the objects can not be uploaded until the controllers set a renewed policy,
+    // the round restarts from the beginning in the next interval.
+    if target.policy.expires_at <= ic_cdk::api::time() / SECONDS {
+        store::state::with_mut(|s| {
+            if let Some(ref mut t) = s.backup_target {
+                if t.round_started_at == round_started_at {
+                    t.round_started_at = 0;
+                    t.cursor = 0;
+                    t.last_error = Some("the signed policy expired".to_string());
+                }
+            }
+        });
+        return;
+    }
+
+    let (files, next) = store::fs::backup_candidates(
+        target.cursor,
+        target.exported_until,
+        target.round_started_at,
+        BACKUP_FILES_PER_ROUND,
+        BACKUP_MAX_SCAN,
+    );
+    let (mut exported, mut failed) = (0u64, 0u64);
+    let mut last_error: Option<String> = None;
+    for (id, file) in files {
+        match export_file(&target, id, &file).await {
+            Ok(()) => exported += 1,
+            Err(err) => {
+                failed += 1;
+                last_error = Some(format!("file {}: {}", id, err));
+            }
+        }
+    }
+
+    store::state::with_mut(|s| {
+        // the target may be replaced or removed during the round
+        if let Some(ref mut t) = s.backup_target {
+            if t.round_started_at != round_started_at {
+                return;
+            }
+            t.exported_files += exported;
+            t.failed_files += failed;
+            if last_error.is_some() {
+                t.last_error = last_error;
+            }
+            match next {
+                Some(cursor) => t.cursor = cursor,
+                None => {
+                    t.exported_until = round_started_at;
+                    t.round_started_at = 0;
+                    t.cursor = 0;
+                }
+            }
+        }
+    });
+
+    if next.is_some() {
+        ic_cdk_timers::set_timer(Duration::from_secs(0), || ic_cdk::spawn(backup_round()));
+    }
+}
+
+// exports the file to the object prefix + id, a file larger than BACKUP_PART_SIZE is
+// exported to the objects prefix + id + "." + part index, then a JSON manifest of the
+// parts is exported to prefix + id + ".manifest".
+async fn export_file(
+    target: &store::BackupTarget,
+    id: u32,
+    file: &store::FileMetadata,
+) -> Result<(), String> {
+    // the content type is sent in the form data, it should not break the form
+    let content_type = if file.content_type.chars().any(|c| c.is_control()) {
+        "application/octet-stream"
+    } else {
+        file.content_type.as_str()
+    };
+    let key = format!("{}{}", target.prefix, id);
+    if file.size <= BACKUP_PART_SIZE {
+        let content = store::fs::get_full_chunks(id)?;
+        return put_object(target, &key, content_type, &content).await;
+    }
+
+    let parts = file.size.div_ceil(BACKUP_PART_SIZE);
+    for i in 0..parts {
+        let offset = i * BACKUP_PART_SIZE;
+        let content = store::fs::read_range(id, offset, BACKUP_PART_SIZE.min(file.size - offset))?;
+        put_object(
+            target,
+            &format!("{}.{}", key, i),
+            "application/octet-stream",
+            &content,
+        )
+        .await?;
+    }
+    // the parts are read across several messages
+    if store::fs::get_file(id).map_or(true, |f| f.updated_at != file.updated_at) {
+        Err("file changed during export".to_string())?;
+    }
+    let manifest = format!(
+        r#"{{"size":{},"parts":{},"part_size":{},"content_type":"{}","hash":{}}}"#,
+        file.size,
+        parts,
+        BACKUP_PART_SIZE,
+        content_type.replace('\\', "\\\\").replace('"', "\\\""),
+        file.hash
+            .as_ref()
+            .map(|h| format!(r#""{}""#, hex::encode(h.as_slice())))
+            .unwrap_or("null".to_string())
+    );
+    put_object(
+        target,
+        &format!("{}.manifest", key),
+        "application/json",
+        manifest.as_bytes(),
+    )
+    .await
+}
+
+async fn put_object(
+    target: &store::BackupTarget,
+    key: &str,
+    content_type: &str,
+    content: &[u8],
+) -> Result<(), String> {
+    let form = s3::PostObjectForm {
+        policy: &target.policy,
+        key,
+        content_type,
+    };
+    let (form_type, body) = form.build(content);
+    let (status, res) = outcall::http_call(
+        HttpMethod::POST,
+        target.endpoint.clone(),
+        vec![HttpHeader {
+            name: "content-type".to_string(),
+            value: form_type,
+        }],
+        Some(body),
+        10_000,
+    )
+    .await?;
+    if !(200..300).contains(&status) {
+        let msg = String::from_utf8_lossy(&res.body);
+        return Err(format!(
+            "unexpected response status {}, {}",
+            status,
+            msg.chars().take(200).collect::<String>()
+        ));
+    }
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use candid::Principal;
+use candid::{Nat, Principal};
 use ic_cdk::api::management_canister::main::CanisterStatusResponse;
 use serde_bytes::{ByteArray, ByteBuf};
 use std::collections::BTreeSet;
@@ -6,13 +6,20 @@ use std::collections::BTreeSet;
 mod api_admin;
 mod api_http;
 mod api_init;
+mod api_nft;
 mod api_query;
 mod api_update;
+#[cfg(feature = "canbench-rs")]
+mod benches;
+mod ecdsa;
+mod job;
+mod outcall;
 mod permission;
+mod s3;
 mod store;
 
-use api_init::CanisterArgs;
-use ic_oss_types::{bucket::*, file::*, folder::*};
+use ic_oss_types::bucket::CanisterArgs;
+use ic_oss_types::{bucket::*, file::*, folder::*, nft::*};
 
 const MILLISECONDS: u64 = 1_000_000;
 const SECONDS: u64 = 1_000_000_000;
@@ -21,7 +28,21 @@ static ANONYMOUS: Principal = Principal::anonymous();
 
 fn is_controller() -> Result<(), String> {
     let caller = ic_cdk::caller();
-    if ic_cdk::api::is_controller(&caller) || store::state::is_controller(&caller) {
+    if store::state::is_controller(&caller)
+        || (ic_cdk::api::is_controller(&caller) && !store::state::with(|s| s.governance_managed))
+    {
+        Ok(())
+    } else {
+        Err("user is not a controller".to_string())
+    }
+}
+
+// the IC controllers are accepted even in the governance managed mode, e.g. the cluster
+// that deployed the bucket. it is used only by the APIs that the cluster calls to sync
+// its state, they grant nothing that a controller could not get by upgrading the bucket.
+fn is_any_controller() -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    if store::state::is_controller(&caller) || ic_cdk::api::is_controller(&caller) {
         Ok(())
     } else {
         Err("user is not a controller".to_string())
@@ -0,0 +1,116 @@
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs,
+    TransformContext,
+};
+
+// the response of an HTTPS outcall, including headers, is limited to 2MB
+pub const MAX_RESPONSE_BYTES: u64 = 2_000_000;
+
+// the room for the response headers when the size of the body is known
+pub const MAX_HEADERS_BYTES: u64 = 16 * 1024;
+
+// the outcalls are priced on the largest subnet, unused cycles are refunded
+const SUBNET_SIZE: u128 = 34;
+
+// the response headers that are kept by the transform function,
+// other headers (date, cookies, etc.) may differ between replicas.
+static KEPT_HEADERS: [&str; 4] = ["content-length", "content-range", "content-type", "etag"];
+
+pub async fn http_call(
+    method: HttpMethod,
+    url: String,
+    headers: Vec<HttpHeader>,
+    body: Option<Vec<u8>>,
+    max_response_bytes: u64,
+) -> Result<(u16, HttpResponse), String> {
+    let request_size = url.len()
+        + headers
+            .iter()
+            .map(|h| h.name.len() + h.value.len())
+            .sum::<usize>()
+        + body.as_ref().map_or(0, |b| b.len());
+    let cycles = (3_000_000 + 60_000 * SUBNET_SIZE) * SUBNET_SIZE
+        + 400 * SUBNET_SIZE * request_size as u128
+        + 800 * SUBNET_SIZE * max_response_bytes as u128;
+
+    let args = CanisterHttpRequestArgument {
+        url,
+        max_response_bytes: Some(max_response_bytes),
+        method,
+        headers,
+        body,
+        transform: Some(TransformContext::from_name(
+            "transform_response".to_string(),
+            vec![],
+        )),
+    };
+    let (res,) = http_request(args, cycles)
+        .await
+        .map_err(|(code, msg)| format!("http outcall failed, code: {:?}, msg: {}", code, msg))?;
+    let status: u64 = res.status.0.clone().try_into().unwrap_or(0);
+    Ok((status as u16, res))
+}
+
+// returns the value of the header, the header names are lowercased by the transform function.
+pub fn header<'a>(res: &'a HttpResponse, name: &str) -> Option<&'a str> {
+    res.headers
+        .iter()
+        .find(|h| h.name == name)
+        .map(|h| h.value.as_str())
+}
+
+#[ic_cdk::query(hidden = true)]
+fn transform_response(args: TransformArgs) -> HttpResponse {
+    let mut res = args.response;
+    res.headers = res
+        .headers
+        .into_iter()
+        .filter_map(|h| {
+            let name = h.name.to_ascii_lowercase();
+            if KEPT_HEADERS.contains(&name.as_str()) {
+                Some(HttpHeader {
+                    name,
+                    value: h.value,
+                })
+            } else {
+                None
+            }
+        })
+        .collect();
+    res
+}
+
+// parses the `Content-Range: bytes start-end/total` header, the total may be unknown ("*").
+pub fn parse_content_range(value: &str) -> Option<(u64, u64, Option<u64>)> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+    if start > end {
+        return None;
+    }
+    let total = match total {
+        "*" => None,
+        v => Some(v.parse().ok()?),
+    };
+    Some((start, end, total))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(
+            parse_content_range("bytes 0-99/1000"),
+            Some((0, 99, Some(1000)))
+        );
+        assert_eq!(
+            parse_content_range("bytes 100-199/*"),
+            Some((100, 199, None))
+        );
+        assert_eq!(parse_content_range("bytes 100-99/1000"), None);
+        assert_eq!(parse_content_range("bytes */1000"), None);
+        assert_eq!(parse_content_range("0-99/1000"), None);
+    }
+}
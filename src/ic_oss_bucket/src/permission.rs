@@ -5,6 +5,36 @@ use ic_oss_types::permission::{
 
 use crate::store::fs;
 
+// the deny rules on the folders and files take precedence over the grants on the bucket,
+// a deny rule on a folder applies to all its descendants.
+fn folder_denied(
+    ps: &Policies,
+    operation: Operation,
+    constraint: Option<Resource>,
+    folder: u32,
+) -> bool {
+    ps.has_deny()
+        && ps.denies_any(
+            &Permission {
+                resource: Resource::Folder,
+                operation,
+                constraint,
+            },
+            &fs::get_ancestors(folder),
+        )
+}
+
+fn file_denied(ps: &Policies, operation: Operation, id: u32) -> bool {
+    ps.denies(
+        &Permission {
+            resource: Resource::File,
+            operation,
+            constraint: None,
+        },
+        id.to_string(),
+    )
+}
+
 pub fn check_bucket_read(ps: &Policies, bucket: &Principal) -> bool {
     ps.has_permission(
         &Permission {
@@ -17,6 +47,9 @@ pub fn check_bucket_read(ps: &Policies, bucket: &Principal) -> bool {
 }
 
 pub fn check_folder_list(ps: &Policies, bucket: &Principal, parent: u32) -> bool {
+    if folder_denied(ps, Operation::List, None, parent) {
+        return false;
+    }
     if !ps.has_permission(
         &Permission {
             resource: Resource::Bucket,
@@ -41,6 +74,9 @@ pub fn check_folder_list(ps: &Policies, bucket: &Principal, parent: u32) -> bool
 }
 
 pub fn check_folder_read(ps: &Policies, bucket: &Principal, id: u32) -> bool {
+    if folder_denied(ps, Operation::Read, Some(Resource::Folder), id) {
+        return false;
+    }
     if !ps.has_permission(
         &Permission {
             resource: Resource::Bucket,
@@ -65,6 +101,9 @@ pub fn check_folder_read(ps: &Policies, bucket: &Principal, id: u32) -> bool {
 }
 
 pub fn check_file_list(ps: &Policies, bucket: &Principal, parent: u32) -> bool {
+    if folder_denied(ps, Operation::List, Some(Resource::File), parent) {
+        return false;
+    }
     if !ps.has_permission(
         &Permission {
             resource: Resource::Bucket,
@@ -89,6 +128,11 @@ pub fn check_file_list(ps: &Policies, bucket: &Principal, parent: u32) -> bool {
 }
 
 pub fn check_file_read(ps: &Policies, bucket: &Principal, id: u32, parent: u32) -> bool {
+    if file_denied(ps, Operation::Read, id)
+        || folder_denied(ps, Operation::Read, Some(Resource::File), parent)
+    {
+        return false;
+    }
     if !ps.has_permission(
         &Permission {
             resource: Resource::File,
@@ -120,6 +164,9 @@ pub fn check_file_read(ps: &Policies, bucket: &Principal, id: u32, parent: u32)
 }
 
 pub fn check_file_create(ps: &Policies, bucket: &Principal, parent: u32) -> bool {
+    if folder_denied(ps, Operation::Write, Some(Resource::File), parent) {
+        return false;
+    }
     if !ps.has_permission(
         &Permission {
             resource: Resource::Bucket,
@@ -144,6 +191,9 @@ pub fn check_file_create(ps: &Policies, bucket: &Principal, parent: u32) -> bool
 }
 
 pub fn check_file_delete(ps: &Policies, bucket: &Principal, parent: u32) -> bool {
+    if folder_denied(ps, Operation::Delete, Some(Resource::File), parent) {
+        return false;
+    }
     if !ps.has_permission(
         &Permission {
             resource: Resource::Bucket,
@@ -168,6 +218,9 @@ pub fn check_file_delete(ps: &Policies, bucket: &Principal, parent: u32) -> bool
 }
 
 pub fn check_file_update(ps: &Policies, bucket: &Principal, id: u32, parent: u32) -> bool {
+    if file_denied(ps, Operation::Write, id) {
+        return false;
+    }
     if !ps.has_permission(
         &Permission {
             resource: Resource::File,
@@ -182,6 +235,9 @@ pub fn check_file_update(ps: &Policies, bucket: &Principal, id: u32, parent: u32
 }
 
 pub fn check_folder_create(ps: &Policies, bucket: &Principal, parent: u32) -> bool {
+    if folder_denied(ps, Operation::Write, Some(Resource::Folder), parent) {
+        return false;
+    }
     if !ps.has_permission(
         &Permission {
             resource: Resource::Bucket,
@@ -206,6 +262,9 @@ pub fn check_folder_create(ps: &Policies, bucket: &Principal, parent: u32) -> bo
 }
 
 pub fn check_folder_delete(ps: &Policies, bucket: &Principal, parent: u32) -> bool {
+    if folder_denied(ps, Operation::Delete, Some(Resource::Folder), parent) {
+        return false;
+    }
     if !ps.has_permission(
         &Permission {
             resource: Resource::Bucket,
@@ -230,6 +289,16 @@ pub fn check_folder_delete(ps: &Policies, bucket: &Principal, parent: u32) -> bo
 }
 
 pub fn check_folder_update(ps: &Policies, bucket: &Principal, id: u32, parent: u32) -> bool {
+    if ps.denies(
+        &Permission {
+            resource: Resource::Folder,
+            operation: Operation::Write,
+            constraint: None,
+        },
+        id.to_string(),
+    ) {
+        return false;
+    }
     if !ps.has_permission(
         &Permission {
             resource: Resource::Folder,
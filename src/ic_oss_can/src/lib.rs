@@ -1,4 +1,5 @@
 pub mod store;
+pub mod stream;
 pub mod types;
 
 #[cfg(test)]
@@ -34,6 +35,169 @@ mod test {
     // need to define `FS_CHUNKS_STORE` before `ic_oss_can::ic_oss_fs!()`
     ic_oss_fs!();
 
+    #[test]
+    fn test_file_reader() {
+        use ic_oss_types::file::CHUNK_SIZE;
+        use std::io::{Read, Seek, SeekFrom};
+
+        let data: Vec<u8> = (0..(CHUNK_SIZE * 2 + 100))
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let id = fs::add_file(FileMetadata {
+            name: "f1".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(fs::open_file(99).is_err());
+        for (i, chunk) in data.chunks(CHUNK_SIZE as usize).enumerate() {
+            fs::update_chunk(id, i as u32, 0, chunk.to_vec()).unwrap();
+        }
+
+        let mut reader = fs::open_file(id).unwrap();
+        assert_eq!(reader.size(), data.len() as u64);
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, data);
+        assert_eq!(reader.position(), data.len() as u64);
+
+        let pos = reader
+            .seek(SeekFrom::End(-(CHUNK_SIZE as i64) - 10))
+            .unwrap();
+        let mut buf = vec![0u8; 20];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[pos as usize..pos as usize + 20]);
+        assert!(reader
+            .seek(SeekFrom::Current(-(data.len() as i64)))
+            .is_err());
+    }
+
+    #[test]
+    fn test_large_file() {
+        use ic_oss_types::file::{file_chunks, CHUNK_SIZE};
+        use std::io::{Read, Seek, SeekFrom};
+
+        // a file of 5GiB, only the chunks around 4GiB are stored
+        let size = 5u64 << 30;
+        let id = fs::add_file(FileMetadata {
+            name: "f1".to_string(),
+            size,
+            filled: size,
+            chunks: file_chunks(size, CHUNK_SIZE),
+            ..Default::default()
+        })
+        .unwrap();
+        let base = file_chunks(1 << 32, CHUNK_SIZE);
+        FS_CHUNKS_STORE.with(|r| {
+            let mut m = r.borrow_mut();
+            m.insert(FileId(id, base - 1), Chunk(vec![1u8; CHUNK_SIZE as usize]));
+            m.insert(FileId(id, base), Chunk(vec![2u8; CHUNK_SIZE as usize]));
+        });
+
+        let mut reader = fs::open_file(id).unwrap();
+        assert_eq!(reader.size(), size);
+        reader.seek(SeekFrom::Start((1 << 32) - 10)).unwrap();
+        let mut buf = vec![0u8; 20];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..10], &[1u8; 10]);
+        assert_eq!(&buf[10..], &[2u8; 10]);
+        assert_eq!(reader.position(), (1 << 32) + 10);
+
+        let last = file_chunks(size, CHUNK_SIZE) - 1;
+        assert!(fs::update_chunk(id, last, 0, vec![3u8; 10]).is_ok());
+        assert!(fs::update_chunk(id, u32::MAX, 0, vec![3u8; 10]).is_err());
+        fs::set_max_file_size(size);
+        assert!(fs::update_chunk(id, last + 1, 0, vec![3u8; 10]).is_err());
+    }
+
+    #[test]
+    fn test_max_file_sizes() {
+        fs::set_max_file_size(1000);
+        fs::set_max_file_sizes(
+            [
+                ("image/".to_string(), 100),
+                ("image/png".to_string(), 200),
+                ("text/".to_string(), 2000),
+            ]
+            .into(),
+        );
+        let file = |content_type: &str, size: u64| FileMetadata {
+            name: "f".to_string(),
+            content_type: content_type.to_string(),
+            size,
+            ..Default::default()
+        };
+        assert!(fs::add_file(file("image/jpeg", 101)).is_err());
+        assert!(fs::add_file(file("image/png", 200)).is_ok());
+        assert!(fs::add_file(file("text/plain", 1001)).is_err());
+        assert!(fs::add_file(file("application/json", 1000)).is_ok());
+
+        let id = fs::add_file(file("image/jpeg", 0)).unwrap();
+        fs::update_chunk(id, 0, 0, vec![0u8; 100]).unwrap();
+        assert!(fs::update_file(
+            ic_oss_types::file::UpdateFileInput {
+                id,
+                content_type: Some("image/gif".to_string()),
+                ..Default::default()
+            },
+            0
+        )
+        .is_ok());
+        assert!(fs::update_chunk(id, 1, 0, vec![0u8; 1]).is_err());
+
+        let id = fs::add_file(file("application/json", 0)).unwrap();
+        fs::update_chunk(id, 0, 0, vec![0u8; 150]).unwrap();
+        assert!(fs::update_file(
+            ic_oss_types::file::UpdateFileInput {
+                id,
+                content_type: Some("image/gif".to_string()),
+                ..Default::default()
+            },
+            0
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_memory_report_and_cleanup_orphans() {
+        for i in 0..4 {
+            let id = fs::add_file(FileMetadata {
+                name: format!("f{}", i),
+                ..Default::default()
+            })
+            .unwrap();
+            fs::update_chunk(id, 0, 0, vec![0u8; 100]).unwrap();
+            fs::update_chunk(id, 1, 0, vec![0u8; 50]).unwrap();
+        }
+        fs::save();
+        let report = fs::memory_report(10);
+        assert_eq!(report.files, 4);
+        assert_eq!(report.chunks, 8);
+        assert_eq!(report.chunk_bytes, 600);
+
+        // orphaned chunks of a deleted file and beyond the chunks of a file
+        assert!(fs::delete_file(2).unwrap());
+        FS_CHUNKS_STORE.with(|r| {
+            let mut m = r.borrow_mut();
+            m.insert(FileId(2, 0), Chunk(vec![0u8; 10]));
+            m.insert(FileId(3, 5), Chunk(vec![0u8; 20]));
+            m.insert(FileId(99, 0), Chunk(vec![0u8; 30]));
+        });
+
+        let report = fs::cleanup_orphans(None, 4);
+        assert_eq!(report.scanned, 4);
+        assert_eq!(report.removed_chunks, 1);
+        assert_eq!(report.removed_bytes, 10);
+        assert_eq!(report.next, Some((3, 1)));
+
+        let report = fs::cleanup_orphans(report.next, 100);
+        assert_eq!(report.scanned, 5);
+        assert_eq!(report.removed_chunks, 2);
+        assert_eq!(report.removed_bytes, 50);
+        assert_eq!(report.next, None);
+        assert_eq!(fs::memory_report(10).chunks, 6);
+        assert_eq!(fs::get_full_chunks(3).unwrap().len(), 150);
+    }
+
     #[test]
     fn test_ic_oss_fs() {
         let files = fs::list_files(u32::MAX, 2);
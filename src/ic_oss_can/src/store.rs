@@ -1,20 +1,41 @@
 // Usage example:
 // ic_oss_can::ic_oss_fs!();
 //
+// or with a larger chunk size for large files, such as model weights:
+// ic_oss_can::ic_oss_fs!(chunk_size = 1024 * 1024);
+//
+// the chunk size should not be changed after files are stored, and it should be
+// the same as the `chunk_size` of the buckets that pull files from the canister.
 #[macro_export]
 macro_rules! ic_oss_fs {
     () => {
+        $crate::ic_oss_fs!(chunk_size = ic_oss_types::file::CHUNK_SIZE);
+    };
+    (chunk_size = $chunk_size:expr) => {
         #[allow(dead_code)]
         pub mod fs {
             use candid::Principal;
             use ciborium::{from_reader, into_writer};
-            use ic_oss_types::file::{FileChunk, FileInfo, UpdateFileInput, CHUNK_SIZE};
+            use ic_oss_types::file::{
+                FileChunk, FileEvent, FileInfo, UpdateFileInput, MAX_FILE_SIZE_PER_CALL,
+            };
             use serde_bytes::ByteBuf;
-            use std::{cell::RefCell, collections::BTreeSet};
+            use std::{
+                cell::RefCell,
+                collections::{BTreeMap, BTreeSet},
+                io::{self, Read, Seek, SeekFrom},
+            };
 
             use super::FS_CHUNKS_STORE;
             use $crate::types::*;
 
+            // all chunks of a file except the last one should be `CHUNK_SIZE` bytes
+            pub const CHUNK_SIZE: u32 = $chunk_size;
+            const _: () = assert!(
+                CHUNK_SIZE > 0 && CHUNK_SIZE <= MAX_CHUNK_SIZE,
+                "chunk_size should be between 1 and MAX_CHUNK_SIZE"
+            );
+
             thread_local! {
                 static FS_METADATA: RefCell<Files> = RefCell::new(Files::default());
             }
@@ -27,6 +48,12 @@ macro_rules! ic_oss_fs {
                 with_mut(|r| r.max_file_size = size);
             }
 
+            // sets the max file sizes by content type prefix, e.g. "image/" => 10MB,
+            // they are limited by `max_file_size` too.
+            pub fn set_max_file_sizes(sizes: BTreeMap<String, u64>) {
+                with_mut(|r| r.max_file_sizes = sizes);
+            }
+
             pub fn set_visibility(visibility: u8) {
                 with_mut(|r| r.visibility = if visibility == 0 { 0 } else { 1 });
             }
@@ -35,6 +62,23 @@ macro_rules! ic_oss_fs {
                 with_mut(|r| r.managers = managers);
             }
 
+            // sets the canister that receives the file events, a file is added when its
+            // last chunk fills its size. the events are sent by one-way calls, so they
+            // may be lost.
+            pub fn set_event_sink(sink: Option<Principal>) {
+                with_mut(|r| r.event_sink = sink);
+            }
+
+            fn emit(event: impl FnOnce() -> FileEvent) {
+                if let Some(sink) = with(|r| r.event_sink) {
+                    let _ = ic_cdk::notify(sink, "on_file_event", (event(),));
+                }
+            }
+
+            fn file_info(id: u32) -> Option<FileInfo> {
+                with(|r| r.files.get(&id).map(|f| f.clone().into_info(id)))
+            }
+
             pub fn is_manager(caller: &Principal) -> bool {
                 with(|r| r.managers.contains(caller))
             }
@@ -70,6 +114,62 @@ macro_rules! ic_oss_fs {
                 FS_CHUNKS_STORE.with(|r| r.borrow().len())
             }
 
+            // reports the usage of the memory of `FS_CHUNKS_STORE`, the `pages` should be the
+            // size of the memory, e.g. `MEMORY_MANAGER.with_borrow(|m| m.get(id)).size()`.
+            pub fn memory_report(pages: u64) -> MemoryReport {
+                // the metadata is stored as chunk (0, 0)
+                let chunks = FS_CHUNKS_STORE.with(|r| {
+                    let m = r.borrow();
+                    m.len() - m.contains_key(&FileId(0, 0)) as u64
+                });
+                let (files, chunk_bytes) = with(|r| {
+                    (
+                        r.files.len() as u64,
+                        r.files.values().map(|f| f.filled).sum(),
+                    )
+                });
+                MemoryReport {
+                    pages,
+                    files,
+                    chunks,
+                    chunk_bytes,
+                }
+            }
+
+            // removes the orphaned chunks that do not belong to any file in the range scanned,
+            // at most `max_scan` chunks are scanned in a round, so it can be called in timers.
+            // the memory is not compacted, the nodes of the removed chunks are reused by new
+            // chunks.
+            pub fn cleanup_orphans(start: Option<(u32, u32)>, max_scan: usize) -> CleanupReport {
+                let start = start.map(|(id, i)| FileId(id, i)).unwrap_or(FileId(1, 0));
+                let mut report = CleanupReport::default();
+                let mut orphans: Vec<FileId> = Vec::new();
+                with(|fs| {
+                    FS_CHUNKS_STORE.with(|r| {
+                        for (key, chunk) in r.borrow().range(start..) {
+                            if report.scanned as usize >= max_scan {
+                                report.next = Some((key.0, key.1));
+                                break;
+                            }
+                            report.scanned += 1;
+                            if !fs.files.get(&key.0).is_some_and(|f| key.1 < f.chunks) {
+                                report.removed_bytes += chunk.0.len() as u64;
+                                orphans.push(key);
+                            }
+                        }
+                    })
+                });
+
+                report.removed_chunks = orphans.len() as u64;
+                FS_CHUNKS_STORE.with(|r| {
+                    let mut m = r.borrow_mut();
+                    for key in orphans {
+                        m.remove(&key);
+                    }
+                });
+                report
+            }
+
             pub fn get_file(id: u32) -> Option<FileMetadata> {
                 if id == 0 {
                     return None;
@@ -83,8 +183,9 @@ macro_rules! ic_oss_fs {
 
             pub fn add_file(file: FileMetadata) -> Result<u32, String> {
                 with_mut(|r| {
-                    if file.size > r.max_file_size {
-                        Err(format!("file size exceeds limit: {}", r.max_file_size))?;
+                    let max_file_size = r.max_file_size_of(&file.content_type);
+                    if file.size > max_file_size {
+                        Err(format!("file size exceeds limit: {}", max_file_size))?;
                     }
 
                     let id = r.file_id;
@@ -102,6 +203,10 @@ macro_rules! ic_oss_fs {
                 if change.id == 0 {
                     Err("invalid file id".to_string())?;
                 }
+                let max_file_size = change
+                    .content_type
+                    .as_ref()
+                    .map(|content_type| with(|r| r.max_file_size_of(content_type)));
                 with_mut(|r| match r.files.get_mut(&change.id) {
                     None => Err(format!("file not found: {}", change.id)),
                     Some(file) => {
@@ -113,6 +218,11 @@ macro_rules! ic_oss_fs {
                             file.name = name;
                         }
                         if let Some(content_type) = change.content_type {
+                            if let Some(max_file_size) = max_file_size {
+                                if file.size > max_file_size {
+                                    Err(format!("file size exceeds limit: {}", max_file_size))?;
+                                }
+                            }
                             file.content_type = content_type;
                         }
                         if change.hash.is_some() {
@@ -121,7 +231,9 @@ macro_rules! ic_oss_fs {
                         file.updated_at = now_ms;
                         Ok(())
                     }
-                })
+                })?;
+                emit(|| FileEvent::Updated(file_info(change.id).unwrap_or_default()));
+                Ok(())
             }
 
             pub fn get_chunk(id: u32, chunk_index: u32) -> Option<FileChunk> {
@@ -135,6 +247,119 @@ macro_rules! ic_oss_fs {
                 })
             }
 
+            pub fn get_chunks(id: u32, chunk_index: u32, max_take: u32) -> Vec<FileChunk> {
+                if id == 0 {
+                    return Vec::new();
+                }
+                FS_CHUNKS_STORE.with(|r| {
+                    let mut buf: Vec<FileChunk> = Vec::with_capacity(max_take as usize);
+                    let mut filled = 0usize;
+                    let m = r.borrow();
+                    for i in chunk_index..chunk_index.saturating_add(max_take) {
+                        if let Some(Chunk(chunk)) = m.get(&FileId(id, i)) {
+                            filled += chunk.len();
+                            if filled > MAX_FILE_SIZE_PER_CALL as usize {
+                                break;
+                            }
+                            buf.push(FileChunk(i, ByteBuf::from(chunk)));
+                        }
+                    }
+                    buf
+                })
+            }
+
+            /// Reads a file sequentially from the stable memory, only the current chunk
+            /// is held in the heap, so files larger than the heap can be streamed.
+            /// All chunks except the last one should be `CHUNK_SIZE` bytes.
+            pub struct FileReader {
+                id: u32,
+                size: u64,
+                pos: u64,
+                chunk: Option<(u32, Vec<u8>)>,
+            }
+
+            pub fn open_file(id: u32) -> Result<FileReader, String> {
+                let size = with(|r| match r.files.get(&id) {
+                    None => Err(format!("file not found: {}", id)),
+                    Some(file) => {
+                        if file.size != file.filled {
+                            return Err("file not fully uploaded".to_string());
+                        }
+                        Ok(file.size)
+                    }
+                })?;
+                Ok(FileReader {
+                    id,
+                    size,
+                    pos: 0,
+                    chunk: None,
+                })
+            }
+
+            impl FileReader {
+                pub fn size(&self) -> u64 {
+                    self.size
+                }
+
+                pub fn position(&self) -> u64 {
+                    self.pos
+                }
+            }
+
+            impl Read for FileReader {
+                fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                    if self.pos >= self.size || buf.is_empty() {
+                        return Ok(0);
+                    }
+
+                    let index = (self.pos / CHUNK_SIZE as u64) as u32;
+                    let offset = (self.pos % CHUNK_SIZE as u64) as usize;
+                    if self.chunk.as_ref().map(|c| c.0) != Some(index) {
+                        let chunk = FS_CHUNKS_STORE
+                            .with(|r| r.borrow().get(&FileId(self.id, index)))
+                            .ok_or_else(|| {
+                                io::Error::new(
+                                    io::ErrorKind::UnexpectedEof,
+                                    format!("file chunk not found: {}, {}", self.id, index),
+                                )
+                            })?;
+                        self.chunk = Some((index, chunk.0));
+                    }
+
+                    let data = &self.chunk.as_ref().expect("chunk should be loaded").1;
+                    if offset >= data.len() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("file chunk too small: {}, {}", self.id, index),
+                        ));
+                    }
+                    let n = buf.len().min(data.len() - offset);
+                    buf[..n].copy_from_slice(&data[offset..offset + n]);
+                    self.pos += n as u64;
+                    Ok(n)
+                }
+            }
+
+            impl Seek for FileReader {
+                fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+                    let pos = match pos {
+                        SeekFrom::Start(n) => Some(n),
+                        SeekFrom::End(n) => self.size.checked_add_signed(n),
+                        SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+                    };
+                    match pos {
+                        Some(pos) => {
+                            self.pos = pos;
+                            Ok(pos)
+                        }
+                        None => Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "invalid seek to a negative or overflowing position",
+                        )),
+                    }
+                }
+            }
+
             pub fn get_full_chunks(id: u32) -> Result<Vec<u8>, String> {
                 if id == 0 {
                     Err("invalid file id".to_string())?;
@@ -149,6 +374,11 @@ macro_rules! ic_oss_fs {
                     }
                 })?;
 
+                // usize is 32 bits in canisters
+                if size > usize::MAX as u64 {
+                    Err(format!("file size exceeds limit: {}", usize::MAX))?;
+                }
+
                 FS_CHUNKS_STORE.with(|r| {
                     let mut filled = 0usize;
                     let mut buf = Vec::with_capacity(size as usize);
@@ -198,13 +428,25 @@ macro_rules! ic_oss_fs {
                     ))?;
                 }
 
-                with_mut(|r| match r.files.get_mut(&file_id) {
+                let max_file_size = with(|r| match r.files.get(&file_id) {
+                    None => r.max_file_size,
+                    Some(file) => r.max_file_size_of(&file.content_type),
+                });
+                // the chunk should start within the size limit, it also keeps the
+                // chunks count in u32 with a small CHUNK_SIZE
+                if chunk_index == u32::MAX
+                    || chunk_index as u64 * CHUNK_SIZE as u64 >= max_file_size
+                {
+                    Err(format!("chunk index out of range: {}", chunk_index))?;
+                }
+                let (filled, completed) = with_mut(|r| match r.files.get_mut(&file_id) {
                     None => Err(format!("file not found: {}", file_id)),
                     Some(file) => {
+                        let (size, prev_filled) = (file.size, file.filled);
                         file.updated_at = now_ms;
                         file.filled += chunk.len() as u64;
-                        if file.filled > r.max_file_size {
-                            Err(format!("file size exceeds limit: {}", r.max_file_size))?;
+                        if file.filled > max_file_size {
+                            Err(format!("file size exceeds limit: {}", max_file_size))?;
                         }
 
                         match FS_CHUNKS_STORE.with(|r| {
@@ -226,9 +468,15 @@ macro_rules! ic_oss_fs {
                             file.size = filled;
                         }
 
-                        Ok(filled)
+                        // rewriting a chunk of a complete file does not complete it again
+                        Ok((filled, size > 0 && prev_filled < size && filled == size))
                     }
-                })
+                })?;
+                // the file is announced once, when the chunk fills it
+                if completed {
+                    emit(|| FileEvent::Added(file_info(file_id).unwrap_or_default()));
+                }
+                Ok(filled)
             }
 
             pub fn delete_file(id: u32) -> Result<bool, String> {
@@ -236,7 +484,7 @@ macro_rules! ic_oss_fs {
                     Err("invalid file id".to_string())?;
                 }
 
-                with_mut(|r| match r.files.remove(&id) {
+                let deleted = with_mut(|r| match r.files.remove(&id) {
                     Some(file) => {
                         FS_CHUNKS_STORE.with(|r| {
                             let mut fs_data = r.borrow_mut();
@@ -247,15 +495,23 @@ macro_rules! ic_oss_fs {
                         Ok(true)
                     }
                     None => Ok(false),
-                })
+                })?;
+                if deleted {
+                    emit(|| FileEvent::Deleted(id));
+                }
+                Ok(deleted)
             }
         }
 
         pub mod api {
-            use ic_oss_types::file::*;
+            use ic_oss_types::{
+                file::*,
+                stream::{StreamChunk, StreamHandle},
+            };
             use serde_bytes::ByteBuf;
+            use std::io::{Read, Seek, SeekFrom};
 
-            use super::fs;
+            use super::fs::{self, CHUNK_SIZE};
             use $crate::types::*;
 
             #[ic_cdk::query]
@@ -277,6 +533,92 @@ macro_rules! ic_oss_fs {
                 Ok(fs::list_files(prev, take))
             }
 
+            // the same as the bucket's get_file_info, so that a bucket can pull files from the canister
+            #[ic_cdk::query]
+            fn get_file_info(id: u32, _access_token: Option<ByteBuf>) -> Result<FileInfo, String> {
+                let caller = ic_cdk::api::caller();
+                fs::with(|r| {
+                    if r.visibility == 0 && !r.managers.contains(&caller) {
+                        Err("permission denied".to_string())?;
+                    }
+                    Ok::<(), String>(())
+                })?;
+                match fs::get_file(id) {
+                    None => Err("file not found".to_string()),
+                    Some(file) => Ok(file.into_info(id)),
+                }
+            }
+
+            // the same as the bucket's get_file_chunks, at most 8 chunks per call
+            #[ic_cdk::query]
+            fn get_file_chunks(
+                id: u32,
+                index: u32,
+                take: Option<u32>,
+                _access_token: Option<ByteBuf>,
+            ) -> Result<Vec<FileChunk>, String> {
+                let caller = ic_cdk::api::caller();
+                fs::with(|r| {
+                    if r.visibility == 0 && !r.managers.contains(&caller) {
+                        Err("permission denied".to_string())?;
+                    }
+                    Ok::<(), String>(())
+                })?;
+                if fs::get_file(id).is_none() {
+                    Err("file not found".to_string())?;
+                }
+                Ok(fs::get_chunks(id, index, take.unwrap_or(8).min(8)))
+            }
+
+            // see ic_oss_types::stream, so other canisters can read the files in chunks
+            // in the same way as from a bucket
+            #[ic_cdk::query]
+            fn open_read(id: u32, _access_token: Option<ByteBuf>) -> Result<StreamHandle, String> {
+                let caller = ic_cdk::api::caller();
+                fs::with(|r| {
+                    if r.visibility == 0 && !r.managers.contains(&caller) {
+                        Err("permission denied".to_string())?;
+                    }
+                    Ok::<(), String>(())
+                })?;
+                // checks that the file is fully uploaded
+                fs::open_file(id)?;
+                let file = fs::get_file(id).ok_or_else(|| "file not found".to_string())?;
+                Ok(StreamHandle {
+                    id,
+                    offset: 0,
+                    size: file.size,
+                    content_type: file.content_type,
+                    hash: file.hash,
+                    updated_at: file.updated_at,
+                })
+            }
+
+            #[ic_cdk::query]
+            fn read_next(
+                handle: StreamHandle,
+                _access_token: Option<ByteBuf>,
+            ) -> Result<StreamChunk, String> {
+                let caller = ic_cdk::api::caller();
+                fs::with(|r| {
+                    if r.visibility == 0 && !r.managers.contains(&caller) {
+                        Err("permission denied".to_string())?;
+                    }
+                    Ok::<(), String>(())
+                })?;
+                let file = fs::get_file(handle.id).ok_or_else(|| "file not found".to_string())?;
+                let (offset, len) = handle.next_range(file.size, file.updated_at)?;
+                let mut reader = fs::open_file(handle.id)?;
+                reader
+                    .seek(SeekFrom::Start(offset))
+                    .map_err(|err| err.to_string())?;
+                let mut data = vec![0u8; len as usize];
+                reader
+                    .read_exact(&mut data)
+                    .map_err(|err| err.to_string())?;
+                Ok(handle.chunk(data))
+            }
+
             #[ic_cdk::update]
             fn create_file(
                 input: CreateFileInput,
@@ -288,7 +630,11 @@ macro_rules! ic_oss_fs {
                     Err("permission denied".to_string())?;
                 }
 
-                let size = input.size.unwrap_or(0);
+                // the size of the content is set, so the file is added when it is filled
+                let size = input
+                    .size
+                    .or(input.content.as_ref().map(|c| c.len() as u64))
+                    .unwrap_or(0);
                 let now_ms = ic_cdk::api::time() / MILLISECONDS;
                 let res: Result<CreateFileOutput, String> = {
                     let id = fs::add_file(FileMetadata {
@@ -302,7 +648,7 @@ macro_rules! ic_oss_fs {
                     })?;
 
                     if let Some(content) = input.content {
-                        if size > 0 && content.len() != size as usize {
+                        if size > 0 && content.len() as u64 != size {
                             Err("content size mismatch".to_string())?;
                         }
 
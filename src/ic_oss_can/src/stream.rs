@@ -0,0 +1,37 @@
+use candid::Principal;
+use ic_oss_types::stream::{StreamChunk, StreamHandle};
+use serde_bytes::ByteBuf;
+
+/// Reads a file from a bucket or a canister with `ic_oss_fs!()` chunk by chunk, the
+/// chunks are passed to `f` in order, so the file is not held in the heap. Returns
+/// the handle of the opened stream with the size, content type and hash of the file.
+pub async fn read_stream<F>(
+    canister: Principal,
+    id: u32,
+    access_token: Option<ByteBuf>,
+    mut f: F,
+) -> Result<StreamHandle, String>
+where
+    F: FnMut(&[u8]) -> Result<(), String>,
+{
+    let (res,): (Result<StreamHandle, String>,) =
+        ic_cdk::call(canister, "open_read", (id, &access_token))
+            .await
+            .map_err(|(code, msg)| {
+                format!("failed to open_read, code: {:?}, message: {}", code, msg)
+            })?;
+    let handle = res?;
+    let mut next = Some(handle.clone());
+    while let Some(h) = next {
+        let (res,): (Result<StreamChunk, String>,) =
+            ic_cdk::call(canister, "read_next", (h, &access_token))
+                .await
+                .map_err(|(code, msg)| {
+                    format!("failed to read_next, code: {:?}, message: {}", code, msg)
+                })?;
+        let chunk = res?;
+        f(&chunk.data)?;
+        next = chunk.next;
+    }
+    Ok(handle)
+}
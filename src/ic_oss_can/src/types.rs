@@ -1,4 +1,4 @@
-use candid::Principal;
+use candid::{CandidType, Principal};
 use ciborium::{from_reader, into_writer};
 use ic_oss_types::file::*;
 use ic_stable_structures::{storable::Bound, Storable};
@@ -11,6 +11,8 @@ use std::{
 };
 
 pub const MILLISECONDS: u64 = 1_000_000_000;
+// the upper bound of the chunk size of an `ic_oss_fs!` instance
+pub use ic_oss_types::file::MAX_CHUNK_SIZE;
 
 #[derive(Clone, Deserialize, Serialize)]
 pub struct Files {
@@ -19,6 +21,12 @@ pub struct Files {
     pub visibility: u8,                // 0: private; 1: public
     pub managers: BTreeSet<Principal>, // managers can read and write
     pub files: BTreeMap<u32, FileMetadata>,
+    // the canister that receives the file events with `on_file_event`
+    #[serde(default)]
+    pub event_sink: Option<Principal>,
+    // the max file sizes by content type prefix, e.g. "image/" or "application/json"
+    #[serde(default)]
+    pub max_file_sizes: BTreeMap<String, u64>,
 }
 
 impl Files {
@@ -39,6 +47,17 @@ impl Files {
         }
         res
     }
+
+    // returns the max file size of the content type, the longest matched prefix wins,
+    // and the size is still limited by `max_file_size`.
+    pub fn max_file_size_of(&self, content_type: &str) -> u64 {
+        self.max_file_sizes
+            .iter()
+            .filter(|(prefix, _)| content_type.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, size)| (*size).min(self.max_file_size))
+            .unwrap_or(self.max_file_size)
+    }
 }
 
 impl Default for Files {
@@ -49,6 +68,8 @@ impl Default for Files {
             visibility: 0,
             managers: BTreeSet::new(),
             files: BTreeMap::new(),
+            event_sink: None,
+            max_file_sizes: BTreeMap::new(),
         }
     }
 }
@@ -67,6 +88,25 @@ impl Storable for Files {
     }
 }
 
+// the usage of the stable memory of `FS_CHUNKS_STORE`. the nodes freed by deletions are
+// reused by new chunks, but the stable memory never shrinks.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct MemoryReport {
+    pub pages: u64, // the wasm pages (64KiB) of the memory
+    pub files: u64,
+    pub chunks: u64,
+    pub chunk_bytes: u64, // the filled bytes of the files
+}
+
+// the result of an orphan cleanup round, the next round should start from `next`.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CleanupReport {
+    pub scanned: u64,
+    pub removed_chunks: u64,
+    pub removed_bytes: u64,
+    pub next: Option<(u32, u32)>, // (file id, chunk index), None: completed
+}
+
 #[derive(Clone, Default, Deserialize, Serialize, Ord, PartialOrd, Eq, PartialEq)]
 pub struct FileId(pub u32, pub u32);
 impl Storable for FileId {
@@ -134,7 +174,7 @@ pub struct Chunk(pub Vec<u8>);
 
 impl Storable for Chunk {
     const BOUND: Bound = Bound::Bounded {
-        max_size: CHUNK_SIZE,
+        max_size: MAX_CHUNK_SIZE,
         is_fixed_size: false,
     };
 
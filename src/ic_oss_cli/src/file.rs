@@ -1,17 +1,19 @@
+use candid::CandidType;
 use chrono::prelude::*;
+use futures::{stream, StreamExt};
 use ic_oss_types::{file::*, format_error};
-use serde_bytes::ByteArray;
 use sha3::{Digest, Sha3_256};
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::{time, time::Duration};
 
 pub async fn upload_file(
     cli: &ic_oss::bucket::Client,
     enable_hash_index: bool,
+    max_custom_data_size: u16,
     parent: u32,
     file: &str,
     retry: u8,
-) -> Result<(), String> {
+) -> Result<u32, String> {
     let file_path = std::path::Path::new(file);
     let metadata = std::fs::metadata(file_path).map_err(format_error)?;
     if !metadata.is_file() {
@@ -19,35 +21,32 @@ pub async fn upload_file(
     }
 
     let file_size = metadata.len();
-    let content_type = infer::get_from_path(file_path)
-        .map_err(format_error)?
-        .map(|f| f.mime_type());
-
-    let content_type = if let Some(content_type) = content_type {
-        content_type
-    } else {
-        mime_db::lookup(file).unwrap_or("application/octet-stream")
-    };
-
-    let hash: Option<ByteArray<32>> = if enable_hash_index {
+    let mut input = CreateFileInput::new(
+        parent,
+        file_path.file_name().unwrap().to_string_lossy().to_string(),
+        detect_content_type(file)?,
+    )
+    .with_size(file_size);
+    if enable_hash_index {
         let fs = tokio::fs::File::open(&file_path)
             .await
             .map_err(format_error)?;
-        Some(pre_sum_hash(fs).await?.into())
-    } else {
-        None
-    };
+        input = input.with_hash(pre_sum_hash(fs).await?);
+    }
+    let input = input.build(max_custom_data_size)?;
+    upload_input(cli, input, file, retry).await
+}
 
+// uploads the file with the built input, the upload is resumed up to `retry` times.
+pub async fn upload_input(
+    cli: &ic_oss::bucket::Client,
+    input: CreateFileInput,
+    file: &str,
+    retry: u8,
+) -> Result<u32, String> {
+    let file_path = std::path::Path::new(file);
+    let file_size = input.size.unwrap_or_default();
     let start_ts: DateTime<Local> = Local::now();
-    let input = CreateFileInput {
-        parent,
-        name: file_path.file_name().unwrap().to_string_lossy().to_string(),
-        content_type: content_type.to_string(),
-        size: Some(file_size),
-        hash,
-        ..Default::default()
-    };
-
     let fs = tokio::fs::File::open(&file_path)
         .await
         .map_err(format_error)?;
@@ -62,8 +61,7 @@ pub async fn upload_file(
                 progress
             );
         })
-        .await
-        .map_err(format_error)?;
+        .await?;
 
     let mut i = 0u8;
     while let Some(err) = res.error {
@@ -109,10 +107,234 @@ pub async fn upload_file(
         i,
         Local::now().signed_duration_since(start_ts)
     );
+    Ok(res.id)
+}
+
+#[derive(CandidType, Clone, Debug, Default)]
+pub struct UploadReport {
+    pub uploaded: u32,
+    pub failed: u32,
+    pub files: Vec<UploadFileResult>,
+}
+
+#[derive(CandidType, Clone, Debug, Default)]
+pub struct UploadFileResult {
+    pub path: String,
+    pub id: Option<u32>,
+    pub error: Option<String>,
+}
+
+// uploads the files with at most `workers` uploads in flight, they share the client
+// and its agent. the results are in the order of the files.
+pub async fn upload_files(
+    cli: &ic_oss::bucket::Client,
+    enable_hash_index: bool,
+    max_custom_data_size: u16,
+    parent: u32,
+    files: Vec<String>,
+    retry: u8,
+    workers: u8,
+) -> UploadReport {
+    let results: Vec<UploadFileResult> = stream::iter(files)
+        .map(|file| async move {
+            match upload_file(
+                cli,
+                enable_hash_index,
+                max_custom_data_size,
+                parent,
+                &file,
+                retry,
+            )
+            .await
+            {
+                Ok(id) => UploadFileResult {
+                    path: file,
+                    id: Some(id),
+                    error: None,
+                },
+                Err(err) => UploadFileResult {
+                    path: file,
+                    id: None,
+                    error: Some(err),
+                },
+            }
+        })
+        .buffered(workers.max(1) as usize)
+        .collect()
+        .await;
+
+    let failed = results.iter().filter(|r| r.error.is_some()).count() as u32;
+    UploadReport {
+        uploaded: results.len() as u32 - failed,
+        failed,
+        files: results,
+    }
+}
+
+// reads the file paths from the manifest, one path per line, the empty lines and
+// the lines starting with '#' are ignored.
+pub fn read_manifest(manifest: &str) -> Result<Vec<String>, String> {
+    let content = std::fs::read_to_string(manifest).map_err(format_error)?;
+    Ok(content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+// writes the bytes of the file in the range [start, end) to the writer, it stops after
+// the given number of lines if `lines` is set.
+pub async fn cat_file<W: AsyncWrite + Unpin>(
+    cli: &ic_oss::bucket::Client,
+    id: u32,
+    start: u64,
+    end: Option<u64>,
+    lines: Option<usize>,
+    w: &mut W,
+) -> Result<(), String> {
+    let info = cli.get_file_info(id).await?;
+    if info.size != info.filled {
+        Err("file not fully uploaded".to_string())?;
+    }
+
+    let end = end.unwrap_or(info.size).min(info.size);
+    let mut offset = start;
+    let mut lines_left = lines;
+    while offset < end && lines_left != Some(0) {
+        let len = (end - offset).min(MAX_FILE_SIZE_PER_CALL);
+        let data = cli.read_range(id, offset, len).await?;
+        if data.is_empty() {
+            break;
+        }
+        offset += data.len() as u64;
+
+        let mut data = data.as_slice();
+        if let Some(n) = lines_left.as_mut() {
+            for (i, b) in data.iter().enumerate() {
+                if *b == b'\n' {
+                    *n -= 1;
+                    if *n == 0 {
+                        data = &data[..=i];
+                        break;
+                    }
+                }
+            }
+        }
+        w.write_all(data).await.map_err(format_error)?;
+    }
+    w.flush().await.map_err(format_error)?;
     Ok(())
 }
 
-async fn pre_sum_hash(mut fs: tokio::fs::File) -> Result<[u8; 32], String> {
+#[derive(CandidType, Clone, Debug, Default)]
+pub struct VerifyReport {
+    pub id: u32,
+    pub size: u64,
+    pub chunks: u32,
+    pub hash: String,                // the SHA3-256 hash of the downloaded content
+    pub stored_hash: Option<String>, // the hash stored in the file metadata
+    pub hash_match: bool,
+    pub chunk_errors: Vec<String>, // missing or misaligned chunks
+    pub scrub_corrupted_chunks: Option<Vec<u32>>, // None: the scrub report is not readable
+    pub server_hash_verified: Option<bool>,
+    pub certified: bool, // the file is included in the certified state digest
+    pub certified_error: Option<String>,
+    pub passed: bool,
+}
+
+// downloads the file and checks the content hash, the chunk layout, the scrubber's
+// checksum findings and the inclusion of the file in the certified state digest.
+pub async fn verify_file(cli: &ic_oss::bucket::Client, id: u32) -> Result<VerifyReport, String> {
+    let info = cli.get_file_info(id).await?;
+    if info.size != info.filled {
+        Err("file not fully uploaded".to_string())?;
+    }
+
+    let mut report = VerifyReport {
+        id,
+        size: info.size,
+        chunks: info.chunks,
+        stored_hash: info.hash.map(|h| hex::encode(*h)),
+        server_hash_verified: info.hash_verified,
+        ..Default::default()
+    };
+    let mut hasher = Sha3_256::new();
+    let mut filled = 0u64;
+    let mut next = 0u32;
+    let chunk_size = cli.chunk_size() as u64;
+    let take = ic_oss::bucket::chunks_per_download(cli.chunk_size());
+    for index in (0..info.chunks).step_by(take as usize) {
+        let chunks = cli.get_file_chunks(id, index, Some(take)).await?;
+        for chunk in chunks.iter() {
+            if chunk.0 != next {
+                report
+                    .chunk_errors
+                    .push(format!("chunk {} is missing, got {}", next, chunk.0));
+            }
+            let expected = if chunk.0 + 1 < info.chunks {
+                chunk_size
+            } else {
+                info.size - chunk_size * chunk.0 as u64
+            };
+            if chunk.1.len() as u64 != expected {
+                report.chunk_errors.push(format!(
+                    "chunk {} has {} bytes, expected {}",
+                    chunk.0,
+                    chunk.1.len(),
+                    expected
+                ));
+            }
+            hasher.update(&chunk.1);
+            filled += chunk.1.len() as u64;
+            next = chunk.0 + 1;
+        }
+    }
+    if next != info.chunks || filled != info.size {
+        report.chunk_errors.push(format!(
+            "downloaded {} chunks and {} bytes, expected {} chunks and {} bytes",
+            next, filled, info.chunks, info.size
+        ));
+    }
+
+    let hash: [u8; 32] = hasher.finalize().into();
+    report.hash = hex::encode(hash);
+    report.hash_match = info.hash.map_or(false, |h| *h == hash);
+    report.scrub_corrupted_chunks = cli.get_scrub_report().await.ok().map(|r| {
+        r.corrupted_chunks
+            .into_iter()
+            .filter(|(file, _)| *file == id)
+            .map(|(_, chunk)| chunk)
+            .collect()
+    });
+    match cli.verify_file(id, &hash).await {
+        Ok(_) => report.certified = true,
+        Err(err) => report.certified_error = Some(err.to_string()),
+    }
+
+    report.passed = report.hash_match
+        && report.chunk_errors.is_empty()
+        && report
+            .scrub_corrupted_chunks
+            .as_ref()
+            .map_or(true, |c| c.is_empty())
+        && report.server_hash_verified != Some(false)
+        && report.certified;
+    Ok(report)
+}
+
+// detects the content type by the magic bytes of the file, then by its extension.
+pub fn detect_content_type(file: &str) -> Result<String, String> {
+    let content_type = infer::get_from_path(file)
+        .map_err(format_error)?
+        .map(|f| f.mime_type());
+    Ok(content_type
+        .or_else(|| mime_db::lookup(file))
+        .unwrap_or("application/octet-stream")
+        .to_string())
+}
+
+pub async fn pre_sum_hash(mut fs: tokio::fs::File) -> Result<[u8; 32], String> {
     let mut hasher = Sha3_256::new();
     let mut buf = vec![0u8; 1024 * 1024 * 2];
     loop {
@@ -6,24 +6,21 @@ use ic_agent::{
 };
 use ic_oss::agent::build_agent;
 use ic_oss_types::{
-    cluster::AddWasmInput,
-    file::{MoveInput, CHUNK_SIZE},
-    folder::CreateFolderInput,
-    format_error,
+    bucket::BackupTargetInput, cluster::AddWasmInput, file::MoveInput, folder::CreateFolderInput,
+    format_error, s3::SignedPostPolicy, ApiCompatibility,
 };
 use ring::{rand, signature::Ed25519KeyPair};
 use serde_bytes::{ByteArray, ByteBuf};
-use sha3::{Digest, Sha3_256};
 use std::{
-    io::SeekFrom,
     path::{Path, PathBuf},
     sync::Arc,
 };
-use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 
 mod file;
+mod site;
 
-use file::upload_file;
+use file::{cat_file, read_manifest, upload_file, upload_files, verify_file};
+use site::{deploy_site, DeployOptions};
 
 static IC_HOST: &str = "https://icp-api.io";
 
@@ -42,6 +39,10 @@ pub struct Cli {
     #[arg(long, default_value = "false")]
     ic: bool,
 
+    /// Caps the upload and download throughput in bytes per second, e.g. "500K" or "2M"
+    #[arg(long, value_parser = parse_rate)]
+    limit_rate: Option<u64>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -57,7 +58,13 @@ impl Cli {
         let host = if is_ic { IC_HOST } else { self.host.as_str() };
         let agent = build_agent(host, identity).await?;
         let bucket = Principal::from_text(bucket).map_err(format_error)?;
-        Ok(ic_oss::bucket::Client::new(Arc::new(agent), bucket))
+        let mut cli = ic_oss::bucket::Client::new(Arc::new(agent), bucket);
+        cli.set_limit_rate(self.limit_rate);
+        let compatibility = cli.check_api_version().await?;
+        warn_api_version("bucket", compatibility);
+        // the chunk size is used by the verify command
+        cli.load_chunk_size().await?;
+        Ok(cli)
     }
 
     async fn cluster(
@@ -70,7 +77,21 @@ impl Cli {
         let host = if is_ic { IC_HOST } else { self.host.as_str() };
         let agent = build_agent(host, identity).await?;
         let cluster = Principal::from_text(cluster).map_err(format_error)?;
-        Ok(ic_oss::cluster::Client::new(Arc::new(agent), cluster))
+        let cli = ic_oss::cluster::Client::new(Arc::new(agent), cluster);
+        warn_api_version("cluster", cli.check_api_version().await?);
+        Ok(cli)
+    }
+}
+
+fn warn_api_version(canister: &str, compatibility: ApiCompatibility) {
+    match compatibility {
+        ApiCompatibility::Compatible => {}
+        ApiCompatibility::Newer => {
+            eprintln!("warning: the {canister} API is newer than the CLI, please upgrade the CLI");
+        }
+        ApiCompatibility::Older => {
+            eprintln!("warning: the {canister} API is older than the CLI, some commands may fail");
+        }
     }
 }
 
@@ -135,9 +156,17 @@ pub enum Commands {
         #[arg(short, long, default_value = "0")]
         parent: u32,
 
-        /// file path
+        /// file paths, multiple files are uploaded concurrently
+        #[arg(long, num_args = 1.., required_unless_present = "manifest")]
+        path: Vec<String>,
+
+        /// a file that lists the file paths to upload, one path per line
         #[arg(long)]
-        path: String,
+        manifest: Option<String>,
+
+        /// the number of files to upload concurrently
+        #[arg(long, default_value = "4")]
+        workers: u8,
 
         /// retry times
         #[arg(long, default_value = "3")]
@@ -150,6 +179,63 @@ pub enum Commands {
         /// digest algorithm, default is SHA3-256
         #[arg(long, default_value = "SHA3-256")]
         digest: String,
+
+        /// skips the upload if a file with the same hash exists in the bucket
+        #[arg(long, default_value = "false")]
+        dedup: bool,
+    },
+    /// Deploys a static site directory as a new release, only the changed files are uploaded
+    Deploy {
+        /// the directory of the site
+        dir: String,
+
+        /// bucket
+        #[arg(short, long, value_name = "CANISTER")]
+        bucket: String,
+
+        /// the folder id that holds the release folders
+        #[arg(short, long, default_value = "0")]
+        parent: u32,
+
+        /// the tag that points to the manifest of the current release
+        #[arg(long, default_value = "current")]
+        tag: String,
+
+        /// the release name, default is the current time
+        #[arg(long)]
+        release: Option<String>,
+
+        /// the page of the directories
+        #[arg(long, default_value = "index.html")]
+        index: String,
+
+        /// the page of the missing paths, it is ignored if it does not exist
+        #[arg(long, default_value = "404.html")]
+        not_found: String,
+
+        /// the Cache-Control header of the assets
+        #[arg(long, default_value = "max-age=2592000, public")]
+        cache_control: String,
+
+        /// the Cache-Control header of the html pages
+        #[arg(long, default_value = "no-cache")]
+        html_cache_control: String,
+
+        /// the number of files to upload concurrently
+        #[arg(long, default_value = "4")]
+        workers: u8,
+
+        /// retry times
+        #[arg(long, default_value = "3")]
+        retry: u8,
+
+        /// prints the changes without uploading
+        #[arg(long, default_value = "false")]
+        dry_run: bool,
+
+        /// Use the ic network
+        #[arg(long, default_value = "false")]
+        ic: bool,
     },
     /// Downloads an file from a target bucket to the local file system
     Get {
@@ -177,6 +263,60 @@ pub enum Commands {
         #[arg(long, default_value = "SHA3-256")]
         digest: String,
     },
+    /// Writes the content of a file to the standard output
+    Cat {
+        /// bucket
+        #[arg(short, long, value_name = "CANISTER")]
+        bucket: String,
+
+        /// file id
+        #[arg(long)]
+        id: u32,
+
+        /// the byte range to write, e.g. "0-1023" or "1024-", the end is inclusive
+        #[arg(long)]
+        range: Option<String>,
+
+        /// Use the ic network
+        #[arg(long, default_value = "false")]
+        ic: bool,
+    },
+    /// Writes the first lines or bytes of a file to the standard output
+    Head {
+        /// bucket
+        #[arg(short, long, value_name = "CANISTER")]
+        bucket: String,
+
+        /// file id
+        #[arg(long)]
+        id: u32,
+
+        /// the number of lines to write, default is 10
+        #[arg(short = 'n', long, conflicts_with = "bytes")]
+        lines: Option<usize>,
+
+        /// the number of bytes to write
+        #[arg(short = 'c', long)]
+        bytes: Option<u64>,
+
+        /// Use the ic network
+        #[arg(long, default_value = "false")]
+        ic: bool,
+    },
+    /// Verifies the content of a file against its stored hash and the certified state
+    Verify {
+        /// bucket
+        #[arg(short, long, value_name = "CANISTER")]
+        bucket: String,
+
+        /// file id
+        #[arg(long)]
+        id: u32,
+
+        /// Use the ic network
+        #[arg(long, default_value = "false")]
+        ic: bool,
+    },
     /// Lists files or folders in a folder
     Ls {
         /// bucket
@@ -257,6 +397,41 @@ pub enum Commands {
         #[arg(short, long, default_value = "0")]
         kind: u8,
 
+        /// Use the ic network
+        #[arg(long, default_value = "false")]
+        ic: bool,
+    },
+    /// Signs an S3 POST policy with AWS_SECRET_ACCESS_KEY locally and sets the backup target
+    /// of a bucket, it should be run again before the policy expires
+    Backup {
+        /// bucket
+        #[arg(short, long, value_name = "CANISTER")]
+        bucket: String,
+
+        /// the https url of the S3 bucket
+        #[arg(long)]
+        endpoint: String,
+
+        /// region of the S3 bucket
+        #[arg(long)]
+        region: String,
+
+        /// access key id
+        #[arg(long)]
+        access_key_id: String,
+
+        /// the object key prefix
+        #[arg(long, default_value = "")]
+        prefix: String,
+
+        /// the interval of export rounds in seconds
+        #[arg(long, default_value = "86400")]
+        interval_secs: u64,
+
+        /// the signed policy expires after ttl_secs, at most 7 days
+        #[arg(long, default_value = "604800")]
+        ttl_secs: u64,
+
         /// Use the ic network
         #[arg(long, default_value = "false")]
         ic: bool,
@@ -316,8 +491,7 @@ async fn main() -> Result<(), String> {
                 },
                 prev_hash,
             )
-            .await
-            .map_err(format_error)?;
+            .await?;
             return Ok(());
         }
 
@@ -333,8 +507,7 @@ async fn main() -> Result<(), String> {
                     parent: *parent,
                     name: name.clone(),
                 })
-                .await
-                .map_err(format_error)?;
+                .await?;
             pretty_println(&folder)?;
             return Ok(());
         }
@@ -343,17 +516,98 @@ async fn main() -> Result<(), String> {
             bucket,
             parent,
             path,
+            manifest,
+            workers,
             retry,
             ic,
             digest,
+            dedup,
         }) => {
             if digest != "SHA3-256" {
                 Err("unsupported digest algorithm".to_string())?;
             }
-            let cli = cli.bucket(identity, ic, bucket).await?;
-            let info = cli.get_bucket_info().await.map_err(format_error)?;
-            upload_file(&cli, info.enable_hash_index, *parent, path, *retry).await?;
+            let mut files = path.clone();
+            if let Some(manifest) = manifest {
+                files.extend(read_manifest(manifest)?);
+            }
+            let mut cli = cli.bucket(identity, ic, bucket).await?;
+            let info = cli.get_bucket_info().await?;
+            cli.set_dedup(*dedup && info.enable_hash_index);
+            if files.len() == 1 && manifest.is_none() {
+                upload_file(
+                    &cli,
+                    info.enable_hash_index,
+                    info.max_custom_data_size,
+                    *parent,
+                    &files[0],
+                    *retry,
+                )
+                .await?;
+                return Ok(());
+            }
+
+            let report = upload_files(
+                &cli,
+                info.enable_hash_index,
+                info.max_custom_data_size,
+                *parent,
+                files,
+                *retry,
+                *workers,
+            )
+            .await;
+            pretty_println(&report)?;
+            if report.failed > 0 {
+                Err(format!(
+                    "{} of {} files failed to upload",
+                    report.failed,
+                    report.files.len()
+                ))?;
+            }
+            return Ok(());
+        }
 
+        Some(Commands::Deploy {
+            dir,
+            bucket,
+            parent,
+            tag,
+            release,
+            index,
+            not_found,
+            cache_control,
+            html_cache_control,
+            workers,
+            retry,
+            dry_run,
+            ic,
+        }) => {
+            let mut cli = cli.bucket(identity, ic, bucket).await?;
+            let info = cli.get_bucket_info().await?;
+            // the same content can be stored once if the hash index is enabled
+            cli.set_dedup(info.enable_hash_index);
+            let opts = DeployOptions {
+                parent: *parent,
+                tag: tag.clone(),
+                release: release
+                    .clone()
+                    .unwrap_or_else(|| chrono::Local::now().format("%Y%m%d%H%M%S").to_string()),
+                index: index.clone(),
+                not_found: not_found.clone(),
+                cache_control: cache_control.clone(),
+                html_cache_control: html_cache_control.clone(),
+                workers: *workers,
+                retry: *retry,
+                dry_run: *dry_run,
+            };
+            let report = deploy_site(&cli, info.max_custom_data_size, dir, opts).await?;
+            pretty_println(&report)?;
+            if info.site_tag != *tag {
+                println!(
+                    "the bucket serves the site after its site_tag is set to {:?} by admin_update_bucket",
+                    tag
+                );
+            }
             return Ok(());
         }
 
@@ -371,11 +625,9 @@ async fn main() -> Result<(), String> {
             let cli = cli.bucket(identity, ic, bucket).await?;
             let info = if let Some(hash) = hash {
                 let hash = parse_file_hash(hash)?;
-                cli.get_file_info_by_hash(hash)
-                    .await
-                    .map_err(format_error)?
+                cli.get_file_info_by_hash(hash).await?
             } else if let Some(id) = id {
-                cli.get_file_info(*id).await.map_err(format_error)?
+                cli.get_file_info(*id).await?
             } else {
                 Err("missing file id or hash".to_string())?
             };
@@ -391,41 +643,19 @@ async fn main() -> Result<(), String> {
                 .await
                 .map_err(format_error)?;
             file.set_len(info.size as u64).await.map_err(format_error)?;
-            let mut hasher = Sha3_256::new();
-            let mut filled = 0usize;
-            // TODO: support parallel download
-            for index in (0..info.chunks).step_by(6) {
-                let chunks = cli
-                    .get_file_chunks(info.id, index, Some(6))
-                    .await
-                    .map_err(format_error)?;
-                for chunk in chunks.iter() {
-                    file.seek(SeekFrom::Start(chunk.0 as u64 * CHUNK_SIZE as u64))
-                        .await
-                        .map_err(format_error)?;
-                    hasher.update(&chunk.1);
-                    file.write_all(&chunk.1).await.map_err(format_error)?;
-                    filled += chunk.1.len();
-                }
-
-                println!(
-                    "downloaded chunks: {}/{}, {:.2}%",
-                    index as usize + chunks.len(),
-                    info.chunks,
-                    (filled as f32 / info.size as f32) * 100.0,
-                );
-            }
-
-            let hash: [u8; 32] = hasher.finalize().into();
-            if let Some(h) = info.hash {
-                if *h != hash {
-                    Err(format!(
-                        "file hash mismatch, expected {}, got {}",
-                        hex::encode(*h),
-                        hex::encode(hash),
-                    ))?;
-                }
-            }
+            let chunks = info.chunks;
+            let size = info.size;
+            let hash = cli
+                .download(info.id, &mut file, |progress| {
+                    println!(
+                        "downloaded chunks: {}/{}, {:.2}%, concurrency: {}",
+                        progress.chunk_index + 1,
+                        chunks,
+                        (progress.filled as f32 / size as f32) * 100.0,
+                        progress.concurrency,
+                    );
+                })
+                .await?;
 
             println!(
                 "\n{}:\n{}\t{}",
@@ -447,16 +677,14 @@ async fn main() -> Result<(), String> {
             match kind {
                 0 => {
                     let files = cli
-                        .list_files(*parent, None, None)
-                        .await
-                        .map_err(format_error)?;
+                        .list_files_with_options(*parent, Default::default())
+                        .await?;
                     pretty_println(&files)?;
                 }
                 1 => {
                     let folders = cli
-                        .list_folders(*parent, None, None)
-                        .await
-                        .map_err(format_error)?;
+                        .list_folders_with_options(*parent, Default::default())
+                        .await?;
                     pretty_println(&folders)?;
                 }
                 _ => return Err("invalid kind".to_string()),
@@ -476,21 +704,19 @@ async fn main() -> Result<(), String> {
                 0 => {
                     let info = if let Some(hash) = hash {
                         let hash = parse_file_hash(hash)?;
-                        cli.get_file_info_by_hash(hash)
-                            .await
-                            .map_err(format_error)?
+                        cli.get_file_info_by_hash(hash).await?
                     } else {
-                        cli.get_file_info(*id).await.map_err(format_error)?
+                        cli.get_file_info(*id).await?
                     };
 
                     pretty_println(&info)?;
                 }
                 1 => {
-                    let info = cli.get_folder_info(*id).await.map_err(format_error)?;
+                    let info = cli.get_folder_info(*id).await?;
                     pretty_println(&info)?;
                 }
                 _ => {
-                    let info = cli.get_bucket_info().await.map_err(format_error)?;
+                    let info = cli.get_bucket_info().await?;
                     pretty_println(&info)?;
                 }
             }
@@ -514,8 +740,7 @@ async fn main() -> Result<(), String> {
                             from: *from,
                             to: *to,
                         })
-                        .await
-                        .map_err(format_error)?;
+                        .await?;
                     pretty_println(&res)?;
                 }
                 1 => {
@@ -525,8 +750,7 @@ async fn main() -> Result<(), String> {
                             from: *from,
                             to: *to,
                         })
-                        .await
-                        .map_err(format_error)?;
+                        .await?;
                     pretty_println(&res)?;
                 }
                 _ => return Err("invalid kind".to_string()),
@@ -534,6 +758,48 @@ async fn main() -> Result<(), String> {
             return Ok(());
         }
 
+        Some(Commands::Cat {
+            bucket,
+            id,
+            range,
+            ic,
+        }) => {
+            let (start, end) = match range {
+                Some(range) => parse_range(range)?,
+                None => (0, None),
+            };
+            let cli = cli.bucket(identity, ic, bucket).await?;
+            cat_file(&cli, *id, start, end, None, &mut tokio::io::stdout()).await?;
+            return Ok(());
+        }
+
+        Some(Commands::Head {
+            bucket,
+            id,
+            lines,
+            bytes,
+            ic,
+        }) => {
+            let cli = cli.bucket(identity, ic, bucket).await?;
+            let lines = match bytes {
+                Some(_) => None,
+                None => Some(lines.unwrap_or(10)),
+            };
+            cat_file(&cli, *id, 0, *bytes, lines, &mut tokio::io::stdout()).await?;
+            return Ok(());
+        }
+
+        Some(Commands::Verify { bucket, id, ic }) => {
+            let cli = cli.bucket(identity, ic, bucket).await?;
+            let report = verify_file(&cli, *id).await?;
+            pretty_println(&report)?;
+            if !report.passed {
+                Err(format!("file {} verification failed", id))?;
+            }
+            println!("file {} verification passed", id);
+            return Ok(());
+        }
+
         Some(Commands::Rm {
             bucket,
             id,
@@ -543,11 +809,11 @@ async fn main() -> Result<(), String> {
             let cli = cli.bucket(identity, ic, bucket).await?;
             match kind {
                 0 => {
-                    let res = cli.delete_file(*id).await.map_err(format_error)?;
+                    let res = cli.delete_file(*id).await?;
                     pretty_println(&res)?;
                 }
                 1 => {
-                    let res = cli.delete_folder(*id).await.map_err(format_error)?;
+                    let res = cli.delete_folder(*id).await?;
                     pretty_println(&res)?;
                 }
                 _ => return Err("invalid kind".to_string()),
@@ -555,6 +821,40 @@ async fn main() -> Result<(), String> {
             return Ok(());
         }
 
+        Some(Commands::Backup {
+            bucket,
+            endpoint,
+            region,
+            access_key_id,
+            prefix,
+            interval_secs,
+            ttl_secs,
+            ic,
+        }) => {
+            // the secret access key never leaves this machine, only the signed policy is sent
+            let secret = std::env::var("AWS_SECRET_ACCESS_KEY")
+                .map_err(|_| "missing AWS_SECRET_ACCESS_KEY".to_string())?;
+            let policy = SignedPostPolicy::sign(
+                region,
+                access_key_id,
+                &secret,
+                prefix,
+                chrono::Utc::now().timestamp() as u64,
+                *ttl_secs,
+            );
+            let cli = cli.bucket(identity, ic, bucket).await?;
+            cli.admin_set_backup_target(Some(BackupTargetInput {
+                endpoint: endpoint.clone(),
+                prefix: prefix.clone(),
+                interval_secs: *interval_secs,
+                policy,
+            }))
+            .await?;
+            let status = cli.get_backup_status().await?;
+            pretty_println(&status)?;
+            return Ok(());
+        }
+
         None => {}
     }
 
@@ -586,6 +886,47 @@ where
     Ok(())
 }
 
+// parses the byte range "start-end" or "start-", the end is inclusive, returns the
+// start and the exclusive end.
+fn parse_range(s: &str) -> Result<(u64, Option<u64>), String> {
+    let (start, end) = s
+        .split_once('-')
+        .ok_or_else(|| format!("invalid range: {}", s))?;
+    let start: u64 = start.trim().parse().map_err(format_error)?;
+    let end = match end.trim() {
+        "" => None,
+        end => {
+            let end: u64 = end.parse().map_err(format_error)?;
+            if end < start {
+                Err(format!("invalid range: {}", s))?;
+            }
+            Some(end + 1)
+        }
+    };
+    Ok((start, end))
+}
+
+// parses the bytes per second with an optional K, M or G suffix of 1024 multiples
+fn parse_rate(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (num, unit) = match s.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&s[..i], c.to_ascii_uppercase()),
+        _ => (s, 'B'),
+    };
+    let multiple = match unit {
+        'B' => 1,
+        'K' => 1024,
+        'M' => 1024 * 1024,
+        'G' => 1024 * 1024 * 1024,
+        _ => Err(format!("invalid rate unit: {}", s))?,
+    };
+    let num: u64 = num.trim().parse().map_err(format_error)?;
+    match num.checked_mul(multiple) {
+        Some(rate) if rate > 0 => Ok(rate),
+        _ => Err(format!("invalid rate: {}", s)),
+    }
+}
+
 fn parse_file_hash(s: &str) -> Result<ByteArray<32>, String> {
     let s = s.replace("\\", "");
     let data = hex::decode(s.strip_prefix("0x").unwrap_or(&s)).map_err(format_error)?;
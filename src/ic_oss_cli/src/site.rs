@@ -0,0 +1,255 @@
+use candid::CandidType;
+use futures::{stream, StreamExt};
+use ic_oss::error::ErrorKind;
+use ic_oss_types::{file::*, folder::CreateFolderInput, format_error, MapValue};
+use icrc_ledger_types::icrc::generic_metadata_value::MetadataValue;
+use std::{collections::BTreeMap, path::Path};
+
+use crate::file::{cat_file, detect_content_type, pre_sum_hash, upload_input};
+
+pub static SITE_MANIFEST_NAME: &str = "site-manifest.cbor";
+
+pub struct DeployOptions {
+    pub parent: u32, // the folder that holds the release folders
+    pub tag: String, // the tag that points to the manifest of the current release
+    pub release: String,
+    pub index: String,
+    pub not_found: String,
+    pub cache_control: String,      // for the assets
+    pub html_cache_control: String, // for the html pages, they should be revalidated
+    pub workers: u8,
+    pub retry: u8,
+    pub dry_run: bool,
+}
+
+#[derive(CandidType, Clone, Debug, Default)]
+pub struct DeployReport {
+    pub release: String,
+    pub folder: u32,
+    pub manifest: u32, // the file id of the manifest, 0 in a dry run
+    pub previous_manifest: Option<u32>,
+    pub uploaded: Vec<String>,
+    pub unchanged: u32,
+    pub removed: Vec<String>,
+}
+
+// a local file of the site
+struct LocalAsset {
+    path: String, // relative path with "/" separators
+    file: String,
+    size: u64,
+    hash: [u8; 32],
+    content_type: String,
+    cache_control: String,
+}
+
+// deploys the static site in the directory as a new release: it uploads the changed
+// files into a release folder, reuses the unchanged files of the previous release,
+// uploads the manifest and then points the tag to it, so the switch is atomic.
+pub async fn deploy_site(
+    cli: &ic_oss::bucket::Client,
+    max_custom_data_size: u16,
+    dir: &str,
+    opts: DeployOptions,
+) -> Result<DeployReport, String> {
+    let mut files = Vec::new();
+    walk_dir(Path::new(dir), "", &mut files)?;
+    if files.is_empty() {
+        Err(format!("no files to deploy in {:?}", dir))?;
+    }
+
+    let mut assets = Vec::with_capacity(files.len());
+    for (path, file) in files {
+        let size = std::fs::metadata(&file).map_err(format_error)?.len();
+        let fs = tokio::fs::File::open(&file).await.map_err(format_error)?;
+        let content_type = detect_content_type(&file)?;
+        let cache_control = if content_type.starts_with("text/html") {
+            opts.html_cache_control.clone()
+        } else {
+            opts.cache_control.clone()
+        };
+        assets.push(LocalAsset {
+            path,
+            file,
+            size,
+            hash: pre_sum_hash(fs).await?,
+            content_type,
+            cache_control,
+        });
+    }
+    if !assets.iter().any(|a| a.path == opts.index) {
+        Err(format!(
+            "index page {:?} not found in {:?}",
+            opts.index, dir
+        ))?;
+    }
+
+    let previous_manifest = match cli.get_tag(opts.tag.clone()).await {
+        Ok(id) => Some(id),
+        Err(err) if err.kind == ErrorKind::NotFound => None,
+        Err(err) => Err(err)?,
+    };
+    let previous = match previous_manifest {
+        Some(id) => {
+            let mut data = Vec::new();
+            cat_file(cli, id, 0, None, None, &mut data).await?;
+            SiteManifest::from_cbor(&data)?
+        }
+        None => SiteManifest::default(),
+    };
+    // the unchanged files are reused by their content and headers, even if moved
+    let reusable: BTreeMap<([u8; 32], &str, &str), &SiteAsset> = previous
+        .files
+        .values()
+        .map(|a| {
+            (
+                (*a.hash, a.content_type.as_str(), a.cache_control.as_str()),
+                a,
+            )
+        })
+        .collect();
+
+    let mut manifest = SiteManifest {
+        release: opts.release.clone(),
+        folder: 0,
+        index: opts.index.clone(),
+        not_found: assets
+            .iter()
+            .any(|a| a.path == opts.not_found)
+            .then(|| opts.not_found.clone()),
+        files: BTreeMap::new(),
+    };
+    let mut report = DeployReport {
+        release: opts.release.clone(),
+        previous_manifest,
+        removed: previous
+            .files
+            .keys()
+            .filter(|p| !assets.iter().any(|a| &a.path == *p))
+            .cloned()
+            .collect(),
+        ..Default::default()
+    };
+
+    // the files with the same content and headers are uploaded once
+    let mut changed: Vec<(LocalAsset, Vec<String>)> = Vec::new();
+    for asset in assets {
+        let key = (
+            asset.hash,
+            asset.content_type.as_str(),
+            asset.cache_control.as_str(),
+        );
+        if let Some(prev) = reusable.get(&key) {
+            report.unchanged += 1;
+            manifest.files.insert(asset.path, (*prev).clone());
+            continue;
+        }
+
+        report.uploaded.push(asset.path.clone());
+        match changed.iter_mut().find(|(a, _)| {
+            a.hash == asset.hash
+                && a.content_type == asset.content_type
+                && a.cache_control == asset.cache_control
+        }) {
+            Some((_, paths)) => paths.push(asset.path),
+            None => changed.push((asset, Vec::new())),
+        }
+    }
+    if opts.dry_run {
+        return Ok(report);
+    }
+
+    let folder = cli
+        .create_folder(CreateFolderInput {
+            parent: opts.parent,
+            name: opts.release.clone(),
+        })
+        .await?;
+    report.folder = folder.id;
+    manifest.folder = folder.id;
+
+    let folder = folder.id;
+    let retry = opts.retry;
+    let uploaded: Vec<Result<(LocalAsset, Vec<String>, u32), String>> = stream::iter(changed)
+        .map(|(asset, paths)| async move {
+            // the files are named by their hash, the paths are kept in the manifest
+            let name = match Path::new(&asset.path).extension() {
+                Some(ext) if ext.len() <= 16 => {
+                    format!("{}.{}", hex::encode(asset.hash), ext.to_string_lossy())
+                }
+                _ => hex::encode(asset.hash),
+            };
+            let input = CreateFileInput::new(folder, name, asset.content_type.clone())
+                .with_size(asset.size)
+                .with_hash(asset.hash)
+                .with_custom(MapValue::from([(
+                    CUSTOM_KEY_CACHE_CONTROL.to_string(),
+                    MetadataValue::Text(asset.cache_control.clone()),
+                )]))
+                .build(max_custom_data_size)?;
+            let id = upload_input(cli, input, &asset.file, retry)
+                .await
+                .map_err(|err| format!("{}: {}", asset.path, err))?;
+            Ok((asset, paths, id))
+        })
+        .buffered(opts.workers.max(1) as usize)
+        .collect()
+        .await;
+    for res in uploaded {
+        let (asset, paths, id) = res?;
+        let site_asset = SiteAsset {
+            id,
+            size: asset.size,
+            hash: asset.hash.into(),
+            content_type: asset.content_type,
+            cache_control: asset.cache_control,
+        };
+        for path in paths {
+            manifest.files.insert(path, site_asset.clone());
+        }
+        manifest.files.insert(asset.path, site_asset);
+    }
+
+    let data = manifest.to_cbor();
+    let input = CreateFileInput::new(
+        folder,
+        SITE_MANIFEST_NAME.to_string(),
+        "application/cbor".to_string(),
+    )
+    .with_size(data.len() as u64)
+    .build(max_custom_data_size)?;
+    let res = cli.upload(data.as_slice(), input, |_| {}).await?;
+    if let Some(err) = res.error {
+        Err(format!("failed to upload the manifest: {}", err))?;
+    }
+    report.manifest = res.id;
+    cli.set_tag(opts.tag, res.id).await?;
+    Ok(report)
+}
+
+// collects the files in the directory recursively, the hidden files are skipped.
+fn walk_dir(dir: &Path, prefix: &str, files: &mut Vec<(String, String)>) -> Result<(), String> {
+    let mut entries = std::fs::read_dir(dir)
+        .map_err(format_error)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(format_error)?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+        let file_type = entry.file_type().map_err(format_error)?;
+        if file_type.is_dir() {
+            walk_dir(&entry.path(), &path, files)?;
+        } else if file_type.is_file() {
+            files.push((path, entry.path().to_string_lossy().to_string()));
+        }
+    }
+    Ok(())
+}
@@ -1,25 +1,31 @@
 use candid::Principal;
-use ed25519_dalek::{Signer, SigningKey};
+use ed25519_dalek::SigningKey;
 use ic_cdk::api::management_canister::main::*;
 use ic_oss_types::{
-    cluster::{AddWasmInput, DeployWasmInput},
-    cose::{cose_sign1, coset::CborSerializable, sha256, EdDSA, Token, BUCKET_TOKEN_AAD, ES256K},
+    bucket::{BucketUsage, UpdateBucketInput},
+    cluster::{AddWasmInput, BucketLookup, BucketTemplate, DeployWasmInput, Tenant},
+    cose::{coset::CborSerializable, sha256, EdDSA, Token, BUCKET_TOKEN_AAD, ES256K},
+    file::{CreateFileOutput, FileInfo},
+    folder::{CreateFolderInput, CreateFolderOutput, FolderChild, FolderInfo},
     format_error,
     permission::Policies,
 };
 use serde_bytes::{ByteArray, ByteBuf};
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::time::Duration;
 
 use crate::{
-    create_canister_on, ecdsa, is_controller, is_controller_or_manager,
-    is_controller_or_manager_or_committer, schnorr, store, validate_principals, MILLISECONDS,
-    SECONDS, TOKEN_KEY_DERIVATION_PATH,
+    call, check_audience, create_canister_on, ecdsa, is_controller, is_controller_or_manager,
+    is_controller_or_manager_or_committer, is_controller_or_manager_or_tenant_manager,
+    is_controller_or_tenant_manager, reserve_tenant_bucket, schnorr, store, validate_principals,
+    ANONYMOUS, MILLISECONDS, SECONDS, TOKEN_KEY_DERIVATION_PATH,
 };
 
 // encoded candid arguments: ()
 // println!("{:?}", candid::utils::encode_args(()).unwrap());
 static EMPTY_CANDID_ARGS: &[u8] = &[68, 73, 68, 76, 0, 0];
+// the IC keeps one snapshot per canister for now, the oldest one is replaced
+const MAX_BUCKET_SNAPSHOTS: usize = 1;
 
 #[ic_cdk::update(guard = "is_controller")]
 fn admin_set_managers(args: BTreeSet<Principal>) -> Result<(), String> {
@@ -102,14 +108,18 @@ fn validate_admin_remove_committers(args: BTreeSet<Principal>) -> Result<String,
     Ok("ok".to_string())
 }
 
-#[ic_cdk::update(guard = "is_controller_or_manager")]
+#[ic_cdk::update(guard = "is_controller_or_manager_or_tenant_manager")]
 pub async fn admin_sign_access_token(token: Token) -> Result<ByteBuf, String> {
+    check_audience(&token.audience)?;
     let now_sec = ic_cdk::api::time() / SECONDS;
     let (ecdsa_key_name, token_expiration) =
         store::state::with(|r| (r.ecdsa_key_name.clone(), r.token_expiration));
-    let mut claims = token.to_cwt(now_sec as i64, token_expiration as i64);
-    claims.issuer = Some(ic_cdk::id().to_text());
-    let mut sign1 = cose_sign1(claims, ES256K, None)?;
+    let mut sign1 = token.to_sign1(
+        ES256K,
+        Some(ic_cdk::id().to_text()),
+        now_sec as i64,
+        token_expiration as i64,
+    )?;
     let tbs_data = sign1.tbs_data(BUCKET_TOKEN_AAD);
     let message_hash = sha256(&tbs_data);
 
@@ -124,15 +134,19 @@ pub async fn admin_sign_access_token(token: Token) -> Result<ByteBuf, String> {
     Ok(ByteBuf::from(token))
 }
 
-#[ic_cdk::update(guard = "is_controller_or_manager")]
+#[ic_cdk::update(guard = "is_controller_or_manager_or_tenant_manager")]
 pub async fn admin_ed25519_access_token(token: Token) -> Result<ByteBuf, String> {
+    check_audience(&token.audience)?;
     let now_sec = ic_cdk::api::time() / SECONDS;
     let (schnorr_key_name, token_expiration) =
         store::state::with(|r| (r.schnorr_key_name.clone(), r.token_expiration));
 
-    let mut claims = token.to_cwt(now_sec as i64, token_expiration as i64);
-    claims.issuer = Some(ic_cdk::id().to_text());
-    let mut sign1 = cose_sign1(claims, EdDSA, None)?;
+    let mut sign1 = token.to_sign1(
+        EdDSA,
+        Some(ic_cdk::id().to_text()),
+        now_sec as i64,
+        token_expiration as i64,
+    )?;
     let tbs_data = sign1.tbs_data(BUCKET_TOKEN_AAD);
 
     let sig = schnorr::sign_with_schnorr(
@@ -147,39 +161,126 @@ pub async fn admin_ed25519_access_token(token: Token) -> Result<ByteBuf, String>
     Ok(ByteBuf::from(token))
 }
 
-#[ic_cdk::query(guard = "is_controller_or_manager")]
+#[ic_cdk::query(guard = "is_controller_or_manager_or_tenant_manager")]
 pub fn admin_weak_access_token(
     token: Token,
     now_sec: u64,
     expiration_sec: u64,
 ) -> Result<ByteBuf, String> {
+    check_audience(&token.audience)?;
     let secret_key = store::state::with(|r| r.weak_ed25519_secret_key);
-    let mut claims = token.to_cwt(now_sec as i64, expiration_sec as i64);
-    claims.issuer = Some(ic_cdk::id().to_text());
-    let mut sign1 = cose_sign1(claims, EdDSA, None)?;
-    let tbs_data = sign1.tbs_data(BUCKET_TOKEN_AAD);
-
-    let signing_key = SigningKey::from_bytes(&secret_key);
-    let sig = signing_key.sign(&tbs_data).to_bytes();
-    sign1.signature = sig.to_vec();
-    let token = sign1.to_vec().map_err(|err| err.to_string())?;
-    Ok(ByteBuf::from(token))
+    token.sign_ed25519(
+        &SigningKey::from_bytes(&secret_key),
+        Some(ic_cdk::id().to_text()),
+        now_sec as i64,
+        expiration_sec as i64,
+    )
 }
 
-#[ic_cdk::update(guard = "is_controller_or_manager")]
+#[ic_cdk::update(guard = "is_controller_or_manager_or_tenant_manager")]
 async fn admin_attach_policies(args: Token) -> Result<(), String> {
+    check_audience(&args.audience)?;
     let policies = Policies::try_from(args.policies.as_str())?;
     store::auth::attach_policies(args.subject, args.audience, policies);
     Ok(())
 }
 
-#[ic_cdk::update(guard = "is_controller_or_manager")]
+#[ic_cdk::update(guard = "is_controller_or_manager_or_tenant_manager")]
 async fn admin_detach_policies(args: Token) -> Result<(), String> {
+    check_audience(&args.audience)?;
     let policies = Policies::try_from(args.policies.as_str())?;
     store::auth::detach_policies(args.subject, args.audience, policies);
     Ok(())
 }
 
+// sets the member buckets of an audience group, an empty set removes the group.
+// the group id is used as the token audience, the member buckets are synced with
+// their groups, call it again to retry if the sync failed.
+#[ic_cdk::update(guard = "is_controller_or_manager")]
+async fn admin_set_audience_group(
+    group: Principal,
+    buckets: BTreeSet<Principal>,
+) -> Result<(), String> {
+    let affected = store::state::with_mut(|s| {
+        if group == ANONYMOUS
+            || group == ic_cdk::id()
+            || s.bucket_deployed_list.contains_key(&group)
+        {
+            return Err("invalid audience group".to_string());
+        }
+        for id in &buckets {
+            if !s.bucket_deployed_list.contains_key(id) {
+                return Err(format!("canister {} is not deployed", id));
+            }
+        }
+
+        let mut affected = s.audience_groups.remove(&group).unwrap_or_default();
+        affected.extend(buckets.iter().cloned());
+        if !buckets.is_empty() {
+            s.audience_groups.insert(group, buckets);
+        }
+        Ok(affected)
+    })?;
+
+    for id in affected {
+        let groups = store::state::bucket_audience_groups(&id);
+        let res: Result<(), String> = call(id, "admin_set_audience_groups", (groups,), 0).await?;
+        res?;
+    }
+    Ok(())
+}
+
+// creates or replaces a tenant, a bucket can only belong to one tenant.
+#[ic_cdk::update(guard = "is_controller")]
+fn admin_set_tenant(args: Tenant) -> Result<(), String> {
+    store::state::set_tenant(args)
+}
+
+#[ic_cdk::update(guard = "is_controller")]
+fn admin_remove_tenant(id: String) -> Result<(), String> {
+    store::state::with_mut(|s| s.tenants.remove(&id))
+        .map(|_| ())
+        .ok_or_else(|| format!("tenant {:?} not found", id))
+}
+
+#[ic_cdk::update]
+fn validate2_admin_set_tenant(args: Tenant) -> Result<String, String> {
+    args.validate()?;
+    for id in &args.buckets {
+        check_deployed(id)?;
+    }
+    Ok("ok".to_string())
+}
+
+#[ic_cdk::update]
+fn validate2_admin_remove_tenant(id: String) -> Result<String, String> {
+    if !store::state::with(|s| s.tenants.contains_key(&id)) {
+        Err(format!("tenant {:?} not found", id))?;
+    }
+    Ok("ok".to_string())
+}
+
+// places the shard key (the first segment of the paths) on the bucket, `lookup`
+// resolves the paths from the placements. None removes the placement.
+#[ic_cdk::update(guard = "is_controller_or_manager")]
+fn admin_set_placement(shard_key: String, bucket: Option<Principal>) -> Result<(), String> {
+    store::state::set_placement(shard_key, bucket)
+}
+
+#[ic_cdk::update]
+fn validate2_admin_set_placement(
+    shard_key: String,
+    bucket: Option<Principal>,
+) -> Result<String, String> {
+    if BucketLookup::shard_key(&shard_key)? != shard_key {
+        Err(format!("invalid shard key: {:?}", shard_key))?;
+    }
+    if let Some(bucket) = bucket {
+        check_deployed(&bucket)?;
+    }
+    Ok("ok".to_string())
+}
+
 #[ic_cdk::update(guard = "is_controller_or_manager_or_committer")]
 async fn admin_add_wasm(
     args: AddWasmInput,
@@ -217,11 +318,15 @@ async fn validate_admin_add_wasm(
     )
 }
 
-#[ic_cdk::update(guard = "is_controller")]
+// creates and installs a new bucket, a tenant manager should create it in its tenant
+// within the tenant's max_buckets.
+#[ic_cdk::update(guard = "is_controller_or_tenant_manager")]
 async fn admin_create_bucket(
     settings: Option<CanisterSettings>,
     args: Option<ByteBuf>,
+    tenant: Option<String>,
 ) -> Result<Principal, String> {
+    let reservation = reserve_tenant_bucket(tenant)?;
     let self_id = ic_cdk::id();
     let mut settings = settings.unwrap_or_default();
     let controllers = settings.controllers.get_or_insert_with(Default::default);
@@ -261,17 +366,22 @@ async fn admin_create_bucket(
     if res.is_ok() {
         store::state::with_mut(|s| {
             s.bucket_deployed_list.insert(canister_id, (id, hash));
-        })
+        });
+        if let Some(reservation) = reservation {
+            reservation.commit(canister_id)?;
+        }
     }
     Ok(canister_id)
 }
 
-#[ic_cdk::update(guard = "is_controller")]
+#[ic_cdk::update(guard = "is_controller_or_tenant_manager")]
 async fn admin_create_bucket_on(
     subnet: Principal,
     settings: Option<CanisterSettings>,
     args: Option<ByteBuf>,
+    tenant: Option<String>,
 ) -> Result<Principal, String> {
+    let reservation = reserve_tenant_bucket(tenant)?;
     let self_id = ic_cdk::id();
     let mut settings = settings.unwrap_or_default();
     let controllers = settings.controllers.get_or_insert_with(Default::default);
@@ -305,7 +415,10 @@ async fn admin_create_bucket_on(
     if res.is_ok() {
         store::state::with_mut(|s| {
             s.bucket_deployed_list.insert(canister_id, (id, hash));
-        })
+        });
+        if let Some(reservation) = reservation {
+            reservation.commit(canister_id)?;
+        }
     }
     Ok(canister_id)
 }
@@ -314,8 +427,12 @@ async fn admin_create_bucket_on(
 fn validate_admin_create_bucket(
     _settings: Option<CanisterSettings>,
     _args: Option<ByteBuf>,
+    tenant: Option<String>,
 ) -> Result<String, String> {
     let _ = store::wasm::get_latest()?;
+    if let Some(tenant) = &tenant {
+        store::state::check_tenant_quota(tenant)?;
+    }
     Ok("ok".to_string())
 }
 
@@ -324,12 +441,18 @@ fn validate_admin_create_bucket_on(
     _subnet: Principal,
     _settings: Option<CanisterSettings>,
     _args: Option<ByteBuf>,
+    tenant: Option<String>,
 ) -> Result<String, String> {
     let _ = store::wasm::get_latest()?;
+    if let Some(tenant) = &tenant {
+        store::state::check_tenant_quota(tenant)?;
+    }
     Ok("ok".to_string())
 }
 
-#[ic_cdk::update(guard = "is_controller")]
+// installs or upgrades the bucket, a tenant manager can deploy the buckets of its
+// tenants, or a new canister into its tenant within the tenant's max_buckets.
+#[ic_cdk::update(guard = "is_controller_or_tenant_manager")]
 async fn admin_deploy_bucket(
     args: DeployWasmInput,
     ignore_prev_hash: Option<ByteArray<32>>,
@@ -348,12 +471,37 @@ async fn admin_deploy_bucket(
             args.canister.to_text()
         ))?;
     }
+    let reservation = match (store::state::bucket_tenant(&args.canister), args.tenant) {
+        (Some(current), Some(tenant)) if current != tenant => Err(format!(
+            "canister {} belongs to tenant {:?}",
+            args.canister, current
+        ))?,
+        (Some(_), _) => {
+            check_audience(&args.canister)?;
+            None
+        }
+        (None, tenant) => {
+            if info.module_hash.is_some() && is_controller().is_err() {
+                Err(format!(
+                    "canister {} is not a bucket of the caller's tenants",
+                    args.canister
+                ))?;
+            }
+            reserve_tenant_bucket(tenant)?
+        }
+    };
 
     let mode = if info.module_hash.is_none() {
         CanisterInstallMode::Install
     } else {
         CanisterInstallMode::Upgrade(None)
     };
+    if let Some(template) = &args.template {
+        if info.module_hash.is_some() {
+            Err("template can only be applied to a new bucket".to_string())?;
+        }
+        template.validate()?;
+    }
 
     let prev_hash: [u8; 32] = if let Some(hash) = info.module_hash {
         hash.try_into().map_err(format_error)?
@@ -361,6 +509,11 @@ async fn admin_deploy_bucket(
         Default::default()
     };
     let prev_hash = ByteArray::from(prev_hash);
+    if matches!(mode, CanisterInstallMode::Upgrade(_))
+        && store::state::with(|s| s.bucket_upgrade_snapshot)
+    {
+        take_bucket_snapshot(args.canister).await?;
+    }
     let (hash, wasm) = if let Some(ignore_prev_hash) = ignore_prev_hash {
         if ignore_prev_hash != prev_hash {
             Err(format!(
@@ -398,9 +551,176 @@ async fn admin_deploy_bucket(
     if res.is_ok() {
         store::state::with_mut(|s| {
             s.bucket_deployed_list.insert(args.canister, (id, hash));
-        })
+        });
+        if let Some(reservation) = reservation {
+            reservation.commit(args.canister)?;
+        }
     }
-    res
+    res?;
+
+    if let Some(template) = args.template {
+        apply_bucket_template(args.canister, template)
+            .await
+            .map_err(|err| {
+                format!(
+                    "failed to apply the template, it can be applied again by admin_apply_bucket_template: {}",
+                    err
+                )
+            })?;
+    }
+    Ok(())
+}
+
+// applies the template to a deployed bucket again, e.g. after it failed on deployment,
+// the existing folders are reused so the template can be applied many times.
+// a tenant manager can apply it to the buckets of its tenants.
+#[ic_cdk::update(guard = "is_controller_or_tenant_manager")]
+async fn admin_apply_bucket_template(
+    canister: Principal,
+    template: BucketTemplate,
+) -> Result<(), String> {
+    check_deployed(&canister)?;
+    check_audience(&canister)?;
+    template.validate()?;
+    apply_bucket_template(canister, template).await
+}
+
+#[ic_cdk::update]
+fn validate2_admin_apply_bucket_template(
+    canister: Principal,
+    template: BucketTemplate,
+) -> Result<String, String> {
+    check_deployed(&canister)?;
+    template.validate()?;
+    Ok("ok".to_string())
+}
+
+// applies the template to the bucket, the cluster is a manager of the bucket while the
+// folders are created, and the role is dropped even if the template fails.
+async fn apply_bucket_template(
+    canister: Principal,
+    template: BucketTemplate,
+) -> Result<(), String> {
+    let self_id = ic_cdk::id();
+    let res: Result<(), String> = call(
+        canister,
+        "admin_add_managers",
+        (BTreeSet::from([self_id]),),
+        0,
+    )
+    .await?;
+    res?;
+
+    let res = apply_template_folders(canister, &template).await;
+    // the cluster drops its manager role even if the folders failed
+    if !template.managers.contains(&self_id) {
+        let removed: Result<(), String> = call(
+            canister,
+            "admin_remove_managers",
+            (BTreeSet::from([self_id]),),
+            0,
+        )
+        .await?;
+        removed?;
+    }
+    res?;
+
+    // the bucket settings are applied at last, they may make the bucket read-only
+    if let Some(input) = template.bucket {
+        let res: Result<(), String> = call(canister, "admin_update_bucket", (input,), 0).await?;
+        res?;
+    }
+    Ok(())
+}
+
+async fn apply_template_folders(
+    canister: Principal,
+    template: &BucketTemplate,
+) -> Result<(), String> {
+    let mut folders: BTreeMap<String, u32> = BTreeMap::new();
+    for folder in &template.folders {
+        let mut id = 0;
+        let mut path = String::new();
+        for name in folder.path.split('/') {
+            if !path.is_empty() {
+                path.push('/');
+            }
+            path.push_str(name);
+            id = match folders.get(&path) {
+                Some(id) => *id,
+                None => {
+                    let child = template_folder(canister, id, name).await?;
+                    folders.insert(path.clone(), child);
+                    child
+                }
+            };
+        }
+
+        if folder.limits.is_some() {
+            let res: Result<(), String> = call(
+                canister,
+                "admin_set_folder_limits",
+                (id, folder.limits.clone()),
+                0,
+            )
+            .await?;
+            res?;
+        }
+        if folder.unlisted {
+            let res: Result<(), String> =
+                call(canister, "admin_set_folder_unlisted", (id, true), 0).await?;
+            res?;
+        }
+        if folder.inbox {
+            let res: Result<(), String> =
+                call(canister, "admin_set_folder_inbox", (id, true), 0).await?;
+            res?;
+        }
+    }
+
+    if !template.managers.is_empty() {
+        let res: Result<(), String> = call(
+            canister,
+            "admin_add_managers",
+            (template.managers.clone(),),
+            0,
+        )
+        .await?;
+        res?;
+    }
+    Ok(())
+}
+
+// returns the id of the folder with the name in the parent, creates it if not found.
+async fn template_folder(canister: Principal, parent: u32, name: &str) -> Result<u32, String> {
+    let res: Result<FolderChild, String> = call(
+        canister,
+        "get_child_by_name",
+        (parent, name.to_string(), None::<ByteBuf>),
+        0,
+    )
+    .await?;
+    match res {
+        Ok(FolderChild::Folder(info)) => return Ok(info.id),
+        Ok(FolderChild::File(_)) => Err(format!("a file exists with the folder name {:?}", name))?,
+        // creating the folder reports the other errors
+        Err(_) => {}
+    }
+
+    let res: Result<CreateFolderOutput, String> = call(
+        canister,
+        "create_folder",
+        (
+            CreateFolderInput {
+                parent,
+                name: name.to_string(),
+            },
+            None::<ByteBuf>,
+        ),
+        0,
+    )
+    .await?;
+    Ok(res?.id)
 }
 
 #[ic_cdk::update]
@@ -431,6 +751,22 @@ async fn validate_admin_deploy_bucket(
             args.canister.to_text()
         ))?;
     }
+    if let Some(template) = &args.template {
+        if info.module_hash.is_some() {
+            Err("template can only be applied to a new bucket".to_string())?;
+        }
+        template.validate()?;
+    }
+    if let Some(tenant) = &args.tenant {
+        match store::state::bucket_tenant(&args.canister) {
+            Some(current) if &current != tenant => Err(format!(
+                "canister {} belongs to tenant {:?}",
+                args.canister, current
+            ))?,
+            Some(_) => {}
+            None => store::state::check_tenant_quota(tenant)?,
+        }
+    }
 
     let prev_hash: [u8; 32] = if let Some(hash) = info.module_hash {
         hash.try_into().map_err(format_error)?
@@ -468,22 +804,31 @@ async fn admin_upgrade_all_buckets(args: Option<ByteBuf>) -> Result<(), String>
     upgrade_buckets().await
 }
 
-#[ic_cdk::update(guard = "is_controller_or_manager")]
+// calls the method on the buckets, all the deployed buckets if empty. a tenant manager
+// can only call the buckets of its tenants.
+#[ic_cdk::update(guard = "is_controller_or_manager_or_tenant_manager")]
 async fn admin_batch_call_buckets(
     buckets: BTreeSet<Principal>,
     method: String,
     args: Option<ByteBuf>,
 ) -> Result<Vec<ByteBuf>, String> {
+    let tenant_buckets = match is_controller_or_manager() {
+        Ok(_) => None,
+        Err(_) => Some(store::state::tenant_buckets(&ic_cdk::caller())),
+    };
     let ids = store::state::with(|s| {
         for id in &buckets {
             if !s.bucket_deployed_list.contains_key(id) {
                 return Err(format!("canister {} is not deployed", id));
             }
+            if tenant_buckets.as_ref().is_some_and(|tb| !tb.contains(id)) {
+                return Err(format!("canister {} is not managed by the caller", id));
+            }
         }
-        if buckets.is_empty() {
-            Ok(s.bucket_deployed_list.keys().cloned().collect())
-        } else {
-            Ok(buckets)
+        match (buckets.is_empty(), tenant_buckets) {
+            (false, _) => Ok(buckets),
+            (true, Some(tenant_buckets)) => Ok(tenant_buckets),
+            (true, None) => Ok(s.bucket_deployed_list.keys().cloned().collect()),
         }
     })?;
 
@@ -541,6 +886,26 @@ async fn admin_topup_all_buckets() -> Result<u128, String> {
     Ok(total)
 }
 
+// polls the usage of the tenants' buckets, it is run by a timer. the buckets that
+// fail to respond are skipped and their egress is accumulated by the next poll.
+pub async fn poll_tenant_usage() {
+    store::state::prune_bucket_usage();
+    let targets = store::state::usage_targets();
+    for ids in targets.chunks(7) {
+        let res = futures::future::join_all(ids.iter().map(|(id, since)| async move {
+            let usage: Result<BucketUsage, String> = call(*id, "get_usage", (since,), 0).await?;
+            usage.map(|usage| (*id, usage))
+        }))
+        .await;
+        let now_ms = ic_cdk::api::time() / MILLISECONDS;
+        for (id, usage) in res.into_iter().filter_map(|r| r.ok()) {
+            store::state::record_bucket_usage(id, usage, now_ms);
+        }
+    }
+}
+
+// updates the settings of the bucket canister, such as the compute allocation and
+// the freezing threshold. the unset settings are not changed.
 #[ic_cdk::update(guard = "is_controller")]
 async fn admin_update_bucket_canister_settings(args: UpdateSettingsArgument) -> Result<(), String> {
     store::state::with(|s| {
@@ -549,10 +914,216 @@ async fn admin_update_bucket_canister_settings(args: UpdateSettingsArgument) ->
         }
         Ok(())
     })?;
+    if let Some(ref controllers) = args.settings.controllers {
+        if !controllers.contains(&ic_cdk::id()) {
+            Err("the cluster should remain a controller of the bucket".to_string())?;
+        }
+    }
     update_settings(args).await.map_err(format_error)?;
     Ok(())
 }
 
+// adds a controller to the bucket canister, e.g. a DAO that manages the storage
+// fleet. the existing controllers, including the cluster, are kept.
+#[ic_cdk::update(guard = "is_controller")]
+async fn admin_add_bucket_controller(
+    canister: Principal,
+    controller: Principal,
+) -> Result<(), String> {
+    check_deployed(&canister)?;
+    if controller == ANONYMOUS {
+        Err("anonymous user is not allowed".to_string())?;
+    }
+
+    let (status,) = canister_status(CanisterIdRecord {
+        canister_id: canister,
+    })
+    .await
+    .map_err(format_error)?;
+    let mut controllers = status.settings.controllers;
+    if controllers.contains(&controller) {
+        return Ok(());
+    }
+    controllers.push(controller);
+    update_settings(UpdateSettingsArgument {
+        canister_id: canister,
+        settings: CanisterSettings {
+            controllers: Some(controllers),
+            ..Default::default()
+        },
+    })
+    .await
+    .map_err(format_error)
+}
+
+#[ic_cdk::update]
+async fn validate2_admin_add_bucket_controller(
+    canister: Principal,
+    controller: Principal,
+) -> Result<String, String> {
+    check_deployed(&canister)?;
+    if controller == ANONYMOUS {
+        Err("anonymous user is not allowed".to_string())?;
+    }
+    Ok("ok".to_string())
+}
+
+// takes a snapshot of the bucket canister, the oldest snapshot is replaced when
+// the limit is reached.
+#[ic_cdk::update(guard = "is_controller")]
+async fn admin_take_snapshot(canister: Principal) -> Result<Snapshot, String> {
+    check_deployed(&canister)?;
+    take_bucket_snapshot(canister).await
+}
+
+#[ic_cdk::update(guard = "is_controller_or_manager")]
+async fn admin_list_snapshots(canister: Principal) -> Result<Vec<Snapshot>, String> {
+    check_deployed(&canister)?;
+    let (snapshots,) = list_canister_snapshots(CanisterIdRecord {
+        canister_id: canister,
+    })
+    .await
+    .map_err(format_error)?;
+    Ok(snapshots)
+}
+
+// restores the bucket canister from the snapshot: the canister is stopped, loaded
+// with the snapshot and started again. the deployed wasm of the bucket is updated.
+#[ic_cdk::update(guard = "is_controller")]
+async fn admin_rollback_to_snapshot(
+    canister: Principal,
+    snapshot_id: ByteBuf,
+) -> Result<(), String> {
+    let (_, prev_hash) = store::state::with(|s| {
+        if s.bucket_upgrade_process.is_some() {
+            return Err("upgrade process is running".to_string());
+        }
+        s.bucket_deployed_list
+            .get(&canister)
+            .cloned()
+            .ok_or_else(|| format!("canister {} is not deployed", canister))
+    })?;
+
+    let arg = CanisterIdRecord {
+        canister_id: canister,
+    };
+    stop_canister(arg).await.map_err(format_error)?;
+    let res = load_canister_snapshot(LoadCanisterSnapshotArgs {
+        canister_id: canister,
+        snapshot_id: snapshot_id.into_vec(),
+        sender_canister_version: Some(ic_cdk::api::canister_version()),
+    })
+    .await
+    .map_err(format_error);
+    // the canister should be started even if the snapshot failed to load
+    start_canister(arg).await.map_err(format_error)?;
+
+    let (info,) = canister_info(CanisterInfoRequest {
+        canister_id: canister,
+        num_requested_changes: None,
+    })
+    .await
+    .map_err(format_error)?;
+    let hash: [u8; 32] = match info.module_hash {
+        Some(hash) => hash.try_into().map_err(format_error)?,
+        None => Default::default(),
+    };
+    let hash = ByteArray::from(hash);
+    let id = store::wasm::add_log(store::DeployLog {
+        deploy_at: ic_cdk::api::time() / MILLISECONDS,
+        canister,
+        prev_hash,
+        wasm_hash: hash,
+        args: ByteBuf::default(),
+        error: res.clone().err(),
+    })?;
+
+    if res.is_ok() {
+        store::state::with_mut(|s| {
+            s.bucket_deployed_list.insert(canister, (id, hash));
+        })
+    }
+    res
+}
+
+#[ic_cdk::update]
+async fn validate2_admin_rollback_to_snapshot(
+    canister: Principal,
+    _snapshot_id: ByteBuf,
+) -> Result<String, String> {
+    check_deployed(&canister)?;
+    Ok("ok".to_string())
+}
+
+// decommissions a bucket: marks it read-only, migrates its folders and files into a folder
+// named by the bucket id in the target bucket, removes it from the registry and optionally
+// stops and deletes the canister. the process pauses on errors, call it again to resume.
+#[ic_cdk::update(guard = "is_controller")]
+async fn admin_decommission_bucket(
+    canister: Principal,
+    target: Principal,
+    delete_canister: bool,
+) -> Result<(), String> {
+    let now_ms = ic_cdk::api::time() / MILLISECONDS;
+    store::state::with_mut(|s| {
+        if canister == target {
+            return Err("the target should be another bucket".to_string());
+        }
+        for id in [&canister, &target] {
+            if !s.bucket_deployed_list.contains_key(id) {
+                return Err(format!("canister {} is not deployed", id));
+            }
+        }
+        match s.bucket_decommission {
+            Some(ref mut d) if d.finished_at == 0 => {
+                if d.canister != canister || d.target != target {
+                    return Err(format!(
+                        "decommissioning of bucket {} is not finished",
+                        d.canister
+                    ));
+                }
+                if d.running {
+                    return Err("decommission process is running".to_string());
+                }
+                d.running = true;
+                d.delete_canister = delete_canister;
+                d.last_error = None;
+            }
+            _ => {
+                s.bucket_decommission = Some(store::BucketDecommission::new(
+                    canister,
+                    target,
+                    delete_canister,
+                    now_ms,
+                ));
+            }
+        }
+        Ok(())
+    })?;
+
+    decommission_bucket().await
+}
+
+#[ic_cdk::update]
+async fn validate2_admin_decommission_bucket(
+    canister: Principal,
+    target: Principal,
+    _delete_canister: bool,
+) -> Result<String, String> {
+    store::state::with(|s| {
+        if canister == target {
+            return Err("the target should be another bucket".to_string());
+        }
+        for id in [&canister, &target] {
+            if !s.bucket_deployed_list.contains_key(id) {
+                return Err(format!("canister {} is not deployed", id));
+            }
+        }
+        Ok(())
+    })?;
+    Ok("ok".to_string())
+}
+
 #[ic_cdk::update]
 async fn validate2_admin_upgrade_all_buckets(_args: Option<ByteBuf>) -> Result<String, String> {
     Ok("ok".to_string())
@@ -591,9 +1162,46 @@ async fn validate_admin_update_bucket_canister_settings(
         }
         Ok(())
     })?;
+    if let Some(ref controllers) = args.settings.controllers {
+        if !controllers.contains(&ic_cdk::id()) {
+            Err("the cluster should remain a controller of the bucket".to_string())?;
+        }
+    }
     Ok("ok".to_string())
 }
 
+fn check_deployed(canister: &Principal) -> Result<(), String> {
+    store::state::with(|s| {
+        if !s.bucket_deployed_list.contains_key(canister) {
+            return Err(format!("canister {} is not deployed", canister));
+        }
+        Ok(())
+    })
+}
+
+async fn take_bucket_snapshot(canister: Principal) -> Result<Snapshot, String> {
+    let (snapshots,) = list_canister_snapshots(CanisterIdRecord {
+        canister_id: canister,
+    })
+    .await
+    .map_err(format_error)?;
+    let replace_snapshot = if snapshots.len() >= MAX_BUCKET_SNAPSHOTS {
+        snapshots
+            .into_iter()
+            .min_by_key(|s| s.taken_at_timestamp)
+            .map(|s| s.id)
+    } else {
+        None
+    };
+    let (snapshot,) = take_canister_snapshot(TakeCanisterSnapshotArgs {
+        canister_id: canister,
+        replace_snapshot,
+    })
+    .await
+    .map_err(format_error)?;
+    Ok(snapshot)
+}
+
 async fn upgrade_buckets() -> Result<(), String> {
     match upgrade_bucket().await {
         Ok(Some(_)) => {
@@ -634,6 +1242,10 @@ async fn upgrade_bucket() -> Result<Option<Principal>, String> {
         Some((canister, prev, hash, args)) => match store::wasm::get_wasm(&hash) {
             None => Err(format!("wasm not found: {}", hex::encode(hash.as_ref()))),
             Some(wasm) => {
+                // the upgrade process stops if the snapshot fails, so the bucket can be rolled back
+                if store::state::with(|s| s.bucket_upgrade_snapshot) {
+                    take_bucket_snapshot(canister).await?;
+                }
                 let res = install_code(InstallCodeArgument {
                     mode: CanisterInstallMode::Upgrade(None),
                     canister_id: canister,
@@ -665,3 +1277,225 @@ async fn upgrade_bucket() -> Result<Option<Principal>, String> {
         },
     }
 }
+
+pub async fn decommission_bucket() -> Result<(), String> {
+    match decommission_round().await {
+        Ok(true) => {
+            ic_cdk_timers::set_timer(Duration::from_secs(0), || {
+                ic_cdk::spawn(async {
+                    let _ = decommission_bucket().await;
+                })
+            });
+            Ok(())
+        }
+        Ok(false) => {
+            with_decommission(|d| d.running = false);
+            Ok(())
+        }
+        Err(err) => {
+            with_decommission(|d| {
+                d.running = false;
+                d.last_error = Some(err.clone());
+            });
+            Err(err)
+        }
+    }
+}
+
+fn with_decommission<R>(f: impl FnOnce(&mut store::BucketDecommission) -> R) -> R {
+    store::state::with_mut(|s| {
+        f(s.bucket_decommission
+            .as_mut()
+            .expect("no decommission process"))
+    })
+}
+
+// migrates a page of folders or files, returns false if the process is finished.
+async fn decommission_round() -> Result<bool, String> {
+    let (canister, target, target_folder, front, folders_listed, cursor) =
+        store::state::with(|s| {
+            let d = s
+                .bucket_decommission
+                .as_ref()
+                .ok_or("no decommission process")?;
+            Ok::<_, String>((
+                d.canister,
+                d.target,
+                d.target_folder,
+                d.queue.front().cloned(),
+                d.folders_listed,
+                d.cursor,
+            ))
+        })?;
+
+    if target_folder == 0 {
+        prepare_decommission(canister, target).await?;
+        return Ok(true);
+    }
+
+    let take = Some(store::DECOMMISSION_PAGE_SIZE);
+    match front {
+        None => {
+            finish_decommission(canister).await?;
+            Ok(false)
+        }
+        Some((src, dst)) if !folders_listed => {
+            let res: Result<Vec<FolderInfo>, String> = call(
+                canister,
+                "list_folders",
+                (src, cursor, take, None::<ByteBuf>),
+                0,
+            )
+            .await?;
+            let folders = res?;
+            for folder in &folders {
+                let res: Result<CreateFileOutput, String> = call(
+                    target,
+                    "create_folder",
+                    (
+                        CreateFolderInput {
+                            parent: dst,
+                            name: folder.name.clone(),
+                        },
+                        None::<ByteBuf>,
+                    ),
+                    0,
+                )
+                .await?;
+                let output = res?;
+                with_decommission(|d| {
+                    d.queue.push_back((folder.id, output.id));
+                    d.migrated_folders += 1;
+                    d.cursor = Some(folder.id);
+                });
+            }
+            with_decommission(|d| d.end_page(folders.len()));
+            Ok(true)
+        }
+        Some((src, dst)) => {
+            let res: Result<Vec<FileInfo>, String> = call(
+                canister,
+                "list_files",
+                (src, cursor, take, None::<ByteBuf>),
+                0,
+            )
+            .await?;
+            let files = res?;
+            for file in &files {
+                let res: Result<FileInfo, String> = call(
+                    target,
+                    "pull_file_from",
+                    (canister, file.id, dst, None::<ByteBuf>),
+                    0,
+                )
+                .await?;
+                with_decommission(|d| {
+                    match res {
+                        Ok(_) => d.migrated_files += 1,
+                        Err(err) => d.add_failed_file(file.id, err),
+                    }
+                    d.cursor = Some(file.id);
+                });
+            }
+            with_decommission(|d| d.end_page(files.len()));
+            Ok(true)
+        }
+    }
+}
+
+// marks the bucket read-only, grants the cluster and the target bucket access to it,
+// and creates the target folder in the target bucket.
+async fn prepare_decommission(canister: Principal, target: Principal) -> Result<(), String> {
+    let self_id = ic_cdk::id();
+    let res: Result<(), String> = call(
+        canister,
+        "admin_update_bucket",
+        (UpdateBucketInput {
+            status: Some(1),
+            ..Default::default()
+        },),
+        0,
+    )
+    .await?;
+    res?;
+    let res: Result<(), String> = call(
+        canister,
+        "admin_add_auditors",
+        (BTreeSet::from([self_id, target]),),
+        0,
+    )
+    .await?;
+    res?;
+    let res: Result<(), String> = call(
+        target,
+        "admin_add_managers",
+        (BTreeSet::from([self_id]),),
+        0,
+    )
+    .await?;
+    res?;
+    let res: Result<CreateFileOutput, String> = call(
+        target,
+        "create_folder",
+        (
+            CreateFolderInput {
+                parent: 0,
+                name: canister.to_text(),
+            },
+            None::<ByteBuf>,
+        ),
+        0,
+    )
+    .await?;
+    let output = res?;
+    with_decommission(|d| {
+        d.target_folder = output.id;
+        d.queue.push_back((0, output.id));
+    });
+    Ok(())
+}
+
+// removes the bucket from the registry and optionally deletes the canister,
+// the bucket is kept if some files failed to migrate.
+async fn finish_decommission(canister: Principal) -> Result<(), String> {
+    let now_ms = ic_cdk::api::time() / MILLISECONDS;
+    let (failed_files, delete, target) =
+        with_decommission(|d| (d.failed_files, d.delete_canister, d.target));
+    if failed_files > 0 {
+        with_decommission(|d| {
+            d.finished_at = now_ms;
+            d.last_error = Some(format!(
+                "{} files failed to migrate, the bucket is kept",
+                failed_files
+            ));
+        });
+        return Ok(());
+    }
+
+    if delete {
+        let arg = CanisterIdRecord {
+            canister_id: canister,
+        };
+        stop_canister(arg).await.map_err(format_error)?;
+        delete_canister(arg).await.map_err(format_error)?;
+    }
+
+    store::state::with_mut(|s| {
+        s.bucket_deployed_list.remove(&canister);
+        s.audience_groups.retain(|_, buckets| {
+            buckets.remove(&canister);
+            !buckets.is_empty()
+        });
+        for tenant in s.tenants.values_mut() {
+            tenant.buckets.remove(&canister);
+        }
+        // the files are migrated to the target, so are the paths
+        for bucket in s.placements.values_mut() {
+            if *bucket == canister {
+                *bucket = target;
+            }
+        }
+    });
+    with_decommission(|d| d.finished_at = now_ms);
+    Ok(())
+}
@@ -1,8 +1,8 @@
 use candid::Principal;
-use ic_oss_types::cose::Token;
+use ic_oss_types::cose::{introspect_sign1, Token, TokenInfo, BUCKET_TOKEN_AAD};
 use serde_bytes::ByteBuf;
 
-use crate::{api_admin, store};
+use crate::{api_admin, store, SECONDS};
 
 #[ic_cdk::update]
 async fn access_token(audience: Principal) -> Result<ByteBuf, String> {
@@ -18,6 +18,30 @@ async fn ed25519_access_token(audience: Principal) -> Result<ByteBuf, String> {
     api_admin::admin_ed25519_access_token(token).await
 }
 
+// decodes and verifies the access token without side effects, for debugging and for
+// resource servers that validate the tokens on the server side. the matching policies
+// are the policies currently attached to the subject for the audience, they may differ
+// from the token's if they were changed after the token was issued.
+#[ic_cdk::query]
+fn introspect_token(sign1_token: ByteBuf) -> Result<TokenInfo, String> {
+    let (ecdsa_pub_keys, eddsa_pub_keys) = store::state::token_public_keys();
+    let mut info = introspect_sign1(
+        &sign1_token,
+        &ecdsa_pub_keys,
+        &eddsa_pub_keys,
+        BUCKET_TOKEN_AAD,
+        (ic_cdk::api::time() / SECONDS) as i64,
+    )?;
+    if info.valid {
+        info.matching_policies = Some(
+            store::auth::get_all_policies(&info.subject)
+                .and_then(|pt| pt.0.get(&info.audience).cloned())
+                .unwrap_or_default(),
+        );
+    }
+    Ok(info)
+}
+
 fn get_token(subject: Principal, audience: Principal) -> Result<Token, String> {
     match store::auth::get_all_policies(&subject) {
         None => Err("no policies found".to_string()),
@@ -27,6 +51,8 @@ fn get_token(subject: Principal, audience: Principal) -> Result<Token, String> {
                 subject,
                 audience,
                 policies: policies.to_owned(),
+                caller: None,
+                jti: None,
             })
         }
     }
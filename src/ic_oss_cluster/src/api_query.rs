@@ -1,13 +1,36 @@
 use candid::{Nat, Principal};
+use ed25519_dalek::{Signer, SigningKey};
 use ic_cdk::api::management_canister::main::*;
+use ic_oss_types::file::FileInfo;
 use ic_oss_types::{
-    cluster::{BucketDeploymentInfo, ClusterInfo, WasmInfo},
-    format_error, nat_to_u64,
+    api_major_version,
+    cluster::{
+        BucketDecommissionStatus, BucketDeploymentInfo, BucketLookup, ClusterInfo,
+        FindFileByHashOutput, Tenant, TenantUsage, WasmInfo, BUCKET_LOOKUP_TTL,
+    },
+    format_error, nat_to_u64, CLUSTER_API_VERSION,
 };
-use serde_bytes::ByteArray;
-use std::collections::BTreeMap;
+use serde_bytes::{ByteArray, ByteBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Bound,
+};
+
+use crate::{
+    call, check_audience, is_controller_or_manager, is_controller_or_manager_or_tenant_manager,
+    store, SECONDS,
+};
+
+#[ic_cdk::query]
+fn api_version() -> u16 {
+    api_major_version(CLUSTER_API_VERSION)
+}
 
-use crate::{is_controller_or_manager, store};
+// the semantic version of the interface, see ic_oss_types::check_api_version
+#[ic_cdk::query]
+fn api_semver() -> String {
+    CLUSTER_API_VERSION.to_string()
+}
 
 #[ic_cdk::query]
 fn get_cluster_info() -> Result<ClusterInfo, String> {
@@ -37,6 +60,102 @@ fn get_buckets() -> Result<Vec<Principal>, String> {
     store::state::with(|s| Ok(s.bucket_deployed_list.keys().cloned().collect()))
 }
 
+#[ic_cdk::query]
+fn get_audience_groups() -> Result<BTreeMap<Principal, BTreeSet<Principal>>, String> {
+    store::state::with(|s| Ok(s.audience_groups.clone()))
+}
+
+#[ic_cdk::query]
+fn get_placements() -> Result<BTreeMap<String, Principal>, String> {
+    store::state::with(|s| Ok(s.placements.clone()))
+}
+
+#[ic_cdk::query]
+fn get_tenants() -> Result<Vec<Tenant>, String> {
+    Ok(store::state::with(|s| {
+        s.tenants.values().cloned().collect()
+    }))
+}
+
+// the usage of the tenant aggregated from the last polls of its buckets, the tenant
+// managers can only read the usage of their own tenants.
+#[ic_cdk::query(guard = "is_controller_or_manager_or_tenant_manager")]
+fn get_tenant_usage(tenant: String) -> Result<TenantUsage, String> {
+    let caller = ic_cdk::caller();
+    store::state::with(|s| match s.tenants.get(&tenant) {
+        Some(t) if is_controller_or_manager().is_ok() || t.managers.contains(&caller) => Ok(()),
+        Some(_) => Err(format!("tenant {:?} is not managed by the caller", tenant)),
+        None => Err(format!("tenant {:?} not found", tenant)),
+    })?;
+    store::state::tenant_usage(&tenant).ok_or_else(|| format!("tenant {:?} not found", tenant))
+}
+
+#[ic_cdk::query]
+fn get_bucket_decommission() -> Result<Option<BucketDecommissionStatus>, String> {
+    store::state::with(|s| Ok(s.bucket_decommission.as_ref().map(|d| d.status())))
+}
+
+// resolves the bucket that serves the logical path for the gateways from the placement
+// of the first segment of the path, see `admin_set_placement`. the result is signed by
+// the cluster's weak ed25519 key and can be cached until it expires.
+#[ic_cdk::query]
+fn lookup(path: String) -> Result<BucketLookup, String> {
+    let shard_key = BucketLookup::shard_key(&path)?;
+    let bucket = store::state::lookup_bucket(&shard_key)
+        .ok_or_else(|| format!("no bucket placed for {:?}", shard_key))?;
+    let secret_key = store::state::with(|s| s.weak_ed25519_secret_key);
+    let issued_at = ic_cdk::api::time() / SECONDS;
+    let mut res = BucketLookup {
+        bucket,
+        shard_key,
+        issued_at,
+        expires_at: issued_at + BUCKET_LOOKUP_TTL,
+        signature: [0u8; 64].into(),
+    };
+    let signing_key = SigningKey::from_bytes(&secret_key);
+    res.signature = signing_key.sign(&res.tbs_data()).to_bytes().into();
+    Ok(res)
+}
+
+// finds the files with the hash in the deployed buckets after `prev`, at most `take`
+// buckets are called, returns the (bucket, file id) pairs and the cursor of the next page.
+// only the files that can be read by hash are found, see the `by_hash` custom key of the files.
+#[ic_cdk::update(guard = "is_controller_or_manager")]
+async fn find_file_by_hash(
+    hash: ByteArray<32>,
+    prev: Option<Principal>,
+    take: Option<u32>,
+) -> Result<FindFileByHashOutput, String> {
+    let take = take.unwrap_or(7).clamp(1, 21) as usize;
+    let (buckets, more) = store::state::with(|s| {
+        let mut iter = match prev {
+            Some(prev) => s
+                .bucket_deployed_list
+                .range((Bound::Excluded(prev), Bound::Unbounded)),
+            None => s.bucket_deployed_list.range(..),
+        }
+        .map(|(id, _)| *id);
+        let buckets: Vec<Principal> = iter.by_ref().take(take).collect();
+        (buckets, iter.next().is_some())
+    });
+    let token = Some(ByteBuf::from(hash.to_vec()));
+    let mut res = FindFileByHashOutput {
+        files: Vec::new(),
+        next: if more { buckets.last().copied() } else { None },
+    };
+    for ids in buckets.chunks(7) {
+        let files = futures::future::join_all(ids.iter().map(|id| async {
+            let info: Result<FileInfo, String> =
+                call(*id, "get_file_info_by_hash", (hash, &token), 0).await?;
+            info.map(|info| (*id, info.id))
+        }))
+        .await;
+        // the buckets without the file or the hash index are skipped
+        res.files.extend(files.into_iter().filter_map(|r| r.ok()));
+    }
+    Ok(res)
+}
+
 #[ic_cdk::update(guard = "is_controller_or_manager")]
 async fn get_canister_status(
     canister: Option<Principal>,
@@ -77,8 +196,9 @@ fn get_subject_policies(subject: Principal) -> Result<BTreeMap<Principal, String
         .ok_or_else(|| "subject not found".to_string())
 }
 
-#[ic_cdk::query(guard = "is_controller_or_manager")]
+#[ic_cdk::query(guard = "is_controller_or_manager_or_tenant_manager")]
 fn get_subject_policies_for(subject: Principal, audience: Principal) -> Result<String, String> {
+    check_audience(&audience)?;
     match store::auth::get_all_policies(&subject) {
         None => Err("subject not found".to_string()),
         Some(ps) => {
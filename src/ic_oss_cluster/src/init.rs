@@ -1,35 +1,8 @@
-use candid::{CandidType, Principal};
-use serde::Deserialize;
+use ic_oss_types::cluster::ChainArgs;
 use std::time::Duration;
 
 use crate::store;
 
-#[derive(Clone, Debug, CandidType, Deserialize)]
-pub enum ChainArgs {
-    Init(InitArgs),
-    Upgrade(UpgradeArgs),
-}
-
-#[derive(Clone, Debug, CandidType, Deserialize)]
-pub struct InitArgs {
-    name: String,
-    ecdsa_key_name: String, // Use "dfx_test_key" for local replica and "test_key_1" for a testing key for testnet and mainnet
-    schnorr_key_name: String, // Use "dfx_test_key" for local replica and "test_key_1" for a testing key for testnet and mainnet
-    token_expiration: u64,    // in seconds
-    bucket_topup_threshold: u128,
-    bucket_topup_amount: u128,
-    governance_canister: Option<Principal>,
-}
-
-#[derive(Clone, Debug, CandidType, Deserialize)]
-pub struct UpgradeArgs {
-    name: Option<String>,
-    token_expiration: Option<u64>, // in seconds
-    bucket_topup_threshold: Option<u128>,
-    bucket_topup_amount: Option<u128>,
-    governance_canister: Option<Principal>,
-}
-
 #[ic_cdk::init]
 fn init(args: Option<ChainArgs>) {
     match args.expect("init args is missing") {
@@ -46,6 +19,7 @@ fn init(args: Option<ChainArgs>) {
                 s.bucket_topup_threshold = args.bucket_topup_threshold;
                 s.bucket_topup_amount = args.bucket_topup_amount;
                 s.governance_canister = args.governance_canister;
+                s.bucket_upgrade_snapshot = args.bucket_upgrade_snapshot.unwrap_or_default();
             });
         }
         ChainArgs::Upgrade(_) => {
@@ -58,6 +32,9 @@ fn init(args: Option<ChainArgs>) {
     ic_cdk_timers::set_timer(Duration::from_secs(0), || {
         ic_cdk::spawn(store::state::try_init_public_key())
     });
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(store::USAGE_POLL_INTERVAL), || {
+        ic_cdk::spawn(crate::api_admin::poll_tenant_usage())
+    });
 }
 
 #[ic_cdk::pre_upgrade]
@@ -91,6 +68,9 @@ fn post_upgrade(args: Option<ChainArgs>) {
                 if let Some(governance_canister) = args.governance_canister {
                     s.governance_canister = Some(governance_canister);
                 }
+                if let Some(bucket_upgrade_snapshot) = args.bucket_upgrade_snapshot {
+                    s.bucket_upgrade_snapshot = bucket_upgrade_snapshot;
+                }
             });
         }
         Some(ChainArgs::Init(_)) => {
@@ -110,4 +90,16 @@ fn post_upgrade(args: Option<ChainArgs>) {
     ic_cdk_timers::set_timer(Duration::from_secs(0), || {
         ic_cdk::spawn(store::state::try_init_public_key())
     });
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(store::USAGE_POLL_INTERVAL), || {
+        ic_cdk::spawn(crate::api_admin::poll_tenant_usage())
+    });
+
+    // the timers are cleared by the upgrade, resumes the running decommission process
+    if store::state::with(|s| s.bucket_decommission.as_ref().is_some_and(|d| d.running)) {
+        ic_cdk_timers::set_timer(Duration::from_secs(0), || {
+            ic_cdk::spawn(async {
+                let _ = crate::api_admin::decommission_bucket().await;
+            })
+        });
+    }
 }
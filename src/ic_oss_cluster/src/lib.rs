@@ -1,9 +1,12 @@
 use candid::{utils::ArgumentEncoder, CandidType, Nat, Principal};
 use ic_cdk::api::management_canister::main::{
-    CanisterSettings, CanisterStatusResponse, UpdateSettingsArgument,
+    CanisterSettings, CanisterStatusResponse, Snapshot, UpdateSettingsArgument,
 };
 use ic_oss_types::{
-    cluster::{AddWasmInput, BucketDeploymentInfo, ClusterInfo, DeployWasmInput, WasmInfo},
+    cluster::{
+        AddWasmInput, BucketDecommissionStatus, BucketDeploymentInfo, ClusterInfo, DeployWasmInput,
+        WasmInfo,
+    },
     cose::Token,
 };
 use serde::{Deserialize, Serialize};
@@ -18,7 +21,7 @@ mod init;
 mod schnorr;
 mod store;
 
-use crate::init::ChainArgs;
+use ic_oss_types::cluster::ChainArgs;
 
 static ANONYMOUS: Principal = Principal::anonymous();
 // NNS Cycles Minting Canister: "rkp4c-7iaaa-aaaaa-aaaca-cai"
@@ -48,6 +51,53 @@ fn is_controller_or_manager() -> Result<(), String> {
     }
 }
 
+fn is_controller_or_manager_or_tenant_manager() -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    if is_controller_or_manager().is_ok() || store::state::is_tenant_manager(&caller) {
+        Ok(())
+    } else {
+        Err("user is not a controller or manager or tenant manager".to_string())
+    }
+}
+
+fn is_controller_or_tenant_manager() -> Result<(), String> {
+    if is_controller().is_ok() || store::state::is_tenant_manager(&ic_cdk::caller()) {
+        Ok(())
+    } else {
+        Err("user is not a controller or tenant manager".to_string())
+    }
+}
+
+// reserves a new bucket in the quota of the tenant, a tenant manager can only add the
+// buckets to its tenants. None: the bucket belongs to no tenant, for the controllers.
+fn reserve_tenant_bucket(
+    tenant: Option<String>,
+) -> Result<Option<store::TenantReservation>, String> {
+    let is_controller = is_controller().is_ok();
+    match tenant {
+        Some(id) => {
+            store::state::reserve_tenant_bucket(&ic_cdk::caller(), id, is_controller).map(Some)
+        }
+        None if is_controller => Ok(None),
+        None => Err("tenant is required for a tenant manager".to_string()),
+    }
+}
+
+// checks that the caller can manage the audience, a tenant manager can only manage
+// the buckets of its tenants.
+fn check_audience(audience: &Principal) -> Result<(), String> {
+    if is_controller_or_manager().is_ok()
+        || store::state::tenant_manages(&ic_cdk::caller(), audience)
+    {
+        Ok(())
+    } else {
+        Err(format!(
+            "audience {} is not managed by the caller",
+            audience
+        ))
+    }
+}
+
 fn is_controller_or_manager_or_committer() -> Result<(), String> {
     let caller = ic_cdk::caller();
     if ic_cdk::api::is_controller(&caller)
@@ -52,7 +52,7 @@ pub async fn sign_with_schnorr(
         MAX_SIGN_WITH_SCHNORR_FEE,
     )
     .await
-    .map_err(|err| format!("sign_with_ecdsa failed {:?}", err))?;
+    .map_err(|err| format!("sign_with_schnorr failed {:?}", err))?;
 
     Ok(res.signature)
 }
@@ -2,7 +2,11 @@ use candid::Principal;
 use ciborium::{from_reader, into_writer};
 use ed25519_dalek::{SigningKey, VerifyingKey};
 use ic_oss_types::{
-    cluster::{AddWasmInput, BucketDeploymentInfo, ClusterInfo},
+    bucket::BucketUsage,
+    cluster::{
+        AddWasmInput, BucketDecommissionStatus, BucketDeploymentInfo, BucketLookup, ClusterInfo,
+        Tenant, TenantUsage,
+    },
     cose::sha256,
     permission::Policies,
 };
@@ -16,7 +20,7 @@ use serde_bytes::{ByteArray, ByteBuf};
 use std::{
     borrow::Cow,
     cell::RefCell,
-    collections::{BTreeMap, BTreeSet, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
 };
 
 use crate::{ecdsa, schnorr, TOKEN_KEY_DERIVATION_PATH};
@@ -59,6 +63,22 @@ pub struct State {
     pub governance_canister: Option<Principal>,
     #[serde(default, rename = "c")]
     pub committers: BTreeSet<Principal>,
+    // audience group id -> member buckets, a token for the group is accepted by all its buckets
+    #[serde(default, rename = "ag")]
+    pub audience_groups: BTreeMap<Principal, BTreeSet<Principal>>,
+    #[serde(default, rename = "bd")]
+    pub bucket_decommission: Option<BucketDecommission>,
+    #[serde(default, rename = "us")]
+    pub bucket_upgrade_snapshot: bool,
+    // tenant id -> tenant, the tenant managers can only manage the buckets of their tenants
+    #[serde(default, rename = "tn")]
+    pub tenants: BTreeMap<String, Tenant>,
+    // bucket -> (polled at in milliseconds, usage with the accumulated egress_bytes)
+    #[serde(default, rename = "bu")]
+    pub bucket_usage: BTreeMap<Principal, (u64, BucketUsage)>,
+    // shard key (a tenant id or the first segment of the paths) -> the bucket that serves it
+    #[serde(default, rename = "pl")]
+    pub placements: BTreeMap<String, Principal>,
 }
 
 impl Storable for State {
@@ -75,6 +95,108 @@ impl Storable for State {
     }
 }
 
+// the interval of polling the usage of the tenants' buckets, in seconds
+pub const USAGE_POLL_INTERVAL: u64 = 3600;
+
+// the number of folders or files migrated in a decommissioning round
+pub const DECOMMISSION_PAGE_SIZE: u32 = 10;
+const MAX_FAILED_FILE_ERRORS: usize = 100;
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct BucketDecommission {
+    #[serde(rename = "c")]
+    pub canister: Principal,
+    #[serde(rename = "t")]
+    pub target: Principal,
+    #[serde(rename = "tf")]
+    pub target_folder: u32,
+    #[serde(rename = "d")]
+    pub delete_canister: bool,
+    #[serde(rename = "sa")]
+    pub started_at: u64,
+    #[serde(rename = "fa")]
+    pub finished_at: u64,
+    #[serde(rename = "r")]
+    pub running: bool,
+    #[serde(rename = "mf")]
+    pub migrated_folders: u64,
+    #[serde(rename = "mi")]
+    pub migrated_files: u64,
+    #[serde(rename = "ff")]
+    pub failed_files: u64,
+    #[serde(rename = "fe")]
+    pub failed_file_errors: Vec<(u32, String)>,
+    // the pending (source folder, target folder) pairs, the front one is being migrated
+    #[serde(rename = "q")]
+    pub queue: VecDeque<(u32, u32)>,
+    // the subfolders of the front folder are listed, its files are being migrated
+    #[serde(rename = "l")]
+    pub folders_listed: bool,
+    #[serde(rename = "cu")]
+    pub cursor: Option<u32>,
+    #[serde(rename = "e")]
+    pub last_error: Option<String>,
+}
+
+impl BucketDecommission {
+    pub fn new(canister: Principal, target: Principal, delete_canister: bool, now_ms: u64) -> Self {
+        Self {
+            canister,
+            target,
+            target_folder: 0,
+            delete_canister,
+            started_at: now_ms,
+            finished_at: 0,
+            running: true,
+            migrated_folders: 0,
+            migrated_files: 0,
+            failed_files: 0,
+            failed_file_errors: Vec::new(),
+            queue: VecDeque::new(),
+            folders_listed: false,
+            cursor: None,
+            last_error: None,
+        }
+    }
+
+    pub fn add_failed_file(&mut self, id: u32, err: String) {
+        self.failed_files += 1;
+        if self.failed_file_errors.len() < MAX_FAILED_FILE_ERRORS {
+            self.failed_file_errors.push((id, err));
+        }
+    }
+
+    // ends a listed page, the subfolders of the front folder are listed first, then its files.
+    // the front folder is done when a page is not full.
+    pub fn end_page(&mut self, len: usize) {
+        if len < DECOMMISSION_PAGE_SIZE as usize {
+            self.cursor = None;
+            if self.folders_listed {
+                self.queue.pop_front();
+            }
+            self.folders_listed = !self.folders_listed;
+        }
+    }
+
+    pub fn status(&self) -> BucketDecommissionStatus {
+        BucketDecommissionStatus {
+            canister: self.canister,
+            target: self.target,
+            target_folder: self.target_folder,
+            delete_canister: self.delete_canister,
+            started_at: self.started_at,
+            finished_at: self.finished_at,
+            running: self.running,
+            migrated_folders: self.migrated_folders,
+            migrated_files: self.migrated_files,
+            failed_files: self.failed_files,
+            failed_file_errors: self.failed_file_errors.clone(),
+            pending_folders: self.queue.len() as u64,
+            last_error: self.last_error.clone(),
+        }
+    }
+}
+
 #[derive(Clone, Default, Deserialize, Serialize)]
 pub struct PoliciesTable(pub BTreeMap<Principal, String>);
 
@@ -178,6 +300,9 @@ const INSTALL_LOG_DATA_MEMORY_ID: MemoryId = MemoryId::new(4);
 thread_local! {
     static STATE: RefCell<State> = RefCell::new(State::default());
 
+    // tenant id -> the buckets being created for the tenant, they count in its quota
+    static TENANT_RESERVATIONS: RefCell<BTreeMap<String, u32>> = RefCell::new(BTreeMap::new());
+
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
         RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
 
@@ -208,6 +333,49 @@ thread_local! {
     );
 }
 
+// a bucket reserved in the quota of a tenant, see state::reserve_tenant_bucket
+pub struct TenantReservation {
+    tenant: String,
+}
+
+impl TenantReservation {
+    // adds the bucket to the tenant, the reservation is released.
+    pub fn commit(self, bucket: Principal) -> Result<(), String> {
+        state::with_mut(|s| {
+            if let Some(other) = s
+                .tenants
+                .values()
+                .find(|t| t.id != self.tenant && t.buckets.contains(&bucket))
+            {
+                return Err(format!(
+                    "canister {} belongs to tenant {:?}",
+                    bucket, other.id
+                ));
+            }
+            match s.tenants.get_mut(&self.tenant) {
+                Some(t) => {
+                    t.buckets.insert(bucket);
+                    Ok(())
+                }
+                None => Err(format!("tenant {:?} not found", self.tenant)),
+            }
+        })
+    }
+}
+
+impl Drop for TenantReservation {
+    fn drop(&mut self) {
+        TENANT_RESERVATIONS.with_borrow_mut(|r| {
+            if let Some(n) = r.get_mut(&self.tenant) {
+                *n -= 1;
+                if *n == 0 {
+                    r.remove(&self.tenant);
+                }
+            }
+        });
+    }
+}
+
 pub mod state {
     use super::*;
 
@@ -228,6 +396,247 @@ pub mod state {
         STATE.with(|r| r.borrow().committers.contains(caller))
     }
 
+    pub fn is_tenant_manager(caller: &Principal) -> bool {
+        with(|s| s.tenants.values().any(|t| t.managers.contains(caller)))
+    }
+
+    // the buckets of the tenants that the caller manages
+    pub fn tenant_buckets(caller: &Principal) -> BTreeSet<Principal> {
+        with(|s| {
+            s.tenants
+                .values()
+                .filter(|t| t.managers.contains(caller))
+                .flat_map(|t| t.buckets.iter().cloned())
+                .collect()
+        })
+    }
+
+    // checks that the audience is a bucket of the caller's tenants, or an audience
+    // group whose member buckets all belong to them.
+    pub fn tenant_manages(caller: &Principal, audience: &Principal) -> bool {
+        let buckets = tenant_buckets(caller);
+        if buckets.contains(audience) {
+            return true;
+        }
+        with(|s| {
+            s.audience_groups
+                .get(audience)
+                .is_some_and(|members| members.is_subset(&buckets))
+        })
+    }
+
+    // the tenant that the bucket belongs to
+    pub fn bucket_tenant(bucket: &Principal) -> Option<String> {
+        with(|s| {
+            s.tenants
+                .values()
+                .find(|t| t.buckets.contains(bucket))
+                .map(|t| t.id.clone())
+        })
+    }
+
+    // checks that the tenant can take one more bucket, the buckets being created count.
+    pub fn check_tenant_quota(id: &str) -> Result<(), String> {
+        let reserved = TENANT_RESERVATIONS.with_borrow(|r| r.get(id).copied().unwrap_or(0));
+        with(|s| match s.tenants.get(id) {
+            Some(t) if t.buckets.len() as u64 + reserved as u64 >= t.max_buckets as u64 => {
+                Err(format!(
+                    "tenant {:?} reached the quota of {} buckets",
+                    id, t.max_buckets
+                ))
+            }
+            Some(_) => Ok(()),
+            None => Err(format!("tenant {:?} not found", id)),
+        })
+    }
+
+    // reserves a bucket in the tenant's quota while the bucket is created or deployed,
+    // the reservation is released when it is dropped without being committed.
+    pub fn reserve_tenant_bucket(
+        caller: &Principal,
+        id: String,
+        is_controller: bool,
+    ) -> Result<TenantReservation, String> {
+        if !is_controller
+            && !with(|s| {
+                s.tenants
+                    .get(&id)
+                    .is_some_and(|t| t.managers.contains(caller))
+            })
+        {
+            return Err(format!("tenant {:?} is not managed by the caller", id));
+        }
+        check_tenant_quota(&id)?;
+        TENANT_RESERVATIONS.with_borrow_mut(|r| *r.entry(id.clone()).or_default() += 1);
+        Ok(TenantReservation { tenant: id })
+    }
+
+    // creates or replaces the tenant, a bucket can only belong to one tenant.
+    pub fn set_tenant(tenant: Tenant) -> Result<(), String> {
+        tenant.validate()?;
+        with_mut(|s| {
+            for id in &tenant.buckets {
+                if !s.bucket_deployed_list.contains_key(id) {
+                    return Err(format!("canister {} is not deployed", id));
+                }
+                if let Some(other) = s
+                    .tenants
+                    .values()
+                    .find(|t| t.id != tenant.id && t.buckets.contains(id))
+                {
+                    return Err(format!("canister {} belongs to tenant {:?}", id, other.id));
+                }
+            }
+            s.tenants.insert(tenant.id.clone(), tenant);
+            Ok(())
+        })
+    }
+
+    // the buckets of all tenants with the start of their next egress window
+    pub fn usage_targets() -> Vec<(Principal, u64)> {
+        with(|s| {
+            s.tenants
+                .values()
+                .flat_map(|t| t.buckets.iter())
+                .map(|id| (*id, s.bucket_usage.get(id).map_or(0, |(_, u)| u.until)))
+                .collect()
+        })
+    }
+
+    // replaces the storage and files of the bucket and accumulates its egress
+    pub fn record_bucket_usage(bucket: Principal, usage: BucketUsage, now_ms: u64) {
+        with_mut(|s| {
+            let egress_bytes = s
+                .bucket_usage
+                .get(&bucket)
+                .map_or(0, |(_, u)| u.egress_bytes)
+                .saturating_add(usage.egress_bytes);
+            s.bucket_usage.insert(
+                bucket,
+                (
+                    now_ms,
+                    BucketUsage {
+                        egress_bytes,
+                        ..usage
+                    },
+                ),
+            );
+        })
+    }
+
+    // drops the usage of the buckets that no longer belong to any tenant
+    pub fn prune_bucket_usage() {
+        with_mut(|s| {
+            let buckets: BTreeSet<Principal> = s
+                .tenants
+                .values()
+                .flat_map(|t| t.buckets.iter().cloned())
+                .collect();
+            s.bucket_usage.retain(|id, _| buckets.contains(id));
+        })
+    }
+
+    pub fn tenant_usage(id: &str) -> Option<TenantUsage> {
+        with(|s| {
+            let tenant = s.tenants.get(id)?;
+            let mut usage = TenantUsage {
+                tenant: tenant.id.clone(),
+                buckets: tenant.buckets.len() as u32,
+                updated_at: u64::MAX,
+                ..Default::default()
+            };
+            for id in &tenant.buckets {
+                match s.bucket_usage.get(id) {
+                    Some((polled_at, u)) => {
+                        usage.storage_bytes = usage.storage_bytes.saturating_add(u.storage_bytes);
+                        usage.total_files = usage.total_files.saturating_add(u.total_files);
+                        usage.egress_bytes = usage.egress_bytes.saturating_add(u.egress_bytes);
+                        usage.updated_at = usage.updated_at.min(*polled_at);
+                    }
+                    None => usage.updated_at = 0,
+                }
+            }
+            if usage.updated_at == u64::MAX {
+                usage.updated_at = 0;
+            }
+            Some(usage)
+        })
+    }
+
+    pub fn bucket_audience_groups(bucket: &Principal) -> BTreeSet<Principal> {
+        with(|s| {
+            s.audience_groups
+                .iter()
+                .filter(|(_, buckets)| buckets.contains(bucket))
+                .map(|(group, _)| *group)
+                .collect()
+        })
+    }
+
+    // the public keys that verify the access tokens signed by the cluster,
+    // the keys that are not initialized yet are skipped.
+    pub fn token_public_keys() -> (Vec<ByteBuf>, Vec<ByteArray<32>>) {
+        with(|s| {
+            let ecdsa = hex::decode(&s.ecdsa_token_public_key)
+                .ok()
+                .filter(|key| !key.is_empty())
+                .map(ByteBuf::from)
+                .into_iter()
+                .collect();
+            let eddsa = [
+                &s.schnorr_ed25519_token_public_key,
+                &s.weak_ed25519_token_public_key,
+            ]
+            .into_iter()
+            .filter_map(|key| {
+                let key: [u8; 32] = hex::decode(key).ok()?.try_into().ok()?;
+                Some(ByteArray::from(key))
+            })
+            .collect();
+            (ecdsa, eddsa)
+        })
+    }
+
+    // the bucket that serves the shard key, from its placement. a tenant without a
+    // placement is served by its bucket if it has only one.
+    pub fn lookup_bucket(shard_key: &str) -> Option<Principal> {
+        with(|s| {
+            s.placements.get(shard_key).cloned().or_else(|| {
+                s.tenants
+                    .get(shard_key)
+                    .filter(|t| t.buckets.len() == 1)
+                    .and_then(|t| t.buckets.first().cloned())
+            })
+        })
+    }
+
+    // places the shard key on the deployed bucket, or removes its placement.
+    pub fn set_placement(shard_key: String, bucket: Option<Principal>) -> Result<(), String> {
+        if BucketLookup::shard_key(&shard_key)? != shard_key {
+            Err(format!("invalid shard key: {:?}", shard_key))?;
+        }
+        with_mut(|s| {
+            match bucket {
+                Some(bucket) => {
+                    if !s.bucket_deployed_list.contains_key(&bucket) {
+                        Err(format!("canister {} is not deployed", bucket))?;
+                    }
+                    if s.bucket_decommission
+                        .as_ref()
+                        .is_some_and(|d| d.canister == bucket && d.finished_at == 0)
+                    {
+                        Err(format!("canister {} is being decommissioned", bucket))?;
+                    }
+                    s.placements.insert(shard_key, bucket);
+                }
+                None => {
+                    s.placements.remove(&shard_key);
+                }
+            }
+            Ok(())
+        })
+    }
+
     pub fn get_cluster_info() -> ClusterInfo {
         with(|s| ClusterInfo {
             name: s.name.clone(),
@@ -245,6 +654,7 @@ pub mod state {
             bucket_deployed_total: s.bucket_deployed_list.len() as u64,
             bucket_deployment_logs: INSTALL_LOGS.with(|r| r.borrow().len()),
             governance_canister: s.governance_canister,
+            bucket_upgrade_snapshot: s.bucket_upgrade_snapshot,
         })
     }
 
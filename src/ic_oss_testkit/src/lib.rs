@@ -0,0 +1,250 @@
+use candid::{decode_one, encode_args, encode_one, utils::ArgumentEncoder, CandidType, Principal};
+use ed25519_dalek::SigningKey;
+use ic_oss_types::{
+    bucket::{self, UpdateBucketInput},
+    cluster,
+    cose::Token,
+    file::{CreateFileInput, CreateFileOutput},
+};
+use pocket_ic::{PocketIc, PocketIcBuilder, WasmResult};
+use serde::Deserialize;
+use serde_bytes::{ByteArray, ByteBuf};
+use sha3::{Digest, Sha3_256};
+use std::{collections::BTreeSet, path::PathBuf, time::UNIX_EPOCH};
+
+/// The environment variable for the bucket wasm path.
+pub static BUCKET_WASM_ENV: &str = "IC_OSS_BUCKET_WASM";
+/// The environment variable for the cluster wasm path.
+pub static CLUSTER_WASM_ENV: &str = "IC_OSS_CLUSTER_WASM";
+
+const INIT_CYCLES: u128 = 10_000_000_000_000;
+const TOKEN_EXPIRATION: u64 = 3600; // in seconds
+
+/// The init args of the buckets created by `TestEnv::create_bucket`.
+pub fn bucket_init_args() -> bucket::CanisterArgs {
+    bucket::CanisterArgs::Init(bucket::InitArgs {
+        name: "testkit".to_string(),
+        ..Default::default()
+    })
+}
+
+/// The init args of the clusters created by `TestEnv::create_cluster`.
+pub fn cluster_init_args() -> cluster::ChainArgs {
+    cluster::ChainArgs::Init(cluster::InitArgs {
+        name: "testkit".to_string(),
+        ecdsa_key_name: "dfx_test_key".to_string(),
+        schnorr_key_name: "dfx_test_key".to_string(),
+        token_expiration: TOKEN_EXPIRATION,
+        ..Default::default()
+    })
+}
+
+/// Signs the access tokens with a fixed Ed25519 key, it works without PocketIC.
+pub struct TokenIssuer {
+    signing_key: SigningKey,
+}
+
+impl Default for TokenIssuer {
+    fn default() -> Self {
+        TokenIssuer {
+            signing_key: SigningKey::from_bytes(&[8u8; 32]),
+        }
+    }
+}
+
+impl TokenIssuer {
+    /// The public key to add to the bucket's trusted_eddsa_pub_keys.
+    pub fn public_key(&self) -> ByteArray<32> {
+        self.signing_key.verifying_key().to_bytes().into()
+    }
+
+    /// Issues an access token for the audience (a bucket or an audience group),
+    /// it expires in TOKEN_EXPIRATION seconds after now_sec.
+    pub fn issue(
+        &self,
+        audience: Principal,
+        subject: Principal,
+        policies: &str,
+        now_sec: i64,
+    ) -> ByteBuf {
+        let token = Token {
+            subject,
+            audience,
+            policies: policies.to_string(),
+            caller: None,
+            jti: None,
+        };
+        token
+            .sign_ed25519(&self.signing_key, None, now_sec, TOKEN_EXPIRATION as i64)
+            .unwrap()
+    }
+}
+
+/// A PocketIC instance with the fixtures for ic-oss canisters.
+pub struct TestEnv {
+    pub pic: PocketIc,
+    /// the controller of the installed canisters
+    pub controller: Principal,
+    /// a normal user for tests
+    pub user: Principal,
+    /// signs the access tokens, trusted by the buckets created by the env
+    pub issuer: TokenIssuer,
+}
+
+impl Default for TestEnv {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestEnv {
+    /// Starts a PocketIC instance, the `POCKET_IC_BIN` environment variable should be set.
+    pub fn new() -> Self {
+        let pic = PocketIcBuilder::new()
+            .with_ii_subnet() // provides the threshold keys for the cluster
+            .with_application_subnet()
+            .build();
+        TestEnv {
+            pic,
+            controller: Principal::self_authenticating(b"ic-oss-testkit-controller"),
+            user: Principal::self_authenticating(b"ic-oss-testkit-user"),
+            issuer: TokenIssuer::default(),
+        }
+    }
+
+    /// Creates a bucket with the managers, the bucket trusts the tokens issued by `issue_token`.
+    pub fn create_bucket(&self, managers: BTreeSet<Principal>) -> Principal {
+        let wasm = load_wasm(BUCKET_WASM_ENV, "ic_oss_bucket");
+        let bucket = self.install(wasm, encode_one(Some(bucket_init_args())).unwrap());
+
+        if !managers.is_empty() {
+            let res: Result<(), String> =
+                self.update(bucket, self.controller, "admin_set_managers", (managers,));
+            res.unwrap();
+        }
+        let input = UpdateBucketInput {
+            trusted_eddsa_pub_keys: Some(vec![self.issuer.public_key()]),
+            ..Default::default()
+        };
+        let res: Result<(), String> =
+            self.update(bucket, self.controller, "admin_update_bucket", (input,));
+        res.unwrap();
+        bucket
+    }
+
+    /// Creates a cluster with the test threshold keys.
+    pub fn create_cluster(&self) -> Principal {
+        let wasm = load_wasm(CLUSTER_WASM_ENV, "ic_oss_cluster");
+        self.install(wasm, encode_one(Some(cluster_init_args())).unwrap())
+    }
+
+    /// Issues an access token for the audience (a bucket or an audience group).
+    pub fn issue_token(&self, audience: Principal, subject: Principal, policies: &str) -> ByteBuf {
+        let now_sec = self
+            .pic
+            .get_time()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        self.issuer.issue(audience, subject, policies, now_sec)
+    }
+
+    /// Uploads a small file in one request and returns the file id.
+    pub fn upload_file(
+        &self,
+        bucket: Principal,
+        sender: Principal,
+        access_token: &ByteBuf,
+        parent: u32,
+        name: &str,
+        content: &[u8],
+    ) -> Result<u32, String> {
+        let hash: [u8; 32] = Sha3_256::digest(content).into();
+        let input = CreateFileInput {
+            parent,
+            name: name.to_string(),
+            content_type: "application/octet-stream".to_string(),
+            size: Some(content.len() as u64),
+            content: Some(ByteBuf::from(content)),
+            hash: Some(hash.into()),
+            ..Default::default()
+        };
+        let res: Result<CreateFileOutput, String> =
+            self.update(bucket, sender, "create_file", (input, Some(access_token)));
+        res.map(|out| out.id)
+    }
+
+    /// Makes an update call and decodes the single result, it panics if the call is rejected.
+    pub fn update<In, Out>(
+        &self,
+        canister: Principal,
+        sender: Principal,
+        method: &str,
+        args: In,
+    ) -> Out
+    where
+        In: ArgumentEncoder,
+        Out: CandidType + for<'a> Deserialize<'a>,
+    {
+        let res = self
+            .pic
+            .update_call(canister, sender, method, encode_args(args).unwrap());
+        decode_reply(method, res)
+    }
+
+    /// Makes a query call and decodes the single result, it panics if the call is rejected.
+    pub fn query<In, Out>(
+        &self,
+        canister: Principal,
+        sender: Principal,
+        method: &str,
+        args: In,
+    ) -> Out
+    where
+        In: ArgumentEncoder,
+        Out: CandidType + for<'a> Deserialize<'a>,
+    {
+        let res = self
+            .pic
+            .query_call(canister, sender, method, encode_args(args).unwrap());
+        decode_reply(method, res)
+    }
+
+    fn install(&self, wasm: Vec<u8>, args: Vec<u8>) -> Principal {
+        let canister = self
+            .pic
+            .create_canister_with_settings(Some(self.controller), None);
+        self.pic.add_cycles(canister, INIT_CYCLES);
+        self.pic
+            .install_canister(canister, wasm, args, Some(self.controller));
+        canister
+    }
+}
+
+fn decode_reply<Out>(method: &str, res: Result<WasmResult, pocket_ic::UserError>) -> Out
+where
+    Out: CandidType + for<'a> Deserialize<'a>,
+{
+    match res {
+        Ok(WasmResult::Reply(data)) => decode_one(&data)
+            .unwrap_or_else(|err| panic!("failed to decode the result of {}: {}", method, err)),
+        Ok(WasmResult::Reject(msg)) => panic!("{} rejected: {}", method, msg),
+        Err(err) => panic!("{} failed: {}", method, err),
+    }
+}
+
+// loads the wasm from the path in the environment variable, or from the release build.
+fn load_wasm(env: &str, name: &str) -> Vec<u8> {
+    let path = match std::env::var(env) {
+        Ok(path) => PathBuf::from(path),
+        Err(_) => PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../../target/wasm32-unknown-unknown/release")
+            .join(format!("{}.wasm", name)),
+    };
+    std::fs::read(&path).unwrap_or_else(|err| {
+        panic!(
+            "failed to read wasm {:?}: {}, build it with `make build-wasm` or set {}",
+            path, err, env
+        )
+    })
+}
@@ -0,0 +1,47 @@
+use candid::Principal;
+use ic_oss_testkit::TestEnv;
+use ic_oss_types::file::{FileChunk, FileInfo};
+use serde_bytes::ByteBuf;
+use std::collections::BTreeSet;
+
+#[test]
+#[ignore = "requires POCKET_IC_BIN and the bucket wasm"]
+fn bucket_upload_and_read() {
+    let env = TestEnv::new();
+    let bucket = env.create_bucket(BTreeSet::new());
+    let token = env.issue_token(bucket, env.user, "Folder.*:0 Bucket.Read.*");
+
+    let id = env
+        .upload_file(bucket, env.user, &token, 0, "hello.txt", b"hello world")
+        .unwrap();
+    let info: Result<FileInfo, String> =
+        env.query(bucket, env.user, "get_file_info", (id, Some(&token)));
+    let info = info.unwrap();
+    assert_eq!(info.name, "hello.txt");
+    assert_eq!(info.size, 11);
+
+    let chunks: Result<Vec<FileChunk>, String> = env.query(
+        bucket,
+        env.user,
+        "get_file_chunks",
+        (id, 0u32, None::<u32>, Some(&token)),
+    );
+    let chunks = chunks.unwrap();
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(chunks[0].1.as_slice(), b"hello world");
+
+    // a private bucket can not be read without token
+    let info: Result<FileInfo, String> =
+        env.query(bucket, env.user, "get_file_info", (id, None::<ByteBuf>));
+    assert!(info.is_err());
+
+    // a token for another bucket is rejected
+    let token = env.issue_token(
+        Principal::management_canister(),
+        env.user,
+        "Folder.*:0 Bucket.Read.*",
+    );
+    let info: Result<FileInfo, String> =
+        env.query(bucket, env.user, "get_file_info", (id, Some(&token)));
+    assert!(info.is_err());
+}
@@ -0,0 +1,58 @@
+use candid::{decode_one, encode_one, Principal};
+use ic_oss_testkit::{bucket_init_args, cluster_init_args, TokenIssuer};
+use ic_oss_types::{
+    bucket::CanisterArgs,
+    cluster::ChainArgs,
+    cose::{Token, BUCKET_TOKEN_AAD, CLOCK_SKEW},
+};
+
+#[test]
+fn issued_token_verifies() {
+    let issuer = TokenIssuer::default();
+    let bucket = Principal::self_authenticating(b"bucket");
+    let user = Principal::self_authenticating(b"user");
+    let token = issuer.issue(bucket, user, "Folder.*:0 Bucket.Read.*", 1_700_000_000);
+
+    let res = Token::from_sign1(
+        &token,
+        &[],
+        &[issuer.public_key()],
+        BUCKET_TOKEN_AAD,
+        1_700_000_001,
+    )
+    .unwrap();
+    assert_eq!(res.audience, bucket);
+    assert_eq!(res.subject, user);
+    assert_eq!(res.policies, "Folder.*:0 Bucket.Read.*");
+
+    // expired
+    assert!(Token::from_sign1(
+        &token,
+        &[],
+        &[issuer.public_key()],
+        BUCKET_TOKEN_AAD,
+        1_700_000_000 + 3600 + CLOCK_SKEW + 1,
+    )
+    .is_err());
+}
+
+#[test]
+fn init_args_decode() {
+    let data = encode_one(Some(bucket_init_args())).unwrap();
+    match decode_one::<Option<CanisterArgs>>(&data).unwrap() {
+        Some(CanisterArgs::Init(args)) => {
+            assert_eq!(args.name, "testkit");
+            args.validate().unwrap();
+        }
+        _ => panic!("expected the bucket Init args"),
+    }
+
+    let data = encode_one(Some(cluster_init_args())).unwrap();
+    match decode_one::<Option<ChainArgs>>(&data).unwrap() {
+        Some(ChainArgs::Init(args)) => {
+            assert_eq!(args.name, "testkit");
+            assert_eq!(args.schnorr_key_name, "dfx_test_key");
+        }
+        _ => panic!("expected the cluster Init args"),
+    }
+}
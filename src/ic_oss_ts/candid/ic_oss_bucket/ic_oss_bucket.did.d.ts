@@ -4,6 +4,7 @@ import type { IDL } from '@dfinity/candid';
 
 export interface BucketInfo {
   'status' : number,
+  'chunk_size' : number,
   'total_chunks' : bigint,
   'trusted_eddsa_pub_keys' : Array<Uint8Array | number[]>,
   'managers' : Array<Principal>,
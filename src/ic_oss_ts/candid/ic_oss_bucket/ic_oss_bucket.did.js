@@ -65,6 +65,7 @@ export const idlFactory = ({ IDL }) => {
   const Result_3 = IDL.Variant({ 'Ok' : IDL.Bool, 'Err' : IDL.Text });
   const BucketInfo = IDL.Record({
     'status' : IDL.Int8,
+    'chunk_size' : IDL.Nat32,
     'total_chunks' : IDL.Nat64,
     'trusted_eddsa_pub_keys' : IDL.Vec(IDL.Vec(IDL.Nat8)),
     'managers' : IDL.Vec(IDL.Principal),
@@ -123,6 +123,7 @@ export class BucketCanister extends Canister<BucketService> {
     return this.#resultOk(res)
   }
 
+  /** @deprecated use list_files_with_options, its cursor works with all the sort orders */
   async listFiles(
     parent: number,
     prev: number = 0,
@@ -137,6 +138,7 @@ export class BucketCanister extends Canister<BucketService> {
     return this.#resultOk(res)
   }
 
+  /** @deprecated use list_folders_with_options, its cursor works with all the sort orders */
   async listFolders(
     parent: number,
     prev: number = 0,
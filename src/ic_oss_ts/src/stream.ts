@@ -3,6 +3,8 @@ import type { FileHandle } from 'node:fs/promises'
 import { ReadableStream } from 'web-streams-polyfill'
 import { FileConfig } from './types.js'
 
+// the default chunk size of the buckets, a bucket may be installed with another
+// chunk size, see BucketInfo.chunk_size
 export const CHUNK_SIZE = 256 * 1024
 
 // https://stackoverflow.com/questions/76700924/ts2504-type-readablestreamuint8array-must-have-a-symbol-asynciterator
@@ -19,7 +21,10 @@ export async function* readableStreamAsyncIterator<T>(self: ReadableStream<T>) {
   }
 }
 
-export async function toFixedChunkSizeReadable(file: FileConfig) {
+export async function toFixedChunkSizeReadable(
+  file: FileConfig,
+  chunkSize: number = CHUNK_SIZE
+) {
   if (typeof File === 'function' && file.content instanceof File) {
     if (!file.name) {
       file.name = file.content.name
@@ -31,7 +36,7 @@ export async function toFixedChunkSizeReadable(file: FileConfig) {
       file.size = file.content.size
     }
     return streamToFixedChunkSizeReadable(
-      CHUNK_SIZE,
+      chunkSize,
       file.content.stream() as any as ReadableStream<Uint8Array>
     )
   }
@@ -44,7 +49,7 @@ export async function toFixedChunkSizeReadable(file: FileConfig) {
       file.size = file.content.size
     }
     return streamToFixedChunkSizeReadable(
-      CHUNK_SIZE,
+      chunkSize,
       file.content.stream() as any as ReadableStream<Uint8Array>
     )
   }
@@ -55,14 +60,14 @@ export async function toFixedChunkSizeReadable(file: FileConfig) {
     file.content instanceof ArrayBuffer
   ) {
     return uint8ArrayToFixedChunkSizeReadable(
-      CHUNK_SIZE,
+      chunkSize,
       Uint8Array.from(file.content as ArrayLike<number>)
     )
   }
 
   if (file.content instanceof ReadableStream) {
     return streamToFixedChunkSizeReadable(
-      CHUNK_SIZE,
+      chunkSize,
       file.content as any as ReadableStream<Uint8Array>
     )
   }
@@ -83,7 +88,7 @@ export async function toFixedChunkSizeReadable(file: FileConfig) {
     // try to fix "Closing file descriptor xx on garbage collection"
     ;(file as any).originFile = fs
     return streamToFixedChunkSizeReadable(
-      CHUNK_SIZE,
+      chunkSize,
       fs.readableWebStream() as any as ReadableStream<Uint8Array>,
       fs
     )
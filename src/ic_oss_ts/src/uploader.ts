@@ -3,7 +3,6 @@ import { type ReadableStream } from 'web-streams-polyfill'
 import { BucketCanister } from './bucket.canister.js'
 import { ConcurrencyQueue } from './queue.js'
 import {
-  CHUNK_SIZE,
   readableStreamAsyncIterator,
   readAll,
   toFixedChunkSizeReadable
@@ -16,6 +15,7 @@ export class Uploader {
   readonly #cli: BucketCanister
   readonly concurrency: number
   readonly setReadonly: boolean
+  #chunkSize: Promise<number> | null = null
 
   constructor(
     client: BucketCanister,
@@ -27,11 +27,24 @@ export class Uploader {
     this.setReadonly = setReadonly
   }
 
+  // the chunk size that the bucket was installed with, it is loaded on the first upload
+  chunkSize(): Promise<number> {
+    if (!this.#chunkSize) {
+      this.#chunkSize = this.#cli
+        .getBucketInfo()
+        .then((info) => info.chunk_size)
+      this.#chunkSize.catch(() => {
+        this.#chunkSize = null
+      })
+    }
+    return this.#chunkSize
+  }
+
   async upload(
     file: FileConfig,
     onProgress: (progress: Progress) => void = () => {}
   ): Promise<UploadFileChunksResult> {
-    const stream = await toFixedChunkSizeReadable(file)
+    const stream = await toFixedChunkSizeReadable(file, await this.chunkSize())
     const size = file.size || 0
     if (size > 0 && size <= MAX_FILE_SIZE_PER_CALL) {
       const content = await readAll(stream, size)
@@ -94,9 +107,10 @@ export class Uploader {
     onProgress: (progress: Progress) => void = () => {}
   ): Promise<UploadFileChunksResult> {
     const queue = new ConcurrencyQueue(this.concurrency)
+    const chunkSize = await this.chunkSize()
 
     let chunkIndex = 0
-    let prevChunkSize = CHUNK_SIZE
+    let prevChunkSize = chunkSize
     const hasher = sha3_256.create()
     const rt: UploadFileChunksResult = {
       id,
@@ -107,9 +121,9 @@ export class Uploader {
 
     try {
       for await (const value of readableStreamAsyncIterator(stream)) {
-        if (prevChunkSize !== CHUNK_SIZE) {
+        if (prevChunkSize !== chunkSize) {
           throw new Error(
-            `Prev chunk size mismatch, expected ${CHUNK_SIZE} but got ${prevChunkSize}`
+            `Prev chunk size mismatch, expected ${chunkSize} but got ${prevChunkSize}`
           )
         }
         const chunk = new Uint8Array(value)
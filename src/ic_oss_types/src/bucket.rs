@@ -1,9 +1,51 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use candid::{CandidType, Principal};
+use ciborium::{from_reader, into_writer};
 use serde::{Deserialize, Serialize};
 use serde_bytes::{ByteArray, ByteBuf};
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use unicode_normalization::UnicodeNormalization;
+use url::Url;
 
-use crate::file::MAX_FILE_SIZE;
+use crate::{
+    cose::sha256,
+    file::{
+        valid_file_name, valid_tag_name, MAX_CHUNK_SIZE, MAX_FILE_NAME_SIZE, MAX_FILE_SIZE,
+        MAX_FILE_SIZE_PER_CALL, MIN_CHUNK_SIZE,
+    },
+    format_error,
+    permission::{validate_name, Policies},
+    s3::SignedPostPolicy,
+    to_cbor_bytes,
+};
+
+// the HTTP header of the file proof, it is added to the certified responses of the
+// files that are exposed as NFTs.
+pub static FILE_PROOF_HEADER: &str = "ic-oss-file-proof";
+
+// the features returned by the bucket's get_features, so clients can adapt to what the
+// deployed bucket supports. the optional features are listed only when they are enabled.
+pub const FEATURE_ACCESS_LOGS: &str = "access_logs";
+pub const FEATURE_ANNOTATIONS: &str = "annotations";
+pub const FEATURE_ANONYMOUS_UPLOAD: &str = "anonymous_upload";
+pub const FEATURE_BACKUP: &str = "backup";
+pub const FEATURE_CERTIFIED_STATE: &str = "certified_state";
+pub const FEATURE_COMPRESSION: &str = "compression";
+pub const FEATURE_GOVERNANCE_MANAGED: &str = "governance_managed";
+pub const FEATURE_HASH_INDEX: &str = "hash_index"; // required by dedup uploads
+pub const FEATURE_HASH_VERIFICATION: &str = "hash_verification";
+pub const FEATURE_IMPORT_FROM_URL: &str = "import_from_url";
+pub const FEATURE_METRICS: &str = "metrics";
+pub const FEATURE_NFT: &str = "nft";
+pub const FEATURE_PULL_FILE: &str = "pull_file";
+pub const FEATURE_RANGE_READ: &str = "range_read";
+pub const FEATURE_RECEIPTS: &str = "receipts";
+pub const FEATURE_ROLES: &str = "roles";
+pub const FEATURE_SCRUB: &str = "scrub";
+pub const FEATURE_SHARES: &str = "shares";
+pub const FEATURE_SIGNED_URL: &str = "signed_url";
+pub const FEATURE_TAGS: &str = "tags";
+pub const FEATURE_WELL_KNOWN: &str = "well_known";
 
 #[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
 pub struct BucketInfo {
@@ -11,12 +53,14 @@ pub struct BucketInfo {
     pub file_id: u32,
     pub folder_id: u32,
     pub max_file_size: u64,
+    // all chunks of a file except the last one should be of this size
+    pub chunk_size: u32,
     pub max_folder_depth: u8,
     pub max_children: u16,
     pub max_custom_data_size: u16,
     pub enable_hash_index: bool,
     pub status: i8,     // -1: archived; 0: readable and writable; 1: readonly
-    pub visibility: u8, // 0: private; 1: public
+    pub visibility: u8, // 0: private; 1: public; 2: unlisted, files are readable but not listable
     pub total_files: u64,
     pub total_chunks: u64,
     pub total_folders: u64,
@@ -28,6 +72,655 @@ pub struct BucketInfo {
     // used to verify the request token signed with ED25519
     pub trusted_eddsa_pub_keys: Vec<ByteArray<32>>,
     pub governance_canister: Option<Principal>,
+    // origins allowed to hotlink files of a public bucket over HTTP, empty means no restriction
+    pub allowed_origins: BTreeSet<String>,
+    // a signed token is required in the url to read files of a public bucket over HTTP
+    pub require_signed_url: bool,
+    pub anonymous_upload: AnonymousUploadConfig,
+    // the audience groups the bucket belongs to, synced from the cluster
+    pub audience_groups: BTreeSet<Principal>,
+    // the chunks of new files are compressed at rest if it saves space
+    pub enable_compression: bool,
+    pub compressed_original_bytes: u64, // the original size of the compressed chunks
+    pub compressed_stored_bytes: u64,   // the stored size of the compressed chunks
+    pub name_policy: NamePolicy,
+    // the security headers of the HTTP responses, overridden by the folders' headers
+    pub security_headers: SecurityHeaders,
+    pub folder_security_headers: BTreeMap<u32, SecurityHeaders>,
+    // the percentage of the HTTP requests that are recorded in the access log, 0: disabled
+    pub access_log_sample_rate: u8,
+    // the threshold ECDSA key that signs the download receipts, empty: disabled
+    pub receipt_key_name: String,
+    // the secp256k1 public key to verify the receipts, available after the first receipt
+    pub receipt_public_key: Option<ByteBuf>,
+    // folders whose children can not be listed without a token, even if the bucket is public
+    pub unlisted_folders: BTreeSet<u32>,
+    // upload-only folders, only the managers and auditors can list or read their files
+    pub inbox_folders: BTreeSet<u32>,
+    // the maximum number of annotations in the bucket, 0: annotations are disabled
+    pub max_annotations: u32,
+    pub annotations: u64, // the number of annotations in the bucket
+    // the admin APIs can only be called by the governance canister
+    pub governance_managed: bool,
+    // the custom domains and the alternative origins served under /.well-known/
+    pub well_known: WellKnown,
+    // the denied reads of existing files are reported as not found, so their existence is not leaked
+    pub hide_forbidden: bool,
+    // the tag that points to the manifest of the static site served by the bucket, empty: disabled
+    pub site_tag: String,
+}
+
+// the rules applied to the names of files and folders when they are created or renamed.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct NamePolicy {
+    pub normalize_nfc: bool, // names are stored in Unicode Normalization Form C
+    pub forbid_leading_dot: bool, // hidden names like ".env" are rejected
+    pub max_name_bytes: u16, // 0: MAX_FILE_NAME_SIZE
+    // names should be unique within a folder ignoring case
+    pub case_insensitive: bool,
+    // names should be unique within a folder, so a path resolves to one file or folder
+    pub unique_names: bool,
+}
+
+impl NamePolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_name_bytes as usize > MAX_FILE_NAME_SIZE {
+            return Err(format!(
+                "max_name_bytes should be smaller than or equal to {}",
+                MAX_FILE_NAME_SIZE
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the name to store, or an error if the name violates the policy.
+    pub fn apply(&self, name: &str) -> Result<String, String> {
+        let name = self.normalize(name);
+
+        if !valid_file_name(&name) {
+            return Err(format!("invalid name: {:?}", name));
+        }
+        if self.forbid_leading_dot && name.starts_with('.') {
+            return Err(format!("name cannot start with a dot: {:?}", name));
+        }
+        if self.max_name_bytes > 0 && name.len() > self.max_name_bytes as usize {
+            return Err(format!(
+                "name size exceeds the limit {}: {:?}",
+                self.max_name_bytes, name
+            ));
+        }
+        Ok(name)
+    }
+
+    pub fn normalize(&self, name: &str) -> String {
+        if self.normalize_nfc {
+            name.nfc().collect()
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// Returns true if the names should be unique within a folder.
+    pub fn unique(&self) -> bool {
+        self.unique_names || self.case_insensitive
+    }
+
+    /// Returns true if the stored name matches the name, ignoring case if case-insensitive.
+    pub fn matches(&self, stored: &str, name: &str) -> bool {
+        stored == name || (self.case_insensitive && stored.to_lowercase() == name.to_lowercase())
+    }
+
+    /// Returns true if the names conflict within a folder.
+    pub fn conflicts(&self, a: &str, b: &str) -> bool {
+        self.unique() && self.matches(a, b)
+    }
+}
+
+/// The maximum size of a security header value.
+pub const MAX_SECURITY_HEADER_SIZE: usize = 1024;
+
+// the security headers added to the HTTP responses of the bucket. None inherits the
+// value from the parent folder or the bucket, an empty string removes the header.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SecurityHeaders {
+    pub content_security_policy: Option<String>,
+    pub content_type_options: Option<String>, // "nosniff" if not set
+    pub strict_transport_security: Option<String>,
+}
+
+impl SecurityHeaders {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in self.iter() {
+            if let Some(value) = value {
+                if value.len() > MAX_SECURITY_HEADER_SIZE {
+                    return Err(format!(
+                        "{} size exceeds the limit {}",
+                        name, MAX_SECURITY_HEADER_SIZE
+                    ));
+                }
+                if value.chars().any(|c| c.is_control()) {
+                    return Err(format!("invalid {} value: {:?}", name, value));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().all(|(_, value)| value.is_none())
+    }
+
+    /// Returns the headers with the unset values taken from the fallback.
+    pub fn or(self, fallback: &SecurityHeaders) -> SecurityHeaders {
+        SecurityHeaders {
+            content_security_policy: self
+                .content_security_policy
+                .or_else(|| fallback.content_security_policy.clone()),
+            content_type_options: self
+                .content_type_options
+                .or_else(|| fallback.content_type_options.clone()),
+            strict_transport_security: self
+                .strict_transport_security
+                .or_else(|| fallback.strict_transport_security.clone()),
+        }
+    }
+
+    /// Returns the lowercase header names with their values.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, Option<&String>)> {
+        [
+            (
+                "content-security-policy",
+                self.content_security_policy.as_ref(),
+            ),
+            ("x-content-type-options", self.content_type_options.as_ref()),
+            (
+                "strict-transport-security",
+                self.strict_transport_security.as_ref(),
+            ),
+        ]
+        .into_iter()
+    }
+}
+
+pub const MAX_IC_DOMAINS: usize = 20;
+// Internet Identity accepts up to 10 alternative origins
+pub const MAX_II_ALTERNATIVE_ORIGINS: usize = 10;
+
+// the well-known files of the bucket. they are served at /.well-known/ic-domains and
+// /.well-known/ii-alternative-origins with the headers that the boundary nodes and
+// Internet Identity expect, so the bucket can be served on custom domains.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct WellKnown {
+    pub ic_domains: BTreeSet<String>, // e.g. "files.example.com"
+    pub ii_alternative_origins: BTreeSet<String>, // e.g. "https://files.example.com"
+}
+
+impl WellKnown {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.ic_domains.len() > MAX_IC_DOMAINS {
+            return Err(format!("ic_domains exceeds the limit {}", MAX_IC_DOMAINS));
+        }
+        for domain in &self.ic_domains {
+            if !valid_domain(domain) {
+                return Err(format!("invalid domain: {}", domain));
+            }
+        }
+
+        if self.ii_alternative_origins.len() > MAX_II_ALTERNATIVE_ORIGINS {
+            return Err(format!(
+                "ii_alternative_origins exceeds the limit {}",
+                MAX_II_ALTERNATIVE_ORIGINS
+            ));
+        }
+        for origin in &self.ii_alternative_origins {
+            // the origins are put into the JSON file as they are
+            if !origin.starts_with("https://")
+                || !origin
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b":/.-".contains(&b))
+                || url_origin(origin).as_ref() != Some(origin)
+            {
+                return Err(format!("invalid origin: {}", origin));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ic_domains.is_empty() && self.ii_alternative_origins.is_empty()
+    }
+
+    /// Returns the content type and the content of the well-known file,
+    /// None if the file is not configured.
+    pub fn file(&self, name: &str) -> Option<(&'static str, String)> {
+        match name {
+            "ic-domains" if !self.ic_domains.is_empty() => Some((
+                "text/plain",
+                self.ic_domains
+                    .iter()
+                    .map(|d| d.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            )),
+            "ii-alternative-origins" if !self.ii_alternative_origins.is_empty() => Some((
+                "application/json",
+                format!(
+                    "{{\"alternativeOrigins\":[{}]}}",
+                    self.ii_alternative_origins
+                        .iter()
+                        .map(|o| format!("\"{}\"", o))
+                        .collect::<Vec<_>>()
+                        .join(",")
+                ),
+            )),
+            _ => None,
+        }
+    }
+}
+
+// a lowercase DNS name with at least two labels, e.g. "files.example.com".
+fn valid_domain(domain: &str) -> bool {
+    domain.len() <= 253
+        && domain.split('.').count() >= 2
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        })
+}
+
+// the folder children are kept in a stable memory index that is listed by pages, so a
+// folder can hold more children than the bucket's u16 max_children with an override.
+pub const MAX_FOLDER_CHILDREN: u32 = 1_000_000;
+// the children returned in the FolderInfo of a folder, the rest are listed by pages
+pub const MAX_FOLDER_INFO_CHILDREN: usize = 10_000;
+
+// the limits of a folder that override the bucket's limits. max_children applies to
+// the folder itself, max_folder_depth applies to the folder and its subfolders, the
+// nearest folder's override wins.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FolderLimits {
+    pub max_children: Option<u32>,
+    pub max_folder_depth: Option<u8>,
+}
+
+impl FolderLimits {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(max_children) = self.max_children {
+            if max_children == 0 || max_children > MAX_FOLDER_CHILDREN {
+                return Err(format!(
+                    "max_children should be in [1, {}]",
+                    MAX_FOLDER_CHILDREN
+                ));
+            }
+        }
+        if self.max_folder_depth == Some(0) {
+            return Err("max_folder_depth should be greater than 0".to_string());
+        }
+        if self.max_children.is_none() && self.max_folder_depth.is_none() {
+            return Err("empty folder limits".to_string());
+        }
+        Ok(())
+    }
+}
+
+// a sampled HTTP request served by the bucket.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct AccessLog {
+    pub id: u64,
+    pub path: String, // the url path without the query string
+    pub status: u16,
+    pub bytes: u64,                     // the size of the response body
+    pub subject: Option<ByteArray<32>>, // the SHA-256 hash of the access token's subject
+    pub timestamp: u64,                 // unix timestamp in milliseconds
+}
+
+// the hourly aggregates of a bucket. the requests and bytes_out count exactly the HTTP
+// requests served by update calls (share links and sampled access logs), because the
+// query calls can not record them.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct MetricsSample {
+    pub hour: u64, // unix timestamp in seconds of the start of the hour
+    pub requests: u64,
+    pub bytes_in: u64,  // the size of the uploaded chunks
+    pub bytes_out: u64, // the size of the HTTP response bodies
+    pub files_created: u64,
+    pub files_deleted: u64,
+    pub cycles: u128, // the latest cycles balance in the hour, 0: not sampled
+}
+
+// the usage of a bucket polled by the cluster. the egress_bytes are the bytes_out of
+// the completed hours in [since, until), so the cluster can accumulate them.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct BucketUsage {
+    pub storage_bytes: u64, // the size of the stable memory
+    pub total_files: u64,
+    pub egress_bytes: u64,
+    pub until: u64, // unix timestamp in seconds of the start of the current hour
+}
+
+// a background task of the bucket, the tasks are scheduled by timers.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct TaskInfo {
+    pub name: String,
+    pub interval_secs: u64, // 0: the task is disabled, it can still be triggered
+    pub default_interval_secs: u64,
+    pub last_run_at: u64, // unix timestamp in milliseconds, 0: not run since the last upgrade
+    pub runs: u64,        // the number of runs since the last upgrade
+    pub running: bool,    // a multi-round task is in progress
+}
+
+pub const MAX_ROLES: usize = 100;
+pub const MAX_ROLE_NAME_SIZE: usize = 64;
+pub const MAX_ROLE_POLICIES_SIZE: usize = 2048;
+
+// a named bundle of policies, the principals that are assigned the role are granted
+// its policies without embedding them in every access token.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RoleInfo {
+    pub name: String,
+    pub policies: String,
+    pub members: BTreeSet<Principal>,
+}
+
+// the effective access of a principal or a token subject, to debug permission errors.
+// the policies are the union of the token's and the roles' policies.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct AccessInfo {
+    pub principal: Principal, // the principal or the token subject that the policies apply to
+    pub builtin_roles: BTreeSet<String>, // "Manager" and "Auditor", empty for a user
+    pub roles: BTreeSet<String>, // the named roles assigned to the principal
+    pub read: Result<String, String>, // the read policies or the reason of the denial
+    pub write: Result<String, String>, // the write policies or the reason of the denial
+    pub status: i8,
+    pub visibility: u8,
+    pub max_file_size: u64,
+    pub max_folder_depth: u8,
+    pub max_children: u16,
+    pub max_custom_data_size: u16,
+}
+
+// validates the name and the policies of a role.
+pub fn validate_role(name: &str, policies: &str) -> Result<(), String> {
+    if name.len() > MAX_ROLE_NAME_SIZE {
+        return Err(format!(
+            "role name size exceeds the limit {}",
+            MAX_ROLE_NAME_SIZE
+        ));
+    }
+    validate_name(name).map_err(|err| format!("invalid role name: {}", err))?;
+    if policies.len() > MAX_ROLE_POLICIES_SIZE {
+        return Err(format!(
+            "role policies size exceeds the limit {}",
+            MAX_ROLE_POLICIES_SIZE
+        ));
+    }
+    Policies::try_from(policies).map_err(|err| format!("invalid role policies: {}", err))?;
+    Ok(())
+}
+
+// anonymous callers can create small files in a drop-box folder if they
+// present a one-time upload token or solve a proof of work challenge.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct AnonymousUploadConfig {
+    pub folder: u32,             // the folder that anonymous files are created in
+    pub max_file_size: u64,      // should be smaller than or equal to MAX_FILE_SIZE_PER_CALL
+    pub max_files_per_hour: u32, // 0: anonymous upload is disabled
+    pub pow_difficulty: u8,      // leading zero bits of the PoW hash, 0: PoW challenge is disabled
+}
+
+impl AnonymousUploadConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_file_size > MAX_FILE_SIZE_PER_CALL {
+            return Err(format!(
+                "anonymous upload max_file_size should be smaller than or equal to {}",
+                MAX_FILE_SIZE_PER_CALL
+            ));
+        }
+        if self.pow_difficulty > 64 {
+            return Err("pow_difficulty should be smaller than or equal to 64".to_string());
+        }
+        Ok(())
+    }
+}
+
+// an S3-compatible bucket that the files are exported to for disaster recovery.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct BackupTargetInput {
+    // the https url of the S3 bucket, e.g. https://my-bucket.s3.us-east-1.amazonaws.com
+    pub endpoint: String,
+    pub prefix: String,     // the object key is prefix + file id
+    pub interval_secs: u64, // the interval of export rounds
+    // allows uploading the objects under the prefix, it should be renewed before it expires
+    pub policy: SignedPostPolicy,
+}
+
+impl BackupTargetInput {
+    pub fn validate(&self) -> Result<(), String> {
+        let endpoint = Url::parse(&self.endpoint).map_err(|err| err.to_string())?;
+        if endpoint.scheme() != "https" {
+            return Err("endpoint should be an https url".to_string());
+        }
+        self.policy.validate()?;
+        if self.interval_secs < 3600 {
+            return Err("interval_secs should be at least 3600".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct BackupStatus {
+    pub endpoint: String,
+    pub prefix: String,
+    pub credential: String,     // the SigV4 credential of the signed policy
+    pub policy_expires_at: u64, // unix timestamp in seconds
+    pub interval_secs: u64,
+    pub exported_until: u64, // unix timestamp in milliseconds, files updated before it are exported
+    pub round_started_at: u64, // unix timestamp in milliseconds, 0: no running round
+    pub exported_files: u64,
+    pub failed_files: u64,
+    pub last_error: Option<String>,
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
+pub enum UploadChallenge {
+    // a one-time access token issued by the cluster with the `Folder.Write.File` permission
+    Token(ByteBuf),
+    // proof of work, see `upload_pow_hash`
+    Pow { timestamp: u64, nonce: u64 },
+}
+
+/// Computes the proof of work hash of an anonymous upload:
+/// sha256(bucket || sha256(content) || timestamp || nonce),
+/// timestamp is the unix timestamp in seconds.
+pub fn upload_pow_hash(
+    bucket: &Principal,
+    content_hash: &[u8; 32],
+    timestamp: u64,
+    nonce: u64,
+) -> [u8; 32] {
+    let bucket = bucket.as_slice();
+    let mut data = Vec::with_capacity(bucket.len() + 48);
+    data.extend_from_slice(bucket);
+    data.extend_from_slice(content_hash);
+    data.extend_from_slice(&timestamp.to_be_bytes());
+    data.extend_from_slice(&nonce.to_be_bytes());
+    sha256(&data)
+}
+
+/// Solves the proof of work challenge of an anonymous upload, returns the nonce.
+pub fn solve_upload_pow(
+    bucket: &Principal,
+    content_hash: &[u8; 32],
+    timestamp: u64,
+    difficulty: u8,
+) -> u64 {
+    (0u64..)
+        .find(|nonce| {
+            leading_zero_bits(&upload_pow_hash(bucket, content_hash, timestamp, *nonce))
+                >= difficulty as u32
+        })
+        .expect("failed to solve the proof of work challenge")
+}
+
+pub fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for b in hash {
+        bits += b.leading_zeros();
+        if *b != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+// the Merkle root over the files (id, parent, name, size, hash) and the folders (id, parent, name)
+// of a bucket, mirrored buckets and backups with the same contents have the same root.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct StateDigest {
+    pub root: ByteArray<32>,
+    pub certificate: Option<ByteBuf>, // the data certificate of the canister
+    // the CBOR encoded hash tree of the certified data that contains the root:
+    // fork(pruned(http_expr), labeled("state_digest", leaf(root)))
+    pub witness: ByteBuf,
+}
+
+// the inclusion proof of a file in the state digest, the leaf of the file is
+// sha256(0 || cbor(id, parent, name, size, hash)) in a Merkle tree of depth 32.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FileProof {
+    pub id: u32,
+    pub parent: u32,
+    pub name: String,
+    pub size: u64,
+    pub hash: Option<ByteArray<32>>,
+    pub siblings: Vec<ByteArray<32>>, // from the leaf level to the root of the files tree
+    pub folders_root: ByteArray<32>,
+    pub digest: StateDigest, // the state digest at the time of the proof
+}
+
+impl FileProof {
+    // computes the state digest root from the file leaf and the siblings.
+    pub fn root(&self) -> [u8; 32] {
+        let mut hash = merkle_leaf(&(self.id, self.parent, &self.name, self.size, &self.hash));
+        for (level, sibling) in self.siblings.iter().enumerate() {
+            hash = if (self.id >> level) & 1 == 0 {
+                merkle_node(&hash, sibling)
+            } else {
+                merkle_node(sibling, &hash)
+            };
+        }
+        merkle_node(&hash, &self.folders_root)
+    }
+
+    // verifies that the file with the hash is included in the state digest,
+    // the certificate of the state digest should be verified by the caller.
+    pub fn verify(&self, hash: &[u8; 32]) -> Result<(), String> {
+        if self.siblings.len() != 32 {
+            return Err("invalid proof siblings".to_string());
+        }
+        if self.hash.as_deref() != Some(hash) {
+            return Err("file hash mismatch".to_string());
+        }
+        if self.root() != *self.digest.root {
+            return Err("proof does not match the state digest".to_string());
+        }
+        Ok(())
+    }
+
+    // encodes the proof as the value of the FILE_PROOF_HEADER: base64(cbor(proof)).
+    pub fn to_header_value(&self) -> String {
+        BASE64.encode(to_cbor_bytes(self))
+    }
+
+    pub fn from_header_value(value: &str) -> Result<Self, String> {
+        let data = BASE64.decode(value.trim()).map_err(format_error)?;
+        from_reader(&data[..]).map_err(format_error)
+    }
+}
+
+pub fn merkle_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    if left == &[0u8; 32] && right == &[0u8; 32] {
+        return [0u8; 32];
+    }
+    let mut data = [0u8; 65];
+    data[0] = 1;
+    data[1..33].copy_from_slice(left);
+    data[33..].copy_from_slice(right);
+    sha256(&data)
+}
+
+pub fn merkle_leaf(data: &impl Serialize) -> [u8; 32] {
+    let mut buf = vec![0u8];
+    into_writer(data, &mut buf).expect("failed to encode leaf data");
+    sha256(&buf)
+}
+
+// the report of the background scrubber that validates the stored chunk checksums
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ScrubReport {
+    pub started_at: u64,  // unix timestamp in milliseconds
+    pub finished_at: u64, // unix timestamp in milliseconds, 0: the scrub is running
+    pub checked_chunks: u64,
+    pub unchecked_chunks: u64, // chunks uploaded before checksums were recorded
+    pub corrupted_chunks: Vec<(u32, u32)>, // (file id, chunk index)
+}
+
+// the numbers of orphans removed by a garbage collection run
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct GcReport {
+    pub orphaned_chunks: u64,       // chunks without file metadata
+    pub orphaned_checksums: u64,    // chunk checksums without chunk
+    pub dangling_hash_entries: u64, // hash index entries pointing at deleted or changed files
+    pub dangling_folder_refs: u64,  // folder child references to missing files or folders
+    pub scanned: u64,
+    pub next: Option<GcCursor>, // the cursor to resume from, None if the run is finished
+}
+
+// the position of a garbage collection run
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct GcCursor {
+    pub phase: u8,  // 0: chunks, 1: checksums, 2: chunk sizes, 3: hash index, 4: folders
+    pub id: u32,    // the file or folder id
+    pub index: u32, // the chunk index
+    pub hash: Option<ByteArray<32>>,
+}
+
+// the instruction limit of the pre_upgrade and post_upgrade hooks together
+pub const UPGRADE_INSTRUCTION_LIMIT: u64 = 300_000_000_000;
+
+// the estimated cost of the next upgrade, returned by admin_prepare_upgrade
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct UpgradeReport {
+    pub schema_version: u32,    // the version of the stable state
+    pub state_bytes: u64,       // the encoded size of the heap state saved in pre_upgrade
+    pub save_instructions: u64, // the instructions to save the heap state
+    pub load_instructions: u64, // the instructions to decode the saved state
+    pub instruction_limit: u64, // UPGRADE_INSTRUCTION_LIMIT
+    pub ready: bool,            // saving and loading take less than a tenth of the limit
+}
+
+impl UpgradeReport {
+    pub fn new(
+        schema_version: u32,
+        state_bytes: u64,
+        save_instructions: u64,
+        load_instructions: u64,
+    ) -> Self {
+        Self {
+            schema_version,
+            state_bytes,
+            save_instructions,
+            load_instructions,
+            instruction_limit: UPGRADE_INSTRUCTION_LIMIT,
+            ready: save_instructions + load_instructions <= UPGRADE_INSTRUCTION_LIMIT / 10,
+        }
+    }
 }
 
 #[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
@@ -39,9 +732,20 @@ pub struct UpdateBucketInput {
     pub max_custom_data_size: Option<u16>,
     pub enable_hash_index: Option<bool>,
     pub status: Option<i8>, // -1: archived; 0: readable and writable; 1: readonly
-    pub visibility: Option<u8>, // 0: private; 1: public
+    pub visibility: Option<u8>, // 0: private; 1: public; 2: unlisted
     pub trusted_ecdsa_pub_keys: Option<Vec<ByteBuf>>,
     pub trusted_eddsa_pub_keys: Option<Vec<ByteArray<32>>>,
+    pub allowed_origins: Option<BTreeSet<String>>,
+    pub require_signed_url: Option<bool>,
+    pub anonymous_upload: Option<AnonymousUploadConfig>,
+    pub enable_compression: Option<bool>,
+    pub name_policy: Option<NamePolicy>,
+    pub security_headers: Option<SecurityHeaders>,
+    pub access_log_sample_rate: Option<u8>,
+    pub receipt_key_name: Option<String>,
+    pub max_annotations: Option<u32>,
+    pub hide_forbidden: Option<bool>,
+    pub site_tag: Option<String>, // an empty string disables the site
 }
 
 impl UpdateBucketInput {
@@ -88,10 +792,333 @@ impl UpdateBucketInput {
         }
 
         if let Some(visibility) = self.visibility {
-            if visibility != 0 && visibility != 1 {
-                return Err("visibility should be 0 or 1".to_string());
+            if visibility > 2 {
+                return Err("visibility should be 0, 1 or 2".to_string());
+            }
+        }
+
+        if let Some(allowed_origins) = &self.allowed_origins {
+            for origin in allowed_origins {
+                if url_origin(origin).as_ref() != Some(origin) {
+                    return Err(format!("invalid origin: {}", origin));
+                }
+            }
+        }
+
+        if let Some(anonymous_upload) = &self.anonymous_upload {
+            anonymous_upload.validate()?;
+        }
+
+        if let Some(name_policy) = &self.name_policy {
+            name_policy.validate()?;
+        }
+
+        if let Some(security_headers) = &self.security_headers {
+            security_headers.validate()?;
+        }
+
+        if let Some(access_log_sample_rate) = self.access_log_sample_rate {
+            if access_log_sample_rate > 100 {
+                return Err(
+                    "access_log_sample_rate should be smaller than or equal to 100".to_string(),
+                );
+            }
+        }
+
+        if let Some(site_tag) = &self.site_tag {
+            if !site_tag.is_empty() && !valid_tag_name(site_tag) {
+                return Err(format!("invalid site tag: {:?}", site_tag));
             }
         }
         Ok(())
     }
 }
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
+/// The init and upgrade args of the bucket canister.
+pub enum CanisterArgs {
+    Init(InitArgs),
+    Upgrade(UpgradeArgs),
+}
+
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct InitArgs {
+    pub name: String,              // bucket name
+    pub file_id: u32,              // the first file id, default is 0
+    pub max_file_size: u64,        // in bytes, default is 384GB
+    pub max_folder_depth: u8,      // default is 10
+    pub max_children: u16, //  maximum number of subfolders and subfiles in a folder., default is 1000
+    pub max_custom_data_size: u16, // in bytes, default is 4KB
+    pub enable_hash_index: bool, // if enabled, indexing will be built using file hash, allowing files to be read by their hash and preventing duplicate hash for files except the copies of copy_file. default is false
+    pub visibility: u8, // 0: private; 1: public, can be accessed by anyone; 2: unlisted, default is 0
+    pub governance_canister: Option<Principal>,
+    // if true, the admin APIs can only be called by the governance canister, e.g. an SNS
+    pub governance_managed: Option<bool>,
+    // in bytes, default is 256KB, it can not be changed after the installation
+    pub chunk_size: Option<u32>,
+}
+
+impl InitArgs {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_file_size > MAX_FILE_SIZE {
+            return Err(format!(
+                "max_file_size should be smaller than or equal to {}",
+                MAX_FILE_SIZE
+            ));
+        }
+        if let Some(chunk_size) = self.chunk_size {
+            if !(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk_size) {
+                return Err(format!(
+                    "chunk_size should be between {} and {}",
+                    MIN_CHUNK_SIZE, MAX_CHUNK_SIZE
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct UpgradeArgs {
+    pub max_file_size: Option<u64>,
+    pub max_folder_depth: Option<u8>,
+    pub max_children: Option<u16>,
+    pub max_custom_data_size: Option<u16>,
+    pub enable_hash_index: Option<bool>,
+    pub governance_canister: Option<Principal>,
+    pub governance_managed: Option<bool>,
+}
+
+impl UpgradeArgs {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(max_file_size) = self.max_file_size {
+            if max_file_size == 0 {
+                return Err("max_file_size should be greater than 0".to_string());
+            }
+            if max_file_size >= MAX_FILE_SIZE {
+                return Err(format!(
+                    "max_file_size should be smaller than or equal to {}",
+                    MAX_FILE_SIZE
+                ));
+            }
+        }
+        if let Some(max_folder_depth) = self.max_folder_depth {
+            if max_folder_depth == 0 {
+                return Err("max_folder_depth should be greater than 0".to_string());
+            }
+        }
+        if let Some(max_children) = self.max_children {
+            if max_children == 0 {
+                return Err("max_children should be greater than 0".to_string());
+            }
+        }
+
+        if let Some(max_custom_data_size) = self.max_custom_data_size {
+            if max_custom_data_size == 0 {
+                return Err("max_custom_data_size should be greater than 0".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the serialized origin (scheme://host[:port]) of the given url,
+/// it can be used to normalize the `Origin` or `Referer` request header.
+pub fn url_origin(url: &str) -> Option<String> {
+    let origin = Url::parse(url).ok()?.origin();
+    if origin.is_tuple() {
+        Some(origin.ascii_serialization())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn well_known_works() {
+        let mut wk = WellKnown::default();
+        assert!(wk.validate().is_ok());
+        assert!(wk.is_empty());
+        assert_eq!(wk.file("ic-domains"), None);
+
+        wk.ic_domains.insert("files.example.com".to_string());
+        wk.ic_domains.insert("example.com".to_string());
+        wk.ii_alternative_origins
+            .insert("https://files.example.com".to_string());
+        assert!(wk.validate().is_ok());
+        assert_eq!(
+            wk.file("ic-domains"),
+            Some(("text/plain", "example.com\nfiles.example.com".to_string()))
+        );
+        assert_eq!(
+            wk.file("ii-alternative-origins"),
+            Some((
+                "application/json",
+                r#"{"alternativeOrigins":["https://files.example.com"]}"#.to_string()
+            ))
+        );
+        assert_eq!(wk.file("other"), None);
+
+        for domain in ["localhost", "Example.com", "a..com", "-a.com", "a.com/x"] {
+            let wk = WellKnown {
+                ic_domains: BTreeSet::from([domain.to_string()]),
+                ..Default::default()
+            };
+            assert!(wk.validate().is_err(), "{}", domain);
+        }
+        for origin in [
+            "http://example.com",
+            "https://example.com/",
+            "https://example.com/x",
+            "https://example.com\"",
+        ] {
+            let wk = WellKnown {
+                ii_alternative_origins: BTreeSet::from([origin.to_string()]),
+                ..Default::default()
+            };
+            assert!(wk.validate().is_err(), "{}", origin);
+        }
+    }
+
+    #[test]
+    fn security_headers_works() {
+        let bucket = SecurityHeaders {
+            content_security_policy: Some("default-src 'self'".to_string()),
+            strict_transport_security: Some("max-age=31536000".to_string()),
+            ..Default::default()
+        };
+        assert!(bucket.validate().is_ok());
+        assert!(!bucket.is_empty());
+        assert!(SecurityHeaders::default().is_empty());
+
+        let folder = SecurityHeaders {
+            content_security_policy: Some("".to_string()),
+            content_type_options: Some("nosniff".to_string()),
+            ..Default::default()
+        };
+        let headers = folder.or(&bucket);
+        assert_eq!(headers.content_security_policy, Some("".to_string()));
+        assert_eq!(headers.content_type_options, Some("nosniff".to_string()));
+        assert_eq!(
+            headers.strict_transport_security,
+            Some("max-age=31536000".to_string())
+        );
+
+        let invalid = SecurityHeaders {
+            content_security_policy: Some("default-src 'self'\r\nx-evil: 1".to_string()),
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+        let invalid = SecurityHeaders {
+            strict_transport_security: Some("a".repeat(MAX_SECURITY_HEADER_SIZE + 1)),
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn file_proof_header_value_works() {
+        let proof = FileProof {
+            id: 1,
+            name: "hello.txt".to_string(),
+            size: 5,
+            hash: Some([1u8; 32].into()),
+            siblings: vec![[0u8; 32].into(); 32],
+            ..Default::default()
+        };
+        let value = proof.to_header_value();
+        assert_eq!(FileProof::from_header_value(&value).unwrap(), proof);
+        assert!(FileProof::from_header_value("invalid").is_err());
+    }
+
+    #[test]
+    fn url_origin_works() {
+        assert_eq!(
+            url_origin("https://example.com"),
+            Some("https://example.com".to_string())
+        );
+        assert_eq!(
+            url_origin("https://example.com/page/index.html?q=1"),
+            Some("https://example.com".to_string())
+        );
+        assert_eq!(
+            url_origin("http://localhost:8080/"),
+            Some("http://localhost:8080".to_string())
+        );
+        assert_eq!(url_origin("example.com"), None);
+        assert_eq!(url_origin("data:text/plain,hello"), None);
+    }
+
+    #[test]
+    fn update_bucket_input_validate_origins() {
+        let input = UpdateBucketInput {
+            allowed_origins: Some(BTreeSet::from(["https://example.com".to_string()])),
+            ..Default::default()
+        };
+        assert!(input.validate().is_ok());
+
+        let input = UpdateBucketInput {
+            allowed_origins: Some(BTreeSet::from(["https://example.com/".to_string()])),
+            ..Default::default()
+        };
+        assert!(input.validate().is_err());
+    }
+
+    #[test]
+    fn upload_pow_works() {
+        assert_eq!(leading_zero_bits(&[0, 0, 0b0001_0000, 0]), 19);
+        assert_eq!(leading_zero_bits(&[0xff]), 0);
+        assert_eq!(leading_zero_bits(&[0, 0]), 16);
+
+        let bucket = Principal::from_text("mmrxu-fqaaa-aaaap-ahhna-cai").unwrap();
+        let content_hash = sha256(b"hello world");
+        let timestamp = 1720676064;
+        let nonce = solve_upload_pow(&bucket, &content_hash, timestamp, 8);
+        let hash = upload_pow_hash(&bucket, &content_hash, timestamp, nonce);
+        assert!(leading_zero_bits(&hash) >= 8);
+        let hash = upload_pow_hash(&bucket, &content_hash, timestamp + 1, nonce);
+        assert_ne!(
+            hash,
+            upload_pow_hash(&bucket, &content_hash, timestamp, nonce)
+        );
+    }
+
+    #[test]
+    fn name_policy_works() {
+        let policy = NamePolicy::default();
+        assert_eq!(policy.apply("e\u{301}.txt").unwrap(), "e\u{301}.txt");
+        assert_eq!(policy.apply(".env").unwrap(), ".env");
+        assert!(!policy.conflicts("a.txt", "A.txt"));
+
+        let policy = NamePolicy {
+            normalize_nfc: true,
+            forbid_leading_dot: true,
+            max_name_bytes: 8,
+            case_insensitive: true,
+            unique_names: false,
+        };
+        assert!(policy.validate().is_ok());
+        assert_eq!(policy.apply("e\u{301}.txt").unwrap(), "\u{e9}.txt");
+        assert!(policy.apply(".env").is_err());
+        assert!(policy.apply("a/b").is_err());
+        assert!(policy.apply("a\tb").is_err());
+        assert!(policy.apply("123456789").is_err());
+        assert!(policy.conflicts("\u{e9}.txt", "\u{c9}.TXT"));
+
+        let policy = NamePolicy {
+            unique_names: true,
+            ..Default::default()
+        };
+        assert!(policy.conflicts("a.txt", "a.txt"));
+        assert!(!policy.conflicts("a.txt", "A.txt"));
+
+        let policy = NamePolicy {
+            max_name_bytes: MAX_FILE_NAME_SIZE as u16 + 1,
+            ..Default::default()
+        };
+        assert!(policy.validate().is_err());
+    }
+}
@@ -1,8 +1,15 @@
 use candid::{CandidType, Principal};
+use ciborium::into_writer;
+use ed25519_dalek::{Signature, VerifyingKey};
 use serde::{Deserialize, Serialize};
 use serde_bytes::{ByteArray, ByteBuf};
 use std::collections::BTreeSet;
 
+use crate::{
+    bucket::{FolderLimits, UpdateBucketInput},
+    file::valid_file_name,
+};
+
 #[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
 pub struct ClusterInfo {
     pub name: String,
@@ -20,6 +27,37 @@ pub struct ClusterInfo {
     pub bucket_deployed_total: u64,
     pub bucket_deployment_logs: u64,
     pub governance_canister: Option<Principal>,
+    // a snapshot of the bucket is taken before it is upgraded
+    pub bucket_upgrade_snapshot: bool,
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
+/// The init and upgrade args of the cluster canister.
+pub enum ChainArgs {
+    Init(InitArgs),
+    Upgrade(UpgradeArgs),
+}
+
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct InitArgs {
+    pub name: String,
+    pub ecdsa_key_name: String, // Use "dfx_test_key" for local replica and "test_key_1" for a testing key for testnet and mainnet
+    pub schnorr_key_name: String, // Use "dfx_test_key" for local replica and "test_key_1" for a testing key for testnet and mainnet
+    pub token_expiration: u64,    // in seconds
+    pub bucket_topup_threshold: u128,
+    pub bucket_topup_amount: u128,
+    pub governance_canister: Option<Principal>,
+    pub bucket_upgrade_snapshot: Option<bool>,
+}
+
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct UpgradeArgs {
+    pub name: Option<String>,
+    pub token_expiration: Option<u64>, // in seconds
+    pub bucket_topup_threshold: Option<u128>,
+    pub bucket_topup_amount: Option<u128>,
+    pub governance_canister: Option<Principal>,
+    pub bucket_upgrade_snapshot: Option<bool>,
 }
 
 #[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
@@ -41,6 +79,61 @@ pub struct AddWasmInput {
 pub struct DeployWasmInput {
     pub canister: Principal,
     pub args: Option<ByteBuf>,
+    // applied after the bucket is installed, it can not be used for an upgrade
+    pub template: Option<BucketTemplate>,
+    // the tenant that a new bucket is added to, within the tenant's max_buckets
+    pub tenant: Option<String>,
+}
+
+pub const MAX_TEMPLATE_FOLDERS: usize = 100;
+
+// the settings applied to a newly deployed bucket, so it comes up ready to use for an
+// application profile.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct BucketTemplate {
+    // visibility, allowed origins, trusted keys, limits and so on
+    pub bucket: Option<UpdateBucketInput>,
+    pub managers: BTreeSet<Principal>,
+    pub folders: Vec<TemplateFolder>,
+}
+
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TemplateFolder {
+    pub path: String, // e.g. "assets/images", the missing parent folders are created
+    pub limits: Option<FolderLimits>,
+    pub unlisted: bool,
+    pub inbox: bool,
+}
+
+impl BucketTemplate {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(bucket) = &self.bucket {
+            bucket.validate()?;
+        }
+        if self.managers.contains(&Principal::anonymous()) {
+            return Err("anonymous user is not allowed".to_string());
+        }
+        if self.folders.len() > MAX_TEMPLATE_FOLDERS {
+            return Err(format!(
+                "folders exceeds the limit {}",
+                MAX_TEMPLATE_FOLDERS
+            ));
+        }
+
+        let mut paths = BTreeSet::new();
+        for folder in &self.folders {
+            if !folder.path.split('/').all(valid_file_name) {
+                return Err(format!("invalid folder path: {:?}", folder.path));
+            }
+            if !paths.insert(folder.path.as_str()) {
+                return Err(format!("duplicate folder path: {:?}", folder.path));
+            }
+            if let Some(limits) = &folder.limits {
+                limits.validate()?;
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
@@ -52,3 +145,228 @@ pub struct BucketDeploymentInfo {
     pub args: Option<ByteBuf>,
     pub error: Option<String>,
 }
+
+// a page of the files found by find_file_by_hash, `next` is the last scanned bucket,
+// the lookup continues from it with `prev`, None: all buckets are scanned.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct FindFileByHashOutput {
+    pub files: Vec<(Principal, u32)>,
+    pub next: Option<Principal>,
+}
+
+// the progress of a bucket decommissioning, the folders and files of the bucket are
+// migrated into a folder named by the bucket id in the target bucket.
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
+pub struct BucketDecommissionStatus {
+    pub canister: Principal,
+    pub target: Principal,
+    pub target_folder: u32, // 0: the target folder is not created yet
+    pub delete_canister: bool,
+    pub started_at: u64,  // in milliseconds
+    pub finished_at: u64, // in milliseconds, 0: the process is running or paused
+    pub running: bool,
+    pub migrated_folders: u64,
+    pub migrated_files: u64,
+    pub failed_files: u64,
+    pub failed_file_errors: Vec<(u32, String)>, // the first failed files and their errors
+    pub pending_folders: u64,
+    pub last_error: Option<String>,
+}
+
+pub const MAX_TENANT_ID_SIZE: usize = 32;
+
+// a tenant of the cluster, its managers can issue the tokens, manage the policies and
+// call the buckets of the tenant only, so that one cluster can serve many applications.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Tenant {
+    pub id: String, // e.g. "my-app", lowercase letters, digits and '-'
+    pub managers: BTreeSet<Principal>,
+    pub buckets: BTreeSet<Principal>,
+    pub max_buckets: u32, // the quota of the buckets
+}
+
+impl Tenant {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.id.is_empty()
+            || self.id.len() > MAX_TENANT_ID_SIZE
+            || !self
+                .id
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        {
+            return Err(format!("invalid tenant id: {:?}", self.id));
+        }
+        if self.managers.contains(&Principal::anonymous()) {
+            return Err("anonymous user is not allowed".to_string());
+        }
+        if self.buckets.len() > self.max_buckets as usize {
+            return Err(format!("buckets exceeds the quota {}", self.max_buckets));
+        }
+        Ok(())
+    }
+}
+
+// the usage aggregated across the buckets of a tenant, the buckets are polled
+// periodically by the cluster.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct TenantUsage {
+    pub tenant: String,
+    pub buckets: u32,
+    pub storage_bytes: u64,
+    pub total_files: u64,
+    pub egress_bytes: u64, // accumulated since the buckets were polled first
+    // unix timestamp in milliseconds of the oldest poll of the buckets, 0: not polled yet
+    pub updated_at: u64,
+}
+
+// the lifetime of a bucket lookup, in seconds
+pub const BUCKET_LOOKUP_TTL: u64 = 300;
+static BUCKET_LOOKUP_DOMAIN: &str = "ic_oss_bucket_lookup";
+
+// the bucket that serves a logical path, returned by the cluster's `lookup`.
+// the bucket is resolved from the placements that the cluster managers set with
+// `admin_set_placement`.
+// it is signed by the cluster's weak_ed25519_token_public_key, so gateways can
+// cache and share it until it expires.
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
+pub struct BucketLookup {
+    pub bucket: Principal,
+    pub shard_key: String, // the first segment of the path
+    pub issued_at: u64,    // in seconds
+    pub expires_at: u64,   // in seconds
+    pub signature: ByteArray<64>,
+}
+
+impl BucketLookup {
+    // the shard key of a path like "/tenant/images/a.png?v=1", it is "tenant".
+    pub fn shard_key(path: &str) -> Result<String, String> {
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+        match path.trim_start_matches('/').split('/').next() {
+            Some(key) if !key.is_empty() && key.len() <= 256 => Ok(key.to_string()),
+            _ => Err("invalid path".to_string()),
+        }
+    }
+
+    // the signed data: CBOR array of the domain and the fields except the signature
+    pub fn tbs_data(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        into_writer(
+            &(
+                BUCKET_LOOKUP_DOMAIN,
+                ByteBuf::from(self.bucket.as_slice()),
+                &self.shard_key,
+                self.issued_at,
+                self.expires_at,
+            ),
+            &mut buf,
+        )
+        .expect("failed to encode BucketLookup");
+        buf
+    }
+
+    pub fn verify(&self, pub_key: &[u8; 32], now_sec: u64) -> Result<(), String> {
+        if self.expires_at <= now_sec {
+            return Err("lookup expired".to_string());
+        }
+        let key = VerifyingKey::from_bytes(pub_key).map_err(|_| "invalid verifying key")?;
+        let sig = Signature::from_bytes(&self.signature);
+        key.verify_strict(&self.tbs_data(), &sig)
+            .map_err(|_| "signature verification failed".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    #[test]
+    fn test_tenant() {
+        let mut tenant = Tenant {
+            id: "my-app-1".to_string(),
+            managers: BTreeSet::from([Principal::management_canister()]),
+            buckets: BTreeSet::from([Principal::management_canister()]),
+            max_buckets: 1,
+        };
+        assert!(tenant.validate().is_ok());
+
+        tenant.max_buckets = 0;
+        assert!(tenant.validate().is_err());
+        tenant.max_buckets = 1;
+
+        tenant.managers.insert(Principal::anonymous());
+        assert!(tenant.validate().is_err());
+        tenant.managers.remove(&Principal::anonymous());
+
+        for id in ["", "My-App", "my_app", &"a".repeat(MAX_TENANT_ID_SIZE + 1)] {
+            tenant.id = id.to_string();
+            assert!(tenant.validate().is_err(), "{id}");
+        }
+    }
+
+    #[test]
+    fn test_bucket_template() {
+        let mut template = BucketTemplate {
+            bucket: Some(UpdateBucketInput {
+                visibility: Some(1),
+                ..Default::default()
+            }),
+            folders: vec![
+                TemplateFolder {
+                    path: "assets/images".to_string(),
+                    ..Default::default()
+                },
+                TemplateFolder {
+                    path: "uploads".to_string(),
+                    inbox: true,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        assert!(template.validate().is_ok());
+
+        for path in ["", "/assets", "assets/", "assets//images"] {
+            template.folders[1].path = path.to_string();
+            assert!(template.validate().is_err(), "{:?}", path);
+        }
+        template.folders[1].path = "assets/images".to_string();
+        assert_eq!(
+            template.validate().unwrap_err(),
+            "duplicate folder path: \"assets/images\""
+        );
+    }
+
+    #[test]
+    fn test_bucket_lookup() {
+        assert_eq!(BucketLookup::shard_key("/a/b/c.png").unwrap(), "a");
+        assert_eq!(BucketLookup::shard_key("a?x=/y").unwrap(), "a");
+        assert_eq!(BucketLookup::shard_key("//a#b").unwrap(), "a");
+        assert!(BucketLookup::shard_key("/").is_err());
+        assert!(BucketLookup::shard_key("?a/b").is_err());
+
+        let signing_key = SigningKey::from_bytes(&[8u8; 32]);
+        let pub_key = signing_key.verifying_key().to_bytes();
+        let mut lookup = BucketLookup {
+            bucket: Principal::management_canister(),
+            shard_key: "a".to_string(),
+            issued_at: 100,
+            expires_at: 100 + BUCKET_LOOKUP_TTL,
+            signature: [0u8; 64].into(),
+        };
+        lookup.signature = signing_key.sign(&lookup.tbs_data()).to_bytes().into();
+        assert!(lookup.verify(&pub_key, 100).is_ok());
+        assert_eq!(
+            lookup
+                .verify(&pub_key, 100 + BUCKET_LOOKUP_TTL)
+                .unwrap_err(),
+            "lookup expired"
+        );
+
+        lookup.shard_key = "b".to_string();
+        assert_eq!(
+            lookup.verify(&pub_key, 100).unwrap_err(),
+            "signature verification failed"
+        );
+    }
+}
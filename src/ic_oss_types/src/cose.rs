@@ -1,31 +1,48 @@
+use aes_gcm::{
+    aead::{Aead, Payload},
+    Aes256Gcm, KeyInit, Nonce,
+};
 use candid::{CandidType, Principal};
 use coset::{
     cwt::{ClaimName, ClaimsSet, Timestamp},
-    iana, Algorithm, CborSerializable, CoseSign1, CoseSign1Builder, HeaderBuilder,
+    iana, Algorithm, CborSerializable, CoseEncrypt0, CoseEncrypt0Builder, CoseSign1,
+    CoseSign1Builder, HeaderBuilder,
+};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use hmac::{Hmac, Mac};
+use k256::{
+    ecdsa,
+    ecdsa::signature::hazmat::{PrehashSigner, PrehashVerifier},
 };
-use ed25519_dalek::{Signature, VerifyingKey};
-use k256::{ecdsa, ecdsa::signature::hazmat::PrehashVerifier};
 use num_traits::ToPrimitive;
 use serde::{Deserialize, Serialize};
 use serde_bytes::{ByteArray, ByteBuf};
 use sha2::Digest;
 
 pub use coset;
-pub use iana::Algorithm::{EdDSA, ES256K};
+pub use iana::Algorithm::{EdDSA, A256GCM, ES256K};
 
-const CLOCK_SKEW: i64 = 5 * 60; // 5 minutes
+pub const CLOCK_SKEW: i64 = 5 * 60; // 5 minutes
 const ALG_ED25519: Algorithm = Algorithm::Assigned(EdDSA);
 const ALG_SECP256K1: Algorithm = Algorithm::Assigned(ES256K);
+const ALG_A256GCM: Algorithm = Algorithm::Assigned(A256GCM);
 
 static SCOPE_NAME: ClaimName = ClaimName::Assigned(iana::CwtClaimName::Scope);
+static CALLER_NAME: &str = "caller";
 
 pub static BUCKET_TOKEN_AAD: &[u8] = b"ic_oss_bucket";
+pub static RECEIPT_AAD: &[u8] = b"ic_oss_receipt";
+pub static DEK_AAD: &[u8] = b"ic_oss_dek";
 
 #[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 pub struct Token {
     pub subject: Principal,
     pub audience: Principal,
     pub policies: String,
+    // the token can only be presented by this caller, None: a bearer token
+    pub caller: Option<Principal>,
+    // the unique id of a single-use token, it is consumed by the first update call
+    pub jti: Option<ByteBuf>,
 }
 
 impl Token {
@@ -55,6 +72,13 @@ impl Token {
     }
 
     pub fn to_cwt(self, now_sec: i64, expiration_sec: i64) -> ClaimsSet {
+        let mut rest = vec![(SCOPE_NAME.clone(), self.policies.into())];
+        if let Some(caller) = self.caller {
+            rest.push((
+                ClaimName::Text(CALLER_NAME.to_string()),
+                caller.to_text().into(),
+            ));
+        }
         ClaimsSet {
             issuer: None,
             subject: Some(self.subject.to_text()),
@@ -62,11 +86,83 @@ impl Token {
             expiration_time: Some(Timestamp::WholeSeconds(now_sec + expiration_sec)),
             not_before: Some(Timestamp::WholeSeconds(now_sec)),
             issued_at: Some(Timestamp::WholeSeconds(now_sec)),
-            cwt_id: None,
-            rest: vec![(SCOPE_NAME.clone(), self.policies.into())],
+            cwt_id: self.jti.map(|jti| jti.into_vec()),
+            rest,
+        }
+    }
+
+    /// Checks that the token is presented by the caller that it is bound to, a token
+    /// without the binding can be presented by anyone.
+    pub fn verify_caller(&self, caller: &Principal) -> Result<(), String> {
+        match self.caller {
+            Some(ref bound) if bound != caller => {
+                Err("token is bound to another caller".to_string())
+            }
+            _ => Ok(()),
         }
     }
 
+    /// Returns the unsigned COSE_Sign1 token with the CWT claims as the payload, the
+    /// signature should be computed over `tbs_data(BUCKET_TOKEN_AAD)`. It is used by
+    /// the issuers that sign with a remote key, e.g. threshold signatures.
+    pub fn to_sign1(
+        self,
+        alg: iana::Algorithm,
+        issuer: Option<String>,
+        now_sec: i64,
+        expiration_sec: i64,
+    ) -> Result<CoseSign1, String> {
+        let mut claims = self.to_cwt(now_sec, expiration_sec);
+        claims.issuer = issuer;
+        cose_sign1(claims, alg, None)
+    }
+
+    /// Signs the token with the Ed25519 key and returns the encoded COSE_Sign1 token,
+    /// the verifying key should be one of the bucket's `trusted_eddsa_pub_keys`.
+    pub fn sign_ed25519(
+        self,
+        signing_key: &SigningKey,
+        issuer: Option<String>,
+        now_sec: i64,
+        expiration_sec: i64,
+    ) -> Result<ByteBuf, String> {
+        let mut sign1 = self.to_sign1(EdDSA, issuer, now_sec, expiration_sec)?;
+        let tbs_data = sign1.tbs_data(BUCKET_TOKEN_AAD);
+        sign1.signature = signing_key.sign(&tbs_data).to_bytes().to_vec();
+        let token = sign1.to_vec().map_err(|err| err.to_string())?;
+        Ok(ByteBuf::from(token))
+    }
+
+    /// Signs the SHA-256 digest of the token with the secp256k1 key and returns the
+    /// encoded COSE_Sign1 token, the verifying key should be one of the bucket's
+    /// `trusted_ecdsa_pub_keys`.
+    pub fn sign_secp256k1(
+        self,
+        signing_key: &ecdsa::SigningKey,
+        issuer: Option<String>,
+        now_sec: i64,
+        expiration_sec: i64,
+    ) -> Result<ByteBuf, String> {
+        let mut sign1 = self.to_sign1(ES256K, issuer, now_sec, expiration_sec)?;
+        let digest = sha256(&sign1.tbs_data(BUCKET_TOKEN_AAD));
+        let sig: ecdsa::Signature = signing_key
+            .sign_prehash(&digest)
+            .map_err(|err| err.to_string())?;
+        sign1.signature = sig.to_bytes().to_vec();
+        let token = sign1.to_vec().map_err(|err| err.to_string())?;
+        Ok(ByteBuf::from(token))
+    }
+
+    /// Decodes the COSE_Sign1 token without verifying the signature or the expiration,
+    /// it is used by the holders to inspect their tokens.
+    pub fn decode_sign1(sign1_token: &[u8]) -> Result<Self, String> {
+        let cs1 = CoseSign1::from_slice(sign1_token)
+            .map_err(|err| format!("invalid COSE sign1 token: {}", err))?;
+        let claims = ClaimsSet::from_slice(cs1.payload.as_deref().unwrap_or_default())
+            .map_err(|err| format!("invalid claims: {}", err))?;
+        Self::try_from(claims)
+    }
+
     fn secp256k1_verify(
         pub_keys: &[ByteBuf],
         tbs_data: &[u8],
@@ -115,20 +211,14 @@ impl Token {
     fn from_cwt_bytes(data: &[u8], now_sec: i64) -> Result<Self, String> {
         let claims =
             ClaimsSet::from_slice(data).map_err(|err| format!("invalid claims: {}", err))?;
-        if let Some(ref exp) = claims.expiration_time {
-            let exp = match exp {
-                Timestamp::WholeSeconds(v) => *v,
-                Timestamp::FractionalSeconds(v) => (*v).to_i64().unwrap_or_default(),
-            };
+        if claims.expiration_time.is_some() {
+            let exp = timestamp_sec(claims.expiration_time.as_ref()).unwrap_or_default();
             if exp < now_sec - CLOCK_SKEW {
                 return Err("token expired".to_string());
             }
         }
-        if let Some(ref nbf) = claims.not_before {
-            let nbf = match nbf {
-                Timestamp::WholeSeconds(v) => *v,
-                Timestamp::FractionalSeconds(v) => (*v).to_i64().unwrap_or_default(),
-            };
+        if claims.not_before.is_some() {
+            let nbf = timestamp_sec(claims.not_before.as_ref()).unwrap_or_default();
             if nbf > now_sec + CLOCK_SKEW {
                 return Err("token not yet valid".to_string());
             }
@@ -137,6 +227,143 @@ impl Token {
     }
 }
 
+/// A receipt signed by the bucket with threshold ECDSA (secp256k1), it proves that
+/// the file with the hash was delivered to the recipient.
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Receipt {
+    pub bucket: Principal,
+    pub file: u32,
+    pub hash: ByteArray<32>, // SHA3-256 hash of the file content
+    pub size: u64,
+    pub recipient: Principal,
+    pub timestamp: u64, // unix timestamp in milliseconds
+}
+
+impl Receipt {
+    /// Returns the unsigned COSE_Sign1 with the CBOR encoded receipt as the payload,
+    /// the signature should be computed over `tbs_data(RECEIPT_AAD)`.
+    pub fn to_sign1(&self) -> Result<CoseSign1, String> {
+        let mut payload = Vec::new();
+        ciborium::into_writer(self, &mut payload).map_err(|err| err.to_string())?;
+        Ok(CoseSign1Builder::new()
+            .protected(HeaderBuilder::new().algorithm(ES256K).build())
+            .payload(payload)
+            .build())
+    }
+
+    /// Verifies the signed receipt with the secp256k1 public keys of the bucket.
+    pub fn from_sign1(
+        sign1_receipt: &[u8],
+        secp256k1_pub_keys: &[ByteBuf],
+    ) -> Result<Self, String> {
+        let cs1 = CoseSign1::from_slice(sign1_receipt)
+            .map_err(|err| format!("invalid COSE sign1 receipt: {}", err))?;
+        match cs1.protected.header.alg {
+            Some(ALG_SECP256K1) => {
+                Token::secp256k1_verify(
+                    secp256k1_pub_keys,
+                    &cs1.tbs_data(RECEIPT_AAD),
+                    &cs1.signature,
+                )?;
+            }
+            alg => {
+                Err(format!("unsupported algorithm: {:?}", alg))?;
+            }
+        }
+        ciborium::from_reader(cs1.payload.unwrap_or_default().as_slice())
+            .map_err(|err| format!("invalid receipt: {}", err))
+    }
+}
+
+/// Returns the expiration time (in seconds) of a COSE_Sign1 token without verifying it,
+/// it is used by clients to renew the token before it expires.
+pub fn sign1_expiration(sign1_token: &[u8]) -> Option<i64> {
+    let cs1 = CoseSign1::from_slice(sign1_token).ok()?;
+    let claims = ClaimsSet::from_slice(cs1.payload.as_ref()?).ok()?;
+    timestamp_sec(claims.expiration_time.as_ref())
+}
+
+/// Returns the seconds until the COSE_Sign1 token expires, negative if it has expired.
+/// It is None if the token can not be decoded or has no expiration.
+pub fn sign1_expires_in(sign1_token: &[u8], now_sec: i64) -> Option<i64> {
+    sign1_expiration(sign1_token).map(|exp| exp - now_sec)
+}
+
+/// The decoded claims of an access token and the result of its verification.
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct TokenInfo {
+    pub subject: Principal,
+    pub audience: Principal,
+    pub policies: String,
+    pub caller: Option<Principal>, // the caller that the token is bound to
+    pub jti: Option<ByteBuf>,      // the unique id of a single-use token
+    pub issuer: Option<String>,
+    pub algorithm: String,       // "ES256K" or "EdDSA"
+    pub issued_at: Option<i64>,  // unix timestamp in seconds
+    pub not_before: Option<i64>, // unix timestamp in seconds
+    pub expires_at: Option<i64>, // unix timestamp in seconds
+    pub valid: bool,
+    pub error: Option<String>, // why the token is not valid
+    // the policies that the verifier would apply for the token, None if the token is not valid
+    pub matching_policies: Option<String>,
+}
+
+/// Decodes the claims of the COSE_Sign1 token and verifies it like `Token::from_sign1`,
+/// a token that can be decoded but fails the verification is returned with the error.
+pub fn introspect_sign1(
+    sign1_token: &[u8],
+    secp256k1_pub_keys: &[ByteBuf],
+    ed25519_pub_keys: &[ByteArray<32>],
+    aad: &[u8],
+    now_sec: i64,
+) -> Result<TokenInfo, String> {
+    let cs1 = CoseSign1::from_slice(sign1_token)
+        .map_err(|err| format!("invalid COSE sign1 token: {}", err))?;
+    let algorithm = match cs1.protected.header.alg {
+        Some(ALG_SECP256K1) => "ES256K".to_string(),
+        Some(ALG_ED25519) => "EdDSA".to_string(),
+        ref alg => format!("{:?}", alg),
+    };
+    let claims = ClaimsSet::from_slice(cs1.payload.as_deref().unwrap_or_default())
+        .map_err(|err| format!("invalid claims: {}", err))?;
+    let issuer = claims.issuer.clone();
+    let issued_at = timestamp_sec(claims.issued_at.as_ref());
+    let not_before = timestamp_sec(claims.not_before.as_ref());
+    let expires_at = timestamp_sec(claims.expiration_time.as_ref());
+    let token = Token::try_from(claims)?;
+    let error = Token::from_sign1(
+        sign1_token,
+        secp256k1_pub_keys,
+        ed25519_pub_keys,
+        aad,
+        now_sec,
+    )
+    .err();
+
+    Ok(TokenInfo {
+        subject: token.subject,
+        audience: token.audience,
+        policies: token.policies,
+        caller: token.caller,
+        jti: token.jti,
+        issuer,
+        algorithm,
+        issued_at,
+        not_before,
+        expires_at,
+        valid: error.is_none(),
+        error,
+        matching_policies: None,
+    })
+}
+
+fn timestamp_sec(ts: Option<&Timestamp>) -> Option<i64> {
+    match ts? {
+        Timestamp::WholeSeconds(v) => Some(*v),
+        Timestamp::FractionalSeconds(v) => v.to_i64(),
+    }
+}
+
 /// algorithm: EdDSA | ES256K
 pub fn cose_sign1(
     cs: ClaimsSet,
@@ -165,6 +392,20 @@ impl TryFrom<ClaimsSet> for Token {
             .find(|(key, _)| key == &SCOPE_NAME)
             .ok_or("missing scope")?;
         let scope = scope.1.as_text().ok_or("invalid scope text")?;
+        let caller = match claims
+            .rest
+            .iter()
+            .find(|(key, _)| matches!(key, ClaimName::Text(name) if name == CALLER_NAME))
+        {
+            Some((_, caller)) => {
+                let caller = caller.as_text().ok_or("invalid caller text")?;
+                Some(
+                    Principal::from_text(caller)
+                        .map_err(|err| format!("invalid caller: {}", err))?,
+                )
+            }
+            None => None,
+        };
 
         Ok(Token {
             subject: Principal::from_text(claims.subject.as_ref().ok_or("missing subject")?)
@@ -172,10 +413,94 @@ impl TryFrom<ClaimsSet> for Token {
             audience: Principal::from_text(claims.audience.as_ref().ok_or("missing audience")?)
                 .map_err(|err| format!("invalid audience: {}", err))?,
             policies: scope.to_string(),
+            caller,
+            jti: claims.cwt_id.map(ByteBuf::from),
         })
     }
 }
 
+/// Encrypts the payload with the AES-256-GCM key and returns the encoded COSE_Encrypt0,
+/// the nonce should never be reused with the same key. It wraps the data encryption key
+/// of a file into `FileMetadata.dek` with `DEK_AAD`.
+pub fn cose_encrypt0(
+    payload: &[u8],
+    key: &[u8; 32],
+    nonce: [u8; 12],
+    aad: &[u8],
+    key_id: Option<Vec<u8>>,
+) -> Result<ByteBuf, String> {
+    let cipher = Aes256Gcm::new_from_slice(key).map_err(|err| err.to_string())?;
+    let mut protected = HeaderBuilder::new().algorithm(A256GCM);
+    if let Some(key_id) = key_id {
+        protected = protected.key_id(key_id);
+    }
+    let e0 = CoseEncrypt0Builder::new()
+        .protected(protected.build())
+        .unprotected(HeaderBuilder::new().iv(nonce.to_vec()).build())
+        .try_create_ciphertext(payload, aad, |plain, enc| {
+            cipher
+                .encrypt(
+                    Nonce::from_slice(&nonce),
+                    Payload {
+                        msg: plain,
+                        aad: enc,
+                    },
+                )
+                .map_err(|err| err.to_string())
+        })?;
+    let data = e0.to_vec().map_err(|err| err.to_string())?;
+    Ok(ByteBuf::from(data))
+}
+
+/// Returns the key id in the protected header of the COSE_Encrypt0, it tells the holders
+/// which key to decrypt it with.
+pub fn encrypt0_key_id(encrypt0: &[u8]) -> Result<Vec<u8>, String> {
+    let e0 = CoseEncrypt0::from_slice(encrypt0)
+        .map_err(|err| format!("invalid COSE encrypt0: {}", err))?;
+    Ok(e0.protected.header.key_id)
+}
+
+/// Decrypts the COSE_Encrypt0 produced by `cose_encrypt0` and returns the payload.
+pub fn cose_decrypt0(encrypt0: &[u8], key: &[u8; 32], aad: &[u8]) -> Result<Vec<u8>, String> {
+    let e0 = CoseEncrypt0::from_slice(encrypt0)
+        .map_err(|err| format!("invalid COSE encrypt0: {}", err))?;
+    if e0.protected.header.alg != Some(ALG_A256GCM) {
+        Err(format!(
+            "unsupported algorithm: {:?}",
+            e0.protected.header.alg
+        ))?;
+    }
+    if e0.unprotected.iv.len() != 12 || e0.ciphertext.is_none() {
+        Err("invalid COSE encrypt0".to_string())?;
+    }
+    let cipher = Aes256Gcm::new_from_slice(key).map_err(|err| err.to_string())?;
+    let nonce = Nonce::clone_from_slice(&e0.unprotected.iv);
+    e0.decrypt(aad, |data, enc| {
+        cipher
+            .decrypt(
+                &nonce,
+                Payload {
+                    msg: data,
+                    aad: enc,
+                },
+            )
+            .map_err(|_| "failed to decrypt COSE encrypt0".to_string())
+    })
+}
+
+/// Derives a 32-byte key from the key material with HKDF-SHA256 (RFC 5869), e.g. the
+/// key-encryption key of the files from a BYOK secret or a vetKey.
+pub fn hkdf256(ikm: &[u8], salt: &[u8], info: &[u8]) -> [u8; 32] {
+    let mut extract = <Hmac<sha2::Sha256> as Mac>::new_from_slice(salt).expect("any key size");
+    extract.update(ikm);
+    let prk = extract.finalize().into_bytes();
+    // one block of the expand step is enough for 32 bytes
+    let mut expand = <Hmac<sha2::Sha256> as Mac>::new_from_slice(&prk).expect("any key size");
+    expand.update(info);
+    expand.update(&[1u8]);
+    expand.finalize().into_bytes().into()
+}
+
 pub fn sha256(data: &[u8]) -> [u8; 32] {
     let mut hasher = sha2::Sha256::new();
     hasher.update(data);
@@ -186,7 +511,56 @@ pub fn sha256(data: &[u8]) -> [u8; 32] {
 mod test {
     use super::*;
     use crate::permission::{Operation, Permission, Policies, Policy, Resource, Resources};
-    use ed25519_dalek::Signer;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    #[test]
+    fn test_receipt() {
+        let signing_key = ecdsa::SigningKey::from_bytes(&[7u8; 32].into()).unwrap();
+        let pub_key = ByteBuf::from(
+            signing_key
+                .verifying_key()
+                .to_encoded_point(true)
+                .as_bytes()
+                .to_vec(),
+        );
+        let receipt = Receipt {
+            bucket: Principal::from_text("mmrxu-fqaaa-aaaap-ahhna-cai").unwrap(),
+            file: 1,
+            hash: [1u8; 32].into(),
+            size: 1024,
+            recipient: Principal::from_text(
+                "z7wjp-v6fe3-kksu5-26f64-dedtw-j7ndj-57onx-qga6c-et5e3-njx53-tae",
+            )
+            .unwrap(),
+            timestamp: 1720676064000,
+        };
+
+        let mut sign1 = receipt.to_sign1().unwrap();
+        let digest = sha256(&sign1.tbs_data(RECEIPT_AAD));
+        let sig: ecdsa::Signature = signing_key.sign_prehash(&digest).unwrap();
+        sign1.signature = sig.to_bytes().to_vec();
+        let data = sign1.to_vec().unwrap();
+
+        let receipt2 = Receipt::from_sign1(&data, &[pub_key.clone()]).unwrap();
+        assert_eq!(receipt, receipt2);
+
+        let other_key = ecdsa::SigningKey::from_bytes(&[8u8; 32].into()).unwrap();
+        let other_key = ByteBuf::from(
+            other_key
+                .verifying_key()
+                .to_encoded_point(true)
+                .as_bytes()
+                .to_vec(),
+        );
+        assert!(Receipt::from_sign1(&data, &[other_key]).is_err());
+
+        // a token signature is not a valid receipt signature
+        let digest = sha256(&sign1.tbs_data(BUCKET_TOKEN_AAD));
+        let sig: ecdsa::Signature = signing_key.sign_prehash(&digest).unwrap();
+        sign1.signature = sig.to_bytes().to_vec();
+        let data = sign1.to_vec().unwrap();
+        assert!(Receipt::from_sign1(&data, &[pub_key]).is_err());
+    }
 
     #[test]
     fn test_ed25519_token() {
@@ -219,6 +593,8 @@ mod test {
             .unwrap(),
             audience: Principal::from_text("mmrxu-fqaaa-aaaap-ahhna-cai").unwrap(),
             policies: ps.to_string(),
+            caller: None,
+            jti: None,
         };
         println!("token: {:?}", &token);
 
@@ -242,5 +618,149 @@ mod test {
         )
         .unwrap();
         assert_eq!(token, token2);
+
+        // the token is rejected by other keys and after it expires
+        let other_key = ed25519_dalek::SigningKey::from_bytes(&[9u8; 32]);
+        let other_key: &VerifyingKey = other_key.as_ref();
+        assert!(Token::from_sign1(
+            &sign1_token,
+            &[],
+            &[other_key.to_bytes().into()],
+            BUCKET_TOKEN_AAD,
+            now_sec,
+        )
+        .is_err());
+        assert!(Token::from_sign1(&sign1_token, &[], &[], BUCKET_TOKEN_AAD, now_sec).is_err());
+        assert!(Token::from_sign1(
+            &sign1_token,
+            &[],
+            &[pub_key.into()],
+            BUCKET_TOKEN_AAD,
+            now_sec + 3600 + CLOCK_SKEW + 1,
+        )
+        .is_err());
+        assert_eq!(sign1_expiration(&sign1_token), Some(now_sec + 3600));
+        assert_eq!(sign1_expiration(&[1, 2, 3]), None);
+    }
+
+    #[test]
+    fn test_introspect_sign1() {
+        let signing_key = SigningKey::from_bytes(&[8u8; 32]);
+        let pub_key = ByteArray::from(signing_key.verifying_key().to_bytes());
+        let token = Token {
+            subject: Principal::anonymous(),
+            audience: Principal::management_canister(),
+            policies: "Folder.*:1".to_string(),
+            caller: None,
+            jti: None,
+        };
+        let mut sign1 = cose_sign1(token.clone().to_cwt(1000, 3600), EdDSA, None).unwrap();
+        sign1.signature = signing_key
+            .sign(&sign1.tbs_data(BUCKET_TOKEN_AAD))
+            .to_bytes()
+            .to_vec();
+        let data = sign1.to_vec().unwrap();
+
+        let info = introspect_sign1(&data, &[], &[pub_key], BUCKET_TOKEN_AAD, 1000).unwrap();
+        assert!(info.valid);
+        assert_eq!(info.error, None);
+        assert_eq!(info.subject, token.subject);
+        assert_eq!(info.policies, token.policies);
+        assert_eq!(info.algorithm, "EdDSA");
+        assert_eq!(info.issued_at, Some(1000));
+        assert_eq!(info.expires_at, Some(4600));
+
+        let info = introspect_sign1(&data, &[], &[pub_key], BUCKET_TOKEN_AAD, 10000).unwrap();
+        assert!(!info.valid);
+        assert_eq!(info.error, Some("token expired".to_string()));
+
+        let info = introspect_sign1(&data, &[], &[], BUCKET_TOKEN_AAD, 1000).unwrap();
+        assert!(!info.valid);
+
+        assert!(introspect_sign1(b"invalid", &[], &[pub_key], BUCKET_TOKEN_AAD, 1000).is_err());
+    }
+
+    #[test]
+    fn test_cose_encrypt0() {
+        let key = [7u8; 32];
+        let dek = [9u8; 32];
+        let data =
+            cose_encrypt0(&dek, &key, [1u8; 12], DEK_AAD, Some(b"ic_oss/f1".to_vec())).unwrap();
+        assert_eq!(encrypt0_key_id(&data).unwrap(), b"ic_oss/f1".to_vec());
+        assert_eq!(cose_decrypt0(&data, &key, DEK_AAD).unwrap(), dek.to_vec());
+        assert!(cose_decrypt0(&data, &[8u8; 32], DEK_AAD).is_err());
+        assert!(cose_decrypt0(&data, &key, RECEIPT_AAD).is_err());
+        assert!(cose_decrypt0(b"invalid", &key, DEK_AAD).is_err());
+
+        // RFC 5869 test case 1
+        let okm = hkdf256(
+            &[0x0b; 22],
+            &hex::decode("000102030405060708090a0b0c").unwrap(),
+            &hex::decode("f0f1f2f3f4f5f6f7f8f9").unwrap(),
+        );
+        assert_eq!(
+            hex::encode(okm),
+            "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf"
+        );
+    }
+
+    #[test]
+    fn test_sign_token() {
+        let token = Token {
+            subject: Principal::anonymous(),
+            audience: Principal::management_canister(),
+            policies: "Bucket.Read.*".to_string(),
+            caller: None,
+            jti: None,
+        };
+        let issuer = Some("backend".to_string());
+
+        let signing_key = SigningKey::from_bytes(&[8u8; 32]);
+        let pub_key = ByteArray::from(signing_key.verifying_key().to_bytes());
+        let data = token
+            .clone()
+            .sign_ed25519(&signing_key, issuer.clone(), 1000, 3600)
+            .unwrap();
+        let token2 = Token::from_sign1(&data, &[], &[pub_key], BUCKET_TOKEN_AAD, 1000).unwrap();
+        assert_eq!(token2, token);
+        assert_eq!(Token::decode_sign1(&data).unwrap(), token);
+        let info = introspect_sign1(&data, &[], &[pub_key], BUCKET_TOKEN_AAD, 1000).unwrap();
+        assert_eq!(info.issuer, issuer);
+        assert_eq!(sign1_expires_in(&data, 1000), Some(3600));
+        assert_eq!(sign1_expires_in(&data, 5000), Some(-400));
+
+        let signing_key = ecdsa::SigningKey::from_bytes(&[7u8; 32].into()).unwrap();
+        let pub_key = ByteBuf::from(
+            signing_key
+                .verifying_key()
+                .to_encoded_point(true)
+                .as_bytes()
+                .to_vec(),
+        );
+        let data = token
+            .clone()
+            .sign_secp256k1(&signing_key, issuer, 1000, 3600)
+            .unwrap();
+        let token2 = Token::from_sign1(&data, &[pub_key], &[], BUCKET_TOKEN_AAD, 1000).unwrap();
+        assert_eq!(token2, token);
+        assert!(Token::from_sign1(&data, &[], &[], BUCKET_TOKEN_AAD, 1000).is_err());
+
+        assert!(Token::decode_sign1(b"invalid").is_err());
+        assert_eq!(sign1_expires_in(b"invalid", 1000), None);
+
+        // the token bound to the caller
+        let caller = Principal::management_canister();
+        let token = Token {
+            caller: Some(caller),
+            ..token
+        };
+        let data = token
+            .clone()
+            .sign_secp256k1(&signing_key, None, 1000, 3600)
+            .unwrap();
+        let token2 = Token::decode_sign1(&data).unwrap();
+        assert_eq!(token2, token);
+        assert!(token2.verify_caller(&caller).is_ok());
+        assert!(token2.verify_caller(&Principal::anonymous()).is_err());
     }
 }
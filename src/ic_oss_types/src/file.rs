@@ -1,17 +1,42 @@
 use base64::{engine::general_purpose, Engine};
-use candid::CandidType;
+use candid::{CandidType, Principal};
+use ciborium::from_reader;
+use icrc_ledger_types::icrc::generic_metadata_value::MetadataValue;
 use serde::{Deserialize, Serialize};
 use serde_bytes::{ByteArray, ByteBuf};
-use std::path::Path;
+use std::{collections::BTreeMap, path::Path};
 use url::Url;
 
-use crate::{format_error, MapValue};
+use crate::{format_error, hash::IncrementalHasher, to_cbor_bytes, MapValue};
 
+// the default chunk size, a bucket can be installed with another one in
+// [MIN_CHUNK_SIZE, MAX_CHUNK_SIZE], see BucketInfo.chunk_size
 pub const CHUNK_SIZE: u32 = 256 * 1024;
+pub const MIN_CHUNK_SIZE: u32 = 1024;
+// a chunk should fit in an ingress message with the other arguments
+pub const MAX_CHUNK_SIZE: u32 = 1024 * 1024;
 pub const MAX_FILE_SIZE: u64 = 384 * 1024 * 1024 * 1024; // 384GB
 pub const MAX_FILE_SIZE_PER_CALL: u64 = 1024 * 2000; // should less than 2MB
 
+// the number of chunks of a file of the size, the sizes are u64 and usize is 32 bits
+// in canisters, so the size math of files larger than 4GiB should not go through usize
+pub fn file_chunks(size: u64, chunk_size: u32) -> u32 {
+    size.div_ceil(chunk_size as u64).min(u32::MAX as u64) as u32
+}
+
+pub const MAX_FILE_NAME_SIZE: usize = 96;
+pub const MAX_CONTENT_TYPE_SIZE: usize = 256;
+pub const MAX_TAG_NAME_SIZE: usize = 64;
+pub const MAX_SHARE_CODE_SIZE: usize = 64;
+pub const MAX_ANNOTATION_SIZE: usize = 1024;
+pub const MAX_IDEMPOTENCY_KEY_SIZE: usize = 64;
+// a retried create_file with the same idempotency key returns the first file in this window
+pub const IDEMPOTENCY_KEY_TTL: u64 = 3600; // in seconds
+
 pub static CUSTOM_KEY_BY_HASH: &str = "by_hash";
+// the Cache-Control header of the file's HTTP responses, e.g. "no-cache" for html pages
+pub static CUSTOM_KEY_CACHE_CONTROL: &str = "cache-control";
+pub const MAX_CACHE_CONTROL_SIZE: usize = 256;
 
 #[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
 pub struct FileInfo {
@@ -29,6 +54,7 @@ pub struct FileInfo {
     pub dek: Option<ByteBuf>, // // Data Encryption Key that encrypted by BYOK or vetKey in COSE_Encrypt0
     pub custom: Option<MapValue>, // custom metadata
     pub ex: Option<MapValue>, // External Resource info
+    pub hash_verified: Option<bool>, // the result of the server-side hash verification
 }
 
 #[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
@@ -42,10 +68,16 @@ pub struct CreateFileInput {
     pub hash: Option<ByteArray<32>>, // recommend sha3 256
     pub dek: Option<ByteBuf>,
     pub custom: Option<MapValue>,
+    // the key chosen by the client for the retries of the call, e.g. a random UUID
+    pub idempotency_key: Option<String>,
 }
 
 pub fn valid_file_name(name: &str) -> bool {
-    if name.is_empty() || name.trim() != name || name.len() > 96 {
+    if name.is_empty()
+        || name.trim() != name
+        || name.len() > MAX_FILE_NAME_SIZE
+        || name.chars().any(char::is_control)
+    {
         return false;
     }
 
@@ -53,6 +85,25 @@ pub fn valid_file_name(name: &str) -> bool {
     p.file_name() == Some(p.as_os_str())
 }
 
+// tag names are used in urls, e.g. "latest" or "v1.2.0"
+pub fn valid_tag_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_TAG_NAME_SIZE
+        && !name.starts_with('.')
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.' || b == b'_')
+}
+
+// the share code is the URL-safe base64 encoding of random bytes
+pub fn valid_share_code(code: &str) -> bool {
+    !code.is_empty()
+        && code.len() <= MAX_SHARE_CODE_SIZE
+        && code
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
 pub fn valid_file_parent(parent: &str) -> bool {
     if parent.is_empty() || parent == "/" {
         return true;
@@ -70,7 +121,118 @@ pub fn valid_file_parent(parent: &str) -> bool {
     true
 }
 
+// checks the media type syntax of RFC 6838 with optional parameters,
+// e.g. "text/plain; charset=utf-8"
+pub fn valid_content_type(content_type: &str) -> bool {
+    if content_type.len() > MAX_CONTENT_TYPE_SIZE {
+        return false;
+    }
+
+    let mut parts = content_type.split(';');
+    match parts.next().and_then(|essence| essence.split_once('/')) {
+        Some((ty, subtype)) if valid_token(ty) && valid_token(subtype) => {}
+        _ => return false,
+    }
+    parts.all(|param| match param.trim().split_once('=') {
+        Some((name, value)) => {
+            valid_token(name)
+                && (valid_token(value)
+                    || (value.len() >= 2 && value.starts_with('"') && value.ends_with('"')))
+        }
+        None => false,
+    })
+}
+
+fn valid_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+// returns the Cache-Control value in the custom metadata if it is a valid header value
+pub fn custom_cache_control(custom: Option<&MapValue>) -> Option<&str> {
+    match custom?.get(CUSTOM_KEY_CACHE_CONTROL)? {
+        MetadataValue::Text(v)
+            if !v.is_empty()
+                && v.len() <= MAX_CACHE_CONTROL_SIZE
+                && v.bytes().all(|b| b == b' ' || b.is_ascii_graphic()) =>
+        {
+            Some(v.as_str())
+        }
+        _ => None,
+    }
+}
+
+pub fn check_custom_data_size(
+    custom: Option<&MapValue>,
+    max_custom_data_size: u16,
+) -> Result<(), String> {
+    if let Some(custom) = custom {
+        if to_cbor_bytes(custom).len() > max_custom_data_size as usize {
+            return Err(format!(
+                "custom data size exceeds the limit {}",
+                max_custom_data_size
+            ));
+        }
+    }
+    Ok(())
+}
+
 impl CreateFileInput {
+    pub fn new(parent: u32, name: String, content_type: String) -> Self {
+        Self {
+            parent,
+            name,
+            content_type,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_size(mut self, size: u64) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    /// Sets the content of a small file, the size is set to the content length.
+    pub fn with_content(mut self, content: Vec<u8>) -> Self {
+        self.size = Some(content.len() as u64);
+        self.content = Some(ByteBuf::from(content));
+        self
+    }
+
+    pub fn with_status(mut self, status: i8) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn with_hash(mut self, hash: [u8; 32]) -> Self {
+        self.hash = Some(hash.into());
+        self
+    }
+
+    pub fn with_dek(mut self, dek: Vec<u8>) -> Self {
+        self.dek = Some(ByteBuf::from(dek));
+        self
+    }
+
+    pub fn with_custom(mut self, custom: MapValue) -> Self {
+        self.custom = Some(custom);
+        self
+    }
+
+    pub fn with_idempotency_key(mut self, key: String) -> Self {
+        self.idempotency_key = Some(key);
+        self
+    }
+
+    /// Validates the input against the limits of the bucket,
+    /// so an invalid request fails before it is sent.
+    pub fn build(self, max_custom_data_size: u16) -> Result<Self, String> {
+        self.validate()?;
+        check_custom_data_size(self.custom.as_ref(), max_custom_data_size)?;
+        Ok(self)
+    }
+
     pub fn validate(&self) -> Result<(), String> {
         if !valid_file_name(&self.name) {
             return Err("invalid file name".to_string());
@@ -79,11 +241,23 @@ impl CreateFileInput {
         if self.content_type.is_empty() {
             return Err("content_type cannot be empty".to_string());
         }
+        if !valid_content_type(&self.content_type) {
+            return Err("invalid content_type".to_string());
+        }
 
         if let Some(content) = &self.content {
             if content.is_empty() {
                 return Err("content cannot be empty".to_string());
             }
+            if content.len() as u64 > MAX_FILE_SIZE_PER_CALL {
+                return Err(format!(
+                    "content size exceeds the limit {}",
+                    MAX_FILE_SIZE_PER_CALL
+                ));
+            }
+            if self.size.is_some_and(|size| size != content.len() as u64) {
+                return Err("content size mismatch".to_string());
+            }
         }
 
         if let Some(status) = self.status {
@@ -91,6 +265,15 @@ impl CreateFileInput {
                 return Err("status should be 0 or 1".to_string());
             }
         }
+
+        if let Some(key) = &self.idempotency_key {
+            if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_SIZE {
+                return Err(format!(
+                    "idempotency_key size should be in [1, {}]",
+                    MAX_IDEMPOTENCY_KEY_SIZE
+                ));
+            }
+        }
         Ok(())
     }
 }
@@ -113,6 +296,51 @@ pub struct UpdateFileInput {
 }
 
 impl UpdateFileInput {
+    pub fn new(id: u32) -> Self {
+        Self {
+            id,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_name(mut self, name: String) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    pub fn with_content_type(mut self, content_type: String) -> Self {
+        self.content_type = Some(content_type);
+        self
+    }
+
+    pub fn with_status(mut self, status: i8) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn with_size(mut self, size: u64) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn with_hash(mut self, hash: [u8; 32]) -> Self {
+        self.hash = Some(hash.into());
+        self
+    }
+
+    pub fn with_custom(mut self, custom: MapValue) -> Self {
+        self.custom = Some(custom);
+        self
+    }
+
+    /// Validates the input against the limits of the bucket,
+    /// so an invalid request fails before it is sent.
+    pub fn build(self, max_custom_data_size: u16) -> Result<Self, String> {
+        self.validate()?;
+        check_custom_data_size(self.custom.as_ref(), max_custom_data_size)?;
+        Ok(self)
+    }
+
     pub fn validate(&self) -> Result<(), String> {
         if let Some(name) = &self.name {
             if !valid_file_name(name) {
@@ -123,6 +351,9 @@ impl UpdateFileInput {
             if content_type.is_empty() {
                 return Err("content_type cannot be empty".to_string());
             }
+            if !valid_content_type(content_type) {
+                return Err("invalid content_type".to_string());
+            }
         }
         if let Some(status) = self.status {
             if !(-1i8..=1i8).contains(&status) {
@@ -138,11 +369,76 @@ pub struct UpdateFileOutput {
     pub updated_at: u64,
 }
 
+// finalizes a fully uploaded file in one call: the hash is set and the file becomes
+// readonly, so no chunk can be written after the hash is taken.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct FinalizeFileInput {
+    pub id: u32,
+    pub hash: ByteArray<32>, // the SHA3-256 hash of the file content
+    // verifies the hash on the server, in the call for the files up to
+    // MAX_FILE_SIZE_PER_CALL bytes, or by a background job for the larger files
+    pub verify: bool,
+}
+
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct FinalizeFileOutput {
+    pub updated_at: u64,
+    // Some(true) if the hash is verified in the call, None if it is not verified
+    // or the verification job is pending, see FileInfo.hash_verified
+    pub hash_verified: Option<bool>,
+}
+
 #[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
 pub struct UpdateFileChunkInput {
     pub id: u32,
     pub chunk_index: u32,
     pub content: ByteBuf, // should be in (0, 1024 * 256]
+    // the chunk lease that covers the chunk, a leased chunk can only be written with it
+    pub lease: Option<u64>,
+}
+
+pub const DEFAULT_CHUNK_LEASE_TTL: u64 = 600; // in seconds
+pub const MAX_CHUNK_LEASE_TTL: u64 = 3600; // in seconds
+pub const MAX_FILL_STATUS_CHUNKS: u32 = 8192; // the chunks reported by a page
+
+// leases the chunks [start, end) of a file to the caller, so several uploaders can
+// fill different parts of a large file in parallel.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ChunkLeaseInput {
+    pub id: u32,
+    pub start: u32,
+    pub end: u32,
+    pub ttl_secs: Option<u64>, // None: DEFAULT_CHUNK_LEASE_TTL
+}
+
+impl ChunkLeaseInput {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.start >= self.end {
+            return Err(format!(
+                "invalid chunk range [{}, {})",
+                self.start, self.end
+            ));
+        }
+        if let Some(ttl) = self.ttl_secs {
+            if ttl == 0 || ttl > MAX_CHUNK_LEASE_TTL {
+                return Err(format!(
+                    "ttl_secs should be in (0, {}]",
+                    MAX_CHUNK_LEASE_TTL
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ChunkLeaseInfo {
+    pub lease: u64,
+    pub id: u32, // file id
+    pub holder: Principal,
+    pub start: u32,
+    pub end: u32,        // exclusive
+    pub expires_at: u64, // unix timestamp in milliseconds
 }
 
 #[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
@@ -151,9 +447,64 @@ pub struct UpdateFileChunkOutput {
     pub updated_at: u64,
 }
 
+// the chunks present in a file, for resumable and parallel uploaders to find what is missing.
+// a page covers the chunks in [start, next), next is None if the page reaches the last chunk.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FileFillStatus {
+    pub id: u32,
+    pub size: u64,
+    pub filled: u64,
+    pub chunks: u32, // the expected number of chunks, derived from the size if known
+    pub start: u32,
+    pub next: Option<u32>,
+    pub bitmap: ByteBuf, // bit (i % 8) of byte (i / 8) is set if chunk start + i is present
+}
+
+impl FileFillStatus {
+    pub fn has_chunk(&self, index: u32) -> bool {
+        index >= self.start
+            && self
+                .bitmap
+                .get((index - self.start) as usize / 8)
+                .is_some_and(|b| b & (1 << ((index - self.start) % 8)) != 0)
+    }
+
+    /// Returns the indexes of the missing chunks in the page.
+    pub fn missing(&self) -> Vec<u32> {
+        (self.start..self.next.unwrap_or(self.chunks))
+            .filter(|i| !self.has_chunk(*i))
+            .collect()
+    }
+
+    /// Appends the next page to the status.
+    pub fn extend(&mut self, page: &FileFillStatus) {
+        let end = page.next.unwrap_or(page.chunks).max(self.start);
+        let len = (end - self.start).div_ceil(8) as usize;
+        if self.bitmap.len() < len {
+            self.bitmap.resize(len, 0);
+        }
+        for i in page.start.max(self.start)..end {
+            if page.has_chunk(i) {
+                let i = i - self.start;
+                self.bitmap[i as usize / 8] |= 1 << (i % 8);
+            }
+        }
+        self.chunks = page.chunks;
+        self.next = page.next;
+    }
+}
+
 #[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
 pub struct FileChunk(pub u32, pub ByteBuf);
 
+// the event sent by ic_oss_can fs to the event sink canister with `on_file_event`
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum FileEvent {
+    Added(FileInfo),
+    Updated(FileInfo), // the file info or content is updated
+    Deleted(u32),
+}
+
 #[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
 pub struct MoveInput {
     pub id: u32,
@@ -161,11 +512,296 @@ pub struct MoveInput {
     pub to: u32,
 }
 
+#[derive(CandidType, Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum SortBy {
+    #[default]
+    Id,
+    Name,
+    Size, // only for files
+    UpdatedAt,
+}
+
+// the sort key of the last item of a page, the next page starts after it.
+// the item id is used as a tie-breaker, so the pagination is stable.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ListCursor {
+    Id(u32),
+    Name(String, u32),
+    Size(u64, u32),
+    UpdatedAt(u64, u32),
+}
+
+impl ListCursor {
+    pub fn new(sort_by: SortBy, id: u32, name: &str, size: u64, updated_at: u64) -> Self {
+        match sort_by {
+            SortBy::Id => ListCursor::Id(id),
+            SortBy::Name => ListCursor::Name(name.to_string(), id),
+            SortBy::Size => ListCursor::Size(size, id),
+            SortBy::UpdatedAt => ListCursor::UpdatedAt(updated_at, id),
+        }
+    }
+
+    pub fn sort_by(&self) -> SortBy {
+        match self {
+            ListCursor::Id(_) => SortBy::Id,
+            ListCursor::Name(_, _) => SortBy::Name,
+            ListCursor::Size(_, _) => SortBy::Size,
+            ListCursor::UpdatedAt(_, _) => SortBy::UpdatedAt,
+        }
+    }
+}
+
+// the opaque cursor token returned to clients, it binds the position to the parent folder
+// and the list options that produced it, so a cursor can not be replayed with another
+// parent, sorting or filters.
+#[derive(Deserialize, Serialize)]
+struct CursorToken {
+    #[serde(rename = "p")]
+    position: ListCursor,
+    #[serde(rename = "pa")]
+    parent: u32,
+    #[serde(rename = "f")]
+    filter: ByteArray<16>,
+}
+
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ListOptions {
+    pub sort_by: Option<SortBy>,             // default is Id
+    pub ascending: Option<bool>,             // default is false, the newest first
+    pub content_type_prefix: Option<String>, // only for files
+    pub status: Option<i8>,
+    pub name_contains: Option<String>,
+    pub cursor: Option<ByteBuf>, // the opaque next_cursor of the previous page
+    pub take: Option<u32>,       // default is 10, max is 100
+}
+
+impl ListOptions {
+    pub fn validate(&self, parent: u32) -> Result<(), String> {
+        self.decode_cursor(parent).map(|_| ())
+    }
+
+    pub fn matches(&self, name: &str, content_type: &str, status: i8) -> bool {
+        self.status.map_or(true, |s| s == status)
+            && self
+                .name_contains
+                .as_ref()
+                .map_or(true, |n| name.contains(n.as_str()))
+            && self
+                .content_type_prefix
+                .as_ref()
+                .map_or(true, |p| content_type.starts_with(p.as_str()))
+    }
+
+    /// Encodes the position in the parent folder as an opaque cursor token for these options.
+    pub fn encode_cursor(&self, parent: u32, position: ListCursor) -> ByteBuf {
+        ByteBuf::from(to_cbor_bytes(&CursorToken {
+            position,
+            parent,
+            filter: self.filter_hash(),
+        }))
+    }
+
+    /// Decodes the cursor token, it should be created for the same parent folder with the
+    /// same sorting and filters.
+    pub fn decode_cursor(&self, parent: u32) -> Result<Option<ListCursor>, String> {
+        let data = match self.cursor {
+            None => return Ok(None),
+            Some(ref data) => data,
+        };
+        let token: CursorToken =
+            from_reader(data.as_slice()).map_err(|_| "invalid cursor".to_string())?;
+        if token.parent != parent {
+            return Err("cursor does not match the parent".to_string());
+        }
+        if token.filter != self.filter_hash()
+            || token.position.sort_by() != self.sort_by.unwrap_or_default()
+        {
+            return Err("cursor does not match the list options".to_string());
+        }
+        Ok(Some(token.position))
+    }
+
+    /// Sorts the items with their sort keys and returns the page after the cursor,
+    /// with the cursor token for the next page if there are more items.
+    /// An invalid cursor is treated as no cursor, call `validate` first.
+    pub fn paginate<T>(
+        &self,
+        parent: u32,
+        mut items: Vec<(ListCursor, T)>,
+    ) -> (Vec<T>, Option<ByteBuf>) {
+        let ascending = self.ascending.unwrap_or(false);
+        let take = self.take.unwrap_or(10).min(100) as usize;
+        if ascending {
+            items.sort_by(|a, b| a.0.cmp(&b.0));
+        } else {
+            items.sort_by(|a, b| b.0.cmp(&a.0));
+        }
+
+        let start = match self.decode_cursor(parent).ok().flatten() {
+            None => 0,
+            Some(ref cursor) => items.partition_point(|(key, _)| {
+                if ascending {
+                    key <= cursor
+                } else {
+                    key >= cursor
+                }
+            }),
+        };
+        let has_more = items.len() > start + take;
+        let page: Vec<(ListCursor, T)> = items.into_iter().skip(start).take(take).collect();
+        let next_cursor = if has_more {
+            page.last()
+                .map(|(key, _)| self.encode_cursor(parent, key.clone()))
+        } else {
+            None
+        };
+        (page.into_iter().map(|(_, v)| v).collect(), next_cursor)
+    }
+
+    fn filter_hash(&self) -> ByteArray<16> {
+        let mut hasher = IncrementalHasher::new();
+        hasher.update(&to_cbor_bytes(&(
+            self.sort_by.unwrap_or_default(),
+            self.ascending.unwrap_or(false),
+            &self.content_type_prefix,
+            self.status,
+            &self.name_contains,
+        )));
+        let hash = hasher.finalize();
+        let mut filter = [0u8; 16];
+        filter.copy_from_slice(&hash[..16]);
+        filter.into()
+    }
+}
+
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ListFilesOutput {
+    pub files: Vec<FileInfo>,
+    pub next_cursor: Option<ByteBuf>,
+}
+
+// a share link that grants the download of a file without an access token
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CreateShareInput {
+    pub file: u32,
+    pub expires_at: Option<u64>, // unix timestamp in milliseconds, None: never expires
+    pub max_downloads: Option<u32>, // None: unlimited
+    // the argon2 PHC string of the password to download the file, e.g. "$argon2id$v=19$..."
+    pub password_hash: Option<String>,
+}
+
+impl CreateShareInput {
+    pub fn validate(&self, now_ms: u64) -> Result<(), String> {
+        if let Some(expires_at) = self.expires_at {
+            if expires_at <= now_ms {
+                return Err("expires_at should be in the future".to_string());
+            }
+        }
+        if self.max_downloads == Some(0) {
+            return Err("max_downloads should be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ShareInfo {
+    pub code: String, // the share link is "/s/<code>"
+    pub file: u32,
+    pub created_by: Principal,
+    pub created_at: u64,         // unix timestamp in milliseconds
+    pub expires_at: Option<u64>, // unix timestamp in milliseconds
+    pub max_downloads: Option<u32>,
+    pub downloads: u32,
+    pub password_protected: bool,
+}
+
+// a comment on a file, e.g. in the review of uploaded artifacts.
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Annotation {
+    pub id: u32, // increasing within the file, starts from 1
+    pub file: u32,
+    pub author: Principal,
+    pub text: String,
+    pub created_at: u64, // unix timestamp in milliseconds
+}
+
+// the manifest of a static site release that is deployed to a bucket, it is stored
+// as a CBOR file and a tag points to the manifest of the current release. The bucket
+// serves the site from the manifest that its `site_tag` points to.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SiteManifest {
+    pub release: String,
+    pub folder: u32,   // the folder that holds the files uploaded by the release
+    pub index: String, // the page of the directories, e.g. "index.html"
+    pub not_found: Option<String>, // the page of the missing paths, e.g. "404.html"
+    pub files: BTreeMap<String, SiteAsset>, // relative path -> asset
+}
+
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SiteAsset {
+    pub id: u32,
+    pub size: u64,
+    pub hash: ByteArray<32>,
+    pub content_type: String,
+    pub cache_control: String,
+}
+
+impl SiteManifest {
+    pub fn from_cbor(data: &[u8]) -> Result<Self, String> {
+        from_reader(data).map_err(format_error)
+    }
+
+    pub fn to_cbor(&self) -> Vec<u8> {
+        to_cbor_bytes(self)
+    }
+
+    // resolves the request url to an asset with the HTTP status, directories are served
+    // by the index page and the missing paths by the not-found page with status 404.
+    pub fn resolve(&self, url: &str) -> Option<(&SiteAsset, u16)> {
+        let path = url.split(['?', '#']).next().unwrap_or_default();
+        let path = percent_decode(path.trim_start_matches('/')).unwrap_or_default();
+        let index = if path.is_empty() || path.ends_with('/') {
+            format!("{}{}", path, self.index)
+        } else {
+            format!("{}/{}", path, self.index)
+        };
+        self.files
+            .get(&path)
+            .or_else(|| self.files.get(&index))
+            .map(|asset| (asset, 200))
+            .or_else(|| {
+                let asset = self.files.get(self.not_found.as_ref()?)?;
+                Some((asset, 404))
+            })
+    }
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut buf = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            buf.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            buf.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(buf).ok()
+}
+
 #[derive(Debug)]
 pub struct UrlFileParam {
     pub file: u32,
     pub hash: Option<ByteArray<32>>,
+    pub tag: Option<String>,
+    pub share: Option<String>,
     pub token: Option<ByteBuf>,
+    pub password: Option<String>,
     pub name: Option<String>,
     pub inline: bool,
 }
@@ -190,7 +826,10 @@ impl UrlFileParam {
                     .parse()
                     .map_err(|_| "invalid file id")?,
                 hash: None,
+                tag: None,
+                share: None,
                 token: None,
+                password: None,
                 name: None,
                 inline: false,
             },
@@ -202,7 +841,42 @@ impl UrlFileParam {
                 Self {
                     file: 0,
                     hash: Some(hash),
+                    tag: None,
+                    share: None,
+                    token: None,
+                    password: None,
+                    name: None,
+                    inline: false,
+                }
+            }
+            Some("t") => {
+                let tag = path_segments.next().unwrap_or_default();
+                if !valid_tag_name(tag) {
+                    return Err(format!("invalid tag name: {}", tag));
+                }
+                Self {
+                    file: 0,
+                    hash: None,
+                    tag: Some(tag.to_string()),
+                    share: None,
+                    token: None,
+                    password: None,
+                    name: None,
+                    inline: false,
+                }
+            }
+            Some("s") => {
+                let code = path_segments.next().unwrap_or_default();
+                if !valid_share_code(code) {
+                    return Err(format!("invalid share code: {}", code));
+                }
+                Self {
+                    file: 0,
+                    hash: None,
+                    tag: None,
+                    share: Some(code.to_string()),
                     token: None,
+                    password: None,
                     name: None,
                     inline: false,
                 }
@@ -219,6 +893,9 @@ impl UrlFileParam {
                     param.token = Some(ByteBuf::from(data));
                     break;
                 }
+                "pwd" => {
+                    param.password = Some(value.to_string());
+                }
                 "filename" => {
                     param.name = Some(value.to_string());
                 }
@@ -242,6 +919,62 @@ impl UrlFileParam {
 mod tests {
     use super::*;
 
+    #[test]
+    fn list_options_paginate() {
+        let items: Vec<(ListCursor, u32)> = [(1, "b"), (2, "a"), (3, "c"), (4, "a")]
+            .into_iter()
+            .map(|(id, name)| (ListCursor::new(SortBy::Name, id, name, 0, 0), id))
+            .collect();
+
+        let mut options = ListOptions {
+            sort_by: Some(SortBy::Name),
+            ascending: Some(true),
+            take: Some(2),
+            ..Default::default()
+        };
+        let (page, cursor) = options.paginate(1, items.clone());
+        assert_eq!(page, vec![2, 4]);
+        options.cursor = cursor;
+        assert_eq!(
+            options.decode_cursor(1).unwrap(),
+            Some(ListCursor::Name("a".to_string(), 4))
+        );
+        let (page, cursor) = options.paginate(1, items.clone());
+        assert_eq!(page, vec![1, 3]);
+        assert_eq!(cursor, None);
+
+        options.cursor = None;
+        options.ascending = None;
+        let (page, cursor) = options.paginate(1, items.clone());
+        assert_eq!(page, vec![3, 1]);
+        options.cursor = cursor;
+        let (page, cursor) = options.paginate(1, items);
+        assert_eq!(page, vec![4, 2]);
+        assert_eq!(cursor, None);
+
+        assert!(options.validate(1).is_ok());
+        assert_eq!(
+            options.validate(2).unwrap_err(),
+            "cursor does not match the parent"
+        );
+        options.name_contains = Some("a".to_string());
+        assert!(options.validate(1).is_err());
+        options.name_contains = None;
+        options.cursor = Some(options.encode_cursor(1, ListCursor::Id(1)));
+        assert!(options.validate(1).is_err());
+        options.cursor = Some(ByteBuf::from(vec![1, 2, 3]));
+        assert!(options.validate(1).is_err());
+
+        let options = ListOptions {
+            content_type_prefix: Some("image/".to_string()),
+            name_contains: Some("cat".to_string()),
+            ..Default::default()
+        };
+        assert!(options.matches("my cat.png", "image/png", 0));
+        assert!(!options.matches("my dog.png", "image/png", 0));
+        assert!(!options.matches("my cat.txt", "text/plain", 0));
+    }
+
     #[test]
     fn valid_file_name_works() {
         assert!(valid_file_name("file"));
@@ -258,6 +991,169 @@ mod tests {
         assert!(!valid_file_name("./file.txt"));
         assert!(!valid_file_name("test/file.txt"));
         assert!(!valid_file_name("file.txt/"));
+        assert!(!valid_file_name("file\n.txt"));
+        assert!(!valid_file_name(&"a".repeat(MAX_FILE_NAME_SIZE + 1)));
+    }
+
+    #[test]
+    fn valid_content_type_works() {
+        assert!(valid_content_type("text/plain"));
+        assert!(valid_content_type("application/vnd.api+json"));
+        assert!(valid_content_type("text/plain; charset=utf-8"));
+        assert!(valid_content_type("text/plain;charset=\"utf-8\""));
+
+        assert!(!valid_content_type(""));
+        assert!(!valid_content_type("text"));
+        assert!(!valid_content_type("text/"));
+        assert!(!valid_content_type("/plain"));
+        assert!(!valid_content_type("text/plain/html"));
+        assert!(!valid_content_type("text /plain"));
+        assert!(!valid_content_type("text/plain; charset"));
+        assert!(!valid_content_type("text/plain; charset=\""));
+    }
+
+    #[test]
+    fn create_file_input_build() {
+        let input = CreateFileInput::new(0, "hello.txt".to_string(), "text/plain".to_string())
+            .with_content(b"hello".to_vec())
+            .with_status(1)
+            .build(1024)
+            .unwrap();
+        assert_eq!(input.size, Some(5));
+
+        assert_eq!(
+            CreateFileInput::new(0, "hello.txt".to_string(), "text".to_string())
+                .build(1024)
+                .unwrap_err(),
+            "invalid content_type"
+        );
+        assert_eq!(
+            CreateFileInput::new(0, "hello.txt".to_string(), "text/plain".to_string())
+                .with_content(b"hello".to_vec())
+                .with_size(10)
+                .validate()
+                .unwrap_err(),
+            "content size mismatch"
+        );
+
+        let custom = MapValue::from([("k".to_string(), MetadataValue::Text("v".repeat(100)))]);
+        let input = UpdateFileInput::new(1).with_custom(custom);
+        assert!(input.clone().build(1024).is_ok());
+        assert_eq!(
+            input.build(64).unwrap_err(),
+            "custom data size exceeds the limit 64"
+        );
+    }
+
+    #[test]
+    fn custom_cache_control_works() {
+        assert_eq!(custom_cache_control(None), None);
+        let custom = MapValue::from([(
+            CUSTOM_KEY_CACHE_CONTROL.to_string(),
+            MetadataValue::Text("max-age=31536000, immutable".to_string()),
+        )]);
+        assert_eq!(
+            custom_cache_control(Some(&custom)),
+            Some("max-age=31536000, immutable")
+        );
+        for v in [
+            MetadataValue::Text("".to_string()),
+            MetadataValue::Text("no-cache\r\nx-evil: 1".to_string()),
+            MetadataValue::Text("a".repeat(MAX_CACHE_CONTROL_SIZE + 1)),
+            MetadataValue::Nat(1u64.into()),
+        ] {
+            let custom = MapValue::from([(CUSTOM_KEY_CACHE_CONTROL.to_string(), v)]);
+            assert_eq!(custom_cache_control(Some(&custom)), None);
+        }
+    }
+
+    #[test]
+    fn site_manifest_resolve() {
+        let asset = |id| SiteAsset {
+            id,
+            ..Default::default()
+        };
+        let manifest = SiteManifest {
+            release: "r1".to_string(),
+            folder: 1,
+            index: "index.html".to_string(),
+            not_found: Some("404.html".to_string()),
+            files: BTreeMap::from([
+                ("index.html".to_string(), asset(2)),
+                ("404.html".to_string(), asset(3)),
+                ("docs/index.html".to_string(), asset(4)),
+                ("app.js".to_string(), asset(5)),
+            ]),
+        };
+        let resolve = |url: &str| manifest.resolve(url).map(|(a, status)| (a.id, status));
+        assert_eq!(resolve("/"), Some((2, 200)));
+        assert_eq!(resolve("/app.js?v=1"), Some((5, 200)));
+        assert_eq!(resolve("/docs"), Some((4, 200)));
+        assert_eq!(resolve("/docs/#intro"), Some((4, 200)));
+        assert_eq!(resolve("/d%6Fcs/index.html"), Some((4, 200)));
+        assert_eq!(resolve("/missing"), Some((3, 404)));
+        assert_eq!(resolve("/%ff%"), Some((3, 404)));
+        assert_eq!(
+            SiteManifest::from_cbor(&manifest.to_cbor()).unwrap(),
+            manifest
+        );
+
+        let manifest = SiteManifest {
+            not_found: None,
+            ..manifest
+        };
+        assert!(manifest.resolve("/missing").is_none());
+    }
+
+    #[test]
+    fn url_file_param_with_tag() {
+        let param = UrlFileParam::from_url("/t/latest?inline").unwrap();
+        assert_eq!(param.tag, Some("latest".to_string()));
+        assert!(param.inline);
+        let param = UrlFileParam::from_url("/t/v1.2.0/app.tar.gz").unwrap();
+        assert_eq!(param.tag, Some("v1.2.0".to_string()));
+        assert_eq!(param.name, Some("app.tar.gz".to_string()));
+
+        assert!(UrlFileParam::from_url("/t/").is_err());
+        assert!(UrlFileParam::from_url("/t/.hidden").is_err());
+        assert!(UrlFileParam::from_url("/t/a%20b").is_err());
+    }
+
+    #[test]
+    fn url_file_param_with_share() {
+        let param = UrlFileParam::from_url("/s/AbC-d_9?pwd=secret").unwrap();
+        assert_eq!(param.share, Some("AbC-d_9".to_string()));
+        assert_eq!(param.password, Some("secret".to_string()));
+        assert_eq!(param.file, 0);
+
+        assert!(UrlFileParam::from_url("/s/").is_err());
+        assert!(UrlFileParam::from_url("/s/a.b").is_err());
+        assert!(UrlFileParam::from_url(&format!("/s/{}", "a".repeat(65))).is_err());
+    }
+
+    #[test]
+    fn url_file_param_with_password() {
+        let param = UrlFileParam::from_url("/f/1?pwd=p%40ss%20word&inline").unwrap();
+        assert_eq!(param.file, 1);
+        assert_eq!(param.password, Some("p@ss word".to_string()));
+        assert!(param.inline);
+        let param = UrlFileParam::from_url("/f/1").unwrap();
+        assert_eq!(param.password, None);
+    }
+
+    #[test]
+    fn file_chunks_works() {
+        assert_eq!(file_chunks(0, CHUNK_SIZE), 0);
+        assert_eq!(file_chunks(1, CHUNK_SIZE), 1);
+        assert_eq!(file_chunks(CHUNK_SIZE as u64, CHUNK_SIZE), 1);
+        assert_eq!(file_chunks(CHUNK_SIZE as u64 + 1, CHUNK_SIZE), 2);
+        // 4GiB and above
+        assert_eq!(file_chunks(1 << 32, CHUNK_SIZE), 16384);
+        assert_eq!(file_chunks((1 << 32) + 1, CHUNK_SIZE), 16385);
+        assert_eq!(file_chunks(MAX_FILE_SIZE, CHUNK_SIZE), 1572864);
+        assert_eq!(file_chunks(MAX_FILE_SIZE, MIN_CHUNK_SIZE), 402653184);
+        assert_eq!(file_chunks(MAX_FILE_SIZE, MAX_CHUNK_SIZE), 393216);
+        assert_eq!(file_chunks(u64::MAX, MIN_CHUNK_SIZE), u32::MAX);
     }
 
     #[test]
@@ -1,8 +1,9 @@
 use candid::CandidType;
 use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
 use std::collections::BTreeSet;
 
-use crate::file::valid_file_name;
+use crate::file::{valid_file_name, FileInfo};
 
 #[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
 pub struct FolderInfo {
@@ -12,8 +13,21 @@ pub struct FolderInfo {
     pub created_at: u64,        // unix timestamp in milliseconds
     pub updated_at: u64,        // unix timestamp in milliseconds
     pub status: i8,             // -1: archived; 0: readable and writable; 1: readonly
-    pub files: BTreeSet<u32>,   // length <= max_children
-    pub folders: BTreeSet<u32>, // length <= max_children
+    pub files: BTreeSet<u32>,   // the first MAX_FOLDER_INFO_CHILDREN files
+    pub folders: BTreeSet<u32>, // the first MAX_FOLDER_INFO_CHILDREN folders
+}
+
+// a file or subfolder of a folder
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum FolderChild {
+    File(FileInfo),
+    Folder(FolderInfo),
+}
+
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ListFoldersOutput {
+    pub folders: Vec<FolderInfo>,
+    pub next_cursor: Option<ByteBuf>,
 }
 
 #[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
@@ -0,0 +1,145 @@
+use ciborium::from_reader;
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+
+use crate::to_cbor_bytes;
+
+const SHA3_256_RATE: usize = 136;
+
+/// An incremental SHA3-256 hasher with CBOR-serializable state.
+///
+/// It can be used by canisters to hash very large files across multiple update calls,
+/// the state should be saved with `to_bytes` at the end of a call and restored with
+/// `from_bytes` in the next call.
+///
+/// # Examples
+/// ```
+/// use ic_oss_types::hash::IncrementalHasher;
+///
+/// let mut hasher = IncrementalHasher::new();
+/// hasher.update(b"hello ");
+/// let state = hasher.to_bytes();
+///
+/// let mut hasher = IncrementalHasher::from_bytes(&state).unwrap();
+/// hasher.update(b"world");
+/// let hash: [u8; 32] = hasher.finalize();
+/// ```
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct IncrementalHasher {
+    #[serde(rename = "s")]
+    state: [u64; 25],
+    #[serde(rename = "b")]
+    buf: ByteBuf, // pending bytes, always smaller than the rate
+    #[serde(rename = "l")]
+    len: u64, // total bytes hashed
+}
+
+impl Default for IncrementalHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IncrementalHasher {
+    pub fn new() -> Self {
+        Self {
+            state: [0u64; 25],
+            buf: ByteBuf::with_capacity(SHA3_256_RATE),
+            len: 0,
+        }
+    }
+
+    /// Returns the total number of bytes hashed.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        if !self.buf.is_empty() {
+            let n = (SHA3_256_RATE - self.buf.len()).min(data.len());
+            self.buf.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.buf.len() < SHA3_256_RATE {
+                return;
+            }
+
+            let block = std::mem::take(&mut self.buf);
+            self.absorb(&block);
+        }
+
+        let mut blocks = data.chunks_exact(SHA3_256_RATE);
+        for block in blocks.by_ref() {
+            self.absorb(block);
+        }
+        self.buf.extend_from_slice(blocks.remainder());
+    }
+
+    /// Finishes the hashing and returns the SHA3-256 digest.
+    pub fn finalize(mut self) -> [u8; 32] {
+        let mut block = [0u8; SHA3_256_RATE];
+        block[..self.buf.len()].copy_from_slice(&self.buf);
+        block[self.buf.len()] ^= 0x06;
+        block[SHA3_256_RATE - 1] ^= 0x80;
+        self.absorb(&block);
+
+        let mut hash = [0u8; 32];
+        for (i, lane) in self.state[..4].iter().enumerate() {
+            hash[i * 8..(i + 1) * 8].copy_from_slice(&lane.to_le_bytes());
+        }
+        hash
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        to_cbor_bytes(self)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let hasher: Self = from_reader(data).map_err(|err| err.to_string())?;
+        if hasher.buf.len() >= SHA3_256_RATE {
+            return Err("invalid hasher state".to_string());
+        }
+        Ok(hasher)
+    }
+
+    fn absorb(&mut self, block: &[u8]) {
+        for (lane, chunk) in self.state.iter_mut().zip(block.chunks_exact(8)) {
+            *lane ^= u64::from_le_bytes(chunk.try_into().unwrap());
+        }
+        keccak::f1600(&mut self.state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha3::{Digest, Sha3_256};
+
+    #[test]
+    fn incremental_hasher_works() {
+        let hasher = IncrementalHasher::new();
+        assert!(hasher.is_empty());
+        assert_eq!(hasher.finalize(), <[u8; 32]>::from(Sha3_256::digest(b"")));
+
+        let data: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+        for size in [1, 7, 135, 136, 137, 1000, 10000] {
+            let mut hasher = IncrementalHasher::new();
+            for chunk in data.chunks(size) {
+                let state = hasher.to_bytes();
+                hasher = IncrementalHasher::from_bytes(&state).unwrap();
+                hasher.update(chunk);
+            }
+            assert_eq!(hasher.len(), data.len() as u64);
+            assert_eq!(
+                hasher.finalize(),
+                <[u8; 32]>::from(Sha3_256::digest(&data)),
+                "chunk size {}",
+                size
+            );
+        }
+    }
+}
@@ -12,7 +12,11 @@ pub mod cluster;
 pub mod cose;
 pub mod file;
 pub mod folder;
+pub mod hash;
+pub mod nft;
 pub mod permission;
+pub mod s3;
+pub mod stream;
 
 // should update to ICRC3Map
 pub type MapValue =
@@ -41,3 +45,80 @@ pub fn to_cbor_bytes(obj: &impl Serialize) -> Vec<u8> {
     into_writer(obj, &mut buf).expect("failed to encode in CBOR format");
     buf
 }
+
+// the semantic versions of the canister interfaces, the major version is increased on
+// breaking changes, and the minor version on additions.
+pub const BUCKET_API_VERSION: &str = "1.2.0";
+// 2.0: find_file_by_hash is paginated, with token introspection, bucket lookup, templates
+// and tenants
+pub const CLUSTER_API_VERSION: &str = "2.0.0";
+
+// the major version of the interface, returned by the `api_version` queries
+pub fn api_major_version(version: &str) -> u16 {
+    parse_version(version).map_or(0, |v| v.0 as u16)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApiCompatibility {
+    Compatible,
+    Newer, // the canister has APIs unknown to the client
+    Older, // the canister lacks some APIs of the client
+}
+
+// checks the interface version of a canister against the version supported by a client,
+// the major versions should be the same.
+pub fn check_api_version(supported: &str, remote: &str) -> Result<ApiCompatibility, String> {
+    let supported = parse_version(supported)?;
+    let remote = parse_version(remote)?;
+    if supported.0 != remote.0 {
+        return Err(format!(
+            "incompatible API version {}.{}.{}, expected {}.x",
+            remote.0, remote.1, remote.2, supported.0
+        ));
+    }
+    Ok(match remote.1.cmp(&supported.1) {
+        std::cmp::Ordering::Equal => ApiCompatibility::Compatible,
+        std::cmp::Ordering::Greater => ApiCompatibility::Newer,
+        std::cmp::Ordering::Less => ApiCompatibility::Older,
+    })
+}
+
+fn parse_version(version: &str) -> Result<(u32, u32, u32), String> {
+    let parts = version
+        .split('.')
+        .map(|v| v.parse::<u32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("invalid API version: {}", version))?;
+    match parts[..] {
+        [major, minor, patch] => Ok((major, minor, patch)),
+        _ => Err(format!("invalid API version: {}", version)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_api_version_works() {
+        assert_eq!(
+            check_api_version("1.1.0", "1.1.3"),
+            Ok(ApiCompatibility::Compatible)
+        );
+        assert_eq!(
+            check_api_version("1.1.0", "1.2.0"),
+            Ok(ApiCompatibility::Newer)
+        );
+        assert_eq!(
+            check_api_version("1.1.0", "1.0.0"),
+            Ok(ApiCompatibility::Older)
+        );
+        assert!(check_api_version("1.1.0", "2.0.0").is_err());
+        assert!(check_api_version("1.1.0", "1.1").is_err());
+        assert!(check_api_version("1.1.0", "v1.1.0").is_err());
+
+        assert_eq!(api_major_version(BUCKET_API_VERSION), 1);
+        assert_eq!(api_major_version(CLUSTER_API_VERSION), 2);
+        assert_eq!(api_major_version("v1"), 0);
+    }
+}
@@ -0,0 +1,142 @@
+use candid::{CandidType, Nat};
+use num_traits::cast::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+
+use crate::bucket::FileProof;
+
+pub use icrc_ledger_types::{
+    icrc::generic_metadata_value::MetadataValue,
+    icrc1::account::{Account, Subaccount},
+};
+
+// the limits of the ICRC-7 facade, see https://github.com/dfinity/ICRC/blob/main/ICRCs/ICRC-7/ICRC-7.md
+pub const MAX_QUERY_BATCH_SIZE: usize = 100;
+pub const MAX_UPDATE_BATCH_SIZE: usize = 20;
+pub const DEFAULT_TAKE_VALUE: u32 = 100;
+pub const MAX_TAKE_VALUE: u32 = 1000;
+pub const MAX_MEMO_SIZE: usize = 32;
+pub const PERMITTED_DRIFT: u64 = 120; // in seconds
+
+pub const MAX_COLLECTION_NAME_SIZE: usize = 64;
+pub const MAX_COLLECTION_SYMBOL_SIZE: usize = 16;
+pub const MAX_COLLECTION_DESCRIPTION_SIZE: usize = 1024;
+pub const MAX_COLLECTION_LOGO_SIZE: usize = 4096;
+
+// the collection of the files exposed as ICRC-7 tokens, the token id is the file id.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct NftCollection {
+    pub name: String,
+    pub symbol: String,
+    pub description: Option<String>,
+    pub logo: Option<String>,    // the url or data url of the logo
+    pub supply_cap: Option<u64>, // the maximum number of tokens
+}
+
+impl NftCollection {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() || self.name.len() > MAX_COLLECTION_NAME_SIZE {
+            return Err(format!(
+                "invalid collection name, the size should be between 1 and {}",
+                MAX_COLLECTION_NAME_SIZE
+            ));
+        }
+        if self.symbol.trim().is_empty() || self.symbol.len() > MAX_COLLECTION_SYMBOL_SIZE {
+            return Err(format!(
+                "invalid collection symbol, the size should be between 1 and {}",
+                MAX_COLLECTION_SYMBOL_SIZE
+            ));
+        }
+        if let Some(description) = &self.description {
+            if description.len() > MAX_COLLECTION_DESCRIPTION_SIZE {
+                return Err(format!(
+                    "description size exceeds the limit {}",
+                    MAX_COLLECTION_DESCRIPTION_SIZE
+                ));
+            }
+        }
+        if let Some(logo) = &self.logo {
+            if logo.len() > MAX_COLLECTION_LOGO_SIZE {
+                return Err(format!(
+                    "logo size exceeds the limit {}",
+                    MAX_COLLECTION_LOGO_SIZE
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SupportedStandard {
+    pub name: String,
+    pub url: String,
+}
+
+// the certified asset of a token for marketplace verification flows, the file hash in
+// the proof is included in the certified state digest of the bucket, so the content
+// served at the url can be verified.
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct NftAsset {
+    pub token_id: Nat,
+    pub url: String,
+    pub content_type: String,
+    pub proof: FileProof,
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Subaccount>,
+    pub to: Account,
+    pub token_id: Nat,
+    pub memo: Option<ByteBuf>,
+    pub created_at_time: Option<u64>, // unix timestamp in nanoseconds
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum TransferError {
+    NonExistingTokenId,
+    InvalidRecipient,
+    Unauthorized,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+pub type TransferResult = Result<Nat, TransferError>;
+
+// returns the file id of the token id, None if it is out of range.
+pub fn token_to_file(token_id: &Nat) -> Option<u32> {
+    token_id.0.to_u32()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nft_collection_validate() {
+        let mut collection = NftCollection {
+            name: "LDC Labs".to_string(),
+            symbol: "LDC".to_string(),
+            ..Default::default()
+        };
+        assert!(collection.validate().is_ok());
+        collection.symbol = "".to_string();
+        assert!(collection.validate().is_err());
+        collection.symbol = "L".repeat(MAX_COLLECTION_SYMBOL_SIZE + 1);
+        assert!(collection.validate().is_err());
+        collection.symbol = "LDC".to_string();
+        collection.logo = Some("x".repeat(MAX_COLLECTION_LOGO_SIZE + 1));
+        assert!(collection.validate().is_err());
+    }
+
+    #[test]
+    fn token_to_file_works() {
+        assert_eq!(token_to_file(&Nat::from(1u32)), Some(1));
+        assert_eq!(token_to_file(&Nat::from(u32::MAX)), Some(u32::MAX));
+        assert_eq!(token_to_file(&Nat::from(u32::MAX as u64 + 1)), None);
+    }
+}
@@ -221,6 +221,29 @@ impl Permission {
             Some(ref c) => value.as_ref().map_or(false, |v| c == v),
         }
     }
+
+    /// Checks if a given `Permission` overlaps with the current one, it is used to match
+    /// the deny rules, so a deny rule on "File.Write" also denies a request for "File.*".
+    ///
+    /// # Arguments
+    /// * `value` - A reference to another `Permission` to compare with.
+    ///
+    /// # Returns
+    /// * `true` if some operations are granted by both permissions.
+    /// * `false` otherwise.
+    ///
+    pub fn overlaps(&self, value: &Permission) -> bool {
+        (self.resource == Resource::All
+            || value.resource == Resource::All
+            || self.resource == value.resource)
+            && (self.operation == Operation::All
+                || value.operation == Operation::All
+                || self.operation == value.operation)
+            && match (&self.constraint, &value.constraint) {
+                (None, _) | (_, None) | (Some(Resource::All), _) | (_, Some(Resource::All)) => true,
+                (Some(a), Some(b)) => a == b,
+            }
+    }
 }
 
 impl fmt::Display for Permission {
@@ -296,6 +319,8 @@ impl TryFrom<&str> for Permission {
 pub type ResourcePath = String;
 
 /// Represents a collection of resource paths.
+/// A path prefixed with "!" is a deny rule, e.g. "!42" denies the resource 42 and "!*"
+/// denies all resources. The deny rules do not grant anything.
 #[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Resources(pub BTreeSet<ResourcePath>);
 
@@ -325,6 +350,30 @@ impl Resources {
     {
         self.is_all() || self.0.contains(value.as_ref())
     }
+
+    /// Checks if the collection contains deny rules.
+    pub fn has_deny(&self) -> bool {
+        self.0.iter().any(|r| r.starts_with('!'))
+    }
+
+    /// Checks if a given resource path is denied by the collection.
+    ///
+    /// # Arguments
+    /// * `value` - The resource path to check.
+    ///
+    /// # Returns
+    /// * `true` if the collection denies the resource path or all resources.
+    /// * `false` otherwise.
+    ///
+    pub fn denies<T>(&self, value: T) -> bool
+    where
+        T: AsRef<str>,
+    {
+        self.0
+            .iter()
+            .filter_map(|r| r.strip_prefix('!'))
+            .any(|r| r == "*" || r == value.as_ref())
+    }
 }
 
 impl Deref for Resources {
@@ -342,20 +391,20 @@ impl AsRef<BTreeSet<ResourcePath>> for Resources {
 }
 
 impl fmt::Display for Resources {
-    /// Formats the `Resources` struct into a comma-separated string.
+    /// Formats the `Resources` struct into a comma-separated string, the allowed
+    /// resources are followed by the denied ones.
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self.0.first() {
-            None => Ok(()),
-            Some(v) => {
-                if !self.is_all() {
-                    write!(f, "{}", v)?;
-                    for r in self.0.iter().skip(1) {
-                        write!(f, ",{}", r)?;
-                    }
-                }
-                Ok(())
+        let (denied, allowed): (Vec<_>, Vec<_>) = self.0.iter().partition(|r| r.starts_with('!'));
+        let mut parts: Vec<&str> = Vec::with_capacity(self.0.len());
+        if self.is_all() {
+            if !denied.is_empty() {
+                parts.push("*");
             }
+        } else {
+            parts.extend(allowed.iter().map(|r| r.as_str()));
         }
+        parts.extend(denied.iter().map(|r| r.as_str()));
+        write!(f, "{}", parts.join(","))
     }
 }
 
@@ -369,6 +418,7 @@ impl TryFrom<&str> for Resources {
     type Error = String;
 
     /// Attempts to create `Resources` from a comma-separated string slice.
+    /// A resource can be "*" for all resources, and can be prefixed with "!" to deny it.
     ///
     /// # Arguments
     /// * `value` - The string slice to parse into `Resources`.
@@ -383,7 +433,10 @@ impl TryFrom<&str> for Resources {
             _ => {
                 let rs: BTreeSet<_> = value.split(',').map(|v| v.to_string()).collect();
                 for r in rs.iter() {
-                    validate_name(r)?;
+                    match r.strip_prefix('!').unwrap_or(r) {
+                        "*" => {}
+                        name => validate_name(name)?,
+                    }
                 }
                 Ok(Resources(rs))
             }
@@ -436,7 +489,9 @@ where
     T: AsRef<str>,
 {
     fn has_permission(&self, permission: &Permission, resource_path: T) -> bool {
-        self.permission.check(permission) && self.resources.check(resource_path.as_ref())
+        self.permission.check(permission)
+            && self.resources.check(resource_path.as_ref())
+            && !self.denies(permission, resource_path.as_ref())
     }
 }
 
@@ -447,13 +502,35 @@ where
     fn has_permission_any(&self, permission: &Permission, resources_path: &[T]) -> bool {
         self.permission.check(permission)
             && (self.resources.is_all() || resources_path.iter().any(|r| self.resources.check(r)))
+            && !resources_path
+                .iter()
+                .any(|r| self.denies(permission, r.as_ref()))
+    }
+}
+
+impl Policy {
+    /// Checks if the policy denies a permission on a resource.
+    ///
+    /// # Arguments
+    /// * `permission` - The permission to check.
+    /// * `resource_path` - The path of the resource.
+    ///
+    /// # Returns
+    /// * `true` if the permission overlaps with the policy's and the resource is denied.
+    /// * `false` otherwise.
+    ///
+    pub fn denies<T>(&self, permission: &Permission, resource_path: T) -> bool
+    where
+        T: AsRef<str>,
+    {
+        self.permission.overlaps(permission) && self.resources.denies(resource_path)
     }
 }
 
 impl fmt::Display for Policy {
     /// Formats the `Policy` struct into a human-readable string.
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if self.resources.is_all() {
+        if self.resources.is_all() && !self.resources.has_deny() {
             if self.permission.is_all() {
                 write!(f, "*")
             } else {
@@ -504,7 +581,12 @@ impl TryFrom<&str> for Policy {
     }
 }
 
-/// Represents a collection of policies.
+/// Represents a collection of policies, e.g. "Folder.Read:* File.Write:!42".
+///
+/// A permission is granted on a resource if any policy allows it and no policy denies it,
+/// the deny rules take precedence regardless of their order and specificity.
+/// For a chain of resources, such as the ancestors of a folder, a deny rule on any of
+/// them denies the permission.
 #[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Policies(pub BTreeSet<Policy>);
 
@@ -544,6 +626,22 @@ impl Policies {
         ]))
     }
 
+    /// Creates policies with read permission for all files, without list permission.
+    ///
+    /// # Returns
+    /// * `Policies` containing a policy with read permission for all files.
+    ///
+    pub fn file_read() -> Self {
+        Self(BTreeSet::from([Policy {
+            permission: Permission {
+                resource: Resource::File,
+                operation: Operation::Read,
+                constraint: None,
+            },
+            resources: Resources::default(),
+        }]))
+    }
+
     // TODO: compress policies
     /// Appends policies to the current collection.
     ///
@@ -562,6 +660,31 @@ impl Policies {
     pub fn remove(&mut self, policies: &Policies) {
         self.0.retain(|p| !policies.0.contains(p));
     }
+
+    /// Checks if any policy contains deny rules.
+    pub fn has_deny(&self) -> bool {
+        self.0.iter().any(|p| p.resources.has_deny())
+    }
+
+    /// Checks if any policy denies a permission on a resource.
+    pub fn denies<T>(&self, permission: &Permission, resource_path: T) -> bool
+    where
+        T: AsRef<str>,
+    {
+        self.0
+            .iter()
+            .any(|p| p.denies(permission, resource_path.as_ref()))
+    }
+
+    /// Checks if any policy denies a permission on any of the resources.
+    pub fn denies_any<T>(&self, permission: &Permission, resources_path: &[T]) -> bool
+    where
+        T: AsRef<str>,
+    {
+        resources_path
+            .iter()
+            .any(|r| self.denies(permission, r.as_ref()))
+    }
 }
 
 impl Deref for Policies {
@@ -583,9 +706,11 @@ where
     T: AsRef<str>,
 {
     fn has_permission(&self, permission: &Permission, resource_path: T) -> bool {
-        self.0
-            .iter()
-            .any(|p| p.has_permission(permission, resource_path.as_ref()))
+        !self.denies(permission, resource_path.as_ref())
+            && self
+                .0
+                .iter()
+                .any(|p| p.has_permission(permission, resource_path.as_ref()))
     }
 }
 
@@ -594,9 +719,11 @@ where
     T: AsRef<str>,
 {
     fn has_permission_any(&self, permission: &Permission, resources_any: &[T]) -> bool {
-        self.0
-            .iter()
-            .any(|p| p.has_permission_any(permission, resources_any))
+        !self.denies_any(permission, resources_any)
+            && self
+                .0
+                .iter()
+                .any(|p| p.has_permission_any(permission, resources_any))
     }
 }
 
@@ -1111,4 +1238,121 @@ mod tests {
             "1"
         ));
     }
+
+    #[test]
+    fn test_permission_overlaps() {
+        let write = Permission::try_from("File.Write").unwrap();
+        assert!(write.overlaps(&Permission::try_from("File.Write").unwrap()));
+        assert!(write.overlaps(&Permission::try_from("File.*").unwrap()));
+        assert!(write.overlaps(&Permission::try_from("*.Write").unwrap()));
+        assert!(write.overlaps(&Permission::try_from("*").unwrap()));
+        assert!(write.overlaps(&Permission::try_from("File.Write.Info").unwrap()));
+        assert!(!write.overlaps(&Permission::try_from("File.Read").unwrap()));
+        assert!(!write.overlaps(&Permission::try_from("Folder.Write").unwrap()));
+
+        let info = Permission::try_from("Bucket.Read.Info").unwrap();
+        assert!(info.overlaps(&Permission::try_from("Bucket.Read").unwrap()));
+        assert!(info.overlaps(&Permission::try_from("Bucket.Read.*").unwrap()));
+        assert!(!info.overlaps(&Permission::try_from("Bucket.Read.File").unwrap()));
+    }
+
+    #[test]
+    fn test_deny_resources() {
+        let rs = Resources::try_from("!42").unwrap();
+        assert_eq!(rs.to_string(), "!42");
+        assert!(rs.has_deny());
+        assert!(!rs.is_all());
+        assert!(!rs.check("42"));
+        assert!(!rs.check("1"));
+        assert!(rs.denies("42"));
+        assert!(!rs.denies("1"));
+
+        let rs = Resources::try_from("*,!42,!43").unwrap();
+        assert_eq!(rs.to_string(), "*,!42,!43");
+        assert_eq!(Resources::try_from("!43,*,!42").unwrap(), rs);
+        assert!(rs.is_all());
+        assert!(rs.check("1"));
+        assert!(rs.denies("42"));
+        assert!(rs.denies("43"));
+        assert!(!rs.denies("1"));
+
+        let rs = Resources::try_from("1,2,!2").unwrap();
+        assert_eq!(rs.to_string(), "1,2,!2");
+        assert!(rs.denies("2"));
+
+        let rs = Resources::try_from("!*").unwrap();
+        assert_eq!(rs.to_string(), "!*");
+        assert!(rs.denies("1"));
+        assert!(rs.denies(""));
+
+        assert!(!Resources::default().denies("1"));
+        assert!(Resources::try_from("!").is_err());
+        assert!(Resources::try_from("!!1").is_err());
+        assert!(Resources::try_from("1!").is_err());
+        assert!(Resources::try_from("!1.2").is_err());
+    }
+
+    #[test]
+    fn test_deny_policies() {
+        let file_write = Permission::try_from("File.Write").unwrap();
+        let file_read = Permission::try_from("File.Read").unwrap();
+        let file_all = Permission::try_from("File.*").unwrap();
+        let folder_read = Permission::try_from("Folder.Read").unwrap();
+
+        // a deny-only policy grants nothing
+        let po = Policy::try_from("File.Write:!42").unwrap();
+        assert_eq!(po.to_string(), "File.Write:!42");
+        assert!(!po.has_permission(&file_write, "1"));
+        assert!(!po.has_permission(&file_write, "42"));
+        assert!(po.denies(&file_write, "42"));
+        assert!(po.denies(&file_all, "42"));
+        assert!(!po.denies(&file_read, "42"));
+        assert!(!po.denies(&file_write, "1"));
+
+        // allow and deny in the same policy
+        let po = Policy::try_from("File.*:*,!42").unwrap();
+        assert_eq!(po.to_string(), "File.*:*,!42");
+        assert!(po.has_permission(&file_write, "1"));
+        assert!(po.has_permission(&file_read, "1"));
+        assert!(!po.has_permission(&file_write, "42"));
+        assert!(!po.has_permission(&file_read, "42"));
+
+        let ps = Policies::try_from("Folder.Read:* File.Write:!42 File.*").unwrap();
+        assert!(ps.has_deny());
+        assert!(!Policies::all().has_deny());
+        assert_eq!(
+            Policies::try_from(ps.to_string().as_str()).unwrap(),
+            ps,
+            "{}",
+            ps
+        );
+        assert!(ps.has_permission(&folder_read, "1"));
+        assert!(ps.has_permission(&file_write, "1"));
+        assert!(ps.has_permission(&file_read, "42"));
+        // deny takes precedence over the broader grant
+        assert!(!ps.has_permission(&file_write, "42"));
+        assert!(!ps.has_permission(&file_all, "42"));
+        assert!(ps.has_permission(&file_all, "1"));
+
+        // the precedence does not depend on the specificity
+        let ps = Policies::try_from("File.Write:42 *.*:!42").unwrap();
+        assert!(!ps.has_permission(&file_write, "42"));
+        assert!(ps.denies(&file_read, "42"));
+
+        // a deny rule on any ancestor denies the chain
+        let ps = Policies::try_from("Folder.*:1 Folder.Read:!3").unwrap();
+        assert!(ps.has_permission_any(&folder_read, &["5", "4", "1"].map(String::from)));
+        assert!(!ps.has_permission_any(&folder_read, &["5", "3", "1"]));
+        assert!(ps.denies_any(&folder_read, &["5", "3", "1"]));
+        assert!(!ps.denies_any(&folder_read, &["5", "4", "1"]));
+        assert!(ps.has_permission_any(
+            &Permission::try_from("Folder.Write").unwrap(),
+            &["5", "3", "1"]
+        ));
+
+        let ps = Policies::try_from("* Bucket.Read:!*").unwrap();
+        assert!(ps.has_permission(&file_read, "1"));
+        assert!(!ps.has_permission(&Permission::try_from("Bucket.Read.Info").unwrap(), "b1"));
+        assert!(ps.has_permission(&Permission::try_from("Bucket.Write").unwrap(), "b1"));
+    }
 }
@@ -0,0 +1,89 @@
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use serde_bytes::{ByteArray, ByteBuf};
+
+use crate::file::MAX_FILE_SIZE_PER_CALL;
+
+// The interface for canisters to read large files chunk by chunk, regardless of the
+// storage backend. It is implemented by the bucket and the canisters that embed
+// `ic_oss_can::ic_oss_fs!()`:
+//
+// open_read : (nat32, opt blob) -> (variant { Ok : StreamHandle; Err : text }) query;
+// read_next : (StreamHandle, opt blob) -> (variant { Ok : StreamChunk; Err : text }) query;
+//
+// The handle is stateless, the reader passes it back with the same access token until
+// the `next` of the chunk is None. The stream fails if the file is changed meanwhile.
+pub const STREAM_CHUNK_SIZE: u64 = MAX_FILE_SIZE_PER_CALL;
+
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct StreamHandle {
+    pub id: u32,
+    pub offset: u64, // the offset of the next chunk
+    pub size: u64,
+    pub content_type: String,
+    pub hash: Option<ByteArray<32>>,
+    pub updated_at: u64, // the version of the file when the stream was opened
+}
+
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct StreamChunk {
+    pub data: ByteBuf,
+    pub next: Option<StreamHandle>, // None: the end of the file
+}
+
+impl StreamHandle {
+    /// Checks that the file is not changed since the stream was opened, returns the
+    /// range of the next chunk.
+    pub fn next_range(&self, size: u64, updated_at: u64) -> Result<(u64, u64), String> {
+        if size != self.size || updated_at != self.updated_at {
+            return Err(format!("file {} changed since the stream opened", self.id));
+        }
+        if self.offset > self.size {
+            return Err(format!("offset out of range, file size {}", self.size));
+        }
+        let len = (self.size - self.offset).min(STREAM_CHUNK_SIZE);
+        Ok((self.offset, len))
+    }
+
+    /// Returns the chunk with the handle of the rest of the file.
+    pub fn chunk(self, data: Vec<u8>) -> StreamChunk {
+        let offset = self.offset + data.len() as u64;
+        StreamChunk {
+            data: ByteBuf::from(data),
+            next: if offset < self.size {
+                Some(StreamHandle { offset, ..self })
+            } else {
+                None
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_handle() {
+        let size = STREAM_CHUNK_SIZE + 10;
+        let handle = StreamHandle {
+            id: 1,
+            size,
+            updated_at: 100,
+            ..Default::default()
+        };
+        assert!(handle.next_range(size, 101).is_err());
+        assert!(handle.next_range(size + 1, 100).is_err());
+
+        let (offset, len) = handle.next_range(size, 100).unwrap();
+        assert_eq!((offset, len), (0, STREAM_CHUNK_SIZE));
+        let chunk = handle.chunk(vec![0u8; len as usize]);
+        let handle = chunk.next.unwrap();
+        assert_eq!(handle.offset, STREAM_CHUNK_SIZE);
+
+        let (offset, len) = handle.next_range(size, 100).unwrap();
+        assert_eq!((offset, len), (STREAM_CHUNK_SIZE, 10));
+        let chunk = handle.chunk(vec![0u8; len as usize]);
+        assert!(chunk.next.is_none());
+    }
+}
@@ -0,0 +1,171 @@
+use candid::Principal;
+use ciborium::from_reader;
+use ic_certificate_verification::{validate_certificate_time, VerifyCertificate};
+use ic_certification::{Certificate, HashTree, LookupResult};
+use ic_oss_types::{
+    bucket::{FileProof, StateDigest},
+    format_error,
+};
+use sha3::{Digest, Sha3_256};
+
+pub use ic_http_certification::{HttpRequest, HttpResponse};
+pub use ic_response_verification::types::VerificationInfo;
+
+/// The default maximum offset between the time of a certificate and the current time.
+pub const MAX_CERT_TIME_OFFSET_NS: u128 = 300_000_000_000; // 5 minutes
+
+// the bucket certifies the responses with the response verification version 2
+const MIN_VERIFICATION_VERSION: u8 = 2;
+
+/// Verifies the responses of a bucket canister.
+#[derive(Clone, Debug)]
+pub struct Verifier {
+    bucket: Principal,
+    root_key: Vec<u8>, // the DER encoded root public key of the IC
+    max_cert_time_offset_ns: u128,
+}
+
+impl Verifier {
+    pub fn new(bucket: Principal, root_key: Vec<u8>) -> Self {
+        Self {
+            bucket,
+            root_key,
+            max_cert_time_offset_ns: MAX_CERT_TIME_OFFSET_NS,
+        }
+    }
+
+    pub fn with_max_cert_time_offset(mut self, offset_ns: u128) -> Self {
+        self.max_cert_time_offset_ns = offset_ns;
+        self
+    }
+
+    /// Verifies the `IC-Certificate` header of an HTTP response of the bucket.
+    ///
+    /// The bucket skips the certification of file bodies, the verified response is
+    /// `None` in that case and the body should be verified with `verify_file_hash`.
+    pub fn verify_response(
+        &self,
+        request: HttpRequest,
+        response: HttpResponse,
+        now_ns: u128,
+    ) -> Result<VerificationInfo, String> {
+        ic_response_verification::verify_request_response_pair(
+            request,
+            response,
+            self.bucket.as_slice(),
+            now_ns,
+            self.max_cert_time_offset_ns,
+            &self.root_key,
+            MIN_VERIFICATION_VERSION,
+        )
+        .map_err(format_error)
+    }
+
+    /// Verifies that the state digest is certified by the bucket: the certificate is
+    /// signed by the IC and is fresh, the witness matches the certified data of the
+    /// bucket and contains the root of the digest.
+    pub fn verify_state_digest(&self, digest: &StateDigest, now_ns: u128) -> Result<(), String> {
+        let certificate = digest
+            .certificate
+            .as_ref()
+            .ok_or("the state digest is not certified")?;
+        let certificate: Certificate = from_reader(certificate.as_slice()).map_err(format_error)?;
+        certificate
+            .verify(self.bucket.as_slice(), &self.root_key)
+            .map_err(format_error)?;
+        validate_certificate_time(&certificate, &now_ns, &self.max_cert_time_offset_ns)
+            .map_err(format_error)?;
+
+        let certified_data = match certificate.tree.lookup_path([
+            b"canister".as_slice(),
+            self.bucket.as_slice(),
+            b"certified_data".as_slice(),
+        ]) {
+            LookupResult::Found(data) => data,
+            _ => Err("certified data not found in the certificate".to_string())?,
+        };
+        let witness: HashTree = from_reader(digest.witness.as_slice()).map_err(format_error)?;
+        if witness.digest().as_slice() != certified_data {
+            Err("the witness does not match the certified data".to_string())?;
+        }
+        match witness.lookup_path([b"state_digest".as_slice()]) {
+            LookupResult::Found(root) if root == digest.root.as_slice() => Ok(()),
+            _ => Err("the state digest does not match the witness".to_string()),
+        }
+    }
+
+    /// Verifies that a file with the SHA3-256 hash is included in the certified
+    /// state digest of the proof. The hash can be computed incrementally from a
+    /// streaming response.
+    pub fn verify_file_hash(
+        &self,
+        proof: &FileProof,
+        hash: &[u8; 32],
+        now_ns: u128,
+    ) -> Result<(), String> {
+        proof.verify(hash)?;
+        self.verify_state_digest(&proof.digest, now_ns)
+    }
+
+    /// Verifies the `ic-oss-file-proof` header of an HTTP response of a file that is
+    /// exposed as an NFT, with the SHA3-256 hash of the response body.
+    pub fn verify_file_proof_header(
+        &self,
+        value: &str,
+        hash: &[u8; 32],
+        now_ns: u128,
+    ) -> Result<FileProof, String> {
+        let proof = FileProof::from_header_value(value)?;
+        self.verify_file_hash(&proof, hash, now_ns)?;
+        Ok(proof)
+    }
+
+    /// Verifies the full content of a downloaded file.
+    pub fn verify_file_content(
+        &self,
+        proof: &FileProof,
+        content: &[u8],
+        now_ns: u128,
+    ) -> Result<(), String> {
+        if content.len() as u64 != proof.size {
+            return Err("file size mismatch".to_string());
+        }
+        let hash: [u8; 32] = Sha3_256::digest(content).into();
+        self.verify_file_hash(proof, &hash, now_ns)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_verify_file_content() {
+        let verifier = Verifier::new(Principal::anonymous(), vec![]);
+        let content = b"hello";
+        let hash: [u8; 32] = Sha3_256::digest(content).into();
+        let mut proof = FileProof {
+            id: 1,
+            name: "hello.txt".to_string(),
+            size: 5,
+            hash: Some(hash.into()),
+            siblings: vec![[0u8; 32].into(); 32],
+            ..Default::default()
+        };
+        proof.digest.root = proof.root().into();
+
+        assert_eq!(
+            verifier.verify_file_content(&proof, b"hello!", 0),
+            Err("file size mismatch".to_string())
+        );
+        assert_eq!(
+            verifier.verify_file_content(&proof, b"hallo", 0),
+            Err("file hash mismatch".to_string())
+        );
+        // the proof is valid but the state digest is not certified
+        assert_eq!(
+            verifier.verify_file_content(&proof, content, 0),
+            Err("the state digest is not certified".to_string())
+        );
+    }
+}